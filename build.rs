@@ -0,0 +1,73 @@
+use std::{env, fs};
+use std::path::Path;
+
+#[allow(dead_code)]
+#[path = "src/charset.rs"]
+mod charset;
+#[allow(dead_code)]
+#[path = "src/isa.rs"]
+mod isa;
+#[allow(dead_code)]
+#[path = "src/asm.rs"]
+mod asm;
+
+// assemble the built-in firmware and examples with the crate's own assembler
+
+const FIRMWARE: &[&str] = &["loader"];
+
+// examples/NAME/NAME.s, carried by rustframe for --example, which also keeps
+// them assembling as the instruction set changes; --selftest runs them too, so
+// each halts with R1 = 0 when it worked
+const EXAMPLES: &[&str] = &["supervisor", "irqstorm"];
+
+// selftest/NAME.s, the guest conformance tests --selftest runs; each halts
+// with R1 = 0 if it passed, else the number of the check that failed
+const SELFTESTS: &[&str] = &["alu", "memory", "devices", "tod", "vector", "system"];
+
+// the conformance tests for instructions a feature adds, built only with it
+const FEATURE_SELFTESTS: &[(&str, &str)] = &[("CARGO_FEATURE_CRYPTO", "crypto")];
+
+fn main() {
+	println!("cargo:rerun-if-changed=src/asm.rs");
+	println!("cargo:rerun-if-changed=src/isa.rs");
+	
+	let out_dir = env::var("OUT_DIR").unwrap();
+	for name in FIRMWARE {
+		let source = format!("firmware/{}.s", name);
+		println!("cargo:rerun-if-changed={}", source);
+		
+		let out = match asm::Assembler::new().assemble_file(&source) {
+			Ok(x) => x,
+			Err(e) => panic!("{}", e),
+		};
+		let (_, image) = out.image();
+		fs::write(Path::new(&out_dir).join(format!("{}.bin", name)), image).unwrap();
+	}
+	
+	let examples: Vec<_> = EXAMPLES.iter().map(|x| (*x, format!("examples/{0}/{0}.s", x))).collect();
+	fs::write(Path::new(&out_dir).join("examples.rs"), table(&out_dir, "example", &examples)).unwrap();
+	let features = FEATURE_SELFTESTS.iter().filter(|x| env::var_os(x.0).is_some()).map(|x| x.1);
+	let selftests: Vec<_> = SELFTESTS.iter().copied().chain(features).map(|x| (x, format!("selftest/{}.s", x))).collect();
+	fs::write(Path::new(&out_dir).join("selftests.rs"), table(&out_dir, "selftest", &selftests)).unwrap();
+}
+
+// assemble each (name, source) to OUT_DIR/KIND-NAME.bin, and return a table of
+// name, origin and image for the emulator to include; the source's whole
+// directory is watched, for anything it includes
+fn table(out_dir: &str, kind: &str, programs: &[(&str, String)]) -> String {
+	let mut table = String::from("&[\n");
+	for (name, source) in programs {
+		println!("cargo:rerun-if-changed={}", Path::new(source).parent().unwrap().display());
+		
+		let out = match asm::Assembler::new().assemble_file(source) {
+			Ok(x) => x,
+			Err(e) => panic!("{}", e),
+		};
+		let (origin, image) = out.image();
+		fs::write(Path::new(out_dir).join(format!("{}-{}.bin", kind, name)), image).unwrap();
+		table.push_str(&format!("\t(\"{0}\", 0x{1:08X}, include_bytes!(concat!(env!(\"OUT_DIR\"), \"/{2}-{0}.bin\"))),\n",
+			name, origin, kind));
+	}
+	table.push_str("]\n");
+	table
+}
@@ -0,0 +1,82 @@
+* Interrupt storm workload: a busy loop at priority level 0, with an entry on
+* each of levels 1 to 7 that only counts. Level n's entry adds one to R(n + 1)
+* and returns, leaving F0 alone, so it can break in anywhere in the loop or in
+* a lower level's entry. The loop goes round R10 times, or PASSES if R10 is 0
+* at the start, then halts with R1 = 0 and the counts in R2-R8.
+*
+* rustframe --irq-storm runs this when given no program, until its time limit,
+* and holds the counts against the interrupts it raised. On its own it takes
+* no interrupts:
+*
+*   rustframe --batch --example irqstorm --exit-reg 1
+
+PASSES = 20000
+
+	.org 0x1000
+start:	LA 1, 7: 15, +@sdt
+	LQ 2, 1			; PEBA and PLBA
+	SSDTR 1, 2
+	LQ 1, 0
+	LQ 2, 0
+	LQ 3, 0
+	LQ 4, 0
+	LQ 5, 0
+	LQ 6, 0
+	LQ 7, 0
+	LQ 8, 0
+	C 10, 0
+	IF 0x10
+	L 10, 7: 15, +@passes
+	L 9, 7: 15, +@level0
+	SF 8, 9
+spin:	SQ 10, 1
+	C 10, 0
+	IFN 0x10
+	LA 15, 7: 15, +@spin
+	HLT
+
+pl1:	LA 2, 7: 2, 1
+	PLR
+pl2:	LA 3, 7: 3, 1
+	PLR
+pl3:	LA 4, 7: 4, 1
+	PLR
+pl4:	LA 5, 7: 5, 1
+	PLR
+pl5:	LA 6, 7: 6, 1
+	PLR
+pl6:	LA 7, 7: 7, 1
+	PLR
+pl7:	LA 8, 7: 8, 1
+	PLR
+
+	.align 4
+passes:	.word PASSES
+level0:	.word 0xF0
+sdt:	.word entry, entry + 0x80
+	.byte 0xEB, 0x01, 0, 0
+	.word link, link + 0x80
+	.byte 0xEB, 0x01, 0, 0
+entry:	.space 0x10
+	.word 0, 0x100000
+	.byte 0xEB, 0x00, 0x00, 0
+	.word pl1
+	.word 0, 0x100000
+	.byte 0xEB, 0x00, 0x00, 0
+	.word pl2
+	.word 0, 0x100000
+	.byte 0xEB, 0x00, 0x00, 0
+	.word pl3
+	.word 0, 0x100000
+	.byte 0xEB, 0x00, 0x00, 0
+	.word pl4
+	.word 0, 0x100000
+	.byte 0xEB, 0x00, 0x00, 0
+	.word pl5
+	.word 0, 0x100000
+	.byte 0xEB, 0x00, 0x00, 0
+	.word pl6
+	.word 0, 0x100000
+	.byte 0xEB, 0x00, 0x00, 0
+	.word pl7
+link:	.space 0x80
@@ -0,0 +1,26 @@
+* Null modem example, receiving side: prints each character the other machine
+* sends on the debug port, and halts when it sends a zero. See sender.s.
+
+INBOUND = 0b1000
+
+	.org 0x1000
+start:	LA 9, 7: 0, DT_DATAPORT
+	BAL 14, 7: 15, +@find
+	MV 1, 8			; R1 = data port
+	LA 9, 7: 0, DT_DEBUGPORT
+	BAL 14, 7: 15, +@find
+	MV 2, 8			; R2 = debug port
+	
+wait:	BTR 4, 7: 1, 2		; lines, cleared by reading them
+	ANQ 4, INBOUND
+	C 4, 0
+	IF 0x10
+	LA 15, 7: 15, +@wait
+	BTR 3, 7: 1, 0		; reading the word lets the next one in
+	C 3, 0
+	IF 0x10
+	HLT
+	BST 3, 7: 2, 0
+	LA 15, 7: 15, +@wait
+	
+	.include "discover.inc"
@@ -0,0 +1,32 @@
+* Null modem example, sending side: passes a message to the other machine a
+* character per word over the data port, waiting for each to be taken, and
+* halts after the terminating zero. Run it against the receiver with
+*
+*   sqasm -f hex sender.s
+*   sqasm -f hex receiver.s
+*   rustframe --batch --load sender.hex --peer receiver.hex
+
+ACKNOWLEDGE = 0b0010
+
+	.org 0x1000
+start:	LA 9, 7: 0, DT_DATAPORT
+	BAL 14, 7: 15, +@find
+	MV 1, 8			; R1 = data port
+	LA 3, 7: 15, +@msg	; R3 walks the message
+	
+next:	BTR 2, 7: 3, 0
+	AQ 3, 1
+	HST 2, 7: 1, 0
+wait:	BTR 4, 7: 1, 2		; lines, cleared by reading them
+	ANQ 4, ACKNOWLEDGE
+	C 4, 0
+	IF 0x10
+	LA 15, 7: 15, +@wait
+	C 2, 0
+	IFN 0x10
+	LA 15, 7: 15, +@next
+	HLT
+	
+	.include "discover.inc"
+
+msg:	.asciz "HELLO FROM THE OTHER MACHINE\n"
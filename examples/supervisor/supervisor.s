@@ -0,0 +1,212 @@
+* Preemptive supervisor example: two application tasks, each in its own
+* segment under its own protection key, share the CPU round-robin on the
+* time-of-day comparator interrupt. Each switch happens in the level 5 entry:
+* the interrupted task's registers, flags and priority level link block go to
+* its control block, the next task's are brought back, and PLR resumes it.
+* After SWITCHES switches the supervisor halts with R1 = 0 if both tasks made
+* progress, 1 if not, and their counters in R2 and R3. A task that faults
+* enters level 7, which halts with R1 = 7.
+*
+* The build assembles this file, and rustframe carries it as a built-in
+* example:
+*
+*   rustframe --batch --example supervisor --exit-reg 1
+
+SWITCHES = 16
+INTERVAL = 2000		; microseconds between switches
+
+* control block layout
+TCB_LINK = 0		; priority level link block, four words
+TCB_REGS = 16		; R1 to R14
+TCB_FLAGS = 72		; F0
+TCB_KEY = 76		; protection key for the task's segment
+TCB_NEXT = 80		; next control block in the round
+
+	.org 0x1000
+start:	LA 1, 7: 15, +@sdt
+	LQ 2, 3			; four descriptors
+	SSDTR 1, 2
+
+	; let task A's key through, then copy its control block's link block
+	; into level 7's, where the PLR below takes it from
+	L 1, 7: 15, +@current
+	L 2, 7: 1, TCB_KEY
+	SMPK 0, 2
+	L 3, 7: 15, +@plba
+	L 2, 7: 1, TCB_LINK
+	ST 2, 7: 3, 0x70
+	L 2, 7: 1, TCB_LINK + 4
+	ST 2, 7: 3, 0x74
+	L 2, 7: 1, TCB_LINK + 8
+	ST 2, 7: 3, 0x78
+	L 2, 7: 1, TCB_LINK + 12
+	ST 2, 7: 3, 0x7C
+
+	BAL 14, 7: 15, +@arm
+	PLR
+
+* arm: set the comparator INTERVAL from now, using R2-R4
+arm:	LTOD 2, 3
+	L 4, 7: 15, +@interval
+	A 2, 4
+	AC 3, 0
+	STODC 2, 3
+	BAL 0, 7: 14, 0
+
+* the level 5 entry: switch tasks
+tick:	ST 1, 7: 15, +@scratch
+	L 1, 7: 15, +@current
+	ST 2, 7: 1, TCB_REGS + 4
+	ST 3, 7: 1, TCB_REGS + 8
+	ST 4, 7: 1, TCB_REGS + 12
+	ST 5, 7: 1, TCB_REGS + 16
+	ST 6, 7: 1, TCB_REGS + 20
+	ST 7, 7: 1, TCB_REGS + 24
+	ST 8, 7: 1, TCB_REGS + 28
+	ST 9, 7: 1, TCB_REGS + 32
+	ST 10, 7: 1, TCB_REGS + 36
+	ST 11, 7: 1, TCB_REGS + 40
+	ST 12, 7: 1, TCB_REGS + 44
+	ST 13, 7: 1, TCB_REGS + 48
+	ST 14, 7: 1, TCB_REGS + 52
+	L 2, 7: 15, +@scratch
+	ST 2, 7: 1, TCB_REGS
+	LF 2, 0
+	ST 2, 7: 1, TCB_FLAGS
+
+	; pl_set left the task's PS and PC in level 5's link block
+	L 3, 7: 15, +@plba
+	L 2, 7: 3, 0x50
+	ST 2, 7: 1, TCB_LINK
+	L 2, 7: 3, 0x54
+	ST 2, 7: 1, TCB_LINK + 4
+	L 2, 7: 3, 0x58
+	ST 2, 7: 1, TCB_LINK + 8
+	L 2, 7: 3, 0x5C
+	ST 2, 7: 1, TCB_LINK + 12
+
+	L 2, 7: 15, +@left
+	SQ 2, 1
+	ST 2, 7: 15, +@left
+	C 2, 0
+	IF 0x10
+	LA 15, 7: 15, +@done
+
+	L 1, 7: 1, TCB_NEXT
+	ST 1, 7: 15, +@current
+	L 2, 7: 1, TCB_LINK
+	ST 2, 7: 3, 0x50
+	L 2, 7: 1, TCB_LINK + 4
+	ST 2, 7: 3, 0x54
+	L 2, 7: 1, TCB_LINK + 8
+	ST 2, 7: 3, 0x58
+	L 2, 7: 1, TCB_LINK + 12
+	ST 2, 7: 3, 0x5C
+	L 2, 7: 1, TCB_KEY
+	SMPK 0, 2
+
+	; a later comparator also clears the pending interrupt
+	BAL 14, 7: 15, +@arm
+
+	L 2, 7: 1, TCB_FLAGS
+	SF 0, 2
+	L 2, 7: 1, TCB_REGS + 4
+	L 3, 7: 1, TCB_REGS + 8
+	L 4, 7: 1, TCB_REGS + 12
+	L 5, 7: 1, TCB_REGS + 16
+	L 6, 7: 1, TCB_REGS + 20
+	L 7, 7: 1, TCB_REGS + 24
+	L 8, 7: 1, TCB_REGS + 28
+	L 9, 7: 1, TCB_REGS + 32
+	L 10, 7: 1, TCB_REGS + 36
+	L 11, 7: 1, TCB_REGS + 40
+	L 12, 7: 1, TCB_REGS + 44
+	L 13, 7: 1, TCB_REGS + 48
+	L 14, 7: 1, TCB_REGS + 52
+	L 1, 7: 1, TCB_REGS
+	PLR
+
+done:	L 2, 7: 15, +@counta
+	L 2, 7: 2, 0
+	L 3, 7: 15, +@countb
+	L 3, 7: 3, 0
+	LQ 1, 1
+	C 2, 0
+	IF 0x10
+	HLT
+	C 3, 0
+	IF 0x10
+	HLT
+	MV 1, 0
+	HLT
+
+* the level 7 entry: a task faulted
+fault:	LQ 1, 7
+	HLT
+
+	.align 4
+scratch:	.word 0
+current:	.word tcba
+left:	.word SWITCHES
+interval:	.word INTERVAL
+plba:	.word link
+counta:	.word a_count
+countb:	.word b_count
+
+* segment descriptors: base, limit, key, flags, two spare bytes; SSDTR takes
+* the entry and link block areas from the first two
+sdt:	.word entry, entry + 0x80
+	.byte 0xEB, 0x01, 0, 0
+	.word link, link + 0x80
+	.byte 0xEB, 0x01, 0, 0
+	.word taska, taska_end
+	.byte 0x0A, 0xE0, 0, 0
+	.word taskb, taskb_end
+	.byte 0x0B, 0xE0, 0, 0
+
+* priority level entry blocks: PS base, limit, key, flags, SR8, and PC
+entry:	.space 0x50
+	.word 0, 0x10000
+	.byte 0xEB, 0x00, 0x00, 0
+	.word tick
+	.space 0x10
+	.word 0, 0x10000
+	.byte 0xEB, 0x00, 0x00, 0
+	.word fault
+
+* priority level link blocks, filled in as levels are entered
+link:	.space 0x80
+
+* task control blocks; a task starts at the beginning of its segment, at
+* priority 0 in application state with faults going to level 7
+tcba:	.word taska, taska_end
+	.byte 0x0A, 0xE0, 0x71, 2
+	.word 0
+	.space 60
+	.word 0x0A, tcbb
+tcbb:	.word taskb, taskb_end
+	.byte 0x0B, 0xE0, 0x71, 3
+	.word 0
+	.space 60
+	.word 0x0B, tcba
+
+* the tasks count as fast as they can, each in its own segment; neither can
+* reach the other's counter, which is under a different key
+
+	.org 0x1800
+taska:	L 1, 7: 15, +@a_count
+	AQ 1, 1
+	ST 1, 7: 15, +@a_count
+	LA 15, 7: 15, +@taska
+	.align 4
+a_count:	.word 0
+taska_end:
+
+	.org 0x1900
+taskb:	L 1, 7: 15, +@b_count
+	AQ 1, 2
+	ST 1, 7: 15, +@b_count
+	LA 15, 7: 15, +@taskb
+	.align 4
+b_count:	.word 0
+taskb_end:
@@ -0,0 +1,194 @@
+* Relocating loader: boot a load deck from the card reader or the operator
+* console
+*
+* Each card is one record, in columns from 1:
+*   B bbbbbbbb            add bbbbbbbb to the addresses on later cards
+*   D aaaaaaaa nn hh...   store nn bytes (hex) at address aaaaaaaa
+*   R nn aaaaaaaa...      add the displacement to the nn words at aaaaaaaa...
+*   E aaaaaaaa            branch to aaaaaaaa
+* Blanks shown above are not punched. The displacement starts at 0, so an
+* absolute deck loads where it was assembled; a B card in front of a deck
+* from sqasm -r places it anywhere word aligned. On a bad card or an empty hopper
+* the loader halts with R2 pointing just past the failing column.
+*
+* With the low panel switch up (--switches 1) the records are replies on the
+* operator console instead, typed or pasted in a line at a time (--paste);
+* the loader waits for each. A reply is blank past its end, so one cut short
+* is a bad card.
+*
+* Runs from reset state: supervisor, flat segments, segment 7 signed. The
+* reader or console is the first one in the discovery table; with none the
+* loader halts with R2 = 0.
+
+DISCOVERY = 0xE0000
+DT_READER = 0x12
+DT_OPCONSOLE = 0x1A
+DT_PANEL = 0x1D
+STATUS  = 80
+COMMAND = 84
+RECEIVE = 2
+LENGTH  = 8
+RECORD  = 16
+RECORD_WORDS = 32
+
+	.org 0xF0000
+boot:	MV 12, 0		; R12 = 0: cards
+	LA 9, 7: 0, DT_PANEL
+	BAL 14, 7: 15, +@find
+	C 8, 0
+	IFN 0x10
+	L 12, 7: 8, 0
+	ANQ 12, 1		; R12 = 1: console
+	LA 9, 7: 0, DT_READER
+	C 12, 0
+	IFN 0x10
+	LA 9, 7: 0, DT_OPCONSOLE
+	BAL 14, 7: 15, +@find
+	MV 1, 8			; R1 = reader or console
+	MV 4, 0
+	C 1, 0
+	IF 0x10
+	LA 15, 7: 15, +@fail
+	LA 2, 7: 0, 0
+	MV 11, 0		; R11 = displacement
+	
+record:	C 12, 0
+	IFN 0x10
+	LA 15, 7: 15, +@reply
+	LQ 2, 1
+	BST 2, 7: 1, COMMAND	; feed the next card
+	BTR 3, 7: 1, STATUS
+	ANQ 3, 1
+	C 3, 0
+	IF 0x10			; not ready: hopper empty
+	LA 15, 7: 15, +@fail
+	MV 4, 1			; R4 walks the card columns
+	LA 15, 7: 15, +@parse
+	
+reply:	LA 4, 7: 1, RECORD
+	L 5, 7: 15, +@blanks
+	LA 7, 7: 0, RECORD_WORDS
+blank:	ST 5, 7: 4, 0
+	AQ 4, 4
+	SQ 7, 1
+	C 7, 0
+	IFN 0x10
+	LA 15, 7: 15, +@blank
+wait:	LQ 2, RECEIVE
+	ST 2, 7: 1, 0
+	L 3, 7: 1, LENGTH
+	AQ 3, 1
+	C 3, 0
+	IF 0x10			; all ones: no reply yet
+	LA 15, 7: 15, +@wait
+	LA 4, 7: 1, RECORD	; R4 walks the reply
+	
+parse:	BTR 5, 7: 4, 0
+	AQ 4, 1
+	LA 9, 7: 0, C'E'
+	C 5, 9
+	IF 0x10
+	LA 15, 7: 15, +@entry
+	LA 9, 7: 0, C'B'
+	C 5, 9
+	IF 0x10
+	LA 15, 7: 15, +@base
+	LA 9, 7: 0, C'R'
+	C 5, 9
+	IF 0x10
+	LA 15, 7: 15, +@reloc
+	LA 9, 7: 0, C'D'
+	C 5, 9
+	IFN 0x10
+	LA 15, 7: 15, +@fail
+	
+	LQ 10, 8
+	BAL 14, 7: 15, +@hexn
+	MV 6, 8
+	A 6, 11			; R6 = load address
+	LQ 10, 2
+	BAL 14, 7: 15, +@hexn
+	MV 7, 8			; R7 = byte count
+	
+data:	C 7, 0
+	IF 0x10
+	LA 15, 7: 15, +@record
+	LQ 10, 2
+	BAL 14, 7: 15, +@hexn
+	BST 8, 7: 6, 0
+	AQ 6, 1
+	SQ 7, 1
+	LA 15, 7: 15, +@data
+	
+entry:	LQ 10, 8
+	BAL 14, 7: 15, +@hexn
+	A 8, 11
+	BAL 0, 7: 8, 0
+	
+base:	LQ 10, 8
+	BAL 14, 7: 15, +@hexn
+	MV 11, 8
+	LA 15, 7: 15, +@record
+	
+reloc:	LQ 10, 2
+	BAL 14, 7: 15, +@hexn
+	MV 7, 8			; R7 = word count
+fix:	C 7, 0
+	IF 0x10
+	LA 15, 7: 15, +@record
+	LQ 10, 8
+	BAL 14, 7: 15, +@hexn
+	A 8, 11
+	L 5, 7: 8, 0
+	A 5, 11
+	ST 5, 7: 8, 0
+	SQ 7, 1
+	LA 15, 7: 15, +@fix
+	
+fail:	MV 2, 4
+	HLT
+	
+* hexn: R8 = value of the R10 hex digits at R4, advancing R4; returns via LR
+hexn:	MV 8, 0
+digit:	BTR 5, 7: 4, 0
+	AQ 4, 1
+	LA 9, 7: 0, C'9'
+	C 5, 9
+	IF 0x40			; above '9': a letter
+	SQ 5, 7
+	LA 9, 7: 0, C'0'
+	S 5, 9
+	LQ 9, 15
+	C 5, 9
+	IF 0x40			; not a hex digit
+	LA 15, 7: 15, +@fail
+	SLQ 8, 4
+	O 8, 5
+	SQ 10, 1
+	C 10, 0
+	IFN 0x10
+	LA 15, 7: 15, +@digit
+	BAL 0, 7: 14, 0
+	
+* find: R8 = the first device of type R9 in the discovery table, or 0 with
+* none, using R3, R5 and R7; returns via LR
+find:	LQ 7, 14
+	SLQL 7, 16		; R7 = DISCOVERY
+	L 3, 7: 7, 0		; R3 = entries left
+	MV 8, 0
+look:	C 3, 0
+	IF 0x10
+	BAL 0, 7: 14, 0
+	AQ 7, 4			; R7 = next entry
+	L 5, 7: 7, 0
+	C 5, 9
+	IF 0x10
+	LA 15, 7: 15, +@found
+	AQ 7, 12
+	SQ 3, 1
+	LA 15, 7: 15, +@look
+found:	L 8, 7: 7, 4
+	BAL 0, 7: 14, 0
+	
+	.align 4
+blanks:	.word 0x20202020
@@ -0,0 +1,326 @@
+* Self-test: integer arithmetic, logic and shifts
+*
+* Each check loads its number into R1 and halts if the result is wrong, so
+* the test halts with R1 = 0 when everything passed. See build.rs.
+
+	.org 0x1000
+start:	; 1: add and subtract
+	LQ 1, 1
+	L 2, 7: 15, +@big
+	L 3, 7: 15, +@small
+	A 2, 3
+	L 4, 7: 15, +@sum
+	C 2, 4
+	IFN 0x10
+	HLT
+	S 2, 3
+	L 4, 7: 15, +@big
+	C 2, 4
+	IFN 0x10
+	HLT
+
+	; 2: quick add and subtract
+	LQ 1, 2
+	MV 2, 0
+	AQ 2, 15
+	SQ 2, 5
+	LQ 4, 10
+	C 2, 4
+	IFN 0x10
+	HLT
+
+	; 3: carry into the high word of a 64-bit add
+	LQ 1, 3
+	L 2, 7: 15, +@ones
+	MV 3, 0
+	LQ 4, 1
+	A 2, 4
+	AC 3, 0
+	C 2, 0
+	IFN 0x10
+	HLT
+	LQ 4, 1
+	C 3, 4
+	IFN 0x10
+	HLT
+
+	; 4: and, or, xor
+	LQ 1, 4
+	L 2, 7: 15, +@pattern
+	MV 3, 2
+	ANQ 3, 15
+	LQ 4, 10
+	C 3, 4
+	IFN 0x10
+	HLT
+	MV 3, 2
+	X 3, 2
+	C 3, 0
+	IFN 0x10
+	HLT
+	MV 3, 0
+	OQ 3, 5
+	LQ 4, 5
+	C 3, 4
+	IFN 0x10
+	HLT
+
+	; 5: logical and arithmetic shifts
+	LQ 1, 5
+	LQ 2, 1
+	SLQ 2, 16
+	SLQ 2, 15
+	L 4, 7: 15, +@sign
+	C 2, 4
+	IFN 0x10
+	HLT
+	MV 3, 2
+	SRQ 3, 16
+	SRQ 3, 15
+	LQ 4, 1
+	C 3, 4
+	IFN 0x10
+	HLT
+	LQ 4, 4
+	ASR 2, 4
+	L 4, 7: 15, +@signs
+	C 2, 4
+	IFN 0x10
+	HLT
+
+	; 6: sign extension and insertion
+	LQ 1, 6
+	L 2, 7: 15, +@pattern
+	BSF 3, 2
+	L 4, 7: 15, +@extended
+	C 3, 4
+	IFN 0x10
+	HLT
+	MV 3, 0
+	BNS 3, 2
+	L 4, 7: 15, +@inserted
+	C 3, 4
+	IFN 0x10
+	HLT
+
+	; 7: compare flags: less and greater
+	LQ 1, 7
+	LQ 2, 3
+	LQ 3, 9
+	C 2, 3
+	IFN 0x20
+	HLT
+	C 3, 2
+	IFN 0x40
+	HLT
+
+	; 8: multiply and divide
+	LQ 1, 8
+	LQ 2, 7
+	LQ 3, 0
+	SQ 3, 6
+	MUL 2, 3
+	IF 0x88
+	HLT
+	L 4, 7: 15, +@minus42
+	C 2, 4
+	IFN 0x10
+	HLT
+	LQ 3, 5
+	DIV 2, 3
+	L 4, 7: 15, +@minus8
+	C 2, 4
+	IFN 0x10
+	HLT
+	LQ 3, 3
+	DIVU 2, 3
+	L 4, 7: 15, +@third
+	C 2, 4
+	IFN 0x10
+	HLT
+	L 2, 7: 15, +@sign
+	LQ 3, 2
+	MULU 2, 3
+	IFN 0x0C
+	HLT
+
+	; 9: widening multiply
+	LQ 1, 9
+	L 2, 7: 15, +@sign
+	LQ 4, 4
+	MULW 2, 4
+	IF 0x8C
+	HLT
+	L 5, 7: 15, +@minus2
+	C 2, 5
+	IFN 0x10
+	HLT
+	LQ 5, 0
+	C 3, 5
+	IFN 0x10
+	HLT
+	L 2, 7: 15, +@sign
+	MULWU 2, 4
+	LQ 5, 2
+	C 2, 5
+	IFN 0x10
+	HLT
+	LQ 5, 0
+	C 3, 5
+	IFN 0x10
+	HLT
+
+	; 10: divide with remainder, -7 / 2 truncated and floored
+	LQ 1, 10
+	LQ 2, 0
+	SQ 2, 7
+	LQ 4, 2
+	DIVR 2, 4
+	L 5, 7: 15, +@minus3
+	C 2, 5
+	IFN 0x10
+	HLT
+	L 5, 7: 15, +@ones
+	C 3, 5
+	IFN 0x10
+	HLT
+	LQ 2, 0
+	SQ 2, 7
+	MODR 2, 4
+	L 5, 7: 15, +@minus4
+	C 2, 5
+	IFN 0x10
+	HLT
+	LQ 5, 1
+	C 3, 5
+	IFN 0x10
+	HLT
+
+	; 11: packed decimal, 123 + -456, then -456 - -456
+	LQ 1, 11
+	LA 3, 7: 15, +@dec2
+	AP 3, 7: 15, +@dec1
+	IFN 0x40
+	HLT
+	L 4, 7: 15, +@dec1lo
+	L 5, 7: 15, +@decsum
+	C 4, 5
+	IFN 0x10
+	HLT
+	ZAP 3, 7: 15, +@dec1
+	SP 3, 7: 15, +@dec1
+	IFN 0x10
+	HLT
+	L 4, 7: 15, +@dec1lo
+	L 5, 7: 15, +@deczero
+	C 4, 5
+	IFN 0x10
+	HLT
+
+	; 12: floating point, 7.0 / 2.0 = 3.5, which FIX makes 3 and flags
+	; inexact; then 3.5 / 0.0 flags divide by zero
+	LQ 1, 12
+	LQ 2, 0
+	SF 1, 2
+	LQ 2, 7
+	FLT 2, 2
+	LQ 3, 2
+	FLT 3, 3
+	FDIV 2, 3
+	FL 6, 7: 15, +@half7
+	FCMP 2, 6
+	IFN 0x10
+	HLT
+	FCMP 2, 3
+	IFN 0x20
+	HLT
+	FIX 4, 2
+	LQ 5, 3
+	C 4, 5
+	IFN 0x10
+	HLT
+	LF 4, 1
+	LQ 5, 1
+	C 4, 5
+	IFN 0x10
+	HLT
+	LQ 4, 0
+	FLT 4, 4
+	FDIV 2, 4
+	LF 4, 1
+	LQ 5, 9
+	C 4, 5
+	IFN 0x10
+	HLT
+
+	; 13: FPUT, FMV, FADD, FSUB and FMUL on 1.5 and 2.25, all exact, FGET
+	; and FST to see the results
+	LQ 1, 13
+	LQ 2, 0
+	SF 1, 2
+	L 2, 7: 15, +@fp15
+	FPUT 2, 2
+	L 3, 7: 15, +@fp225
+	FPUT 3, 3
+	FMV 4, 2
+	FGET 5, 4
+	C 5, 2
+	IFN 0x10
+	HLT
+	FADD 4, 3
+	FGET 5, 4
+	L 6, 7: 15, +@fp375
+	C 5, 6
+	IFN 0x10
+	HLT
+	FMV 4, 2
+	FSUB 4, 3
+	FGET 5, 4
+	L 6, 7: 15, +@fpm075
+	C 5, 6
+	IFN 0x10
+	HLT
+	FMV 4, 2
+	FMUL 4, 3
+	FST 4, 7: 15, +@fpout
+	L 5, 7: 15, +@fpout
+	L 6, 7: 15, +@fp3375
+	C 5, 6
+	IFN 0x10
+	HLT
+	LF 4, 1
+	C 4, 0
+	IFN 0x10
+	HLT
+
+	MV 1, 0
+	HLT
+
+	.align 4
+big:	.word 0x12345678
+small:	.word 0x11111111
+sum:	.word 0x23456789
+ones:	.word 0xFFFFFFFF
+pattern:	.word 0x5A5A5AFA
+sign:	.word 0x80000000
+signs:	.word 0xF8000000
+extended:	.word 0xFFFFFFFA
+inserted:	.word 0x000000FA
+minus42:	.word 0xFFFFFFD6
+minus8:	.word 0xFFFFFFF8
+third:	.word 0x55555552
+minus2:	.word 0xFFFFFFFE
+minus3:	.word 0xFFFFFFFD
+minus4:	.word 0xFFFFFFFC
+half7:	.word 0x40600000
+dec1:	.byte 0, 0, 0, 0
+dec1lo:	.byte 0, 0, 0x12, 0x3C
+dec2:	.byte 0, 0, 0, 0, 0, 0, 0x45, 0x6D
+decsum:	.word 0x3D330000
+deczero:	.word 0x0C000000
+fp15:	.word 0x3FC00000
+fp225:	.word 0x40100000
+fp375:	.word 0x40700000
+fpm075:	.word 0xBF400000
+fp3375:	.word 0x40580000
+fpout:	.word 0
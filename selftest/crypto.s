@@ -0,0 +1,77 @@
+* Self-test: the crypto instruction page, against the FIPS 197 and FIPS 180
+* examples
+*
+* Halts with R1 = 0 if every check passed, else the number of the first one
+* that failed. Only built and run with the crypto feature; without it these
+* are illegal instructions.
+
+	.org 0x1000
+start:	; 1: AESE encrypts the FIPS 197 example block
+	LQ 1, 1
+	LA 2, 7: 15, +@key
+	LA 3, 7: 15, +@block
+	AESE 2, 7: 3, 0
+	LA 4, 7: 15, +@cipher
+	BAL 14, 7: 15, +@same
+
+	; 2: AESD takes it back to the plaintext
+	LQ 1, 2
+	AESD 2, 7: 3, 0
+	LA 4, 7: 15, +@plain
+	BAL 14, 7: 15, +@same
+
+	; 3: SHA256 compresses the padded block for "abc" into the initial state
+	LQ 1, 3
+	LA 2, 7: 15, +@state
+	SHA256 2, 7: 15, +@abc
+	LA 3, 7: 15, +@state
+	LA 4, 7: 15, +@digest
+	BAL 14, 7: 15, +@same
+	LA 3, 7: 3, 16
+	LA 4, 7: 4, 16
+	BAL 14, 7: 15, +@same
+
+	MV 1, 0
+	HLT
+
+* halt unless the 16 bytes at R3 and R4 are the same, using R5 and R6
+same:	L 5, 7: 3, 0
+	L 6, 7: 4, 0
+	C 5, 6
+	IFN 0x10
+	HLT
+	L 5, 7: 3, 4
+	L 6, 7: 4, 4
+	C 5, 6
+	IFN 0x10
+	HLT
+	L 5, 7: 3, 8
+	L 6, 7: 4, 8
+	C 5, 6
+	IFN 0x10
+	HLT
+	L 5, 7: 3, 12
+	L 6, 7: 4, 12
+	C 5, 6
+	IFN 0x10
+	HLT
+	BAL 0, 7: 14, 0
+
+	.align 4
+key:	.byte 0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07
+	.byte 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F
+plain:	.byte 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77
+	.byte 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF
+block:	.byte 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77
+	.byte 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF
+cipher:	.byte 0x69, 0xC4, 0xE0, 0xD8, 0x6A, 0x7B, 0x04, 0x30
+	.byte 0xD8, 0xCD, 0xB7, 0x80, 0x70, 0xB4, 0xC5, 0x5A
+
+* the state is eight words, H0 first
+state:	.word 0x6A09E667, 0xBB67AE85, 0x3C6EF372, 0xA54FF53A
+	.word 0x510E527F, 0x9B05688C, 0x1F83D9AB, 0x5BE0CD19
+digest:	.word 0xBA7816BF, 0x8F01CFEA, 0x414140DE, 0x5DAE2223
+	.word 0xB00361A3, 0x96177A9C, 0xB410FF61, 0xF20015AD
+abc:	.byte 0x61, 0x62, 0x63, 0x80
+	.space 59
+	.byte 0x18
@@ -0,0 +1,203 @@
+* Self-test: the discovery table, device ID blocks and the registers of the
+* devices every machine has
+*
+* Halts with R1 = 0 if every check passed, else the number of the first one
+* that failed. Devices are found through the discovery table, so this passes
+* under --random-layout too.
+
+DISCOVERY = 0xE0000
+DT_DISCOVERY = 0x03
+DT_FONT = 0x04
+DT_PRINTER = 0x10
+DT_DATAPORT = 0x11
+DT_REMOTE = 0x17
+DT_SHARED = 0x18
+DT_PANEL = 0x1D
+DT_DISPLAY = 0x21
+
+	.org 0x1000
+start:	; 1: the table lists itself where it always is
+	LQ 1, 1
+	LQ 9, DT_DISCOVERY
+	BAL 14, 7: 15, +@find
+	L 4, 7: 15, +@discovery
+	C 8, 4
+	IFN 0x10
+	HLT
+
+	; 2: every device has an ID block with the vendor and its own type;
+	; remote and shared memory are not devices of this machine's
+	LQ 1, 2
+	L 5, 7: 15, +@discovery
+	L 6, 7: 5, 0
+	L 10, 7: 15, +@vendor
+next:	C 6, 0
+	IF 0x10
+	LA 15, 7: 15, +@panel
+	AQ 5, 4
+	L 7, 7: 5, 0
+	LQ 4, 15
+	AQ 4, 1
+	C 7, 4			; G: the type is below 0x10
+	IF 0x20
+	LA 15, 7: 15, +@skip
+	LQ 4, 15
+	AQ 4, DT_REMOTE - 15
+	C 7, 4
+	IF 0x10
+	LA 15, 7: 15, +@skip
+	AQ 4, 1
+	C 7, 4
+	IF 0x10
+	LA 15, 7: 15, +@skip
+	L 8, 7: 5, 4
+	L 4, 7: 8, 0xF0
+	C 4, 10
+	IFN 0x10
+	HLT
+	HTR 4, 7: 8, 0xF4
+	C 4, 7
+	IFN 0x10
+	HLT
+skip:	AQ 5, 12
+	SQ 6, 1
+	LA 15, 7: 15, +@next
+
+	; 3: the panel's switches read back as set
+panel:	LQ 1, 3
+	LQ 9, 15
+	AQ 9, DT_PANEL - 15
+	BAL 14, 7: 15, +@find
+	L 2, 7: 15, +@pattern
+	ST 2, 7: 8, 0
+	L 3, 7: 8, 0
+	C 2, 3
+	IFN 0x10
+	HLT
+
+	; 4: a word written to the data port in loopback comes back
+	LQ 1, 4
+	LQ 9, 15
+	AQ 9, DT_DATAPORT - 15
+	BAL 14, 7: 15, +@find
+	LQ 2, 1
+	BST 2, 7: 8, 11
+	L 2, 7: 15, +@pattern
+	HST 2, 7: 8, 0
+	BTR 3, 7: 8, 4
+	ANQ 3, 1
+	LQ 4, 1
+	C 3, 4
+	IFN 0x10
+	HLT
+	HTR 3, 7: 8, 0
+	HTR 2, 2
+	C 2, 3
+	IFN 0x10
+	HLT
+	BST 0, 7: 8, 11
+
+	; 5: the printer reports its default geometry, 144 columns in one buffer
+	LQ 1, 5
+	LQ 9, 15
+	AQ 9, 1
+	BAL 14, 7: 15, +@find
+	L 3, 7: 8, 160
+	L 4, 7: 15, +@geometry
+	C 3, 4
+	IFN 0x10
+	HLT
+
+	; 6: the data port's UART keeps its line settings, and a word looped
+	; back through it keeps only its data bits, 7 here, with no errors
+	LQ 1, 6
+	LQ 9, 15
+	AQ 9, DT_DATAPORT - 15
+	BAL 14, 7: 15, +@find
+	LQ 2, 12
+	HST 2, 7: 8, 12
+	LA 2, 0: 0, 0x1A
+	BST 2, 7: 8, 14
+	HTR 3, 7: 8, 12
+	LQ 4, 12
+	C 3, 4
+	IFN 0x10
+	HLT
+	BTR 3, 7: 8, 14
+	C 2, 3
+	IFN 0x10
+	HLT
+	LQ 2, 1
+	BST 2, 7: 8, 11
+	L 2, 7: 15, +@pattern
+	HST 2, 7: 8, 0
+	HTR 3, 7: 8, 0
+	LA 4, 0: 0, 0x43
+	C 3, 4
+	IFN 0x10
+	HLT
+	BTR 3, 7: 8, 15
+	C 3, 0
+	IFN 0x10
+	HLT
+	BST 0, 7: 8, 11
+	BST 0, 7: 8, 14
+	HST 0, 7: 8, 12
+
+	; 7: the text display is 80 by 24, a cell reads back as written, and
+	; the font ROM holds the built-in A, bit 7 the leftmost pixel
+	LQ 1, 7
+	LA 9, 0: 0, DT_DISPLAY
+	BAL 14, 7: 15, +@find
+	BTR 3, 7: 8, 4
+	LA 4, 0: 0, 80
+	C 3, 4
+	IFN 0x10
+	HLT
+	BTR 3, 7: 8, 5
+	LA 4, 0: 0, 24
+	C 3, 4
+	IFN 0x10
+	HLT
+	L 2, 7: 15, +@pattern
+	HST 2, 7: 8, 0x100
+	HTR 3, 7: 8, 0x100
+	C 2, 3
+	IFN 0x10
+	HLT
+	HST 0, 7: 8, 0x100
+	LQ 9, DT_FONT
+	BAL 14, 7: 15, +@find
+	L 3, 7: 8, 0x208
+	L 4, 7: 15, +@glyph
+	C 3, 4
+	IFN 0x10
+	HLT
+
+	MV 1, 0
+	HLT
+
+* find: R8 = base of the first discovery table entry of type R9, or halt;
+* returns via R14, using R5-R7
+find:	L 5, 7: 15, +@discovery
+	L 6, 7: 5, 0
+look:	C 6, 0
+	IF 0x10
+	HLT
+	AQ 5, 4
+	L 7, 7: 5, 0
+	C 7, 9
+	IFN 0x10
+	LA 15, 7: 15, +@over
+	L 8, 7: 5, 4
+	BAL 0, 7: 14, 0
+over:	AQ 5, 12
+	SQ 6, 1
+	LA 15, 7: 15, +@look
+
+	.align 4
+discovery:	.word DISCOVERY
+vendor:	.word 0x5153
+pattern:	.word 0xA5C3
+geometry:	.word 0x00010090
+glyph:	.word 0xCCCC7830
@@ -0,0 +1,104 @@
+* Self-test: loads, stores, address arithmetic and subroutine linkage
+*
+* Halts with R1 = 0 if every check passed, else the number of the first one
+* that failed.
+
+	.org 0x1000
+start:	; 1: word store and load back
+	LQ 1, 1
+	LA 5, 7: 15, +@buffer
+	L 2, 7: 15, +@pattern
+	ST 2, 7: 5, 0
+	L 3, 7: 5, 0
+	C 2, 3
+	IFN 0x10
+	HLT
+
+	; 2: byte and halfword stores touch only their own bytes
+	LQ 1, 2
+	LQ 2, 7
+	BST 2, 7: 5, 4
+	L 3, 7: 15, +@minus
+	HST 3, 7: 5, 6
+	ST 0, 7: 5, 8
+	BST 3, 7: 5, 5
+	L 3, 7: 5, 4
+	L 4, 7: 15, +@merged
+	C 3, 4
+	IFN 0x10
+	HLT
+	L 3, 7: 5, 8
+	C 3, 0
+	IFN 0x10
+	HLT
+
+	; 3: truncating and sign-extending loads of a byte and a halfword
+	LQ 1, 3
+	BTR 3, 7: 5, 5
+	L 4, 7: 15, +@byte
+	C 3, 4
+	IFN 0x10
+	HLT
+	BSF 3, 7: 5, 5
+	L 4, 7: 15, +@minus
+	C 3, 4
+	IFN 0x10
+	HLT
+	HTR 3, 7: 5, 6
+	L 4, 7: 15, +@half
+	C 3, 4
+	IFN 0x10
+	HLT
+	HSF 3, 7: 5, 6
+	L 4, 7: 15, +@minus
+	C 3, 4
+	IFN 0x10
+	HLT
+
+	; 4: indexed effective addresses
+	LQ 1, 4
+	LQ 6, 4
+	LA 3, 7: 5, 8
+	LA 4, 7: 5, 6, 4
+	C 3, 4
+	IFN 0x10
+	HLT
+
+	; 5: a subroutine call returns past the call with R14 as the link
+	LQ 1, 5
+	MV 2, 0
+	BAL 14, 7: 15, +@bump
+	BAL 14, 7: 15, +@bump
+	LQ 4, 2
+	C 2, 4
+	IFN 0x10
+	HLT
+
+	; 6: a counted loop
+	LQ 1, 6
+	MV 2, 0
+	LQ 3, 10
+loop:	A 2, 3
+	SQ 3, 1
+	C 3, 0
+	IFN 0x10
+	LA 15, 7: 15, +@loop
+	L 4, 7: 15, +@triangle
+	C 2, 4
+	IFN 0x10
+	HLT
+
+	MV 1, 0
+	HLT
+
+bump:	AQ 2, 1
+	BAL 0, 7: 14, 0
+
+	.align 4
+pattern:	.word 0xDEADBEEF
+minus:	.word 0xFFFFFFFF
+merged:	.word 0xFFFFFF07
+byte:	.word 0x000000FF
+half:	.word 0x0000FFFF
+triangle:	.word 55
+buffer:	.space 16
@@ -0,0 +1,207 @@
+* Self-test: supervisor state - protection keys, context blocks, the priority
+* table addresses, the descriptor cache and YIELD
+*
+* Halts with R1 = 0 if every check passed, else the number of the first one
+* that failed. The test runs in supervisor state, as the CPU starts, so none
+* of these faults; the keys it changes are put back before it goes on.
+
+	.org 0x1000
+start:	; 1: STMPK stores the keys as SMPK set them, LDMPK loads them
+	LQ 1, 1
+	STMPK 0, 7: 15, +@saved
+	L 2, 7: 15, +@key
+	SMPK 3, 2
+	LA 5, 7: 15, +@keys
+	STMPK 0, 7: 5, 0
+	BTR 3, 7: 5, 3
+	C 3, 2
+	IFN 0x10
+	HLT
+	BTR 3, 7: 5, 2
+	L 4, 7: 15, +@unkeyed
+	C 3, 4
+	IFN 0x10
+	HLT
+	LQ 3, 7
+	BST 3, 7: 5, 4
+	LDMPK 0, 7: 5, 0
+	LMPK 4, 4
+	C 4, 3
+	IFN 0x10
+	HLT
+	LDMPK 0, 7: 15, +@saved
+	LMPK 4, 3
+	L 3, 7: 15, +@unkeyed
+	C 4, 3
+	IFN 0x10
+	HLT
+
+	; 2: TKEY says whether a key matches a segment register's
+	LQ 1, 2
+	L 2, 7: 15, +@unkeyed
+	TKEY 2, 7
+	LQ 3, 1
+	C 2, 3
+	IFN 0x10
+	HLT
+	LQ 2, 5
+	TKEY 2, 7
+	C 2, 0
+	IFN 0x10
+	HLT
+
+	; 3: STCTX stores the registers in the context block, and LDCTX loads
+	; them back, PC among them
+	LQ 1, 3
+	LQ 3, 9
+	LA 5, 7: 15, +@context
+	STCTX 0, 7: 5, 0
+	L 4, 7: 5, 12
+	C 4, 3
+	IFN 0x10
+	HLT
+	LA 4, 7: 15, +@resumed
+	ST 4, 7: 5, 60
+	LQ 4, 11
+	ST 4, 7: 5, 12
+	LDCTX 0, 7: 5, 0
+	HLT
+resumed:	LQ 4, 11
+	C 3, 4
+	IFN 0x10
+	HLT
+	LQ 4, 3
+	C 1, 4
+	IFN 0x10
+	HLT
+
+	; 4: LPBA reads back what SPBA set
+	LQ 1, 4
+	LPBA 2, 3
+	LA 4, 7: 15, +@entries
+	LA 5, 7: 15, +@links
+	SPBA 4, 5
+	LPBA 8, 9
+	C 8, 4
+	IFN 0x10
+	HLT
+	C 9, 5
+	IFN 0x10
+	HLT
+	SPBA 2, 3
+
+	; 5: SSDTR takes the priority table addresses from the table, and INVSEL
+	; says whether it dropped a cached descriptor
+	LQ 1, 5
+	LA 2, 7: 15, +@sdt
+	LQ 3, 2
+	SSDTR 2, 3
+	LPBA 8, 9
+	C 8, 4
+	IFN 0x10
+	HLT
+	C 9, 5
+	IFN 0x10
+	HLT
+	LQ 2, 2
+	SSEL 4, 2
+	L 3, 4: 0, 0
+	L 4, 7: 15, +@bufa
+	C 3, 4
+	IFN 0x10
+	HLT
+	INVSEL 3, 2
+	LQ 4, 1
+	C 3, 4
+	IFN 0x10
+	HLT
+	INVSEL 3, 2
+	C 3, 0
+	IFN 0x10
+	HLT
+
+	; 6: YIELD goes on to the next instruction with the registers as they were
+	LQ 1, 6
+	LQ 2, 9
+	YIELD
+	LQ 3, 9
+	C 2, 3
+	IFN 0x10
+	HLT
+
+	; 7: while a descriptor is cached SSEL doesn't see its table entry change;
+	; INVSEL or SSDTR makes the next SSEL read the table, and segment
+	; registers already loaded keep their own copy either way
+	LQ 1, 7
+	LQ 2, 2
+	LA 6, 7: 15, +@sdt
+	L 10, 7: 15, +@bufa
+	L 11, 7: 15, +@bufb
+	SSEL 4, 2
+	LA 3, 7: 15, +@bufb
+	ST 3, 7: 6, 24
+	LA 3, 7: 3, 4
+	ST 3, 7: 6, 28
+	SSEL 5, 2
+	L 3, 5: 0, 0
+	C 3, 10
+	IFN 0x10
+	HLT
+	INVSEL 3, 2
+	LQ 4, 1
+	C 3, 4
+	IFN 0x10
+	HLT
+	SSEL 5, 2
+	L 3, 5: 0, 0
+	C 3, 11
+	IFN 0x10
+	HLT
+	L 3, 4: 0, 0
+	C 3, 10
+	IFN 0x10
+	HLT
+	LA 3, 7: 15, +@bufa
+	ST 3, 7: 6, 24
+	LA 3, 7: 3, 4
+	ST 3, 7: 6, 28
+	SSEL 5, 2
+	L 3, 5: 0, 0
+	C 3, 11
+	IFN 0x10
+	HLT
+	LQ 8, 2
+	SSDTR 6, 8
+	INVSEL 3, 2
+	C 3, 0
+	IFN 0x10
+	HLT
+	SSEL 5, 2
+	L 3, 5: 0, 0
+	C 3, 10
+	IFN 0x10
+	HLT
+
+	MV 1, 0
+	HLT
+
+	.align 4
+key:	.word 0x5A
+unkeyed:	.word 0xFF
+saved:	.space 16
+keys:	.space 16
+context:	.space 352
+
+* segment descriptors: base, limit, key, flags, two spare bytes; SSDTR takes
+* the priority table addresses from the first two
+sdt:	.word entries, entries + 0x80
+	.byte 0xFF, 0xFF, 0, 0
+	.word links, links + 0x80
+	.byte 0xFF, 0xFF, 0, 0
+	.word bufa, bufa + 4
+	.byte 0xFF, 0xFF, 0, 0
+
+bufa:	.word 0xA0A0A0A0
+bufb:	.word 0xB0B0B0B0
+entries:	.space 0x80
+links:	.space 0x80
@@ -0,0 +1,67 @@
+* Self-test: the time-of-day clock
+*
+* Halts with R1 = 0 if every check passed, else the number of the first one
+* that failed. The comparator and its interrupt are exercised by the
+* supervisor example, which the suite also runs.
+
+	.org 0x1000
+start:	; 1: the clock never goes backwards
+	LQ 1, 1
+	LTOD 2, 3
+	LQ 6, 15
+again:	LTOD 4, 5
+	C 5, 3			; G: the high word went down
+	IF 0x20
+	HLT
+	C 5, 3
+	IFN 0x10
+	LA 15, 7: 15, +@newer
+	C 4, 2			; same high word, G: the low word went down
+	IF 0x20
+	HLT
+newer:	MV 2, 4
+	MV 3, 5
+	SQ 6, 1
+	C 6, 0
+	IFN 0x10
+	LA 15, 7: 15, +@again
+
+	; 2: it moves on while the CPU is busy
+	LQ 1, 2
+	LTOD 2, 3
+	L 6, 7: 15, +@spins
+spin:	SQ 6, 1
+	C 6, 0
+	IFN 0x10
+	LA 15, 7: 15, +@spin
+	LTOD 4, 5
+	C 4, 2
+	IF 0x10
+	HLT
+
+	; 3: the comparator reads back as set, and the clock runs on from
+	; whatever STOD sets it to
+	LQ 1, 3
+	MV 2, 0
+	L 3, 7: 15, +@never
+	STODC 2, 3
+	LTODC 4, 5
+	C 4, 2
+	IFN 0x10
+	HLT
+	C 5, 3
+	IFN 0x10
+	HLT
+	LQ 3, 5
+	STOD 2, 3
+	LTOD 4, 5
+	C 5, 3
+	IFN 0x10
+	HLT
+
+	MV 1, 0
+	HLT
+
+	.align 4
+spins:	.word 100000
+never:	.word 0xFFFFFFFF
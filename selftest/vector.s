@@ -0,0 +1,193 @@
+* Self-test: the vector extension's packed byte and halfword lanes
+*
+* Halts with R1 = 0 if every check passed, else the number of the first one
+* that failed. Every check works R4-R7 against R8-R11, each register of a
+* quadruple holding the same lanes, so the first and last of the result are
+* compared with what one register should hold.
+
+	.org 0x1000
+start:	L 13, 7: 15, +@a
+	L 8, 7: 15, +@b
+	MV 9, 8
+	MV 10, 8
+	MV 11, 8
+
+	; 1: byte lanes add, wrapping without carrying into the next
+	LQ 1, 1
+	BAL 14, 7: 15, +@reload
+	VADDB 4, 8
+	L 2, 7: 15, +@addb
+	C 4, 2
+	IFN 0x10
+	HLT
+	C 7, 2
+	IFN 0x10
+	HLT
+
+	; 2: byte lanes subtract, wrapping without borrowing from the next
+	LQ 1, 2
+	BAL 14, 7: 15, +@reload
+	VSUBB 4, 8
+	L 2, 7: 15, +@subb
+	C 4, 2
+	IFN 0x10
+	HLT
+	C 7, 2
+	IFN 0x10
+	HLT
+
+	; 3: byte lanes compare equal
+	LQ 1, 3
+	BAL 14, 7: 15, +@reload
+	VCEQB 4, 8
+	L 2, 7: 15, +@ceqb
+	C 4, 2
+	IFN 0x10
+	HLT
+	C 7, 2
+	IFN 0x10
+	HLT
+
+	; 4: byte lanes compare greater, unsigned
+	LQ 1, 4
+	BAL 14, 7: 15, +@reload
+	VCGTB 4, 8
+	L 2, 7: 15, +@cgtb
+	C 4, 2
+	IFN 0x10
+	HLT
+	C 7, 2
+	IFN 0x10
+	HLT
+
+	; 5: byte lane minimum, unsigned
+	LQ 1, 5
+	BAL 14, 7: 15, +@reload
+	VMINB 4, 8
+	L 2, 7: 15, +@minb
+	C 4, 2
+	IFN 0x10
+	HLT
+	C 7, 2
+	IFN 0x10
+	HLT
+
+	; 6: byte lane maximum, unsigned
+	LQ 1, 6
+	BAL 14, 7: 15, +@reload
+	VMAXB 4, 8
+	L 2, 7: 15, +@maxb
+	C 4, 2
+	IFN 0x10
+	HLT
+	C 7, 2
+	IFN 0x10
+	HLT
+
+	; 7: halfword lanes add, wrapping without carrying into the next
+	LQ 1, 7
+	BAL 14, 7: 15, +@reload
+	VADDH 4, 8
+	L 2, 7: 15, +@addh
+	C 4, 2
+	IFN 0x10
+	HLT
+	C 7, 2
+	IFN 0x10
+	HLT
+
+	; 8: halfword lanes subtract, wrapping without borrowing from the next
+	LQ 1, 8
+	BAL 14, 7: 15, +@reload
+	VSUBH 4, 8
+	L 2, 7: 15, +@subh
+	C 4, 2
+	IFN 0x10
+	HLT
+	C 7, 2
+	IFN 0x10
+	HLT
+
+	; 9: halfword lanes compare equal
+	LQ 1, 9
+	BAL 14, 7: 15, +@reload
+	VCEQH 4, 8
+	L 2, 7: 15, +@ceqh
+	C 4, 2
+	IFN 0x10
+	HLT
+	C 7, 2
+	IFN 0x10
+	HLT
+
+	; 10: halfword lanes compare greater, unsigned
+	LQ 1, 10
+	BAL 14, 7: 15, +@reload
+	VCGTH 4, 8
+	L 2, 7: 15, +@cgth
+	C 4, 2
+	IFN 0x10
+	HLT
+	C 7, 2
+	IFN 0x10
+	HLT
+
+	; 11: halfword lane minimum, unsigned
+	LQ 1, 11
+	BAL 14, 7: 15, +@reload
+	VMINH 4, 8
+	L 2, 7: 15, +@minh
+	C 4, 2
+	IFN 0x10
+	HLT
+	C 7, 2
+	IFN 0x10
+	HLT
+
+	; 12: halfword lane maximum, unsigned
+	LQ 1, 12
+	BAL 14, 7: 15, +@reload
+	VMAXH 4, 8
+	L 2, 7: 15, +@maxh
+	C 4, 2
+	IFN 0x10
+	HLT
+	C 7, 2
+	IFN 0x10
+	HLT
+
+	; 13: the r quadruple is left as it was
+	LQ 1, 13
+	L 2, 7: 15, +@b
+	C 8, 2
+	IFN 0x10
+	HLT
+	C 11, 2
+	IFN 0x10
+	HLT
+
+	MV 1, 0
+	HLT
+
+* R4-R7 = the d lanes
+reload:	MV 4, 13
+	MV 5, 13
+	MV 6, 13
+	MV 7, 13
+	BAL 0, 7: 14, 0
+
+	.align 4
+a:	.word 0x01FF8010
+b:	.word 0x02017F10
+addb:	.word 0x0300FF20
+subb:	.word 0xFFFE0100
+ceqb:	.word 0x000000FF
+cgtb:	.word 0x00FFFF00
+minb:	.word 0x01017F10
+maxb:	.word 0x02FF8010
+addh:	.word 0x0400FF20
+subh:	.word 0xFFFE0100
+ceqh:	.word 0x00000000
+cgth:	.word 0x0000FFFF
+minh:	.word 0x01FF7F10
+maxh:	.word 0x02018010
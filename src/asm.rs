@@ -0,0 +1,1096 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::charset::CodePage;
+use crate::isa::{self, Format, Op};
+
+// Asm: two-pass assembler for SeriesQ source
+//
+// Statements are `[label:] [mnemonic|directive operands] [; comment]`, and lines
+// starting with `*` are comments. Memory operands are written `d, s: r, i12` (RM)
+// or `d, s: r, x, i8` (RMX); an index written `+@expr` is made relative to the
+// end of the instruction. Expressions take C operators over numbers (123, 0x7B,
+// 0b1111011, X'7B', B'1111011', C'AB'), symbols and `.` for the current address.
+//
+// Directives: .org .equ .byte .half .word .ascii .asciz .space .align .codepage
+// .include and .macro NAME params ... .endm; inside a macro body `\param` is
+// replaced by its argument and `\@` by a number unique to each expansion.
+//
+// Instructions are stored as big-endian halfwords; .half and .word data is
+// little-endian, as the CPU loads it.
+
+const MAX_DEPTH: usize = 32;
+
+// added to the origin to find the words holding addresses; it changes every
+// byte of such a word, so a byte or halfword holding part of one shows up too
+const RELOCATION_PROBE: u32 = 0x01010104;
+
+#[derive(Debug)]
+pub struct AsmError {
+	pub file: String,
+	pub line: usize,
+	pub msg: String
+}
+
+impl fmt::Display for AsmError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}: {}", self.file, self.line, self.msg)
+	}
+}
+
+// Record: one source statement and the bytes it produced
+#[derive(Debug, Clone)]
+pub struct Record {
+	pub file: String,
+	pub line: usize,
+	pub addr: u32,
+	pub bytes: Vec<u8>,
+	pub text: String,
+	pub expanded: bool	// came from a macro body
+}
+
+// XRef: where a symbol is defined and used, as statement numbers
+#[derive(Debug, Clone)]
+pub struct XRef {
+	pub value: u32,
+	pub label: bool,	// defined as `label:` rather than by = or .equ
+	pub defined: usize,
+	pub refs: Vec<usize>
+}
+
+pub struct Output {
+	pub records: Vec<Record>,
+	pub symbols: BTreeMap<String, u32>,
+	pub xref: BTreeMap<String, XRef>
+}
+
+const LISTING_PAGE: usize = 56;
+const LISTING_OBJECT: usize = 8;	// object bytes per listing line
+
+impl Output {
+	// contiguous runs of assembled bytes in address order
+	pub fn chunks(&self) -> Vec<(u32, Vec<u8>)> {
+		let mut bytes: BTreeMap<u32, u8> = BTreeMap::new();
+		for r in &self.records {
+			for (i, b) in r.bytes.iter().enumerate() {
+				bytes.insert(r.addr.wrapping_add(i as u32), *b);
+			}
+		}
+		
+		let mut result: Vec<(u32, Vec<u8>)> = Vec::new();
+		for (addr, b) in bytes {
+			match result.last_mut() {
+				Some((start, run)) if start.wrapping_add(run.len() as u32) == addr => run.push(b),
+				_ => result.push((addr, vec![b])),
+			}
+		}
+		result
+	}
+	
+	// classic listing: location, object code, statement number and source, then
+	// a cross-reference of every symbol; macro expansions are marked with +
+	pub fn listing(&self, title: &str) -> String {
+		let mut lines: Vec<String> = Vec::new();
+		for (n, r) in self.records.iter().enumerate() {
+			let mut source = String::new();
+			for c in r.text.chars() {
+				if c == '\t' {
+					source.push(' ');
+					while source.len() % 8 != 0 {
+						source.push(' ');
+					}
+				} else {
+					source.push(c);
+				}
+			}
+			source.truncate(96);
+			
+			let mut pieces = r.bytes.chunks(LISTING_OBJECT);
+			let first = pieces.next().unwrap_or(&[]);
+			let object: String = first.iter().map(|b| format!("{:02X}", b)).collect();
+			// statements that neither emit nor label anything get no location
+			let body = strip_comment(&r.text);
+			let placed = !r.bytes.is_empty() || split_label(body).0.is_some()
+				|| body.trim_start().to_ascii_lowercase().starts_with(".org");
+			let loc = if placed && !r.text.trim_start().starts_with('*') {
+				format!("{:08X}", r.addr)
+			} else {
+				"        ".to_string()
+			};
+			lines.push(format!("{}  {:<16} {:>5} {}{}", loc, object, n + 1,
+				if r.expanded { "+" } else { " " }, source.trim_end()));
+			
+			// long data runs on below the statement
+			for (i, piece) in pieces.enumerate() {
+				let object: String = piece.iter().map(|b| format!("{:02X}", b)).collect();
+				lines.push(format!("{:08X}  {}", r.addr.wrapping_add((LISTING_OBJECT * (i + 1)) as u32), object));
+			}
+		}
+		
+		let mut xref = vec![
+			String::new(),
+			"CROSS-REFERENCE".to_string(),
+			String::new(),
+			format!("{:<32} {:<8}  {:>5}  {}", "SYMBOL", "VALUE", "DEFN", "REFERENCES"),
+		];
+		for (name, x) in &self.xref {
+			let defn = if x.defined == 0 { "-".to_string() } else { x.defined.to_string() };
+			let mut refs: Vec<String> = x.refs.iter().map(|r| r.to_string()).collect();
+			refs.dedup();
+			let mut line = format!("{:<32} {:08X}  {:>5}  ", name, x.value, defn);
+			let indent = line.len();
+			let mut width = indent;
+			for r in refs {
+				if width + r.len() + 1 > 120 {
+					xref.push(line.trim_end().to_string());
+					line = " ".repeat(indent);
+					width = indent;
+				}
+				line.push_str(&r);
+				line.push(' ');
+				width += r.len() + 1;
+			}
+			xref.push(line.trim_end().to_string());
+		}
+		
+		let mut out = String::new();
+		let mut page = 0;
+		let mut add_page = |out: &mut String, heading: &str, body: &[String]| {
+			for chunk in body.chunks(LISTING_PAGE) {
+				page += 1;
+				if page > 1 {
+					out.push('\x0C');
+				}
+				out.push_str(&format!("SQASM  {:<80} PAGE {:>4}\n\n", title, page));
+				out.push_str(heading);
+				for l in chunk {
+					out.push_str(l);
+					out.push('\n');
+				}
+			}
+		};
+		add_page(&mut out, "  LOC     OBJECT CODE       STMT  SOURCE STATEMENT\n", &lines);
+		add_page(&mut out, "", &xref);
+		out
+	}
+	
+	// symbol map for debuggers, one `VALUE KIND NAME` line per symbol in value
+	// order; KIND is T for labels and A for values set with = or .equ. Each
+	// instruction follows as `ADDR S LEN FILE:LINE SOURCE` for coverage tools.
+	pub fn map(&self) -> String {
+		let mut syms: Vec<(&String, &XRef)> = self.xref.iter().collect();
+		syms.sort_by_key(|(name, x)| (x.value, !x.label, *name));
+		let mut out = String::new();
+		for (name, x) in syms {
+			out.push_str(&format!("{:08X} {} {}\n", x.value, if x.label { 'T' } else { 'A' }, name));
+		}
+		for r in &self.records {
+			let op = split_label(strip_comment(&r.text)).1.trim_start();
+			if r.bytes.is_empty() || op.starts_with('.') {
+				continue;
+			}
+			out.push_str(&format!("{:08X} S {} {}:{} {}\n", r.addr, r.bytes.len(), r.file, r.line, r.text.trim()));
+		}
+		out
+	}
+	
+	// flat image from the lowest assembled address, gaps zero filled
+	pub fn image(&self) -> (u32, Vec<u8>) {
+		let chunks = self.chunks();
+		let origin = match chunks.first() {
+			Some((x, _)) => *x,
+			None => return (0, Vec::new()),
+		};
+		
+		let mut image = Vec::new();
+		for (addr, run) in chunks {
+			image.resize((addr - origin) as usize, 0);
+			image.extend_from_slice(&run);
+		}
+		(origin, image)
+	}
+}
+
+struct Stmt {
+	file: String,
+	line: usize,
+	text: String,
+	expanded: bool
+}
+
+struct Macro {
+	params: Vec<String>,
+	body: Vec<(usize, String)>,
+	file: String
+}
+
+#[derive(Clone)]
+enum Sym {
+	Value(u32),
+	Expr(String, u32)	// .equ, evaluated when first needed at its own address
+}
+
+pub struct Assembler {
+	pub include_dirs: Vec<PathBuf>,
+	defines: Vec<(String, u32)>,
+	bias: u32,			// added to every origin
+	
+	macros: HashMap<String, Macro>,
+	expansions: u32,
+	symbols: HashMap<String, Sym>,
+	codepage: CodePage,
+	
+	defined: HashMap<String, usize>,
+	labels: HashSet<String>,
+	refs: HashMap<String, Vec<usize>>
+}
+
+fn err<T>(stmt: &Stmt, msg: String) -> Result<T, AsmError> {
+	Err(AsmError { file: stmt.file.clone(), line: stmt.line, msg: msg })
+}
+
+// split on top-level commas, leaving quoted strings and parentheses alone
+fn split_operands(s: &str) -> Vec<String> {
+	let mut result = Vec::new();
+	let mut current = String::new();
+	let mut depth = 0;
+	let mut quote: Option<char> = None;
+	let mut prev = ' ';
+	
+	for c in s.chars() {
+		match quote {
+			Some(q) => {
+				current.push(c);
+				if c == q && prev != '\\' {
+					quote = None;
+				}
+			},
+			None => match c {
+				'"' => { quote = Some('"'); current.push(c); },
+				'\'' => { quote = Some('\''); current.push(c); },
+				'(' => { depth += 1; current.push(c); },
+				')' => { depth -= 1; current.push(c); },
+				',' if depth == 0 => {
+					result.push(current.trim().to_string());
+					current.clear();
+				},
+				_ => current.push(c),
+			},
+		}
+		prev = c;
+	}
+	if !current.trim().is_empty() || !result.is_empty() {
+		result.push(current.trim().to_string());
+	}
+	result
+}
+
+// drop a trailing `;` comment outside of quotes
+fn strip_comment(s: &str) -> &str {
+	let mut quote: Option<char> = None;
+	let mut prev = ' ';
+	for (i, c) in s.char_indices() {
+		match quote {
+			Some(q) => if c == q && prev != '\\' { quote = None; },
+			None => match c {
+				'"' => quote = Some('"'),
+				// X'..', B'..' and C'..' literals; a lone quote is left to the parser
+				'\'' if "XxBbCc".contains(prev) => quote = Some('\''),
+				';' => return &s[..i],
+				_ => { },
+			},
+		}
+		prev = c;
+	}
+	s
+}
+
+fn is_ident_start(c: char) -> bool {
+	c.is_ascii_alphabetic() || c == '_' || c == '$'
+}
+
+fn is_ident(c: char) -> bool {
+	c.is_ascii_alphanumeric() || c == '_' || c == '$' || c == '.'
+}
+
+fn valid_name(s: &str) -> bool {
+	let mut chars = s.chars();
+	match chars.next() {
+		Some(c) if is_ident_start(c) || c == '.' => chars.all(is_ident),
+		_ => false,
+	}
+}
+
+// split `label: rest`; a colon after the operands belongs to a memory operand
+fn split_label(s: &str) -> (Option<&str>, &str) {
+	if let Some(i) = s.find(':') {
+		let head = s[..i].trim();
+		if valid_name(head) && !head.contains(char::is_whitespace) {
+			return (Some(head), s[i + 1..].trim());
+		}
+	}
+	(None, s.trim())
+}
+
+fn parse_string(s: &str, cp: CodePage) -> Result<Vec<u8>, String> {
+	let s = s.trim();
+	if s.len() < 2 || !s.starts_with('"') || !s.ends_with('"') {
+		return Err(format!("expected a quoted string, found {}", s));
+	}
+	
+	let mut text = String::new();
+	let mut chars = s[1..s.len() - 1].chars();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			text.push(c);
+			continue;
+		}
+		match chars.next() {
+			Some('n') => text.push('\n'),
+			Some('r') => text.push('\r'),
+			Some('t') => text.push('\t'),
+			Some('0') => text.push('\0'),
+			Some('\\') => text.push('\\'),
+			Some('"') => text.push('"'),
+			Some('x') => {
+				let hex: String = chars.by_ref().take(2).collect();
+				match u8::from_str_radix(&hex, 16) {
+					Ok(x) => text.push(cp.to_char(x)),
+					Err(_) => return Err(format!("bad escape \\x{}", hex)),
+				}
+			},
+			Some(x) => return Err(format!("bad escape \\{}", x)),
+			None => return Err("unterminated escape".to_string()),
+		}
+	}
+	
+	match cp.encode_strict(&text) {
+		Some(x) => Ok(x),
+		None => Err(format!("string not representable in {}", cp.name())),
+	}
+}
+
+// Expression evaluation: precedence climbing over a token list
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+	Num(u32),
+	Ident(String),
+	Op(&'static str),
+	LParen,
+	RParen
+}
+
+fn tokenize(s: &str, cp: CodePage) -> Result<Vec<Tok>, String> {
+	let chars: Vec<char> = s.chars().collect();
+	let mut toks = Vec::new();
+	let mut n = 0;
+	
+	while n < chars.len() {
+		let c = chars[n];
+		if c.is_whitespace() {
+			n += 1;
+		} else if "XxBbCc".contains(c) && n + 1 < chars.len() && chars[n + 1] == '\'' {
+			let end = match chars[n + 2..].iter().position(|&x| x == '\'') {
+				Some(x) => n + 2 + x,
+				None => return Err("unterminated literal".to_string()),
+			};
+			let body: String = chars[n + 2..end].iter().collect();
+			let value = match c.to_ascii_uppercase() {
+				'X' => u32::from_str_radix(&body, 16).map_err(|_| format!("bad hex literal X'{}'", body))?,
+				'B' => u32::from_str_radix(&body, 2).map_err(|_| format!("bad binary literal B'{}'", body))?,
+				_ => {
+					let bytes = match cp.encode_strict(&body) {
+						Some(x) if x.len() >= 1 && x.len() <= 4 => x,
+						_ => return Err(format!("bad character literal C'{}'", body)),
+					};
+					bytes.iter().fold(0, |acc, &b| (acc << 8) | b as u32)
+				},
+			};
+			toks.push(Tok::Num(value));
+			n = end + 1;
+		} else if c.is_ascii_digit() {
+			let start = n;
+			while n < chars.len() && chars[n].is_ascii_alphanumeric() {
+				n += 1;
+			}
+			let text: String = chars[start..n].iter().collect::<String>().to_ascii_lowercase();
+			let value = if text.starts_with("0x") {
+				u32::from_str_radix(&text[2..], 16)
+			} else if text.starts_with("0b") {
+				u32::from_str_radix(&text[2..], 2)
+			} else {
+				text.parse::<u32>()
+			};
+			toks.push(Tok::Num(value.map_err(|_| format!("bad number {}", text))?));
+		} else if is_ident_start(c) || c == '.' {
+			let start = n;
+			n += 1;
+			while n < chars.len() && is_ident(chars[n]) {
+				n += 1;
+			}
+			toks.push(Tok::Ident(chars[start..n].iter().collect()));
+		} else if c == '(' {
+			toks.push(Tok::LParen);
+			n += 1;
+		} else if c == ')' {
+			toks.push(Tok::RParen);
+			n += 1;
+		} else {
+			let two: String = chars[n..(n + 2).min(chars.len())].iter().collect();
+			let op = match two.as_str() {
+				"<<" => "<<",
+				">>" => ">>",
+				_ => match c {
+					'+' => "+", '-' => "-", '*' => "*", '/' => "/", '%' => "%",
+					'&' => "&", '|' => "|", '^' => "^", '~' => "~",
+					_ => return Err(format!("unexpected '{}' in expression", c)),
+				},
+			};
+			n += op.len();
+			toks.push(Tok::Op(op));
+		}
+	}
+	Ok(toks)
+}
+
+fn precedence(op: &str) -> Option<u8> {
+	match op {
+		"|" => Some(1),
+		"^" => Some(2),
+		"&" => Some(3),
+		"<<" | ">>" => Some(4),
+		"+" | "-" => Some(5),
+		"*" | "/" | "%" => Some(6),
+		_ => None,
+	}
+}
+
+impl Assembler {
+	pub fn new() -> Assembler {
+		Assembler {
+			include_dirs: Vec::new(),
+			defines: Vec::new(),
+			bias: 0,
+			
+			macros: HashMap::new(),
+			expansions: 0,
+			symbols: HashMap::new(),
+			codepage: CodePage::Latin1,
+			
+			defined: HashMap::new(),
+			labels: HashSet::new(),
+			refs: HashMap::new()
+		}
+	}
+	
+	// predefine a symbol, as from the command line
+	pub fn define(&mut self, name: &str, value: u32) {
+		self.defines.push((name.to_string(), value));
+	}
+	
+	// Words in an assembled program that hold addresses, found by assembling the
+	// source again with every origin moved and comparing the two. Those words are
+	// what a loader adjusts to place the program somewhere else; anything else
+	// that moved, such as a label's low byte or an absolute index, cannot be
+	// adjusted and is an error, as is an .org that depends on a label.
+	pub fn relocations(&mut self, path: &str, fixed: &Output) -> Result<Vec<u32>, String> {
+		self.bias = RELOCATION_PROBE;
+		let moved = self.assemble_file(path);
+		self.bias = 0;
+		let moved = moved.map_err(|e| format!("cannot relocate: {}", e))?;
+		
+		let (a, b) = (fixed.chunks(), moved.chunks());
+		let same_shape = a.len() == b.len() && a.iter().zip(b.iter())
+			.all(|(x, y)| x.0.wrapping_add(RELOCATION_PROBE) == y.0 && x.1.len() == y.1.len());
+		if !same_shape {
+			return Err("cannot relocate: origins depend on labels".to_string());
+		}
+		
+		let mut result = Vec::new();
+		for ((addr, x), (_, y)) in a.iter().zip(b.iter()) {
+			let mut i = 0;
+			while i < x.len() {
+				if x[i] == y[i] {
+					i += 1;
+					continue;
+				}
+				let at = addr.wrapping_add(i as u32);
+				let word = |data: &Vec<u8>| data.get(i..i + 4).map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]));
+				match (word(x), word(y)) {
+					(Some(old), Some(new)) if at % 4 == 0 && new == old.wrapping_add(RELOCATION_PROBE) => {
+						result.push(at);
+						i += 4;
+					},
+					_ => return Err(format!("cannot relocate: 0x{:08X} holds part of an address", at)),
+				}
+			}
+		}
+		Ok(result)
+	}
+	
+	pub fn assemble_file(&mut self, path: &str) -> Result<Output, AsmError> {
+		let text = fs::read_to_string(path).map_err(|e| AsmError {
+			file: path.to_string(), line: 0, msg: e.to_string()
+		})?;
+		self.assemble(path, &text)
+	}
+	
+	pub fn assemble(&mut self, name: &str, text: &str) -> Result<Output, AsmError> {
+		self.macros.clear();
+		self.expansions = 0;
+		self.symbols.clear();
+		self.defined.clear();
+		self.labels.clear();
+		self.refs.clear();
+		
+		for r in 0..16 {
+			self.symbols.insert(format!("R{}", r), Sym::Value(r));
+			self.symbols.insert(format!("r{}", r), Sym::Value(r));
+		}
+		for (n, v) in &[("LR", 14), ("PC", 15), ("LS", 6), ("PS", 7)] {
+			self.symbols.insert(n.to_string(), Sym::Value(*v));
+		}
+		for (n, v) in self.defines.clone() {
+			self.symbols.insert(n, Sym::Value(v));
+		}
+		
+		let mut stmts = Vec::new();
+		self.expand_source(name, text, 0, &mut stmts)?;
+		
+		self.pass(&stmts, false)?;
+		let records = self.pass(&stmts, true)?;
+		
+		let mut symbols = BTreeMap::new();
+		let names: Vec<String> = self.symbols.keys().cloned().collect();
+		for name in names {
+			if let Ok(v) = self.lookup(&name, 0) {
+				symbols.insert(name, v);
+			}
+		}
+		for r in 0..16 {
+			symbols.remove(&format!("R{}", r));
+			symbols.remove(&format!("r{}", r));
+		}
+		for n in &["LR", "PC", "LS", "PS"] {
+			symbols.remove(*n);
+		}
+		
+		let mut xref = BTreeMap::new();
+		for (name, value) in &symbols {
+			xref.insert(name.clone(), XRef {
+				value: *value,
+				label: self.labels.contains(name),
+				defined: self.defined.get(name).cloned().unwrap_or(0),
+				refs: self.refs.get(name).cloned().unwrap_or_default()
+			});
+		}
+		
+		Ok(Output { records: records, symbols: symbols, xref: xref })
+	}
+	
+	fn find_include(&self, from: &str, name: &str) -> Option<PathBuf> {
+		let mut dirs: Vec<PathBuf> = Vec::new();
+		if let Some(parent) = Path::new(from).parent() {
+			dirs.push(parent.to_path_buf());
+		}
+		dirs.extend(self.include_dirs.iter().cloned());
+		
+		dirs.into_iter().map(|d| d.join(name)).find(|p| p.is_file())
+	}
+	
+	// stage one: resolve .include, collect .macro bodies and expand invocations
+	fn expand_source(&mut self, file: &str, text: &str, depth: usize, out: &mut Vec<Stmt>) -> Result<(), AsmError> {
+		let lines: Vec<(usize, String)> = text.lines().enumerate().map(|(n, l)| (n + 1, l.to_string())).collect();
+		self.expand_lines(file, &lines, depth, false, out)
+	}
+	
+	fn expand_lines(&mut self, file: &str, lines: &[(usize, String)], depth: usize, expanded: bool,
+		out: &mut Vec<Stmt>) -> Result<(), AsmError> {
+		if depth > MAX_DEPTH {
+			let line = lines.first().map(|x| x.0).unwrap_or(0);
+			return Err(AsmError { file: file.to_string(), line: line, msg: "includes or macros nested too deeply".to_string() });
+		}
+		
+		let mut n = 0;
+		while n < lines.len() {
+			let (line, ref raw) = lines[n];
+			let stmt = Stmt { file: file.to_string(), line: line, text: raw.clone(), expanded: expanded };
+			n += 1;
+			
+			if raw.trim_start().starts_with('*') {
+				out.push(stmt);
+				continue;
+			}
+			let body = strip_comment(raw);
+			let (label, rest) = split_label(body);
+			let (word, operands) = match rest.find(char::is_whitespace) {
+				Some(i) => (&rest[..i], rest[i..].trim()),
+				None => (rest, ""),
+			};
+			
+			match word.to_ascii_lowercase().as_str() {
+				".include" => {
+					let name = parse_string(operands, CodePage::Latin1).ok()
+						.and_then(|x| String::from_utf8(x).ok());
+					let path = match name.as_ref().and_then(|x| self.find_include(file, x)) {
+						Some(p) => p,
+						None => return err(&stmt, format!("cannot find include file {}", operands)),
+					};
+					let text = match fs::read_to_string(&path) {
+						Ok(x) => x,
+						Err(e) => return err(&stmt, format!("{}: {}", path.display(), e)),
+					};
+					out.push(stmt);
+					self.expand_source(&path.to_string_lossy(), &text, depth + 1, out)?;
+				},
+				".macro" => {
+					let mut parts = operands.splitn(2, char::is_whitespace);
+					let name = parts.next().unwrap_or("").to_string();
+					if !valid_name(&name) {
+						return err(&stmt, format!("bad macro name {}", name));
+					}
+					let params: Vec<String> = split_operands(parts.next().unwrap_or(""))
+						.into_iter().filter(|x| !x.is_empty()).collect();
+					
+					let mut body = Vec::new();
+					let mut closed = false;
+					while n < lines.len() {
+						let (l, ref t) = lines[n];
+						n += 1;
+						let first = strip_comment(t).split_whitespace().next().unwrap_or("").to_ascii_lowercase();
+						if first == ".endm" {
+							closed = true;
+							break;
+						}
+						if first == ".macro" {
+							return err(&stmt, "macro definitions cannot nest".to_string());
+						}
+						body.push((l, t.clone()));
+					}
+					if !closed {
+						return err(&stmt, format!("macro {} has no .endm", name));
+					}
+					out.push(stmt);
+					self.macros.insert(name.to_ascii_uppercase(), Macro { params: params, body: body, file: file.to_string() });
+				},
+				".endm" => return err(&stmt, ".endm without .macro".to_string()),
+				w if self.macros.contains_key(&w.to_ascii_uppercase()) => {
+					let args = split_operands(operands);
+					let (params, body, mfile) = {
+						let m = &self.macros[&w.to_ascii_uppercase()];
+						(m.params.clone(), m.body.clone(), m.file.clone())
+					};
+					if args.len() > params.len() {
+						return err(&stmt, format!("too many arguments to macro {}", word));
+					}
+					
+					self.expansions += 1;
+					let unique = self.expansions.to_string();
+					let mut lines: Vec<(usize, String)> = Vec::new();
+					for (l, t) in &body {
+						let mut t = t.replace("\\@", &unique);
+						// longest names first so \ab is not clobbered by \a
+						let mut order: Vec<usize> = (0..params.len()).collect();
+						order.sort_by_key(|&i| std::cmp::Reverse(params[i].len()));
+						for i in order {
+							let arg = args.get(i).map(|x| x.as_str()).unwrap_or("");
+							t = t.replace(&format!("\\{}", params[i]), arg);
+						}
+						lines.push((*l, t));
+					}
+					
+					// keep the label, then show the invocation before its expansion
+					out.push(Stmt {
+						file: file.to_string(), line: line,
+						text: match label { Some(l) => format!("{}: ; {}", l, raw.trim()), None => format!("; {}", raw.trim()) },
+						expanded: expanded
+					});
+					self.expand_lines(&mfile, &lines, depth + 1, true, out)?;
+				},
+				_ => out.push(stmt),
+			}
+		}
+		Ok(())
+	}
+	
+	fn lookup(&mut self, name: &str, depth: usize) -> Result<u32, String> {
+		if depth > MAX_DEPTH {
+			return Err(format!("circular definition of {}", name));
+		}
+		match self.symbols.get(name).cloned() {
+			Some(Sym::Value(v)) => Ok(v),
+			Some(Sym::Expr(e, loc)) => {
+				let v = self.eval_depth(&e, loc, depth + 1)?;
+				self.symbols.insert(name.to_string(), Sym::Value(v));
+				Ok(v)
+			},
+			None => Err(format!("undefined symbol {}", name)),
+		}
+	}
+	
+	pub fn eval(&mut self, s: &str, loc: u32) -> Result<u32, String> {
+		self.eval_depth(s, loc, 0)
+	}
+	
+	fn eval_depth(&mut self, s: &str, loc: u32, depth: usize) -> Result<u32, String> {
+		let toks = tokenize(s, self.codepage)?;
+		if toks.is_empty() {
+			return Err("missing expression".to_string());
+		}
+		let mut pos = 0;
+		let v = self.parse_binary(&toks, &mut pos, 0, loc, depth)?;
+		if pos != toks.len() {
+			return Err(format!("junk after expression {}", s.trim()));
+		}
+		Ok(v)
+	}
+	
+	fn parse_binary(&mut self, toks: &[Tok], pos: &mut usize, min: u8, loc: u32, depth: usize) -> Result<u32, String> {
+		let mut lhs = self.parse_unary(toks, pos, loc, depth)?;
+		
+		while let Some(Tok::Op(op)) = toks.get(*pos) {
+			let prec = match precedence(op) {
+				Some(p) if p > min => p,
+				_ => break,
+			};
+			*pos += 1;
+			let rhs = self.parse_binary(toks, pos, prec, loc, depth)?;
+			lhs = match *op {
+				"|" => lhs | rhs,
+				"^" => lhs ^ rhs,
+				"&" => lhs & rhs,
+				"<<" => lhs.checked_shl(rhs).unwrap_or(0),
+				">>" => lhs.checked_shr(rhs).unwrap_or(0),
+				"+" => lhs.wrapping_add(rhs),
+				"-" => lhs.wrapping_sub(rhs),
+				"*" => lhs.wrapping_mul(rhs),
+				"/" => lhs.checked_div(rhs).ok_or("division by zero")?,
+				_ => lhs.checked_rem(rhs).ok_or("division by zero")?,
+			};
+		}
+		Ok(lhs)
+	}
+	
+	fn parse_unary(&mut self, toks: &[Tok], pos: &mut usize, loc: u32, depth: usize) -> Result<u32, String> {
+		let tok = toks.get(*pos).cloned();
+		*pos += 1;
+		match tok {
+			Some(Tok::Num(x)) => Ok(x),
+			Some(Tok::Ident(ref x)) if x == "." => Ok(loc),
+			Some(Tok::Ident(x)) => self.lookup(&x, depth),
+			Some(Tok::Op("-")) => Ok(self.parse_unary(toks, pos, loc, depth)?.wrapping_neg()),
+			Some(Tok::Op("+")) => self.parse_unary(toks, pos, loc, depth),
+			Some(Tok::Op("~")) => Ok(!self.parse_unary(toks, pos, loc, depth)?),
+			Some(Tok::LParen) => {
+				let v = self.parse_binary(toks, pos, 0, loc, depth)?;
+				match toks.get(*pos) {
+					Some(Tok::RParen) => {
+						*pos += 1;
+						Ok(v)
+					},
+					_ => Err("missing )".to_string()),
+				}
+			},
+			Some(x) => Err(format!("unexpected {:?} in expression", x)),
+			None => Err("expression ends early".to_string()),
+		}
+	}
+	
+	// a 4-bit field: register, segment register or quick immediate
+	fn field(&mut self, s: &str, loc: u32, what: &str) -> Result<u16, String> {
+		let v = self.eval(s, loc)?;
+		if v > 15 {
+			return Err(format!("{} {} out of range", what, s.trim()));
+		}
+		Ok(v as u16)
+	}
+	
+	// an index, with `+@expr` meaning relative to the following instruction
+	fn index(&mut self, s: &str, loc: u32) -> Result<i64, String> {
+		let s = s.trim();
+		if s.starts_with("+@") {
+			let target = self.eval(&s[2..], loc)?;
+			Ok(target.wrapping_sub(loc.wrapping_add(4)) as i32 as i64)
+		} else {
+			Ok(self.eval(s, loc)? as i32 as i64)
+		}
+	}
+	
+	// choose between the RR and memory forms of a mnemonic by operand shape
+	fn select(mnemonic: &str, operands: &[String]) -> Result<&'static Op, String> {
+		let mut ops = isa::by_mnemonic(mnemonic).peekable();
+		if ops.peek().is_none() {
+			return Err(format!("unknown instruction {}", mnemonic));
+		}
+		let memory = operands.iter().any(|x| x.contains(':'));
+		let wanted = |op: &Op| match op.format {
+			Format::RM => memory && operands.len() <= 3,
+			Format::RMX => memory && operands.len() == 4,
+			_ => !memory,
+		};
+		ops.find(|x| wanted(x)).ok_or(format!("bad operands for {}", mnemonic))
+	}
+	
+	fn encode(&mut self, op: &Op, operands: &[String], loc: u32) -> Result<Vec<u8>, String> {
+		let mut iword0: u16 = (op.opcode as u16) << 8;
+		let mut iword1: Option<u16> = None;
+		
+		let count = |n: usize| -> Result<(), String> {
+			if operands.len() != n {
+				Err(format!("{} takes {} operands", op.mnemonic, n))
+			} else {
+				Ok(())
+			}
+		};
+		
+		match op.format {
+			Format::None => {
+				if !operands.is_empty() {
+					return Err(format!("{} takes no operands", op.mnemonic));
+				}
+				if op.opcode == 0xFF {
+					iword0 = 0xFFFF;
+				}
+			},
+			Format::RR => {
+				count(2)?;
+				iword0 |= self.field(&operands[0], loc, "register")? << 4;
+				iword0 |= self.field(&operands[1], loc, "operand")?;
+			},
+			Format::Shift(bias) => {
+				count(2)?;
+				iword0 |= self.field(&operands[0], loc, "register")? << 4;
+				let n = self.eval(&operands[1], loc)?;
+				if n < bias || n > bias + 15 {
+					return Err(format!("shift count {} out of range {}-{}", n, bias, bias + 15));
+				}
+				iword0 |= (n - bias) as u16;
+			},
+			Format::Imm8 => {
+				count(1)?;
+				let v = self.eval(&operands[0], loc)?;
+				if v > 0xFF {
+					return Err(format!("immediate {} out of range", operands[0]));
+				}
+				iword0 |= v as u16;
+			},
+			Format::RM | Format::RMX => {
+				let (s, r) = match operands.get(1).and_then(|x| x.split_once(':')) {
+					Some(x) => x,
+					None => return Err(format!("{} needs a memory operand s: r", op.mnemonic)),
+				};
+				iword0 |= self.field(&operands[0], loc, "register")? << 4;
+				iword0 |= self.field(r, loc, "base register")?;
+				let mut w1 = self.field(s, loc, "segment register")? << 12;
+				
+				if op.format == Format::RM {
+					let i = match operands.get(2) {
+						Some(x) => self.index(x, loc)?,
+						None => 0,
+					};
+					if i < -2048 || i > 4095 {
+						return Err(format!("index {} out of range", i));
+					}
+					w1 |= (i as u16) & 0xFFF;
+				} else {
+					w1 |= self.field(&operands[2], loc, "index register")? << 8;
+					let i = self.index(&operands[3], loc)?;
+					if i < 0 || i > 255 {
+						return Err(format!("index {} out of range", i));
+					}
+					w1 |= i as u16;
+				}
+				iword1 = Some(w1);
+			},
+		}
+		
+		let mut bytes = iword0.to_be_bytes().to_vec();
+		if isa::length(iword0) == 4 {
+			bytes.extend_from_slice(&iword1.unwrap_or(0).to_be_bytes());
+		}
+		Ok(bytes)
+	}
+	
+	fn define_symbol(&mut self, stmt: &Stmt, number: usize, name: &str, sym: Sym, emit: bool) -> Result<(), AsmError> {
+		if !valid_name(name) || name == "." {
+			return err(stmt, format!("bad symbol name {}", name));
+		}
+		if emit {
+			self.defined.insert(name.to_string(), number);
+		}
+		// the first pass defines everything; the second must agree
+		if !emit && self.symbols.contains_key(name) {
+			return err(stmt, format!("{} is already defined", name));
+		}
+		if !emit || !self.symbols.contains_key(name) {
+			self.symbols.insert(name.to_string(), sym);
+		}
+		Ok(())
+	}
+	
+	// remember each symbol named in an operand field for the cross-reference
+	fn note_refs(&mut self, operands: &str, number: usize) {
+		let chars: Vec<char> = operands.chars().collect();
+		let mut n = 0;
+		while n < chars.len() {
+			let c = chars[n];
+			if c == '"' || (c == '\'' && n > 0 && "XxBbCc".contains(chars[n - 1])) {
+				n += 1;
+				while n < chars.len() && chars[n] != c {
+					n += if chars[n] == '\\' { 2 } else { 1 };
+				}
+				n += 1;
+			} else if c.is_ascii_digit() {
+				while n < chars.len() && chars[n].is_ascii_alphanumeric() {
+					n += 1;
+				}
+			} else if is_ident_start(c) {
+				let start = n;
+				while n < chars.len() && is_ident(chars[n]) {
+					n += 1;
+				}
+				if n < chars.len() && chars[n] == '\'' {
+					continue;	// X'..' and friends
+				}
+				let name: String = chars[start..n].iter().collect();
+				if self.symbols.contains_key(&name) {
+					self.refs.entry(name).or_default().push(number);
+				}
+			} else {
+				n += 1;
+			}
+		}
+	}
+	
+	// one pass over the expanded source; the second produces the bytes
+	fn pass(&mut self, stmts: &[Stmt], emit: bool) -> Result<Vec<Record>, AsmError> {
+		let mut loc: u32 = self.bias;
+		let mut records = Vec::new();
+		self.codepage = CodePage::Latin1;
+		
+		for (n, stmt) in stmts.iter().enumerate() {
+			let number = n + 1;
+			let mut bytes: Vec<u8> = Vec::new();
+			let mut at = loc;
+			
+			let trimmed = stmt.text.trim_start();
+			let body = if trimmed.starts_with('*') { "" } else { strip_comment(&stmt.text) };
+			let (label, rest) = split_label(body);
+			let (word, operands) = match rest.find(char::is_whitespace) {
+				Some(i) => (&rest[..i], rest[i..].trim()),
+				None => (rest, ""),
+			};
+			let ops = split_operands(operands);
+			if emit {
+				self.note_refs(operands, number);
+			}
+			
+			if let Some(l) = label {
+				self.define_symbol(stmt, number, l, Sym::Value(loc), emit)?;
+				self.labels.insert(l.to_string());
+			}
+			
+			// NAME = expr
+			if operands.starts_with('=') && valid_name(word) {
+				let e = operands[1..].to_string();
+				self.define_symbol(stmt, number, word, Sym::Expr(e, loc), emit)?;
+				records.push(Record { file: stmt.file.clone(), line: stmt.line, addr: at, bytes: bytes, text: stmt.text.clone(), expanded: stmt.expanded });
+				continue;
+			}
+			
+			let result: Result<(), String> = match word.to_ascii_lowercase().as_str() {
+				"" | ".include" | ".macro" => Ok(()),
+				".org" => self.eval(operands, loc.wrapping_sub(self.bias)).map(|x| {
+					loc = x.wrapping_add(self.bias);
+					at = loc;
+				}),
+				".equ" => {
+					if ops.len() != 2 {
+						Err(".equ takes a name and a value".to_string())
+					} else {
+						self.define_symbol(stmt, number, &ops[0], Sym::Expr(ops[1].clone(), loc), emit)?;
+						Ok(())
+					}
+				},
+				".codepage" => match CodePage::from_name(operands) {
+					Some(cp) => { self.codepage = cp; Ok(()) },
+					None => Err(format!("unknown code page {}", operands)),
+				},
+				".byte" | ".half" | ".word" => {
+					let width = match word.to_ascii_lowercase().as_str() {
+						".byte" => 1,
+						".half" => 2,
+						_ => 4,
+					};
+					let mut r = Ok(());
+					for x in &ops {
+						let v = if emit {
+							match self.eval(x, loc) {
+								Ok(v) => v,
+								Err(e) => { r = Err(e); break; },
+							}
+						} else {
+							0
+						};
+						// accept either an unsigned or a negative value that fits
+						let bits = 8 * width as u32;
+						if bits < 32 && v >> bits != 0 && !((v as i32) < 0 && (v as i32) >= -(1 << (bits - 1))) {
+							r = Err(format!("{} does not fit in {} bytes", x, width));
+							break;
+						}
+						bytes.extend_from_slice(&v.to_le_bytes()[..width]);
+					}
+					r
+				},
+				".ascii" | ".asciz" => {
+					parse_string(operands, self.codepage).map(|mut s| {
+						if word.eq_ignore_ascii_case(".asciz") {
+							s.push(0);
+						}
+						bytes = s;
+					})
+				},
+				".space" => {
+					let fill = match ops.get(1) {
+						Some(x) if emit => self.eval(x, loc),
+						_ => Ok(0),
+					};
+					match (ops.get(0).map(|x| self.eval(x, loc)), fill) {
+						(Some(Ok(n)), Ok(f)) => { bytes = vec![f as u8; n as usize]; Ok(()) },
+						(Some(Err(e)), _) | (_, Err(e)) => Err(e),
+						(None, _) => Err(".space takes a size".to_string()),
+					}
+				},
+				".align" => match self.eval(operands, loc) {
+					Ok(n) if n.is_power_of_two() => {
+						let pad = (n - loc % n) % n;
+						bytes = vec![0; pad as usize];
+						Ok(())
+					},
+					Ok(n) => Err(format!("alignment {} is not a power of two", n)),
+					Err(e) => Err(e),
+				},
+				w if w.starts_with('.') => Err(format!("unknown directive {}", word)),
+				_ => {
+					match Assembler::select(word, &ops) {
+						Ok(op) if emit => self.encode(op, &ops, loc).map(|b| { bytes = b; }),
+						Ok(op) => {
+							bytes = vec![0; isa::length((op.opcode as u16) << 8) as usize];
+							Ok(())
+						},
+						Err(e) => Err(e),
+					}
+				},
+			};
+			if let Err(e) = result {
+				return err(stmt, e);
+			}
+			
+			loc = loc.wrapping_add(bytes.len() as u32);
+			records.push(Record { file: stmt.file.clone(), line: stmt.line, addr: at, bytes: bytes, text: stmt.text.clone(), expanded: stmt.expanded });
+		}
+		Ok(records)
+	}
+}
@@ -0,0 +1,57 @@
+use std::io::{self, Read};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use crate::frontend;
+use crate::transcript;
+
+// Attention: the operator's break key, for getting a runaway guest's notice
+//
+// Pressing it interrupts the guest on ATTENTION_IPL (or the level given with
+// --attention-ipl) with ATTENTION_CODE as the PS selector, so a guest-resident
+// debugger can tell it from other interrupts on that level. Unlike a device
+// line it is taken once per press: the CPU lowers it when it enters the level.
+// The key is the monitor's attn command, or a telnet Break or Interrupt Process
+// on the --attention-port socket.
+
+pub const ATTENTION_IPL: usize = 7;
+pub const ATTENTION_CODE: u8 = 0xA7;
+
+// telnet commands: Interpret As Command, Break, Interrupt Process
+const IAC: u8 = 255;
+const BRK: u8 = 243;
+const IP: u8 = 244;
+
+pub fn press(line: &AtomicBool, source: &str) {
+	line.store(true, Ordering::Relaxed);
+	frontend::attention(source);
+	transcript::record("ATTENTION", source);
+}
+
+// press the key for every Break or Interrupt Process a client sends
+pub fn listen(addr: &str, line: Arc<AtomicBool>) -> io::Result<()> {
+	let listener = TcpListener::bind(addr)?;
+	thread::spawn(move || {
+		for stream in listener.incoming().flatten() {
+			let line = Arc::clone(&line);
+			thread::spawn(move || {
+				let source = stream.peer_addr().map_or("socket".to_string(), |x| x.to_string());
+				let mut command = false;
+				for b in stream.bytes() {
+					match b {
+						Ok(IAC) if !command => command = true,
+						Ok(x) => {
+							if command && (x == BRK || x == IP) {
+								press(&line, &source);
+							}
+							command = false;
+						},
+						Err(_) => break,
+					}
+				}
+			});
+		}
+	});
+	Ok(())
+}
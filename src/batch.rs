@@ -0,0 +1,46 @@
+use crate::bus::Memory32;
+use crate::machine::{Machine, Stop};
+use crate::options::Options;
+
+// Batch: run a job to completion without an operator and report a process exit status
+
+pub const BATCH_CYCLE_LIMIT: u64 = 100_000_000;
+
+pub const EXIT_LIMIT: i32 = 124;
+pub const EXIT_HOST_ERROR: i32 = 125;
+
+pub fn run(machine: &mut Machine, opt: &Options) -> i32 {
+	machine.cpu.lock().unwrap().cycle_limit = opt.max_cycles.unwrap_or(BATCH_CYCLE_LIMIT);
+	machine.start();
+	
+	let limit = match machine.wait_limit(opt.time_limit()) {
+		Stop::Halted => None,
+		Stop::CycleLimit => Some("CYCLE"),
+		Stop::TimeLimit => Some("TIME"),
+	};
+	if let Some(x) = limit {
+		println!("BATCH: {} LIMIT EXCEEDED", x);
+		machine.dump();
+		return EXIT_LIMIT;
+	}
+	
+	// an explicit semihosted exit wins over the register or word
+	if let Some(code) = machine.semihost.lock().unwrap().exit_code {
+		return code & 0xFF;
+	}
+	
+	if let Some(r) = opt.exit_reg {
+		return (machine.cpu.lock().unwrap().R[r] & 0xFF) as i32;
+	}
+	
+	if let Some(addr) = opt.exit_word {
+		return match machine.bus.lock().unwrap().read_w(addr) {
+			Ok(x) => (x & 0xFF) as i32,
+			Err(e) => {
+				println!("BATCH: cannot read exit word at 0x{:08X}: {:?}", addr, e);
+				EXIT_HOST_ERROR
+			},
+		};
+	}
+	0
+}
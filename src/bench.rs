@@ -0,0 +1,91 @@
+use std::time;
+use crate::batch;
+use crate::bus::Memory32;
+use crate::cpu::PC;
+use crate::machine::{Machine, Stop};
+use crate::options::Options;
+
+// Bench: run headless and report how many instructions the CPU got through
+//
+// Without a program on the command line the built-in workload runs: a word by
+// word copy of 2K of main memory, 2000 times over, so nearly every instruction
+// is a bus access. Main memory is also timed from the host side first, before
+// the device threads start competing for the processor.
+
+pub const WORKLOAD_BASE: u32 = 0x4000;
+
+static WORKLOAD: &[u8] = &[
+	0x00, 0x10,					// start:	MV 1, 0
+	0x60, 0x6F, 0x70, 0x32,		//			L 6, 7: 15, +@passes
+	0x60, 0x8F, 0x70, 0x32,		// outer:	L 8, 7: 15, +@src
+	0x60, 0x9F, 0x70, 0x32,		//			L 9, 7: 15, +@dst
+	0x60, 0x7F, 0x70, 0x32,		//			L 7, 7: 15, +@end
+	0x60, 0x58, 0x70, 0x00,		// inner:	L 5, 7: 8, 0
+	0x68, 0x59, 0x70, 0x00,		//			ST 5, 7: 9, 0
+	0x0C, 0x84,					//			AQ 8, 4
+	0x0C, 0x94,					//			AQ 9, 4
+	0x20, 0x87,					//			C 8, 7
+	0x3E, 0x10,					//			IF 0x10
+	0x61, 0xFF, 0x70, 0x04,		//			LA 15, 7: 15, +@next
+	0x61, 0xFF, 0x7F, 0xE8,		//			LA 15, 7: 15, +@inner
+	0x0C, 0x11,					// next:	AQ 1, 1
+	0x20, 0x16,					//			C 1, 6
+	0x3E, 0x10,					//			IF 0x10
+	0xFF, 0xFF, 0x00, 0x00,		//			HLT
+	0x61, 0xFF, 0x7F, 0xCE,		//			LA 15, 7: 15, +@outer
+	0xD0, 0x07, 0x00, 0x00,		// passes:	.word 2000
+	0x00, 0x50, 0x00, 0x00,		// src:		.word 0x5000
+	0x00, 0x60, 0x00, 0x00,		// dst:		.word 0x6000
+	0x00, 0x58, 0x00, 0x00,		// end:		.word 0x5800
+];
+
+pub fn load_workload(machine: &Machine) {
+	let mut bus = machine.bus.lock().unwrap();
+	for (i, b) in WORKLOAD.iter().enumerate() {
+		bus.write_b(WORKLOAD_BASE + i as u32, *b).unwrap();
+	}
+	drop(bus);
+	machine.cpu.lock().unwrap().R[PC] = WORKLOAD_BASE;
+}
+
+const BUS_ACCESSES: u32 = 4_000_000;
+
+// word reads and writes per second through the bus, host side; each word is
+// written back unchanged so the guest image is left alone
+fn bus_rate(machine: &Machine) -> f64 {
+	let mut bus = machine.bus.lock().unwrap();
+	let start = time::Instant::now();
+	for n in 0..BUS_ACCESSES / 2 {
+		let addr = 0x8000 + (n % 0x800) * 4;
+		let x = bus.read_w(addr).unwrap();
+		bus.write_w(addr, x).unwrap();
+	}
+	BUS_ACCESSES as f64 / start.elapsed().as_secs_f64()
+}
+
+pub fn run(machine: &mut Machine, opt: &Options) -> i32 {
+	println!("BENCH: MAIN MEMORY {:.1} MILLION ACCESSES PER SECOND", bus_rate(machine) / 1e6);
+	
+	machine.cpu.lock().unwrap().cycle_limit = opt.max_cycles.unwrap_or(batch::BATCH_CYCLE_LIMIT);
+	let start = time::Instant::now();
+	machine.start();
+	let stop = machine.wait_limit(opt.time_limit());
+	
+	let seconds = start.elapsed().as_secs_f64();
+	let cpu = machine.cpu.lock().unwrap();
+	println!("BENCH: {} INSTRUCTIONS IN {:.3} SECONDS, {:.2} MIPS", cpu.cycles, seconds, cpu.cycles as f64 / seconds / 1e6);
+	println!("BENCH: {} INSTRUCTION FETCHES, {} PREFETCH REFILLS", cpu.prefetch.fetches, cpu.prefetch.refills);
+	drop(cpu);
+	
+	match stop {
+		Stop::Halted => 0,
+		Stop::CycleLimit => {
+			println!("BENCH: CYCLE LIMIT EXCEEDED");
+			batch::EXIT_LIMIT
+		},
+		Stop::TimeLimit => {
+			println!("BENCH: TIME LIMIT EXCEEDED");
+			batch::EXIT_LIMIT
+		},
+	}
+}
@@ -0,0 +1,151 @@
+use std::{env, fs, process};
+use std::path::PathBuf;
+
+#[allow(dead_code)]
+#[path = "../charset.rs"]
+mod charset;
+#[allow(dead_code)]
+#[path = "../isa.rs"]
+mod isa;
+#[path = "../isadoc.rs"]
+mod isadoc;
+#[path = "../asm.rs"]
+mod asm;
+#[allow(dead_code)]
+#[path = "../elf.rs"]
+mod elf;
+#[path = "../elfout.rs"]
+mod elfout;
+#[path = "../hexout.rs"]
+mod hexout;
+
+use crate::asm::Assembler;
+
+// sqasm: assemble SeriesQ source into a raw binary image
+
+const USAGE: &str = "\
+Usage: sqasm [options] SOURCE
+  -o FILE          write the image to FILE (default SOURCE with the format's extension)
+  -f FORMAT        bin (flat image from the lowest address), elf, hex, srec
+                   or deck (card images for the firmware loader)
+  -r               with -f deck, add relocation records so the firmware loader
+                   can place the program at any word-aligned displacement
+  -l FILE          write a listing with cross-reference to FILE
+  -m FILE          write a symbol map to FILE for the monitor
+  -I DIR           search DIR for .include files
+  -D NAME=VALUE    predefine a symbol
+  -R FORMAT        write the instruction set reference, text or html, to -o FILE
+                   or standard output instead of assembling";
+
+fn fail(msg: &str) -> ! {
+	eprintln!("sqasm: {}", msg);
+	process::exit(1);
+}
+
+fn main() {
+	let args: Vec<String> = env::args().collect();
+	let mut asm = Assembler::new();
+	let mut source: Option<String> = None;
+	let mut output: Option<String> = None;
+	let mut format = String::from("bin");
+	let mut listing: Option<String> = None;
+	let mut map: Option<String> = None;
+	let mut relocatable = false;
+	let mut reference: Option<String> = None;
+	
+	let mut n = 1;
+	while n < args.len() {
+		let flag = args[n].as_str();
+		let value = args.get(n + 1).cloned();
+		match (flag, value) {
+			("-o", Some(v)) => { output = Some(v); n += 1; },
+			("-f", Some(v)) if ["bin", "elf", "hex", "srec", "deck"].contains(&v.as_str()) => { format = v; n += 1; },
+			("-l", Some(v)) => { listing = Some(v); n += 1; },
+			("-m", Some(v)) => { map = Some(v); n += 1; },
+			("-r", _) => relocatable = true,
+			("-R", Some(v)) if v == "text" || v == "html" => { reference = Some(v); n += 1; },
+			("-I", Some(v)) => { asm.include_dirs.push(PathBuf::from(v)); n += 1; },
+			("-D", Some(v)) => {
+				let (name, value) = v.split_once('=').unwrap_or((&v, "1"));
+				match asm.eval(value, 0) {
+					Ok(x) => asm.define(name, x),
+					Err(e) => fail(&format!("-D {}: {}", v, e)),
+				}
+				n += 1;
+			},
+			(x, _) if x.starts_with('-') || source.is_some() => {
+				eprintln!("{}", USAGE);
+				process::exit(2);
+			},
+			(x, _) => source = Some(x.to_string()),
+		}
+		n += 1;
+	}
+	
+	if let Some(format) = reference {
+		let card = if format == "html" { isadoc::html() } else { isadoc::text() };
+		match &output {
+			Some(path) => if let Err(e) = fs::write(path, card) {
+				fail(&format!("{}: {}", path, e));
+			},
+			None => print!("{}", card),
+		}
+		return;
+	}
+	
+	let source = match source {
+		Some(x) => x,
+		None => {
+			eprintln!("{}", USAGE);
+			process::exit(2);
+		},
+	};
+	
+	let out = match asm.assemble_file(&source) {
+		Ok(x) => x,
+		Err(e) => fail(&e.to_string()),
+	};
+	
+	if relocatable && format != "deck" {
+		fail("-r needs -f deck");
+	}
+	let relocations = if relocatable {
+		asm.relocations(&source, &out).unwrap_or_else(|e| fail(&e))
+	} else {
+		Vec::new()
+	};
+	
+	if let Some(path) = &listing {
+		if let Err(e) = fs::write(path, out.listing(&source)) {
+			fail(&format!("{}: {}", path, e));
+		}
+	}
+	
+	if let Some(path) = &map {
+		if let Err(e) = fs::write(path, out.map()) {
+			fail(&format!("{}: {}", path, e));
+		}
+	}
+	
+	// all but bin keep each chunk's address and start at `start` if it is defined
+	let chunks = out.chunks();
+	let entry = out.symbols.get("start").cloned().unwrap_or(chunks.first().map_or(0, |x| x.0));
+	let (origin, image) = match format.as_str() {
+		"elf" => (entry, elfout::build(entry, &chunks, &[])),
+		"hex" => (entry, hexout::write_ihex(&chunks, Some(entry)).into_bytes()),
+		"srec" => (entry, hexout::write_srec(&chunks, Some(entry), &source).into_bytes()),
+		"deck" => (entry, hexout::write_deck(&chunks, &relocations, entry).into_bytes()),
+		_ => out.image(),
+	};
+	let path = output.unwrap_or_else(|| {
+		PathBuf::from(&source).with_extension(&format).to_string_lossy().into_owned()
+	});
+	if let Err(e) = fs::write(&path, &image) {
+		fail(&format!("{}: {}", path, e));
+	}
+	if format != "bin" {
+		println!("{}: {} bytes, entry 0x{:08X}", path, image.len(), origin);
+	} else {
+		println!("{}: {} bytes at 0x{:08X}", path, image.len(), origin);
+	}
+}
@@ -0,0 +1,137 @@
+use std::{env, process};
+use std::path::Path;
+
+#[allow(dead_code)]
+#[path = "../diskimage.rs"]
+mod diskimage;
+#[allow(dead_code)]
+#[path = "../transport.rs"]
+mod transport;
+
+use crate::diskimage::{DiskImage, Geometry};
+use crate::transport::{FileTransport, Mode};
+
+// sqdisk: make and look after DASD disk images
+
+const USAGE: &str = "\
+Usage: sqdisk COMMAND IMAGE [ARGS]
+  create IMAGE C,H,S[,SIZE]  make a volume of zeros with C cylinders, H heads,
+                             S sectors per track and SIZE byte blocks (256)
+  info IMAGE                 show the geometry and the bad block map
+  compress IMAGE OUT         write IMAGE to OUT with zero blocks left out
+  expand IMAGE OUT           write IMAGE to OUT uncompressed
+  merge IMAGE OVERLAY OUT    write IMAGE with the blocks written to OVERLAY
+                             to OUT, compressed if IMAGE is
+  bad IMAGE BLOCK [N]        make BLOCK fail its first N reads, or every read
+  good IMAGE BLOCK           take BLOCK out of the bad block map
+  serve IMAGE ADDR           let machines elsewhere mount IMAGE as tcp:ADDR,
+                             read-only if IMAGE can't be written
+BLOCK is (cylinder * heads + head) * sectors + sector, or C/H/S.";
+
+fn fail(msg: &str) -> ! {
+	eprintln!("sqdisk: {}", msg);
+	process::exit(1);
+}
+
+fn usage() -> ! {
+	eprintln!("{}", USAGE);
+	process::exit(2);
+}
+
+fn open(path: &str, read_only: bool) -> DiskImage {
+	DiskImage::open(path, read_only).unwrap_or_else(|e| fail(&format!("{}: {}", path, e)))
+}
+
+fn block(image: &DiskImage, spec: &str) -> u32 {
+	let g = image.geometry;
+	let n = match spec.split('/').map(|x| x.parse::<u32>().ok()).collect::<Option<Vec<_>>>() {
+		Some(v) if v.len() == 1 && v[0] < g.blocks() => Some(v[0]),
+		Some(v) if v.len() == 3 && v[0] <= 0xFFFF && v[1] <= 0xFF && v[2] <= 0xFF =>
+			g.block(v[0] as u16, v[1] as u8, v[2] as u8),
+		_ => None,
+	};
+	n.unwrap_or_else(|| fail(&format!("no block {} on a volume of {} blocks", spec, g.blocks())))
+}
+
+fn main() {
+	let args: Vec<String> = env::args().collect();
+	if args.len() < 3 {
+		usage();
+	}
+	let path = &args[2];
+	let rest: Vec<&str> = args[3..].iter().map(|x| x.as_str()).collect();
+	
+	match (args[1].as_str(), &rest[..]) {
+		("create", [spec]) => {
+			let geometry = Geometry::parse(spec).unwrap_or_else(|| fail(&format!("bad geometry {}", spec)));
+			if let Err(e) = DiskImage::create(path, geometry) {
+				fail(&format!("{}: {}", path, e));
+			}
+			println!("{}: {} blocks of {} bytes", path, geometry.blocks(), geometry.block_size);
+		},
+		("info", []) => {
+			let image = open(path, true);
+			let g = image.geometry;
+			println!("{}: {} cylinders, {} heads, {} sectors, {} blocks of {} bytes{}", path, g.cylinders, g.heads,
+				g.sectors, g.blocks(), g.block_size, if image.compressed { ", compressed" } else { "" });
+			for b in &image.bad {
+				match b.reads {
+					0 => println!("bad block {}", b.block),
+					n => println!("bad block {} for {} reads", b.block, n),
+				}
+			}
+		},
+		(command @ ("compress" | "expand"), [out]) => {
+			if let Err(e) = open(path, true).save_as(out, command == "compress") {
+				fail(&format!("{}: {}", out, e));
+			}
+		},
+		("merge", [overlay, out]) => {
+			let mut image = open(path, true);
+			if !Path::new(overlay).is_file() {
+				fail(&format!("{}: no such overlay", overlay));
+			}
+			if let Err(e) = image.overlay(overlay) {
+				fail(&format!("{}: {}", overlay, e));
+			}
+			let compress = image.compressed;
+			if let Err(e) = image.save_as(out, compress) {
+				fail(&format!("{}: {}", out, e));
+			}
+		},
+		("bad", [spec]) | ("bad", [spec, _]) => {
+			let mut image = open(path, false);
+			let reads = match rest.get(1) {
+				Some(x) => x.parse().unwrap_or_else(|_| fail(&format!("bad read count {}", x))),
+				None => 0,
+			};
+			let n = block(&image, spec);
+			image.inject(n, reads);
+			if let Err(e) = image.save_map() {
+				fail(&format!("{}: {}", path, e));
+			}
+		},
+		("good", [spec]) => {
+			let mut image = open(path, false);
+			let n = block(&image, spec);
+			if !image.repair(n) {
+				fail(&format!("block {} is not bad", spec));
+			}
+			if let Err(e) = image.save_map() {
+				fail(&format!("{}: {}", path, e));
+			}
+		},
+		("serve", [addr]) => {
+			open(path, true);
+			let file = match FileTransport::open(path, Mode::ReadWrite) {
+				Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => FileTransport::open(path, Mode::Read),
+				x => x,
+			}.unwrap_or_else(|e| fail(&format!("{}: {}", path, e)));
+			println!("{}: serving on {}", path, addr);
+			if let Err(e) = transport::serve(addr, Box::new(file)) {
+				fail(&format!("{}: {}", addr, e));
+			}
+		},
+		_ => usage(),
+	}
+}
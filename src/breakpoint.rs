@@ -0,0 +1,493 @@
+use std::fmt;
+use crate::bus::{Bus, Memory32};
+use crate::cpu::{SeriesQ, PC};
+use crate::isa::FLAG_NAMES;
+
+// Breakpoint: stop (or act) when the CPU is about to execute an address
+//
+// Conditions are C-style expressions over machine state: R0-R15, LR, PC, F0-F15,
+// the F0 flags P L G E V C S B (1 when set), CYCLES, and memory as [addr] (word),
+// H[addr] and B[addr]. Numbers are decimal or 0x-prefixed hex. Actions run in
+// order when the condition holds:
+//
+//   log                  print the registers
+//   log "text"           print text
+//   set REG = expr       set a register
+//   set [addr] = expr    store a word (H[...] and B[...] for half and byte)
+//   continue             do not stop
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+	Num(u32),
+	Reg(usize),
+	Flags(usize),
+	Bit(u8),
+	Cycles,
+	Mem(u8, Box<Expr>),
+	Not(Box<Expr>),
+	Neg(Box<Expr>),
+	Binary(&'static str, Box<Expr>, Box<Expr>)
+}
+
+#[derive(Debug, Clone)]
+pub enum Action {
+	Log(Option<String>),
+	SetReg(usize, Expr),
+	SetMem(u8, Expr, Expr),
+	Continue
+}
+
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+	pub id: u32,
+	pub addr: u32,
+	pub condition: Option<Expr>,
+	pub actions: Vec<Action>,
+	pub hits: u64,
+	source: String
+}
+
+#[derive(Default)]
+pub struct Breakpoints {
+	pub list: Vec<Breakpoint>,
+	next_id: u32,
+	resume_at: Option<u32>,	// stopped here last time; let the instruction run once
+	temp: Option<u32>,		// until and next: stop once on reaching this address
+	step_to: Option<u64>	// next: stop once the cycle count reaches this
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+	Num(u32),
+	Word(String),
+	Str(String),
+	Sym(&'static str)
+}
+
+impl fmt::Display for Tok {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Tok::Num(x) => write!(f, "{}", x),
+			Tok::Word(x) => write!(f, "{}", x),
+			Tok::Str(x) => write!(f, "\"{}\"", x),
+			Tok::Sym(x) => write!(f, "{}", x),
+		}
+	}
+}
+
+fn tokenize(s: &str) -> Result<Vec<Tok>, String> {
+	const SYMBOLS: &[&str] = &["==", "!=", "<=", ">=", "&&", "||", "<<", ">>",
+		"<", ">", "+", "-", "*", "&", "|", "^", "!", "~", "(", ")", "[", "]", "=", ";"];
+	
+	let chars: Vec<char> = s.chars().collect();
+	let mut toks = Vec::new();
+	let mut n = 0;
+	'outer: while n < chars.len() {
+		let c = chars[n];
+		if c.is_whitespace() {
+			n += 1;
+		} else if c == '"' {
+			let end = chars[n + 1..].iter().position(|&x| x == '"').ok_or("unterminated string")?;
+			toks.push(Tok::Str(chars[n + 1..n + 1 + end].iter().collect()));
+			n += end + 2;
+		} else if c.is_ascii_digit() {
+			let start = n;
+			while n < chars.len() && chars[n].is_ascii_alphanumeric() {
+				n += 1;
+			}
+			let text: String = chars[start..n].iter().collect::<String>().to_ascii_lowercase();
+			let v = match text.strip_prefix("0x") {
+				Some(hex) => u32::from_str_radix(hex, 16),
+				None => text.parse(),
+			};
+			toks.push(Tok::Num(v.map_err(|_| format!("bad number {}", text))?));
+		} else if c.is_ascii_alphabetic() {
+			let start = n;
+			while n < chars.len() && chars[n].is_ascii_alphanumeric() {
+				n += 1;
+			}
+			toks.push(Tok::Word(chars[start..n].iter().collect::<String>().to_ascii_uppercase()));
+		} else {
+			for sym in SYMBOLS {
+				let len = sym.len();
+				if n + len <= chars.len() && chars[n..n + len].iter().collect::<String>() == *sym {
+					toks.push(Tok::Sym(sym));
+					n += len;
+					continue 'outer;
+				}
+			}
+			return Err(format!("unexpected '{}'", c));
+		}
+	}
+	Ok(toks)
+}
+
+fn register(name: &str) -> Option<usize> {
+	match name {
+		"LR" => Some(14),
+		"PC" => Some(15),
+		_ => match name.strip_prefix('R').and_then(|x| x.parse::<usize>().ok()) {
+			Some(n) if n < 16 => Some(n),
+			_ => None,
+		},
+	}
+}
+
+fn flag_reg(name: &str) -> Option<usize> {
+	match name.strip_prefix('F').and_then(|x| x.parse::<usize>().ok()) {
+		Some(n) if n < 16 => Some(n),
+		_ => None,
+	}
+}
+
+fn precedence(op: &str) -> Option<u8> {
+	match op {
+		"||" => Some(1),
+		"&&" => Some(2),
+		"|" => Some(3),
+		"^" => Some(4),
+		"&" => Some(5),
+		"==" | "!=" => Some(6),
+		"<" | ">" | "<=" | ">=" => Some(7),
+		"<<" | ">>" => Some(8),
+		"+" | "-" => Some(9),
+		"*" => Some(10),
+		_ => None,
+	}
+}
+
+struct Parser {
+	toks: Vec<Tok>,
+	pos: usize
+}
+
+impl Parser {
+	fn peek(&self) -> Option<&Tok> {
+		self.toks.get(self.pos)
+	}
+	
+	fn next(&mut self) -> Option<Tok> {
+		self.pos += 1;
+		self.toks.get(self.pos - 1).cloned()
+	}
+	
+	fn expect(&mut self, sym: &'static str) -> Result<(), String> {
+		match self.next() {
+			Some(Tok::Sym(x)) if x == sym => Ok(()),
+			_ => Err(format!("expected {}", sym)),
+		}
+	}
+	
+	fn expr(&mut self, min: u8) -> Result<Expr, String> {
+		let mut lhs = self.unary()?;
+		while let Some(Tok::Sym(op)) = self.peek().cloned() {
+			let prec = match precedence(op) {
+				Some(p) if p > min => p,
+				_ => break,
+			};
+			self.pos += 1;
+			let rhs = self.expr(prec)?;
+			lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+		}
+		Ok(lhs)
+	}
+	
+	fn memory(&mut self, width: u8) -> Result<Expr, String> {
+		self.expect("[")?;
+		let addr = self.expr(0)?;
+		self.expect("]")?;
+		Ok(Expr::Mem(width, Box::new(addr)))
+	}
+	
+	fn unary(&mut self) -> Result<Expr, String> {
+		match self.next() {
+			Some(Tok::Num(x)) => Ok(Expr::Num(x)),
+			Some(Tok::Sym("!")) => Ok(Expr::Not(Box::new(self.unary()?))),
+			Some(Tok::Sym("~")) => Ok(Expr::Binary("^", Box::new(self.unary()?), Box::new(Expr::Num(0xFFFFFFFF)))),
+			Some(Tok::Sym("-")) => Ok(Expr::Neg(Box::new(self.unary()?))),
+			Some(Tok::Sym("(")) => {
+				let e = self.expr(0)?;
+				self.expect(")")?;
+				Ok(e)
+			},
+			Some(Tok::Sym("[")) => {
+				self.pos -= 1;
+				self.memory(4)
+			},
+			Some(Tok::Word(w)) => {
+				if w == "H" || w == "B" {
+					if let Some(Tok::Sym("[")) = self.peek() {
+						return self.memory(if w == "H" { 2 } else { 1 });
+					}
+				}
+				if w == "CYCLES" {
+					Ok(Expr::Cycles)
+				} else if let Some(r) = register(&w) {
+					Ok(Expr::Reg(r))
+				} else if let Some(f) = flag_reg(&w) {
+					Ok(Expr::Flags(f))
+				} else if w.len() == 1 && FLAG_NAMES.contains(&w) {
+					Ok(Expr::Bit(0x80 >> FLAG_NAMES.find(&w).unwrap()))
+				} else {
+					Err(format!("unknown name {}", w))
+				}
+			},
+			Some(x) => Err(format!("unexpected {}", x)),
+			None => Err("expression ends early".to_string()),
+		}
+	}
+	
+	// set TARGET = expr
+	fn assignment(&mut self) -> Result<Action, String> {
+		let target = match self.next() {
+			Some(Tok::Word(w)) if register(&w).is_some() => {
+				self.expect("=")?;
+				return Ok(Action::SetReg(register(&w).unwrap(), self.expr(0)?));
+			},
+			Some(Tok::Word(w)) if w == "H" || w == "B" => self.memory(if w == "H" { 2 } else { 1 })?,
+			Some(Tok::Sym("[")) => {
+				self.pos -= 1;
+				self.memory(4)?
+			},
+			_ => return Err("set needs a register or memory target".to_string()),
+		};
+		self.expect("=")?;
+		match target {
+			Expr::Mem(width, addr) => Ok(Action::SetMem(width, *addr, self.expr(0)?)),
+			_ => unreachable!(),
+		}
+	}
+	
+	fn actions(&mut self) -> Result<Vec<Action>, String> {
+		let mut actions = Vec::new();
+		loop {
+			match self.next() {
+				Some(Tok::Word(w)) if w == "LOG" => match self.peek().cloned() {
+					Some(Tok::Str(s)) => {
+						self.pos += 1;
+						actions.push(Action::Log(Some(s)));
+					},
+					_ => actions.push(Action::Log(None)),
+				},
+				Some(Tok::Word(w)) if w == "SET" => actions.push(self.assignment()?),
+				Some(Tok::Word(w)) if w == "CONTINUE" => actions.push(Action::Continue),
+				Some(x) => return Err(format!("unknown action {}", x)),
+				None => return Err("missing action".to_string()),
+			}
+			match self.next() {
+				Some(Tok::Sym(";")) => { },
+				None => return Ok(actions),
+				Some(x) => return Err(format!("unexpected {} after action", x)),
+			}
+		}
+	}
+}
+
+pub fn parse_expr(s: &str) -> Result<Expr, String> {
+	let mut p = Parser { toks: tokenize(s)?, pos: 0 };
+	let e = p.expr(0)?;
+	if p.pos != p.toks.len() {
+		return Err("junk after expression".to_string());
+	}
+	Ok(e)
+}
+
+pub fn eval(e: &Expr, cpu: &SeriesQ, bus: &Bus) -> Result<u32, String> {
+	Ok(match e {
+		Expr::Num(x) => *x,
+		Expr::Reg(r) => cpu.R[*r],
+		Expr::Flags(f) => cpu.F[*f] as u32,
+		Expr::Bit(mask) => (cpu.F[0] & mask != 0) as u32,
+		Expr::Cycles => cpu.cycles as u32,
+		Expr::Mem(width, addr) => {
+			let a = eval(addr, cpu, bus)?;
+			let v = match width {
+				1 => bus.read_b(a).map(|x| x as u32),
+				2 => bus.read_h(a).map(|x| x as u32),
+				_ => bus.read_w(a),
+			};
+			v.map_err(|x| format!("{:?} reading 0x{:08X}", x, a))?
+		},
+		Expr::Not(x) => (eval(x, cpu, bus)? == 0) as u32,
+		Expr::Neg(x) => eval(x, cpu, bus)?.wrapping_neg(),
+		Expr::Binary(op, l, r) => {
+			let a = eval(l, cpu, bus)?;
+			// && and || only look right when they must
+			match *op {
+				"&&" => return Ok((a != 0 && eval(r, cpu, bus)? != 0) as u32),
+				"||" => return Ok((a != 0 || eval(r, cpu, bus)? != 0) as u32),
+				_ => { },
+			}
+			let b = eval(r, cpu, bus)?;
+			match *op {
+				"|" => a | b,
+				"^" => a ^ b,
+				"&" => a & b,
+				"==" => (a == b) as u32,
+				"!=" => (a != b) as u32,
+				"<" => (a < b) as u32,
+				">" => (a > b) as u32,
+				"<=" => (a <= b) as u32,
+				">=" => (a >= b) as u32,
+				"<<" => a.checked_shl(b).unwrap_or(0),
+				">>" => a.checked_shr(b).unwrap_or(0),
+				"+" => a.wrapping_add(b),
+				"-" => a.wrapping_sub(b),
+				_ => a.wrapping_mul(b),
+			}
+		},
+	})
+}
+
+impl fmt::Display for Breakpoint {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:>3}  {:08X}  {:>8} hits  {}", self.id, self.addr, self.hits, self.source)
+	}
+}
+
+impl Breakpoints {
+	pub fn is_empty(&self) -> bool {
+		self.list.is_empty() && self.temp.is_none() && self.step_to.is_none()
+	}
+	
+	// one-shot stops for the monitor's until and next; gone after the next stop
+	pub fn until(&mut self, addr: u32) {
+		self.temp = Some(addr);
+	}
+	
+	pub fn step(&mut self, cycles: u64) {
+		self.step_to = Some(cycles.wrapping_add(1));
+	}
+	
+	pub fn cancel_temporary(&mut self) {
+		self.temp = None;
+		self.step_to = None;
+	}
+	
+	// `ADDR [if COND] [do ACTION; ...]`, ADDR in hex
+	pub fn add(&mut self, spec: &str) -> Result<u32, String> {
+		let spec = spec.trim();
+		let (addr, rest) = spec.split_at(spec.find(char::is_whitespace).unwrap_or(spec.len()));
+		let addr = u32::from_str_radix(addr.trim_start_matches("0x"), 16)
+			.map_err(|_| format!("bad address {}", addr))?;
+		
+		let rest = rest.trim();
+		let lower = rest.to_ascii_lowercase();
+		let do_at = lower.find(" do ").map(|x| x + 1).or(if lower.starts_with("do ") { Some(0) } else { None });
+		let (cond, acts) = match do_at {
+			Some(i) => (&rest[..i], Some(&rest[i + 3..])),
+			None => (rest, None),
+		};
+		let cond = cond.trim();
+		let condition = if cond.is_empty() {
+			None
+		} else if cond.to_ascii_lowercase().starts_with("if ") {
+			Some(parse_expr(&cond[3..])?)
+		} else {
+			return Err(format!("expected if or do, found {}", cond));
+		};
+		let actions = match acts {
+			Some(a) => Parser { toks: tokenize(a)?, pos: 0 }.actions()?,
+			None => Vec::new(),
+		};
+		
+		self.next_id += 1;
+		self.list.push(Breakpoint {
+			id: self.next_id,
+			addr: addr,
+			condition: condition,
+			actions: actions,
+			hits: 0,
+			source: rest.to_string()
+		});
+		Ok(self.next_id)
+	}
+	
+	pub fn remove(&mut self, id: u32) -> bool {
+		let before = self.list.len();
+		self.list.retain(|b| b.id != id);
+		self.list.len() != before
+	}
+	
+	pub fn clear(&mut self) {
+		self.list.clear();
+	}
+	
+	fn run_actions(bp: &Breakpoint, cpu: &mut SeriesQ, bus: &mut Bus) -> Result<bool, String> {
+		let mut stop = true;
+		for a in &bp.actions {
+			match a {
+				Action::Log(Some(text)) => println!("BREAK {} @{:08X}: {}", bp.id, cpu.R[PC], text),
+				Action::Log(None) => {
+					let regs: Vec<String> = (1..16).map(|r| format!("R{}={:08X}", r, cpu.R[r])).collect();
+					println!("BREAK {} @{:08X}: F0={:08b} {}", bp.id, cpu.R[PC], cpu.F[0], regs.join(" "));
+				},
+				Action::SetReg(r, e) => {
+					let v = eval(e, cpu, bus)?;
+					cpu.R[*r] = v;
+				},
+				Action::SetMem(width, addr, e) => {
+					let a = eval(addr, cpu, bus)?;
+					let v = eval(e, cpu, bus)?;
+					let result = match width {
+						1 => bus.write_b(a, v as u8),
+						2 => bus.write_h(a, v as u16),
+						_ => bus.write_w(a, v),
+					};
+					result.map_err(|x| format!("{:?} writing 0x{:08X}", x, a))?;
+				},
+				Action::Continue => stop = false,
+			}
+		}
+		Ok(stop)
+	}
+	
+	// called before each instruction; true stops the CPU
+	pub fn check(cpu: &mut SeriesQ, bus: &mut Bus) -> bool {
+		let pc = cpu.R[PC];
+		let mut bps = std::mem::take(&mut cpu.breakpoints);
+		
+		if bps.resume_at.take() == Some(pc) {
+			cpu.breakpoints = bps;
+			return false;
+		}
+		
+		let mut stop = bps.temp == Some(pc) || bps.step_to.map_or(false, |c| cpu.cycles >= c);
+		for bp in bps.list.iter_mut().filter(|b| b.addr == pc) {
+			let hit = match &bp.condition {
+				Some(c) => eval(c, cpu, bus),
+				None => Ok(1),
+			};
+			match hit {
+				Ok(0) => continue,
+				Ok(_) => { },
+				Err(e) => {
+					println!("BREAK {} @{:08X}: {}", bp.id, pc, e);
+					stop = true;
+					continue;
+				},
+			}
+			
+			bp.hits += 1;
+			match Breakpoints::run_actions(bp, cpu, bus) {
+				Ok(x) => {
+					if x {
+						println!("BREAK {} @{:08X}", bp.id, pc);
+					}
+					stop |= x;
+				},
+				Err(e) => {
+					println!("BREAK {} @{:08X}: {}", bp.id, pc, e);
+					stop = true;
+				},
+			}
+		}
+		
+		if stop {
+			bps.resume_at = Some(pc);
+			bps.cancel_temporary();
+		}
+		cpu.breakpoints = bps;
+		stop
+	}
+}
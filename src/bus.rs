@@ -1,4 +1,9 @@
-use std::sync::{Arc, Mutex, Condvar};
+use std::cell::{Cell, RefCell};
+use std::sync::{Arc, TryLockError};
+use crate::sync::{Mutex, Condvar};
+use serde::{Serialize, Deserialize};
+use crate::ram::Ram;
+use crate::dma::{Master, Translation, Window};
 
 // Memory32 trait for use with bus, as well as reference impl for Vec<u8>
 
@@ -6,7 +11,8 @@ use std::sync::{Arc, Mutex, Condvar};
 #[allow(dead_code)]
 pub enum BusError {
 	AlignmentCheck,
-	InvalidAddress
+	InvalidAddress,
+	InvalidState
 }
 
 pub trait Memory32<A, E> {
@@ -18,6 +24,24 @@ pub trait Memory32<A, E> {
 	fn write_b(&mut self, addr: A, data: u8) -> Result<(), E>;
 	fn write_h(&mut self, addr: A, data: u16) -> Result<(), E>;
 	fn write_w(&mut self, addr: A, data: u32) -> Result<(), E>;
+	
+	// snapshot support; regions without persistent state keep the defaults
+	fn save_state(&self) -> Option<Vec<u8>> {
+		None
+	}
+	fn load_state(&mut self, _state: &[u8]) -> Result<(), E> {
+		Ok(())
+	}
+	
+	// wait out work the region has accepted but not finished, as queued
+	// writes; regions that finish every access before returning have none
+	fn drain(&self) { }
+	
+	// whether reading addr leaves the region as it was, so two reads with no
+	// write between them agree; the device contract checks it (contract.rs)
+	fn idempotent(&self, _addr: A) -> bool {
+		false
+	}
 }
 
 impl Memory32<u32, BusError> for Vec<u8> {
@@ -89,6 +113,22 @@ impl Memory32<u32, BusError> for Vec<u8> {
 			Ok(())
 		}
 	}
+	
+	fn save_state(&self) -> Option<Vec<u8>> {
+		Some(self.clone())
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		if state.len() != self.len() {
+			Err(BusError::InvalidState)
+		} else {
+			self.copy_from_slice(state);
+			Ok(())
+		}
+	}
+	
+	fn idempotent(&self, _addr: u32) -> bool {
+		true
+	}
 }
 
 // Bus: Attach and access multiple Memory32 simulated devices
@@ -97,6 +137,9 @@ pub struct Bus {
 	base: Vec<u32>,
 	size: Vec<u32>,
 	pub region: Vec<Arc<Mutex<dyn Memory32<u32, BusError> + Send>>>,
+	audit: Option<RefCell<Vec<(u32, u32)>>>,	// (address, width) of each access, when auditing
+	ram: Option<(u32, u32, Arc<Ram>)>,			// main memory, checked before the region list
+	fixup: Option<Cell<u32>>,					// unaligned accesses made a byte at a time, when fixing up
 }
 
 impl Bus {
@@ -104,7 +147,10 @@ impl Bus {
 		Bus {
 			base: Vec::new(),
 			size: Vec::new(),
-			region: Vec::new()
+			region: Vec::new(),
+			audit: None,
+			ram: None,
+			fixup: None
 		}
 	}
 	
@@ -114,10 +160,113 @@ impl Bus {
 		self.size.push(size);
 		self.region.push(region);
 	}
-}
-
-impl Memory32<u32, BusError> for Bus {
-	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+	
+	// main memory skips the region lock: the bus lock already serializes its users
+	pub fn attach_ram(&mut self, base: u32, ram: Arc<Ram>) {
+		let size = ram.size();
+		self.ram = Some((base, size, Arc::clone(&ram)));
+		self.attach(base, size, Arc::new(Mutex::new(ram)));
+	}
+	
+	// main memory and the offset into it, when addr falls there
+	pub fn ram_at(&self, addr: u32) -> Option<(&Ram, u32)> {
+		match &self.ram {
+			Some((base, size, ram)) if addr >= *base && addr - base < *size => Some((ram, addr - base)),
+			_ => None,
+		}
+	}
+	
+	// physical address of the first uninitialized main memory read since the last call
+	pub fn take_taint(&self) -> Option<u32> {
+		let (base, _, ram) = self.ram.as_ref()?;
+		ram.take_taint().map(|x| x.wrapping_add(*base))
+	}
+	
+	// physical address of the first store into fetched code since the last call
+	pub fn take_modified(&self) -> Option<u32> {
+		let (base, _, ram) = self.ram.as_ref()?;
+		ram.take_modified().map(|x| x.wrapping_add(*base))
+	}
+	
+	// swap the region attached at base for another of the same size; the old
+	// one finishes its queued work first
+	pub fn replace(&mut self, base: u32, region: Arc<Mutex<dyn Memory32<u32, BusError> + Send>>) -> bool {
+		match self.base.iter().position(|&b| b == base) {
+			Some(n) => {
+				self.region[n].lock().unwrap().drain();
+				if self.ram.as_ref().map_or(false, |r| r.0 == base) {
+					self.ram = None;
+				}
+				self.region[n] = region;
+				true
+			},
+			None => false,
+		}
+	}
+	
+	// the region attached at base
+	pub fn region_at(&self, base: u32) -> Option<Arc<Mutex<dyn Memory32<u32, BusError> + Send>>> {
+		self.base.iter().position(|&b| b == base).map(|n| Arc::clone(&self.region[n]))
+	}
+	
+	// change the size of the region attached at base
+	pub fn resize(&mut self, base: u32, size: u32) -> bool {
+		match self.base.iter().position(|&b| b == base) {
+			Some(n) => {
+				self.size[n] = size;
+				true
+			},
+			None => false,
+		}
+	}
+	
+	// move regions to new bases, (from, to) each; the moves happen together, so
+	// one region may take another's old base. Main memory stays put.
+	pub fn relocate(&mut self, moves: &[(u32, u32)]) {
+		let found: Vec<(usize, u32)> = moves.iter()
+			.filter(|(from, _)| self.ram.as_ref().map_or(true, |r| r.0 != *from))
+			.filter_map(|(from, to)| self.base.iter().position(|b| b == from).map(|n| (n, *to)))
+			.collect();
+		for (n, to) in found {
+			self.base[n] = to;
+		}
+	}
+	
+	// start or stop recording every access for the CPU's access assertions
+	pub fn set_audit(&mut self, on: bool) {
+		self.audit = if on { Some(RefCell::new(Vec::new())) } else { None };
+	}
+	
+	// accesses recorded since the last call
+	pub fn take_audit(&self) -> Vec<(u32, u32)> {
+		match &self.audit {
+			Some(log) => log.replace(Vec::new()),
+			None => Vec::new(),
+		}
+	}
+	
+	fn note(&self, addr: u32, width: u32) {
+		if let Some(log) = &self.audit {
+			log.borrow_mut().push((addr, width));
+		}
+	}
+	
+	// start or stop making the unaligned accesses a region refuses a byte at a
+	// time, as the CPU's alignment fixup mode does
+	pub fn set_fixup(&mut self, on: bool) {
+		self.fixup = if on { Some(Cell::new(0)) } else { None };
+	}
+	
+	// accesses fixed up since the last call
+	pub fn take_fixups(&self) -> u32 {
+		self.fixup.as_ref().map_or(0, |n| n.replace(0))
+	}
+	
+	// byte accesses without noting them, for read_b and write_b and fixups
+	fn read_byte(&self, addr: u32) -> Result<u8, BusError> {
+		if let Some((ram, offset)) = self.ram_at(addr) {
+			return ram.read_b(offset);
+		}
 		for n in 0..self.base.len() {
 			if addr >= self.base[n] && addr < self.base[n] + self.size[n] {
 				let mem = self.region[n].lock().unwrap();
@@ -126,16 +275,155 @@ impl Memory32<u32, BusError> for Bus {
 		}
 		return Err(BusError::InvalidAddress);
 	}
+	
+	fn write_byte(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		if let Some((ram, offset)) = self.ram_at(addr) {
+			return ram.write_b(offset, data);
+		}
+		for n in 0..self.base.len() {
+			if addr >= self.base[n] && addr < self.base[n] + self.size[n] {
+				let mut mem = self.region[n].lock().unwrap();
+				return mem.write_b(addr - self.base[n], data);
+			}
+		}
+		return Err(BusError::InvalidAddress);
+	}
+	
+	// little-endian, like the regions; a byte that fails fails the access, and
+	// the audit sees only the access as the CPU made it
+	fn fixed_read(&self, addr: u32, width: u32, result: Result<u32, BusError>) -> Result<u32, BusError> {
+		match (result, &self.fixup) {
+			(Err(BusError::AlignmentCheck), Some(count)) => {
+				let mut x = 0;
+				for n in (0..width).rev() {
+					x = x << 8 | self.read_byte(addr.wrapping_add(n))? as u32;
+				}
+				count.set(count.get() + 1);
+				Ok(x)
+			},
+			(result, _) => result,
+		}
+	}
+	
+	fn fixed_write(&mut self, addr: u32, width: u32, data: u32, result: Result<(), BusError>) -> Result<(), BusError> {
+		match (result, self.fixup.is_some()) {
+			(Err(BusError::AlignmentCheck), true) => {
+				for n in 0..width {
+					self.write_byte(addr.wrapping_add(n), (data >> (8 * n)) as u8)?;
+				}
+				let count = self.fixup.as_ref().unwrap();
+				count.set(count.get() + 1);
+				Ok(())
+			},
+			(result, _) => result,
+		}
+	}
+	
+	// whether one attached region holds all of len bytes at addr
+	pub fn holds(&self, addr: u32, len: u32) -> bool {
+		self.base.iter().zip(self.size.iter()).any(|(&b, &size)| addr >= b && (addr - b) as u64 + len as u64 <= size as u64)
+	}
+	
+	// let every region finish its queued work, for a consistent snapshot
+	pub fn drain(&self) {
+		for r in &self.region {
+			r.lock().unwrap().drain();
+		}
+	}
+	
+	// (base, size) of every attached region, lowest base first
+	pub fn regions(&self) -> Vec<(u32, u32)> {
+		let mut result = self.layout();
+		result.sort();
+		result
+	}
+	
+	// (base, size) of every attached region, in attach order as snapshots keep them
+	pub fn layout(&self) -> Vec<(u32, u32)> {
+		self.base.iter().cloned().zip(self.size.iter().cloned()).collect()
+	}
+	
+	pub fn snapshot(&self) -> BusState {
+		BusState {
+			base: self.base.clone(),
+			size: self.size.clone(),
+			region: self.region.iter().map(|r| r.lock().unwrap().save_state()).collect()
+		}
+	}
+	
+	// as snapshot, but leaving out any region whose lock is held rather than
+	// waiting for it, for a crash that may have left one held for good
+	pub fn try_snapshot(&self) -> BusState {
+		BusState {
+			base: self.base.clone(),
+			size: self.size.clone(),
+			region: self.region.iter().map(|r| match r.try_lock() {
+				Ok(x) => x.save_state(),
+				Err(TryLockError::Poisoned(e)) => e.into_inner().save_state(),
+				Err(TryLockError::WouldBlock) => None,
+			}).collect()
+		}
+	}
+	
+	// the state of the one region attached at base, if it has any
+	pub fn save_region(&self, base: u32) -> Option<Vec<u8>> {
+		let n = self.base.iter().position(|&b| b == base)?;
+		self.region[n].lock().unwrap().save_state()
+	}
+	
+	pub fn load_region(&mut self, base: u32, state: &[u8]) -> Result<(), BusError> {
+		match self.base.iter().position(|&b| b == base) {
+			Some(n) => self.region[n].lock().unwrap().load_state(state),
+			None => Err(BusError::InvalidAddress),
+		}
+	}
+	
+	pub fn restore(&mut self, state: &BusState) -> Result<(), BusError> {
+		// regions are wired up by the host, so the layout has to match exactly
+		if state.base != self.base || state.size != self.size {
+			return Err(BusError::InvalidState);
+		}
+		for (n, saved) in state.region.iter().enumerate() {
+			if let Some(data) = saved {
+				self.region[n].lock().unwrap().load_state(data)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+// BusState: serializable image of every region attached to a bus
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BusState {
+	pub base: Vec<u32>,
+	pub size: Vec<u32>,
+	pub region: Vec<Option<Vec<u8>>>,
+}
+
+impl Memory32<u32, BusError> for Bus {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.note(addr, 1);
+		self.read_byte(addr)
+	}
 	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.note(addr, 2);
+		if let Some((ram, offset)) = self.ram_at(addr) {
+			return self.fixed_read(addr, 2, ram.read_h(offset).map(|x| x as u32)).map(|x| x as u16);
+		}
 		for n in 0..self.base.len() {
 			if addr >= self.base[n] && addr < self.base[n] + self.size[n] {
-				let mem = self.region[n].lock().unwrap();
-				return mem.read_h(addr - self.base[n]);
+				let result = self.region[n].lock().unwrap().read_h(addr - self.base[n]).map(|x| x as u32);
+				return self.fixed_read(addr, 2, result).map(|x| x as u16);
 			}
 		}
 		return Err(BusError::InvalidAddress);
 	}
 	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		self.note(addr, 2);
+		if let Some((ram, offset)) = self.ram_at(addr) {
+			return ram.read_h_big(offset);
+		}
 		for n in 0..self.base.len() {
 			if addr >= self.base[n] && addr < self.base[n] + self.size[n] {
 				let mem = self.region[n].lock().unwrap();
@@ -145,38 +433,47 @@ impl Memory32<u32, BusError> for Bus {
 		return Err(BusError::InvalidAddress);
 	}
 	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		self.note(addr, 4);
+		if let Some((ram, offset)) = self.ram_at(addr) {
+			return self.fixed_read(addr, 4, ram.read_w(offset));
+		}
 		for n in 0..self.base.len() {
 			if addr >= self.base[n] && addr < self.base[n] + self.size[n] {
-				let mem = self.region[n].lock().unwrap();
-				return mem.read_w(addr - self.base[n]);
+				let result = self.region[n].lock().unwrap().read_w(addr - self.base[n]);
+				return self.fixed_read(addr, 4, result);
 			}
 		}
 		return Err(BusError::InvalidAddress);
 	}
 	
 	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
-		for n in 0..self.base.len() {
-			if addr >= self.base[n] && addr < self.base[n] + self.size[n] {
-				let mut mem = self.region[n].lock().unwrap();
-				return mem.write_b(addr - self.base[n], data);
-			}
-		}
-		return Err(BusError::InvalidAddress);
+		self.note(addr, 1);
+		self.write_byte(addr, data)
 	}
 	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		self.note(addr, 2);
+		if let Some((ram, offset)) = self.ram_at(addr) {
+			let result = ram.write_h(offset, data);
+			return self.fixed_write(addr, 2, data as u32, result);
+		}
 		for n in 0..self.base.len() {
 			if addr >= self.base[n] && addr < self.base[n] + self.size[n] {
-				let mut mem = self.region[n].lock().unwrap();
-				return mem.write_h(addr - self.base[n], data);
+				let result = self.region[n].lock().unwrap().write_h(addr - self.base[n], data);
+				return self.fixed_write(addr, 2, data as u32, result);
 			}
 		}
 		return Err(BusError::InvalidAddress);
 	}
 	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		self.note(addr, 4);
+		if let Some((ram, offset)) = self.ram_at(addr) {
+			let result = ram.write_w(offset, data);
+			return self.fixed_write(addr, 4, data, result);
+		}
 		for n in 0..self.base.len() {
 			if addr >= self.base[n] && addr < self.base[n] + self.size[n] {
-				let mut mem = self.region[n].lock().unwrap();
-				return mem.write_w(addr - self.base[n], data);
+				let result = self.region[n].lock().unwrap().write_w(addr - self.base[n], data);
+				return self.fixed_write(addr, 4, data, result);
 			}
 		}
 		return Err(BusError::InvalidAddress);
@@ -184,11 +481,17 @@ impl Memory32<u32, BusError> for Bus {
 }
 
 // Channel - a generic synchronization construct
+//
+// The device on a channel sees the bus through a Master, which translates its
+// addresses if the channel has a translation table and holds it to the
+// channel's DMA window if it has one.
 
 pub struct Channel<T> {
 	bus: Arc<Mutex<T>>,
 	brq: Arc<(Mutex<bool>, Condvar)>,
-	bgr: Arc<(Mutex<bool>, Condvar)>
+	bgr: Arc<(Mutex<bool>, Condvar)>,
+	window: Arc<Mutex<Option<Arc<Window>>>>,
+	translation: Arc<Mutex<Option<Translation>>>
 }
 
 impl<T> Channel<T> {
@@ -196,7 +499,9 @@ impl<T> Channel<T> {
 		Channel {
 			bus: Arc::clone(&bus),
 			brq: Arc::new((Mutex::new(false), Condvar::new())),
-			bgr: Arc::new((Mutex::new(false), Condvar::new()))
+			bgr: Arc::new((Mutex::new(false), Condvar::new())),
+			window: Arc::new(Mutex::new(None)),
+			translation: Arc::new(Mutex::new(None))
 		}
 	}
 	
@@ -204,12 +509,31 @@ impl<T> Channel<T> {
 		Channel {
 			bus: Arc::clone(&ch.bus),
 			brq: Arc::clone(&ch.brq),
-			bgr: Arc::clone(&ch.bgr)
+			bgr: Arc::clone(&ch.bgr),
+			window: Arc::clone(&ch.window),
+			translation: Arc::clone(&ch.translation)
 		}
 	}
 	
+	// shared by every clone of the channel
+	pub fn set_window(&self, window: Option<Window>) {
+		*self.window.lock().unwrap() = window.map(Arc::new);
+	}
+	
+	pub fn window(&self) -> Option<Arc<Window>> {
+		self.window.lock().unwrap().clone()
+	}
+	
+	pub fn set_translation(&self, translation: Option<Translation>) {
+		*self.translation.lock().unwrap() = translation;
+	}
+	
+	pub fn translation(&self) -> Option<Translation> {
+		*self.translation.lock().unwrap()
+	}
+	
 	pub fn in_channel<F, U>(&self, mut f: F) -> U 
-	where F: FnMut(&mut T) -> U {
+	where F: FnMut(&mut Master<T>) -> U, T: Memory32<u32, BusError> {
 		let &(ref rlock, ref rcvar) = &*(self.brq);
 		let &(ref glock, ref gcvar) = &*(self.bgr);
 		
@@ -225,8 +549,9 @@ impl<T> Channel<T> {
 		}
 		
 		// acquire bus and call f
+		let (window, translation) = (self.window(), self.translation());
 		let mut bus = self.bus.lock().unwrap();
-		let result = f(&mut *bus);
+		let result = f(&mut Master::new(&mut *bus, window.as_deref(), translation));
 		drop(bus);
 		
 		// release BRQ
@@ -235,6 +560,12 @@ impl<T> Channel<T> {
 		rcvar.notify_one();
 		drop(rq);
 		
+		// wait for BGR to fall, so a new request can't ride on this grant
+		while *gr {
+			gr = gcvar.wait(gr).unwrap();
+		}
+		drop(gr);
+		
 		result
 	}
 	
@@ -269,6 +600,20 @@ impl<T> Channel<T> {
 		// release BGR
 		gr = glock.lock().unwrap();
 		*gr = false;
+		gcvar.notify_all();
 		drop(gr);
 	}
-}
\ No newline at end of file
+}
+
+// grant the bus once to each channel with a request up; the caller must not
+// hold the bus. Returns the grants made.
+pub fn grant_pending<T>(channels: &[Channel<T>]) -> u32 {
+	let mut grants = 0;
+	for c in channels {
+		if c.check_pending() {
+			c.open();
+			grants += 1;
+		}
+	}
+	grants
+}
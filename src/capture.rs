@@ -0,0 +1,260 @@
+use std::fs;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::screen::{self, Screen, BLINK_MS};
+use crate::sync;
+
+// Capture: pictures of the text display, for documentation and bug reports
+//
+// A screenshot is a PNG of the display as it is, blinking characters and the
+// cursor shown. A recording samples it every FRAME until it is stopped or runs
+// out of time, and is written as an animated PNG (APNG) that loops forever; a
+// frame the same as the one before only makes that one last longer, so a
+// display that hardly changes makes a small file. The pictures are indexed
+// colour, the deflate stream stored rather than compressed: with two colours
+// a frame is 16K or so either way.
+//
+// The monitor's screenshot and record commands write files; --http serves the
+// same pictures (http.rs).
+
+pub const FRAME: Duration = Duration::from_millis(100);
+
+// a recording with no end given
+pub const RECORD_SECONDS: u64 = 10;
+
+// the most a deflate stored block holds
+const STORED_BLOCK: usize = 65535;
+
+// frames for a PNG, the pixels as indexes into a palette of at most 256
+// colours; a frame has a delay in milliseconds
+pub struct Frames {
+	width: usize,
+	height: usize,
+	palette: Vec<u32>,
+	frames: Vec<(Vec<u8>, u16)>
+}
+
+impl Frames {
+	pub fn new(width: usize, height: usize) -> Frames {
+		Frames { width: width, height: height, palette: Vec::new(), frames: Vec::new() }
+	}
+	
+	pub fn len(&self) -> usize {
+		self.frames.len()
+	}
+	
+	// a colour past the first 256 is drawn as the first
+	fn index(&mut self, colour: u32) -> u8 {
+		match self.palette.iter().position(|&x| x == colour) {
+			Some(n) => n as u8,
+			None if self.palette.len() < 256 => {
+				self.palette.push(colour);
+				(self.palette.len() - 1) as u8
+			},
+			None => 0,
+		}
+	}
+	
+	// pixels shown for ms
+	pub fn add(&mut self, pixels: &[u32], ms: u64) {
+		let indexes: Vec<u8> = pixels.iter().map(|&x| self.index(x)).collect();
+		let ms = ms.min(u16::MAX as u64) as u16;
+		if let Some((last, delay)) = self.frames.last_mut() {
+			if *last == indexes && *delay as u64 + ms as u64 <= u16::MAX as u64 {
+				*delay += ms;
+				return;
+			}
+		}
+		self.frames.push((indexes, ms));
+	}
+	
+	fn depth(&self) -> u8 {
+		match self.palette.len() {
+			0..=2 => 1,
+			3..=4 => 2,
+			5..=16 => 4,
+			_ => 8,
+		}
+	}
+	
+	// a frame as PNG scanlines, each a filter byte (none) and the pixels
+	// packed depth bits each, leftmost highest
+	fn scanlines(&self, indexes: &[u8]) -> Vec<u8> {
+		let depth = self.depth() as usize;
+		let per_byte = 8 / depth;
+		let mut out = Vec::with_capacity(self.height * (1 + self.width.div_ceil(per_byte)));
+		for row in indexes.chunks(self.width) {
+			out.push(0);
+			for pixels in row.chunks(per_byte) {
+				let mut b = 0;
+				for (n, &x) in pixels.iter().enumerate() {
+					b |= x << (8 - depth * (n + 1));
+				}
+				out.push(b);
+			}
+		}
+		out
+	}
+	
+	// a PNG of the one frame, or an APNG of them all
+	pub fn png(&self) -> Vec<u8> {
+		let mut out = b"\x89PNG\r\n\x1A\n".to_vec();
+		let mut header = Vec::new();
+		header.extend_from_slice(&(self.width as u32).to_be_bytes());
+		header.extend_from_slice(&(self.height as u32).to_be_bytes());
+		// indexed colour, no interlace
+		header.extend_from_slice(&[self.depth(), 3, 0, 0, 0]);
+		chunk(&mut out, b"IHDR", &header);
+		let mut palette: Vec<u8> = self.palette.iter().flat_map(|x| x.to_be_bytes()[1..].to_vec()).collect();
+		if palette.is_empty() {
+			palette = vec![0, 0, 0];
+		}
+		chunk(&mut out, b"PLTE", &palette);
+		
+		let animated = self.frames.len() > 1;
+		if animated {
+			let mut control = (self.frames.len() as u32).to_be_bytes().to_vec();
+			// played forever
+			control.extend_from_slice(&0u32.to_be_bytes());
+			chunk(&mut out, b"acTL", &control);
+		}
+		let mut sequence = 0u32;
+		for (n, (indexes, delay)) in self.frames.iter().enumerate() {
+			if animated {
+				let mut control = sequence.to_be_bytes().to_vec();
+				sequence += 1;
+				control.extend_from_slice(&(self.width as u32).to_be_bytes());
+				control.extend_from_slice(&(self.height as u32).to_be_bytes());
+				control.extend_from_slice(&[0; 8]);
+				control.extend_from_slice(&delay.to_be_bytes());
+				control.extend_from_slice(&1000u16.to_be_bytes());
+				// no disposal, no blending: each frame is the whole picture
+				control.extend_from_slice(&[0, 0]);
+				chunk(&mut out, b"fcTL", &control);
+			}
+			let data = zlib(&self.scanlines(indexes));
+			if n == 0 {
+				chunk(&mut out, b"IDAT", &data);
+			} else {
+				let mut frame = sequence.to_be_bytes().to_vec();
+				sequence += 1;
+				frame.extend_from_slice(&data);
+				chunk(&mut out, b"fdAT", &frame);
+			}
+		}
+		chunk(&mut out, b"IEND", &[]);
+		out
+	}
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+	out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+	let start = out.len();
+	out.extend_from_slice(kind);
+	out.extend_from_slice(data);
+	let crc = crc32(&out[start..]);
+	out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+	let mut crc = !0u32;
+	for &b in data {
+		crc ^= b as u32;
+		for _ in 0..8 {
+			crc = if crc & 1 != 0 { crc >> 1 ^ 0xEDB88320 } else { crc >> 1 };
+		}
+	}
+	!crc
+}
+
+// a zlib stream of stored deflate blocks
+fn zlib(data: &[u8]) -> Vec<u8> {
+	let mut out = vec![0x78, 0x01];
+	let blocks: Vec<&[u8]> = data.chunks(STORED_BLOCK).collect();
+	for (n, block) in blocks.iter().enumerate() {
+		out.push(if n + 1 == blocks.len() { 1 } else { 0 });
+		out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+		out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+		out.extend_from_slice(block);
+	}
+	if blocks.is_empty() {
+		out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+	}
+	let (mut a, mut b) = (1u32, 0u32);
+	for &x in data {
+		a = (a + x as u32) % 65521;
+		b = (b + a) % 65521;
+	}
+	out.extend_from_slice(&(b << 16 | a).to_be_bytes());
+	out
+}
+
+// a PNG of the display as it is
+pub fn screenshot(screen: &sync::Mutex<Screen>) -> Vec<u8> {
+	let mut frames = Frames::new(screen::WIDTH, screen::HEIGHT);
+	frames.add(&screen.lock().unwrap().render(true), 0);
+	frames.png()
+}
+
+// frames of the display until stop is set or limit has passed
+pub fn record(screen: &sync::Mutex<Screen>, limit: Duration, stop: &AtomicBool) -> Frames {
+	let mut frames = Frames::new(screen::WIDTH, screen::HEIGHT);
+	let start = Instant::now();
+	let mut shown = start;
+	loop {
+		let blink = start.elapsed().as_millis() / BLINK_MS % 2 == 0;
+		let pixels = screen.lock().unwrap().render(blink);
+		thread::sleep(FRAME);
+		frames.add(&pixels, shown.elapsed().as_millis() as u64);
+		shown = Instant::now();
+		if stop.load(Ordering::SeqCst) || start.elapsed() >= limit {
+			return frames;
+		}
+	}
+}
+
+// the monitor's recording, going on in the background
+
+struct Recording {
+	stop: Arc<AtomicBool>,
+	thread: JoinHandle<()>
+}
+
+static RECORDING: std::sync::Mutex<Option<Recording>> = std::sync::Mutex::new(None);
+
+// record the display to path for at most limit, reporting when done
+pub fn start(screen: Arc<sync::Mutex<Screen>>, path: &str, limit: Duration) -> Result<(), String> {
+	let mut recording = RECORDING.lock().unwrap();
+	if recording.as_ref().map_or(false, |x| !x.thread.is_finished()) {
+		return Err("already recording; record stop first".to_string());
+	}
+	// a bad path should fail now, not when the recording ends
+	fs::File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+	let stop = Arc::new(AtomicBool::new(false));
+	let path = path.to_string();
+	let flag = Arc::clone(&stop);
+	let thread = thread::spawn(move || {
+		let frames = record(&screen, limit, &flag);
+		match fs::write(&path, frames.png()) {
+			Ok(()) => println!("RECORDED {} FRAMES TO {}", frames.len(), path),
+			Err(e) => println!("? {}: {}", path, e),
+		}
+	});
+	*recording = Some(Recording { stop: stop, thread: thread });
+	Ok(())
+}
+
+// end the recording, if there is one, and wait for it to be written
+pub fn stop() -> bool {
+	let recording = RECORDING.lock().unwrap().take();
+	match recording {
+		Some(r) => {
+			r.stop.store(true, Ordering::SeqCst);
+			let _ = r.thread.join();
+			true
+		},
+		None => false,
+	}
+}
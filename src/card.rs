@@ -0,0 +1,232 @@
+use std::io;
+use crate::bus::{Memory32, BusError};
+use crate::charset::CodePage;
+use crate::sink::Sink;
+use crate::transcript;
+use crate::transport::{self, Mode};
+use serde::{Serialize, Deserialize};
+
+// Card reader and punch: 80 column card image at 0-79, status at 80, command at 84
+//
+// Status: .......R (..., Ready) for the punch; E.....HR (Error, ..., Hopper empty,
+// Ready) for the reader. Writing 1 to the command byte feeds (reader) or punches
+// (punch) one card; both complete immediately.
+
+pub const CARD_COLUMNS: usize = 80;
+pub const CARD_STATUS: u32 = 80;
+pub const CARD_COMMAND: u32 = 84;
+pub const CARD_REGION_SIZE: u32 = 88;
+
+pub const CARD_READY: u8 = 0b00000001;
+pub const CARD_HOPPER_EMPTY: u8 = 0b00000010;
+pub const CARD_ERROR: u8 = 0b10000000;
+
+#[derive(Serialize, Deserialize)]
+pub struct CardReader {
+	pub regs: Vec<u8>,
+	pub deck: Vec<Vec<u8>>,
+	pub next: usize,
+	pub codepage: CodePage,
+}
+
+impl CardReader {
+	pub fn new() -> CardReader {
+		let mut regs = vec![0 as u8; CARD_REGION_SIZE as usize];
+		regs[CARD_STATUS as usize] = CARD_HOPPER_EMPTY;
+		
+		CardReader {
+			regs: regs,
+			deck: Vec::new(),
+			next: 0,
+			codepage: CodePage::Latin1,
+		}
+	}
+	
+	// place a host text file in the hopper, one card per line
+	pub fn load_deck(&mut self, path: &str) -> io::Result<usize> {
+		let mut deck = transport::open(path, Mode::Read)?;
+		let mut text = vec![0 as u8; deck.size()? as usize];
+		deck.read_at(0, &mut text)?;
+		let text = String::from_utf8(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		Ok(self.load_cards(&text))
+	}
+	
+	pub fn load_cards(&mut self, text: &str) -> usize {
+		let space = self.codepage.encode(" ")[0];
+		
+		for line in text.lines() {
+			let mut card = self.codepage.encode(line);
+			card.resize(CARD_COLUMNS, space);
+			self.deck.push(card);
+		}
+		self.regs[CARD_STATUS as usize] &= !CARD_HOPPER_EMPTY;
+		text.lines().count()
+	}
+	
+	fn feed(&mut self) {
+		if self.next < self.deck.len() {
+			self.regs[0..CARD_COLUMNS].copy_from_slice(&self.deck[self.next]);
+			self.next += 1;
+			self.regs[CARD_STATUS as usize] = CARD_READY;
+		} else {
+			self.regs[CARD_STATUS as usize] = CARD_HOPPER_EMPTY;
+		}
+		if self.next >= self.deck.len() {
+			self.regs[CARD_STATUS as usize] |= CARD_HOPPER_EMPTY;
+		}
+	}
+	
+	fn command(&mut self, addr: u32, width: u32) {
+		if addr <= CARD_COMMAND && CARD_COMMAND < addr + width {
+			match self.regs[CARD_COMMAND as usize] {
+				0 => { },
+				1 => self.feed(),
+				_ => { self.regs[CARD_STATUS as usize] |= CARD_ERROR; },
+			}
+			self.regs[CARD_COMMAND as usize] = 0;
+		}
+	}
+}
+
+impl Memory32<u32, BusError> for CardReader {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.regs.read_b(addr)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h(addr)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h_big(addr)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		self.regs.read_w(addr)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		if addr < CARD_COMMAND {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_b(addr, data)?;
+		self.command(addr, 1);
+		Ok(())
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		if addr < CARD_COMMAND {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_h(addr, data)?;
+		self.command(addr, 2);
+		Ok(())
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		if addr < CARD_COMMAND {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_w(addr, data)?;
+		self.command(addr, 4);
+		Ok(())
+	}
+	
+	fn save_state(&self) -> Option<Vec<u8>> {
+		bincode::serialize(self).ok()
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		match bincode::deserialize(state) {
+			Err(_) => Err(BusError::InvalidState),
+			Ok(x) => {
+				*self = x;
+				Ok(())
+			},
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CardPunch {
+	pub regs: Vec<u8>,
+	pub codepage: CodePage,
+	#[serde(skip)]
+	pub output: Sink,
+}
+
+impl CardPunch {
+	pub fn new() -> CardPunch {
+		let mut regs = vec![0 as u8; CARD_REGION_SIZE as usize];
+		regs[CARD_STATUS as usize] = CARD_READY;
+		
+		CardPunch {
+			regs: regs,
+			codepage: CodePage::Latin1,
+			output: Sink::Frontend("PUNCH"),
+		}
+	}
+	
+	fn command(&mut self, addr: u32, width: u32) {
+		if addr <= CARD_COMMAND && CARD_COMMAND < addr + width {
+			match self.regs[CARD_COMMAND as usize] {
+				0 => { },
+				1 => {
+					let card = self.codepage.decode(&self.regs[0..CARD_COLUMNS]);
+					self.output.write_line(card.trim_end());
+					transcript::record("PUNCH", card.trim_end());
+					self.regs[CARD_STATUS as usize] = CARD_READY;
+				},
+				_ => { self.regs[CARD_STATUS as usize] |= CARD_ERROR; },
+			}
+			self.regs[CARD_COMMAND as usize] = 0;
+		}
+	}
+}
+
+impl Memory32<u32, BusError> for CardPunch {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.regs.read_b(addr)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h(addr)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h_big(addr)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		self.regs.read_w(addr)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		if addr == CARD_STATUS {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_b(addr, data)?;
+		self.command(addr, 1);
+		Ok(())
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		if addr == CARD_STATUS {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_h(addr, data)?;
+		self.command(addr, 2);
+		Ok(())
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		if addr == CARD_STATUS {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_w(addr, data)?;
+		self.command(addr, 4);
+		Ok(())
+	}
+	
+	fn save_state(&self) -> Option<Vec<u8>> {
+		bincode::serialize(&self.regs).ok()
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		match bincode::deserialize(state) {
+			Err(_) => Err(BusError::InvalidState),
+			Ok(x) => {
+				self.regs = x;
+				Ok(())
+			},
+		}
+	}
+}
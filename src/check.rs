@@ -0,0 +1,158 @@
+use std::path::Path;
+use crate::bus::Memory32;
+use crate::cpu::{PC, PS};
+use crate::diskimage::DiskImage;
+use crate::machine::Machine;
+use crate::options::Options;
+use crate::snapshot::Snapshot;
+use crate::symbols::SymbolTable;
+
+// Check: load everything the command line names, validate the result and print
+// the memory map, without starting the CPU or writing any output files
+//
+// Every problem found is listed, not just the first.
+
+pub const EXIT_PROBLEMS: i32 = 1;
+
+// SDT entries: base and limit words, key and flag bytes, two bytes spare
+const SDT_ENTRY: u32 = 12;
+
+fn load(machine: &mut Machine, opt: &Options, problems: &mut Vec<String>) {
+	if let Some(path) = &opt.restore {
+		match Snapshot::load(path) {
+			Ok(snap) => {
+				if let Err(e) = machine.restore(&snap) {
+					problems.push(format!("{}: {}", path, e));
+				}
+			},
+			Err(e) => problems.push(format!("{}: {}", path, e)),
+		}
+	}
+	for (path, addr) in &opt.load {
+		if let Err(e) = machine.load_file(path, *addr) {
+			problems.push(format!("{}: {}", path, e));
+		}
+	}
+	if let Some(path) = &opt.elf {
+		if let Err(e) = machine.load_elf(path) {
+			problems.push(format!("{}: {}", path, e));
+		}
+	}
+	if let Some(name) = &opt.example {
+		if let Err(e) = machine.load_example(name) {
+			problems.push(format!("{}: {}", name, e));
+		}
+	}
+	if let Some(name) = &opt.selftest_case {
+		if let Err(e) = machine.load_selftest(name) {
+			problems.push(format!("{}: {}", name, e));
+		}
+	}
+	if let Some(path) = &opt.deck {
+		if let Err(e) = machine.reader.lock().unwrap().load_deck(path) {
+			problems.push(format!("{}: {}", path, e));
+		}
+	}
+	if let Some(path) = &opt.dasd {
+		if let Err(e) = DiskImage::open(path, true) {
+			problems.push(format!("{}: {}", path, e));
+		}
+	}
+	if let Some(path) = &opt.map {
+		match SymbolTable::load(path) {
+			Ok(table) => machine.symbols = table,
+			Err(e) => problems.push(format!("{}: {}", path, e)),
+		}
+	}
+	
+	// output files are only created by a real run, so just check where they go
+	for path in [&opt.print_out, &opt.punch_out, &opt.coverage].iter().filter_map(|x| x.as_ref()) {
+		let dir = Path::new(path).parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+		if !dir.is_dir() {
+			problems.push(format!("{}: directory {} does not exist", path, dir.display()));
+		}
+	}
+}
+
+fn check_regions(machine: &Machine, problems: &mut Vec<String>) {
+	let attached = machine.bus.lock().unwrap().regions();
+	for pair in attached.windows(2) {
+		let (base, size) = pair[0];
+		if base as u64 + size as u64 > pair[1].0 as u64 {
+			problems.push(format!("regions at 0x{:08X} and 0x{:08X} overlap", base, pair[1].0));
+		}
+	}
+	for (base, _) in &attached {
+		if !machine.regions.iter().any(|r| r.base == *base) {
+			problems.push(format!("region at 0x{:08X} is missing from the memory map", base));
+		}
+	}
+	
+	let levels = machine.cpu.lock().unwrap().ipl.len();
+	for (n, r) in machine.regions.iter().enumerate() {
+		if let Some(ipl) = r.ipl {
+			if ipl == 0 || ipl >= levels {
+				problems.push(format!("{}: IPL {} is outside 1-{}", r.name, ipl, levels - 1));
+			}
+			if let Some(other) = machine.regions[..n].iter().find(|o| o.ipl == Some(ipl)) {
+				problems.push(format!("{}: IPL {} is already taken by the {}", r.name, ipl, other.name));
+			}
+		}
+	}
+}
+
+fn check_segments(machine: &Machine, problems: &mut Vec<String>) {
+	let cpu = machine.cpu.lock().unwrap();
+	let bus = machine.bus.lock().unwrap();
+	
+	for n in 0..16 {
+		if cpu.S_limit[n] < cpu.S_base[n] {
+			problems.push(format!("SSR{}: limit 0x{:08X} is below base 0x{:08X}", n, cpu.S_limit[n], cpu.S_base[n]));
+		}
+	}
+	
+	// the descriptor table, once one is set: every entry readable and in order
+	if cpu.SDTR_len != 0 {
+		for n in 0..=cpu.SDTR_len as u32 {
+			let addr = cpu.SDTR_base.wrapping_add(n * SDT_ENTRY);
+			match (bus.read_w(addr), bus.read_w(addr.wrapping_add(4)), bus.read_h(addr.wrapping_add(8))) {
+				(Ok(base), Ok(limit), Ok(_)) => {
+					if limit < base {
+						problems.push(format!("SDT entry {} at 0x{:08X}: limit 0x{:08X} is below base 0x{:08X}", n, addr, limit, base));
+					}
+				},
+				_ => problems.push(format!("SDT entry {} at 0x{:08X} is not readable", n, addr)),
+			}
+		}
+	}
+	
+	let start = cpu.R[PC].wrapping_add(cpu.S_base[PS]);
+	if bus.read_h_big(start).is_err() {
+		problems.push(format!("start address @{:08X}::{:08X} is not in memory", cpu.S_base[PS], cpu.R[PC]));
+	}
+}
+
+fn print_map(machine: &Machine) {
+	print!("{}", machine.memory_map());
+	let cpu = machine.cpu.lock().unwrap();
+	println!("START @{:08X}::{:08X}", cpu.S_base[PS], cpu.R[PC]);
+}
+
+pub fn run(machine: &mut Machine, opt: &Options) -> i32 {
+	let mut problems = Vec::new();
+	load(machine, opt, &mut problems);
+	check_regions(machine, &mut problems);
+	check_segments(machine, &mut problems);
+	print_map(machine);
+	
+	for p in &problems {
+		println!("CHECK: {}", p);
+	}
+	if problems.is_empty() {
+		println!("CHECK: OK");
+		0
+	} else {
+		println!("CHECK: {} PROBLEM{}", problems.len(), if problems.len() == 1 { "" } else { "S" });
+		EXIT_PROBLEMS
+	}
+}
@@ -0,0 +1,79 @@
+use std::{fs, io, time};
+use crate::snapshot::Snapshot;
+
+// Checkpoint: snapshots written while the guest runs, for recovery with --restore
+//
+// The CPU thread writes them between instructions, every so many cycles or
+// seconds. Files rotate: PATH.0 is always the newest and PATH.1 up to
+// PATH.(keep-1) the older ones. Each is written under a temporary name and
+// renamed into place, so a crash mid-write never costs the previous one.
+
+pub const CHECKPOINT_KEEP: u32 = 3;
+
+// how often the wall clock is looked at, in cycles
+const CLOCK_INTERVAL: u64 = 4096;
+
+pub struct Checkpoint {
+	pub path: String,
+	pub keep: u32,
+	pub every_cycles: Option<u64>,
+	pub every: Option<time::Duration>,
+	pub written: u64,
+	last_cycles: u64,
+	last_time: time::Instant
+}
+
+impl Checkpoint {
+	pub fn new(path: &str, keep: u32, every_cycles: Option<u64>, every: Option<time::Duration>) -> Checkpoint {
+		Checkpoint {
+			path: path.to_string(),
+			keep: keep.max(1),
+			every_cycles: every_cycles,
+			every: every,
+			written: 0,
+			last_cycles: 0,
+			last_time: time::Instant::now()
+		}
+	}
+	
+	// count from the start of this run, not from a restored cycle count
+	pub fn start(&mut self, cycles: u64) {
+		self.last_cycles = cycles;
+		self.last_time = time::Instant::now();
+	}
+	
+	pub fn due(&self, cycles: u64) -> bool {
+		let ran = cycles.wrapping_sub(self.last_cycles);
+		if self.every_cycles.map_or(false, |n| ran >= n) {
+			return true;
+		}
+		match self.every {
+			Some(t) => ran % CLOCK_INTERVAL == 0 && self.last_time.elapsed() >= t,
+			None => false,
+		}
+	}
+	
+	fn rotate(&self) -> io::Result<()> {
+		for n in (0..self.keep - 1).rev() {
+			let from = format!("{}.{}", self.path, n);
+			match fs::rename(&from, format!("{}.{}", self.path, n + 1)) {
+				Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+				_ => { },
+			}
+		}
+		Ok(())
+	}
+	
+	pub fn write(&mut self, snap: &Snapshot, cycles: u64) {
+		let temp = format!("{}.new", self.path);
+		let result = snap.save(&temp)
+			.and_then(|_| self.rotate())
+			.and_then(|_| fs::rename(&temp, format!("{}.0", self.path)));
+		match result {
+			Ok(_) => self.written += 1,
+			Err(e) => println!("CHECKPOINT FAILED: {}: {}", self.path, e),
+		}
+		self.last_cycles = cycles;
+		self.last_time = time::Instant::now();
+	}
+}
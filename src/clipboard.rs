@@ -0,0 +1,172 @@
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use crate::bus::{Memory32, BusError};
+use crate::charset::CodePage;
+use crate::transcript;
+
+// Clipboard: lets the guest put text on the host clipboard and take it back,
+// disabled unless --clipboard is given
+//
+// Registers (words): command at 0, length at 4, status at 8, text at
+// CLIP_BUFFER (CLIP_BUFFER_SIZE bytes, in the code page).
+//
+//   1 PUT  put the length register's bytes of the text on the host clipboard
+//   2 GET  take the host clipboard into the text, its length into the length
+//          register; longer text is cut to the buffer
+//
+// Commands complete at once. Status: 0 done, CLIP_FAILED if the host has no
+// clipboard program or it failed, CLIP_TRUNCATED if a GET was cut. The host
+// clipboard is reached through its usual programs: pbcopy and pbpaste, clip
+// and PowerShell, wl-copy and wl-paste under Wayland, or else xclip.
+
+pub const CLIP_BUFFER: u32 = 0x100;
+pub const CLIP_BUFFER_SIZE: u32 = 0xE00;
+pub const CLIP_REGION_SIZE: u32 = CLIP_BUFFER + CLIP_BUFFER_SIZE;
+
+pub const CLIP_PUT: u32 = 1;
+pub const CLIP_GET: u32 = 2;
+
+pub const CLIP_FAILED: u32 = 1;
+pub const CLIP_TRUNCATED: u32 = 2;
+
+pub struct Clipboard {
+	pub enabled: bool,
+	pub codepage: CodePage,
+	regs: Vec<u8>
+}
+
+// the program and arguments that copy to the clipboard, then those that paste
+fn programs() -> (&'static [&'static str], &'static [&'static str]) {
+	if cfg!(target_os = "macos") {
+		(&["pbcopy"], &["pbpaste"])
+	} else if cfg!(windows) {
+		(&["clip"], &["powershell", "-NoProfile", "-Command", "Get-Clipboard -Raw"])
+	} else if env::var_os("WAYLAND_DISPLAY").is_some() {
+		(&["wl-copy"], &["wl-paste", "--no-newline"])
+	} else {
+		(&["xclip", "-selection", "clipboard"], &["xclip", "-selection", "clipboard", "-o"])
+	}
+}
+
+fn copy(text: &str) -> std::io::Result<()> {
+	let program = programs().0;
+	let mut child = Command::new(program[0]).args(&program[1..])
+		.stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::null()).spawn()?;
+	child.stdin.take().unwrap().write_all(text.as_bytes())?;
+	if child.wait()?.success() {
+		Ok(())
+	} else {
+		Err(std::io::Error::new(std::io::ErrorKind::Other, format!("{} failed", program[0])))
+	}
+}
+
+fn paste() -> std::io::Result<String> {
+	let program = programs().1;
+	let out = Command::new(program[0]).args(&program[1..]).stdin(Stdio::null()).stderr(Stdio::null()).output()?;
+	if out.status.success() {
+		Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+	} else {
+		Err(std::io::Error::new(std::io::ErrorKind::Other, format!("{} failed", program[0])))
+	}
+}
+
+impl Clipboard {
+	pub fn new() -> Clipboard {
+		Clipboard {
+			enabled: false,
+			codepage: CodePage::Latin1,
+			regs: vec![0 as u8; CLIP_REGION_SIZE as usize]
+		}
+	}
+	
+	fn command(&mut self, command: u32) -> u32 {
+		let result = match command {
+			CLIP_PUT => {
+				let len = self.regs.read_w(4).unwrap().min(CLIP_BUFFER_SIZE) as usize;
+				let text = self.codepage.decode(&self.regs[CLIP_BUFFER as usize..CLIP_BUFFER as usize + len]);
+				copy(&text).map(|_| 0)
+			},
+			CLIP_GET => paste().map(|text| {
+				let data = self.codepage.encode(&text);
+				let len = data.len().min(CLIP_BUFFER_SIZE as usize);
+				self.regs[CLIP_BUFFER as usize..CLIP_BUFFER as usize + len].copy_from_slice(&data[..len]);
+				self.regs.write_w(4, len as u32).unwrap();
+				if len < data.len() { CLIP_TRUNCATED } else { 0 }
+			}),
+			_ => Ok(CLIP_FAILED),
+		};
+		result.unwrap_or_else(|e| {
+			transcript::record("CLIPBOARD", &e.to_string());
+			CLIP_FAILED
+		})
+	}
+	
+	// the length and text are the guest's
+	fn writable(&self, addr: u32, width: u32) -> bool {
+		self.enabled && ((addr >= 4 && addr + width <= 8) || addr >= CLIP_BUFFER)
+	}
+}
+
+impl Memory32<u32, BusError> for Clipboard {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		if !self.enabled {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.read_b(addr)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		if !self.enabled {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.read_h(addr)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		if !self.enabled {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.read_h_big(addr)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		if !self.enabled {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.read_w(addr)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		if !self.writable(addr, 1) {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_b(addr, data)
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		if !self.writable(addr, 2) {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_h(addr, data)
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		match addr {
+			0 if self.enabled => {
+				let status = self.command(data);
+				self.regs.write_w(8, status)
+			},
+			_ if self.writable(addr, 4) => self.regs.write_w(addr, data),
+			_ => Err(BusError::InvalidAddress),
+		}
+	}
+	
+	// the host clipboard is the host's; only the registers and text are saved
+	fn save_state(&self) -> Option<Vec<u8>> {
+		bincode::serialize(&self.regs).ok()
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		let regs: Vec<u8> = bincode::deserialize(state).map_err(|_| BusError::InvalidState)?;
+		if regs.len() != self.regs.len() {
+			return Err(BusError::InvalidState);
+		}
+		self.regs = regs;
+		Ok(())
+	}
+}
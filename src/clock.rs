@@ -0,0 +1,193 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::{thread, time};
+use crate::sync::{Condvar, Mutex};
+
+// Clock: the CPU clock, and the device clock domains reckoned from it
+//
+// A timed device has a clock domain running at a rate of its own choosing, and
+// counts its delays in that domain's ticks: the printer's is 1 kHz, a line
+// taking PRINT_TICKS of them. By default every domain runs on host time, and
+// waiting n ticks sleeps for n / rate seconds, as the devices always have.
+//
+// In deterministic mode (--deterministic) the CPU delivers the ticks instead:
+// every TOD_INTERVAL cycles it turns the cycles run into each domain's ticks at
+// the CPU clock (--cpu-hz, CPU_HZ by default), carrying the remainder over,
+// and a device waiting on its domain goes on once enough have come. Device
+// timing is then counted in instructions, the same from run to run, and
+// scales with the CPU clock setting: at twice the clock a printed line lets
+// the guest run twice as many instructions. While the CPU is stopped the
+// domains run on host time, so output under way when a run ends still comes
+// out.
+//
+// A device that works under the bus lock, or that the CPU waits on, must not
+// wait for delivered ticks, which only come while the CPU runs; it takes a
+// domain of its own with Domain::host, which always runs on host time.
+
+pub const CPU_HZ: u64 = 10_000_000;
+
+struct Mode {
+	deterministic: AtomicBool,
+	running: AtomicBool,		// the CPU is running, and delivering ticks
+	cpu_hz: AtomicU64
+}
+
+impl Mode {
+	fn delivered(&self) -> bool {
+		self.deterministic.load(Ordering::SeqCst) && self.running.load(Ordering::SeqCst)
+	}
+}
+
+struct Count {
+	ticks: u64,			// delivered since the domain was made
+	phase: u64			// CPU cycles times the rate not yet a whole tick
+}
+
+pub struct Domain {
+	pub name: &'static str,
+	pub hz: u64,
+	mode: Option<Arc<Mode>>,
+	count: Mutex<Count>,
+	arrived: Condvar
+}
+
+impl Domain {
+	// a domain the CPU never delivers ticks to
+	pub fn host(name: &'static str, hz: u64) -> Arc<Domain> {
+		Arc::new(Domain::new(name, hz, None))
+	}
+	
+	fn new(name: &'static str, hz: u64, mode: Option<Arc<Mode>>) -> Domain {
+		Domain {
+			name: name,
+			hz: hz.max(1),
+			mode: mode,
+			count: Mutex::new(Count { ticks: 0, phase: 0 }),
+			arrived: Condvar::new()
+		}
+	}
+	
+	fn delivered(&self) -> bool {
+		self.mode.as_ref().map_or(false, |m| m.delivered())
+	}
+	
+	fn sleep(&self, ticks: u64) {
+		thread::sleep(time::Duration::from_nanos((ticks as u128 * 1_000_000_000 / self.hz as u128) as u64));
+	}
+	
+	pub fn ticks(&self) -> u64 {
+		self.count.lock().unwrap().ticks
+	}
+	
+	// the ticks cycles of the CPU at cpu_hz make, keeping what is left over
+	fn ticks_in(&self, cycles: u64, cpu_hz: u64) -> u64 {
+		let mut count = self.count.lock().unwrap();
+		let total = count.phase as u128 + cycles as u128 * self.hz as u128;
+		count.phase = (total % cpu_hz as u128) as u64;
+		(total / cpu_hz as u128) as u64
+	}
+	
+	// the scheduler's side: n more ticks have passed
+	pub fn tick(&self, n: u64) {
+		let mut count = self.count.lock().unwrap();
+		count.ticks += n;
+		self.arrived.notify_all();
+	}
+	
+	// the device's side: let n ticks pass
+	pub fn wait(&self, n: u64) {
+		let mut count = self.count.lock().unwrap();
+		let until = count.ticks + n;
+		while count.ticks < until {
+			if !self.delivered() {
+				let left = until - count.ticks;
+				drop(count);
+				self.sleep(left);
+				return;
+			}
+			count = self.arrived.wait(count).unwrap();
+		}
+	}
+	
+	// wake a device waiting for ticks that will no longer come
+	fn release(&self) {
+		let _count = self.count.lock().unwrap();
+		self.arrived.notify_all();
+	}
+}
+
+// the scheduler: the machine's domains and the CPU clock
+
+pub struct Clocks {
+	mode: Arc<Mode>,
+	domains: Mutex<Vec<Arc<Domain>>>
+}
+
+impl Clocks {
+	pub fn new() -> Clocks {
+		Clocks {
+			mode: Arc::new(Mode {
+				deterministic: AtomicBool::new(false),
+				running: AtomicBool::new(false),
+				cpu_hz: AtomicU64::new(CPU_HZ)
+			}),
+			domains: Mutex::new(Vec::new())
+		}
+	}
+	
+	// a device's domain, running at hz
+	pub fn domain(&self, name: &'static str, hz: u64) -> Arc<Domain> {
+		let domain = Arc::new(Domain::new(name, hz, Some(Arc::clone(&self.mode))));
+		self.domains.lock().unwrap().push(Arc::clone(&domain));
+		domain
+	}
+	
+	pub fn set_deterministic(&self, on: bool) {
+		self.mode.deterministic.store(on, Ordering::SeqCst);
+		self.release();
+	}
+	
+	pub fn deterministic(&self) -> bool {
+		self.mode.deterministic.load(Ordering::SeqCst)
+	}
+	
+	pub fn set_cpu_hz(&self, hz: u64) {
+		self.mode.cpu_hz.store(hz.max(1), Ordering::SeqCst);
+	}
+	
+	pub fn cpu_hz(&self) -> u64 {
+		self.mode.cpu_hz.load(Ordering::SeqCst)
+	}
+	
+	pub fn domains(&self) -> Vec<Arc<Domain>> {
+		self.domains.lock().unwrap().clone()
+	}
+	
+	// the CPU starting or stopping
+	pub fn run(&self, running: bool) {
+		self.mode.running.store(running, Ordering::SeqCst);
+		if !running {
+			self.release();
+		}
+	}
+	
+	fn release(&self) {
+		for d in self.domains.lock().unwrap().iter() {
+			d.release();
+		}
+	}
+	
+	// called by the CPU with the cycles run since the last call
+	pub fn advance(&self, cycles: u64) {
+		if !self.deterministic() {
+			return;
+		}
+		let cpu_hz = self.cpu_hz();
+		for d in self.domains.lock().unwrap().iter() {
+			let ticks = d.ticks_in(cycles, cpu_hz);
+			if ticks != 0 {
+				d.tick(ticks);
+			}
+		}
+	}
+}
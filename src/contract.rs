@@ -0,0 +1,319 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use crate::sync::Mutex;
+use crate::bus::{Memory32, BusError};
+use crate::machine::Machine;
+
+// Contract: what a device region promises the bus, and checks that it keeps it
+//
+//   NO_PANIC   an access, save_state or load_state returns rather than panics
+//   NO_STATE   reads and writes fail with InvalidAddress or AlignmentCheck;
+//              InvalidState is for load_state alone
+//   BYTES      a byte access never fails with AlignmentCheck
+//   UNALIGNED  a halfword or word access off its boundary fails with
+//              AlignmentCheck, which the bus's alignment fixup depends on
+//   PAST_END   an access past the end of the region fails with InvalidAddress
+//   STABLE     reading an address the region declares idempotent twice gives
+//              the same value both times
+//   ROUND_TRIP loading the state the region saved gives back the same state
+//   GARBAGE    loading state it didn't save fails with InvalidState, if it
+//              fails at all
+//
+// A Contract goes around a region and checks each access the bus makes
+// through it, reporting a rule the first time the region breaks it. With
+// --contracts the machine puts one around every device, so a new device gets
+// found out by whatever the guest does to it. --check-devices goes further: it puts each
+// device of a machine nothing has run on through every width of read at every
+// address, writes that must be refused, state round trips and state that won't
+// load, reports on each device and exits 1 if any broke the contract. A device
+// declares the addresses whose reads are idempotent with Memory32::idempotent.
+
+pub const EXIT_BROKEN: i32 = 1;
+
+const NO_PANIC: u32 = 0;
+const NO_STATE: u32 = 1;
+const BYTES: u32 = 2;
+const UNALIGNED: u32 = 3;
+const PAST_END: u32 = 4;
+const STABLE: u32 = 5;
+const ROUND_TRIP: u32 = 6;
+const GARBAGE_STATE: u32 = 7;
+
+const RULES: &[&str] = &["NO_PANIC", "NO_STATE", "BYTES", "UNALIGNED", "PAST_END", "STABLE", "ROUND_TRIP", "GARBAGE"];
+
+// problems listed for one device before the rest are only counted
+const SHOW_PROBLEMS: usize = 8;
+
+// state that can't be anyone's saved state
+const GARBAGE: &[u8] = &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+
+type Region = Arc<Mutex<dyn Memory32<u32, BusError> + Send>>;
+
+// one access's outcome, widened so every width can be judged alike
+type Outcome = Result<Result<u32, BusError>, String>;
+
+// f's result, or what it panicked with
+fn guard<U, F: FnOnce() -> U>(f: F) -> Result<U, String> {
+	panic::catch_unwind(AssertUnwindSafe(f)).map_err(|e| {
+		e.downcast_ref::<&str>().map(|x| x.to_string())
+			.or_else(|| e.downcast_ref::<String>().cloned())
+			.unwrap_or_else(|| "a panic".to_string())
+	})
+}
+
+fn describe(result: &Result<u32, BusError>) -> String {
+	match result {
+		Ok(x) => format!("Ok({:X})", x),
+		Err(e) => format!("{:?}", e),
+	}
+}
+
+// the rule an access's outcome breaks, and how, if it does
+fn judge(what: &str, addr: u32, width: u32, size: u32, outcome: &Outcome) -> Option<(u32, String)> {
+	let at = format!("{} at {:X}", what, addr);
+	let result = match outcome {
+		Err(why) => return Some((NO_PANIC, format!("{} panicked: {}", at, why))),
+		Ok(x) => x,
+	};
+	match result {
+		Err(BusError::InvalidState) => Some((NO_STATE, format!("{} failed with InvalidState", at))),
+		Err(BusError::AlignmentCheck) if width == 1 => Some((BYTES, format!("{} failed with AlignmentCheck", at))),
+		Err(BusError::InvalidAddress) => None,
+		Err(BusError::AlignmentCheck) if addr % width != 0 => None,
+		_ if addr as u64 + width as u64 > size as u64 =>
+			Some((PAST_END, format!("{} is past the end but gave {}", at, describe(result)))),
+		_ if addr % width != 0 && !matches!(result, Err(BusError::AlignmentCheck)) =>
+			Some((UNALIGNED, format!("{} is unaligned but gave {}", at, describe(result)))),
+		_ => None,
+	}
+}
+
+fn read(region: &Region, width: u32, addr: u32) -> Outcome {
+	guard(|| {
+		let r = region.lock().unwrap_or_else(|e| e.into_inner());
+		match width {
+			1 => r.read_b(addr).map(|x| x as u32),
+			2 => r.read_h(addr).map(|x| x as u32),
+			_ => r.read_w(addr),
+		}
+	})
+}
+
+fn write(region: &Region, width: u32, addr: u32) -> Outcome {
+	guard(|| {
+		let mut r = region.lock().unwrap_or_else(|e| e.into_inner());
+		match width {
+			1 => r.write_b(addr, 0),
+			2 => r.write_h(addr, 0),
+			_ => r.write_w(addr, 0),
+		}.map(|_| 0)
+	})
+}
+
+// the bus only passes a region addresses inside it, so around one PAST_END
+// never comes up and the region can change size under it
+pub struct Contract {
+	name: &'static str,
+	device: Region,
+	broken: AtomicU32		// a bit for each rule reported
+}
+
+impl Contract {
+	pub fn new(name: &'static str, device: Region) -> Contract {
+		Contract {
+			name: name,
+			device: device,
+			broken: AtomicU32::new(0)
+		}
+	}
+	
+	fn report(&self, rule: u32, why: String) {
+		if self.broken.fetch_or(1 << rule, Ordering::Relaxed) & 1 << rule == 0 {
+			println!("CONTRACT: {} BROKE {}: {}", self.name.to_uppercase(), RULES[rule as usize], why);
+		}
+	}
+	
+	fn check(&self, what: &str, addr: u32, width: u32, outcome: Outcome) -> Result<u32, BusError> {
+		if let Some((rule, why)) = judge(what, addr, width, u32::MAX, &outcome) {
+			self.report(rule, why);
+		}
+		// a panicking device reads as nothing there
+		outcome.unwrap_or(Err(BusError::InvalidAddress))
+	}
+	
+	fn read(&self, what: &str, width: u32, addr: u32) -> Result<u32, BusError> {
+		let outcome = read(&self.device, width, addr);
+		if self.device.lock().unwrap_or_else(|e| e.into_inner()).idempotent(addr) {
+			let again = read(&self.device, width, addr);
+			if matches!((&outcome, &again), (Ok(Ok(x)), Ok(Ok(y))) if x != y) {
+				self.report(STABLE, format!("{} at {:X} read {} then {}", what, addr,
+					describe(outcome.as_ref().unwrap()), describe(again.as_ref().unwrap())));
+			}
+		}
+		self.check(what, addr, width, outcome)
+	}
+}
+
+impl Memory32<u32, BusError> for Contract {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.read("read_b", 1, addr).map(|x| x as u8)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.read("read_h", 2, addr).map(|x| x as u16)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		let outcome = guard(|| self.device.lock().unwrap_or_else(|e| e.into_inner()).read_h_big(addr).map(|x| x as u32));
+		self.check("read_h_big", addr, 2, outcome).map(|x| x as u16)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		self.read("read_w", 4, addr)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		let outcome = guard(|| self.device.lock().unwrap_or_else(|e| e.into_inner()).write_b(addr, data).map(|_| 0));
+		self.check("write_b", addr, 1, outcome).map(|_| ())
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		let outcome = guard(|| self.device.lock().unwrap_or_else(|e| e.into_inner()).write_h(addr, data).map(|_| 0));
+		self.check("write_h", addr, 2, outcome).map(|_| ())
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		let outcome = guard(|| self.device.lock().unwrap_or_else(|e| e.into_inner()).write_w(addr, data).map(|_| 0));
+		self.check("write_w", addr, 4, outcome).map(|_| ())
+	}
+	
+	fn save_state(&self) -> Option<Vec<u8>> {
+		match guard(|| self.device.lock().unwrap_or_else(|e| e.into_inner()).save_state()) {
+			Ok(x) => x,
+			Err(why) => {
+				self.report(NO_PANIC, format!("save_state panicked: {}", why));
+				None
+			},
+		}
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		match guard(|| self.device.lock().unwrap_or_else(|e| e.into_inner()).load_state(state)) {
+			Ok(x) => x,
+			Err(why) => {
+				self.report(NO_PANIC, format!("load_state panicked: {}", why));
+				Err(BusError::InvalidState)
+			},
+		}
+	}
+	fn drain(&self) {
+		self.device.lock().unwrap_or_else(|e| e.into_inner()).drain()
+	}
+	fn idempotent(&self, addr: u32) -> bool {
+		self.device.lock().unwrap_or_else(|e| e.into_inner()).idempotent(addr)
+	}
+}
+
+fn problem(rule: u32, why: String) -> String {
+	format!("{} {}", RULES[rule as usize], why)
+}
+
+// a device that has panicked may have left its own locks poisoned, and what it
+// does after that says nothing more about it
+const GAVE_UP: &str = "NO FURTHER CHECKS AFTER A PANIC";
+
+fn save(region: &Region) -> Result<Option<Vec<u8>>, String> {
+	guard(|| region.lock().unwrap_or_else(|e| e.into_inner()).save_state())
+}
+
+fn load(region: &Region, state: &[u8]) -> Result<Result<(), BusError>, String> {
+	guard(|| region.lock().unwrap_or_else(|e| e.into_inner()).load_state(state))
+}
+
+// every problem one device has, by putting it through every check
+fn exercise(region: &Region, size: u32) -> Vec<String> {
+	let mut problems = Vec::new();
+	let past = [size, size + 1, size + 4, 0xFFFFFFFC];
+	for width in [1, 2, 4] {
+		let what = ["read_b", "read_h", "", "read_w"][width as usize - 1];
+		for addr in (0..size).chain(past) {
+			let outcome = read(region, width, addr);
+			if let Some((rule, why)) = judge(what, addr, width, size, &outcome) {
+				problems.push(problem(rule, why));
+			}
+			let idempotent = guard(|| region.lock().unwrap_or_else(|e| e.into_inner()).idempotent(addr)).unwrap_or(false);
+			if let (true, Ok(Ok(x)), Ok(Ok(y))) = (idempotent, &outcome, &read(region, width, addr)) {
+				if x != y {
+					problems.push(problem(STABLE, format!("{} at {:X} read {:X} then {:X}", what, addr, x, y)));
+				}
+			}
+		}
+	}
+	
+	// writes only where a region must refuse them, so its registers are left alone
+	for width in [1, 2, 4] {
+		let what = ["write_b", "write_h", "", "write_w"][width as usize - 1];
+		let unaligned = (1..size.min(8)).filter(|x| width > 1 && x % width != 0);
+		for addr in unaligned.chain(past) {
+			if let Some((rule, why)) = judge(what, addr, width, size, &write(region, width, addr)) {
+				problems.push(problem(rule, why));
+				if rule == NO_PANIC {
+					problems.push(GAVE_UP.to_string());
+					return problems;
+				}
+			}
+		}
+	}
+	
+	let state = match save(region) {
+		Err(why) => {
+			problems.push(problem(NO_PANIC, format!("save_state panicked: {}", why)));
+			return problems;
+		},
+		Ok(None) => return problems,
+		Ok(Some(x)) => x,
+	};
+	match load(region, &state) {
+		Err(why) => problems.push(problem(NO_PANIC, format!("load_state of its own state panicked: {}", why))),
+		Ok(Err(e)) => problems.push(problem(ROUND_TRIP, format!("load_state of its own state failed with {:?}", e))),
+		Ok(Ok(_)) => {
+			if !matches!(save(region), Ok(Some(x)) if x == state) {
+				problems.push(problem(ROUND_TRIP, "save_state after loading its own state gave something else".to_string()));
+			}
+		},
+	}
+	match load(region, GARBAGE) {
+		Err(why) => problems.push(problem(NO_PANIC, format!("load_state of garbage panicked: {}", why))),
+		Ok(Err(BusError::InvalidState)) | Ok(Ok(_)) => { },
+		Ok(Err(e)) => problems.push(problem(GARBAGE_STATE, format!("load_state of garbage failed with {:?}", e))),
+	}
+	// whatever the garbage did, leave the device as it was
+	load(region, &state).ok();
+	problems
+}
+
+// --check-devices
+pub fn run(machine: &Machine) -> i32 {
+	// a panic is reported as a problem, not by the panic hook
+	let hook = panic::take_hook();
+	panic::set_hook(Box::new(|_| { }));
+	let mut broken = 0;
+	let devices: Vec<_> = machine.regions.iter().filter(|r| r.backing == "device").collect();
+	for r in &devices {
+		let region = match machine.bus.lock().unwrap().region_at(r.base) {
+			Some(x) => x,
+			None => continue,
+		};
+		let problems = exercise(&region, r.size);
+		if problems.is_empty() {
+			println!("DEVCHECK: PASS {}", r.name);
+			continue;
+		}
+		broken += 1;
+		println!("DEVCHECK: FAIL {}", r.name);
+		for p in problems.iter().take(SHOW_PROBLEMS) {
+			println!("  {}", p);
+		}
+		if problems.len() > SHOW_PROBLEMS {
+			println!("  AND {} MORE", problems.len() - SHOW_PROBLEMS);
+		}
+	}
+	panic::set_hook(hook);
+	println!("DEVCHECK: {} PASSED, {} FAILED", devices.len() - broken, broken);
+	if broken > 0 { EXIT_BROKEN } else { 0 }
+}
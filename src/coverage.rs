@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+use crate::symbols::SymbolTable;
+
+// Coverage: how often each guest instruction address was executed
+//
+// Addresses are PC values, which match the assembler's locations as long as
+// the code runs in the segment it was assembled for.
+
+#[derive(Default)]
+pub struct Coverage {
+	counts: HashMap<u32, u64>
+}
+
+impl Coverage {
+	pub fn record(&mut self, pc: u32) {
+		*self.counts.entry(pc).or_insert(0) += 1;
+	}
+	
+	// the map's instructions annotated with execution counts; ##### marks
+	// instructions that never ran, and the untested runs are listed at the end
+	pub fn report(&self, symbols: &SymbolTable) -> String {
+		let mut lines: Vec<_> = symbols.lines.iter().collect();
+		lines.sort_by_key(|l| l.addr);
+		
+		let executed = lines.iter().filter(|l| self.counts.contains_key(&l.addr)).count();
+		let percent = if lines.is_empty() { 0.0 } else { 100.0 * executed as f64 / lines.len() as f64 };
+		let mut out = format!("GUEST COVERAGE: {} OF {} INSTRUCTIONS EXECUTED ({:.1}%)\n\n", executed, lines.len(), percent);
+		out.push_str(&format!("{:>10}  {:<8}  {:<20} {}\n", "COUNT", "LOC", "STATEMENT", "SOURCE"));
+		
+		let mut untested: Vec<(u32, u32)> = Vec::new();
+		for l in &lines {
+			let count = match self.counts.get(&l.addr) {
+				Some(n) => n.to_string(),
+				None => {
+					match untested.last_mut() {
+						Some((_, end)) if *end == l.addr => *end = l.addr + l.len,
+						_ => untested.push((l.addr, l.addr + l.len)),
+					}
+					"#####".to_string()
+				},
+			};
+			out.push_str(&format!("{:>10}  {:08X}  {:<20} {}\n", count, l.addr, l.place, l.text));
+		}
+		
+		if !untested.is_empty() {
+			out.push_str("\nNEVER EXECUTED\n");
+			for (start, end) in untested {
+				out.push_str(&format!("  {:08X}-{:08X}  {}\n", start, end - 1, symbols.describe(start)));
+			}
+		}
+		out
+	}
+}
@@ -1,7 +1,35 @@
-use std::sync::{Arc, Mutex, Condvar};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+use crate::sync::{Mutex, Condvar};
 use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::{thread, time};
-use crate::bus::{Bus, Channel, Memory32, BusError};
+use crate::bus::{self, Bus, Channel, Memory32, BusError};
+use crate::breakpoint::Breakpoints;
+use crate::watch::Watches;
+use crate::selwatch::SelectorWatch;
+use crate::hooks::{Decoded, Hooks, When};
+use crate::coverage::Coverage;
+use crate::trace::{TraceEntry, TraceLog, TraceRing};
+use crate::watchdog::{self, Heartbeat};
+use crate::prefetch::Prefetch;
+use crate::checkpoint::Checkpoint;
+use crate::crash;
+use crate::profiler::Profiler;
+use crate::clock::Clocks;
+use crate::fault::{Fault, Stage};
+use crate::isa;
+use crate::snapshot::Snapshot;
+use crate::simd;
+use crate::decimal::{self, DecimalError, DECIMAL_SIZE};
+use crate::fpu::{self, FP_FLAGS};
+use crate::tod::{self, Tod, TOD_IPL, TOD_INTERVAL};
+use crate::attention::{ATTENTION_IPL, ATTENTION_CODE};
+use crate::irqstorm::{Storm, STORM_CODE};
+use crate::frontend::{Registers, Shown};
+#[cfg(feature = "crypto")]
+use crate::crypto;
+use serde::{Serialize, Deserialize};
 
 pub const PC: usize = 15;
 pub const LR: usize = 14;
@@ -19,6 +47,28 @@ pub const READ_ALIGN: i32 = -7;
 pub const READ_ADDR: i32 = -8;
 pub const WRITE_ALIGN: i32 = -9;
 pub const WRITE_ADDR: i32 = -10;
+pub const STACK_OVERFLOW: i32 = -11;
+pub const DIVIDE_BY_ZERO: i32 = -12;
+pub const DIVIDE_OVERFLOW: i32 = -13;
+pub const DECIMAL_OVERFLOW: i32 = -14;
+pub const DECIMAL_DATA: i32 = -15;
+
+// STCTX/LDCTX context block: R0-R15 as words at 0, F0-F15 at 64, the 16
+// segment registers at 80 (12 bytes each, as base, limit, key, flags, selector
+// and a pad byte), MPK0-MPK15 at 272 and FP0-FP15 as words at 288
+pub const CONTEXT_SIZE: usize = 352;
+
+// the PEBA and PLBA tables hold a 16 byte block for each of 8 priority levels
+pub const PRIORITY_TABLE_SIZE: u32 = 16 * 8;
+
+// a segment descriptor as SSEL and SSELHC load it, less the selector
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Descriptor {
+	pub base: u32,
+	pub limit: u32,
+	pub key: u8,
+	pub flags: u8
+}
 
 // functions for instruction decode
 fn rr_reg_d(iword: u16) -> usize {
@@ -50,22 +100,58 @@ pub struct SeriesQ {
 	pub S_base: [u32; 16],
 	pub S_limit: [u32; 16],
 	pub S_key: [u8; 16],
-	pub S_flags: [u8; 16], // .......U (..., Unsigned RM Offsets)
+	pub S_flags: [u8; 16], // RWXG...U (Read, Write, eXecute, Guard if RWX clear, ..., Unsigned RM Offsets)
 	
 	pub MPK: [u8; 16],
 	
 	pub F: [u8; 16], // F0: PLGEVCSB; F8: .F__P__A (..., Fault Priority Level, Current Priority Level, Application State)
+					 // F9: unaligned accesses fixed up (saturating, in alignment fixup mode)
 					 // F10, F11: Fault Instruction; F12-F15: Fault Address
+					 // F1: ...IZOUX, the FP exceptions (see fpu.rs)
+	
+	pub FP: [u32; 16], // binary32 values, as their bits
 	
 	pub SDTR_base: u32,
 	pub SDTR_len: u8,
+	pub descriptors: BTreeMap<u8, Descriptor>, // descriptor cache, by selector
 	
 	pub PEBA_base: u32,
 	pub PLBA_base: u32,
 	
+	pub tod: Tod,
+	
 	pub running: Arc<AtomicBool>,
 	pub waiting: Arc<AtomicBool>,
+	pub skip: bool,
 	pub cycles: u64,
+	pub cycle_limit: u64, // stop once cycles reaches this; 0 for no limit
+	pub dma_spacing: u64, // instructions between DMA grant rounds, at least 1
+	pub dma_grants: u64,
+	last_grant: u64,
+	pub breakpoints: Breakpoints,
+	pub watches: Watches,
+	pub hooks: Hooks,
+	pub taint: Option<HashSet<u32>>, // taint mode: uninitialized addresses already reported
+	pub smc: Option<HashSet<u32>>, // self-modifying code mode: stores into code already reported
+	pub selectors: Option<SelectorWatch>,
+	pub coverage: Option<Coverage>,
+	pub trace: Arc<TraceRing>,
+	pub trace_log: Option<Arc<TraceLog>>,
+	pub heartbeat: Arc<Heartbeat>,
+	pub prefetch: Prefetch,
+	pub checkpoint: Option<Checkpoint>,
+	pub profiler: Option<Arc<Mutex<Profiler>>>,
+	pub clocks: Option<Arc<Clocks>>,		// the device clock domains, ticked every TOD_INTERVAL cycles
+	pub stage: Stage,
+	pub last_fault: Option<Fault>,
+	fault_addr: Option<u32>, // for the next fault raised, when it concerns an address
+	pub assert_access: bool, // check every guest bus access against access_check
+	pub align_fixup: bool, // make unaligned loads and stores a byte at a time instead of faulting
+	pub low_protect: u32, // application-state stores below this physical address fault
+	pub supervisor_only: Vec<(u32, u32)>, // physical base and size of regions application state can't access
+	pub yield_stop: bool, // YIELD stops the CPU, handing control back to the host
+	approved: RefCell<Vec<(usize, u32)>>,
+	guard_hit: Cell<Option<u32>>, // offset of the last access refused for a guard segment
 	
 	pub bus: Arc<Mutex<Bus>>,
 	pub channels: Vec<Channel<Bus>>,
@@ -74,6 +160,47 @@ pub struct SeriesQ {
 	
 	pub faultpl: Vec<Arc<AtomicBool>>,
 	pub faultcode: Vec<Arc<AtomicU8>>,
+	
+	pub attention: Arc<AtomicBool>, // the operator's attention key, lowered when taken
+	pub attention_ipl: usize,
+	pub storm: Option<Arc<Storm>>, // synthetic interrupts, ORed with the device lines
+	pub shown: Option<Shown>, // registers copied out for the frontend
+}
+
+// CpuState: architectural state of a SeriesQ, minus host wiring (bus, channels)
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CpuState {
+	pub R: [u32; 16],
+	
+	pub S_selector: [u8; 16],
+	pub S_base: [u32; 16],
+	pub S_limit: [u32; 16],
+	pub S_key: [u8; 16],
+	pub S_flags: [u8; 16],
+	
+	pub MPK: [u8; 16],
+	pub F: [u8; 16],
+	pub FP: [u32; 16],
+	
+	pub SDTR_base: u32,
+	pub SDTR_len: u8,
+	pub descriptors: BTreeMap<u8, Descriptor>,
+	
+	pub PEBA_base: u32,
+	pub PLBA_base: u32,
+	
+	pub tod: Tod,
+	
+	pub waiting: bool,
+	pub skip: bool,
+	pub cycles: u64,
+	
+	pub ipl: [bool; 8],
+	pub icode: [u8; 8],
+	pub faultpl: [bool; 8],
+	pub faultcode: [u8; 8],
 }
 
 fn sign_u32(x: u32) -> bool {
@@ -304,6 +431,134 @@ fn alu_sub(dest: u32, src: u32, flags: u8, use_carry: bool) -> (u32, u8) {
 	(y, new_flags)
 }
 
+fn alu_mul(dest: u32, src: u32, flags: u8, signed: bool) -> (u32, u8) {
+	let (y, overflow) = if signed {
+		let (x, overflow) = (dest as i32).overflowing_mul(src as i32);
+		(x as u32, overflow)
+	} else {
+		dest.overflowing_mul(src)
+	};
+	
+	let mut new_flags = flags;
+	// PLGEVCSB
+	if y & 1 == 1 {
+		// odd
+		new_flags |= 0b10000000;
+	} else {
+		// even
+		new_flags &= 0b01111111;
+	}
+	
+	if overflow {
+		// the product doesn't fit in a word
+		new_flags |= 0b00001100;
+	} else {
+		new_flags &= 0b11110011;
+	}
+	
+	(y, new_flags)
+}
+
+// the whole product, which always fits, so only P changes and V and C clear
+fn alu_mul_wide(dest: u32, src: u32, flags: u8, signed: bool) -> (u64, u8) {
+	let y = if signed {
+		(dest as i32 as i64 * src as i32 as i64) as u64
+	} else {
+		dest as u64 * src as u64
+	};
+	
+	let mut new_flags = flags;
+	// PLGEVCSB
+	if y & 1 == 1 {
+		// odd
+		new_flags |= 0b10000000;
+	} else {
+		// even
+		new_flags &= 0b01111111;
+	}
+	new_flags &= 0b11110011;
+	
+	(y, new_flags)
+}
+
+// None for a zero divisor
+fn alu_div(dest: u32, src: u32, flags: u8, signed: bool) -> Option<(u32, u8)> {
+	if src == 0 {
+		return None;
+	}
+	let (y, overflow) = if signed {
+		// only 0x80000000 / -1 overflows; the quotient wraps to 0x80000000
+		let (x, overflow) = (dest as i32).overflowing_div(src as i32);
+		(x as u32, overflow)
+	} else {
+		(dest / src, false)
+	};
+	
+	let mut new_flags = flags;
+	// PLGEVCSB
+	if y & 1 == 1 {
+		// odd
+		new_flags |= 0b10000000;
+	} else {
+		// even
+		new_flags &= 0b01111111;
+	}
+	
+	if overflow {
+		new_flags |= 0b00001000;
+	} else {
+		new_flags &= 0b11110111;
+	}
+	
+	Some((y, new_flags))
+}
+
+// signed quotient and remainder, the quotient toward zero or, with floor, toward
+// minus infinity so the remainder takes the divisor's sign; the fault code for a
+// zero divisor or 0x80000000 / -1, whose quotient doesn't fit
+fn alu_divr(dest: u32, src: u32, flags: u8, floor: bool) -> Result<(u32, u32, u8), i32> {
+	if src == 0 {
+		return Err(DIVIDE_BY_ZERO);
+	}
+	let (dividend, divisor) = (dest as i32, src as i32);
+	let (mut q, overflow) = dividend.overflowing_div(divisor);
+	if overflow {
+		return Err(DIVIDE_OVERFLOW);
+	}
+	let mut r = dividend % divisor;
+	if floor && r != 0 && (r < 0) != (divisor < 0) {
+		q -= 1;
+		r += divisor;
+	}
+	
+	let mut new_flags = flags;
+	// PLGEVCSB
+	if q & 1 == 1 {
+		// odd
+		new_flags |= 0b10000000;
+	} else {
+		// even
+		new_flags &= 0b01111111;
+	}
+	new_flags &= 0b11110111;
+	
+	Ok((q as u32, r as u32, new_flags))
+}
+
+// L, G or E for a decimal result below, above or at zero
+fn decimal_flags(y: i64, flags: u8) -> u8 {
+	let mut new_flags = flags & 0b10001111;
+	// PLGEVCSB
+	if y < 0 {
+		new_flags |= 0b01000000;
+	} else if y > 0 {
+		new_flags |= 0b00100000;
+	} else {
+		new_flags |= 0b00010000;
+	}
+	new_flags
+}
+
 pub trait SQAddr {
 	fn gen_offset_rm(&self, reg_segment: usize, reg_base: usize, index: u16) -> u32;
 	fn gen_offset_rmx(&self, reg_segment: usize, reg_base: usize, reg_offset: usize, index: u8) -> u32;
@@ -350,6 +605,23 @@ impl SQAddr for SeriesQ	{
 	}
 	
 	fn access_check(&self, segment: usize, addr: u32, write: bool, exec: bool) -> bool {
+		// nothing may touch a guard segment, in either state; one grants no
+		// access, so the all-ones flags segments start with are not guards
+		if self.S_flags[segment] & 0b11110000 == 0b00010000 {
+			self.guard_hit.set(Some(addr.wrapping_sub(self.S_base[segment])));
+			return false;
+		}
+		self.guard_hit.set(None);
+		let allowed = self.access_allowed(segment, addr, write, exec);
+		if allowed && self.assert_access {
+			self.approved.borrow_mut().push((segment, addr));
+		}
+		allowed
+	}
+}
+
+impl SeriesQ {
+	fn access_allowed(&self, segment: usize, addr: u32, write: bool, exec: bool) -> bool {
 		let segment_check = (self.MPK.contains(&self.S_key[segment]) || &self.F[8] & 1 == 0)
 			&& addr >= self.S_base[segment]
 			&& addr < self.S_limit[segment];
@@ -359,8 +631,11 @@ impl SQAddr for SeriesQ	{
 		let exec_allowed = (self.S_flags[segment] & 0b00100000 != 0);
 		
 		if &self.F[8] & 1 != 0 { // if application state
+			if self.supervisor_only.iter().any(|&(base, size)| addr >= base && addr - base < size) {
+				return false;
+			}
 			if write {
-				segment_check && write_allowed
+				segment_check && write_allowed && addr >= self.low_protect
 			} else if exec {
 				segment_check && exec_allowed
 			} else {
@@ -372,7 +647,59 @@ impl SQAddr for SeriesQ	{
 	}
 }
 
+// the name of the thread SeriesQ::run starts
+pub const CPU_THREAD: &str = "cpu";
+
+// dumps the trace ring and the machine state if the CPU thread panics;
+// declared before the CPU guard so it runs after the guard has released (and
+// poisoned) the lock
+struct PanicDump(Arc<Mutex<SeriesQ>>);
+
+impl Drop for PanicDump {
+	fn drop(&mut self) {
+		if thread::panicking() {
+			let cpu = self.0.lock().unwrap_or_else(|e| e.into_inner());
+			println!("@{:08X}::{:08X} CPU PANIC", cpu.S_base[PS], cpu.R[PC]);
+			cpu.trace.dump();
+			crash::cpu_unwound(&cpu);
+		}
+	}
+}
+
 impl SeriesQ {
+	// Assertion mode: every bus access an instruction made must lie wholly within
+	// one attached region and within a segment access_check approved for that
+	// address. Descriptor table and priority level link block accesses are
+	// physical by design, so the instructions that make them are not checked.
+	fn audit_accesses(&mut self, bus: &Bus, iword0: u16) {
+		let accesses = bus.take_audit();
+		let approved = self.approved.replace(Vec::new());
+		if matches!(iword0 >> 8, 0x25 | 0x27 | 0x2B | 0x30) {
+			return;
+		}
+		
+		let regions = bus.regions();
+		for (addr, width) in accesses {
+			let end = addr as u64 + width as u64;
+			let straddles = regions.iter().any(|&(base, size)| {
+				addr >= base && addr < base + size && end > base as u64 + size as u64
+			});
+			let in_segment = approved.iter().any(|&(seg, a)| {
+				a == addr && addr >= self.S_base[seg] && end <= self.S_limit[seg] as u64
+			});
+			if straddles || !in_segment {
+				let msg = format!("@{:08X}::{:08X} 0x{:04X} ACCESS ASSERTION: {}-byte access at 0x{:08X} {}",
+					self.S_base[PS], self.R[PC], iword0, width, addr,
+					if straddles { "crosses a region boundary" } else { "was not approved by access_check" });
+				if cfg!(debug_assertions) {
+					panic!("{}", msg);
+				}
+				println!("{}", msg);
+				self.running.store(false, Ordering::Relaxed);
+			}
+		}
+	}
+	
 	fn copy_segment(&mut self, dest: usize, src: usize) {
 		self.S_selector[dest] = self.S_selector[src];
 		self.S_base[dest] = self.S_base[src];
@@ -381,6 +708,107 @@ impl SeriesQ {
 		self.S_flags[dest] = self.S_flags[src];
 	}
 	
+	fn context(&self) -> [u8; CONTEXT_SIZE] {
+		let mut block = [0u8; CONTEXT_SIZE];
+		for n in 0..16 {
+			block[4 * n..4 * n + 4].copy_from_slice(&self.R[n].to_le_bytes());
+			block[288 + 4 * n..292 + 4 * n].copy_from_slice(&self.FP[n].to_le_bytes());
+			let seg = &mut block[80 + 12 * n..92 + 12 * n];
+			seg[0..4].copy_from_slice(&self.S_base[n].to_le_bytes());
+			seg[4..8].copy_from_slice(&self.S_limit[n].to_le_bytes());
+			seg[8] = self.S_key[n];
+			seg[9] = self.S_flags[n];
+			seg[10] = self.S_selector[n];
+		}
+		block[64..80].copy_from_slice(&self.F);
+		block[272..288].copy_from_slice(&self.MPK);
+		block
+	}
+	
+	// PC, F8 and PS come from the block too, so this is a jump and may change state
+	fn set_context(&mut self, block: &[u8; CONTEXT_SIZE]) {
+		let word = |at: usize| u32::from_le_bytes([block[at], block[at + 1], block[at + 2], block[at + 3]]);
+		for n in 0..16 {
+			self.R[n] = word(4 * n);
+			self.FP[n] = word(288 + 4 * n);
+			let seg = 80 + 12 * n;
+			self.S_base[n] = word(seg);
+			self.S_limit[n] = word(seg + 4);
+			self.S_key[n] = block[seg + 8];
+			self.S_flags[n] = block[seg + 9];
+			self.S_selector[n] = block[seg + 10];
+		}
+		self.F.copy_from_slice(&block[64..80]);
+		self.MPK.copy_from_slice(&block[272..288]);
+	}
+	
+	// Descriptor cache: SSEL and SSELHC take a descriptor from the cache when it
+	// holds one for the selector, and otherwise read it from the table and cache
+	// it. A change to a table entry in memory is seen by the next load of that
+	// selector only once INVSEL has dropped it or SSDTR has emptied the cache;
+	// segment registers already loaded keep their own copy either way. The
+	// cache is part of the CPU's saved state.
+	
+	// load segment register dest from descriptor table entry selector, for SSEL
+	// and SSELHC; a descriptor not in the cache is read a field at a time
+	fn set_selector(&mut self, bus: &Bus, iword0: u16, dest: usize, selector: u8) {
+		if self.selectors.is_some() {
+			self.watch_selector(bus, selector);
+		}
+		if selector > self.SDTR_len {
+			self.app_fault(iword0, OUT_OF_BOUNDS as u32);
+			return;
+		}
+		self.S_selector[dest] = selector;
+		if let Some(d) = self.descriptors.get(&selector) {
+			self.S_base[dest] = d.base;
+			self.S_limit[dest] = d.limit;
+			self.S_key[dest] = d.key;
+			self.S_flags[dest] = d.flags;
+			return;
+		}
+		let entry = self.SDTR_base + 12 * selector as u32;
+		match bus.read_w(entry) {
+			Err(e) => return self.read_fault(iword0, entry, e),
+			Ok(x) => self.S_base[dest] = x,
+		}
+		match bus.read_w(entry + 4) {
+			Err(e) => return self.read_fault(iword0, entry + 4, e),
+			Ok(x) => self.S_limit[dest] = x,
+		}
+		match bus.read_b(entry + 8) {
+			Err(e) => return self.read_fault(iword0, entry + 8, e),
+			Ok(x) => self.S_key[dest] = x,
+		}
+		match bus.read_b(entry + 9) {
+			Err(e) => return self.read_fault(iword0, entry + 9, e),
+			Ok(x) => self.S_flags[dest] = x,
+		}
+		self.descriptors.insert(selector, Descriptor {
+			base: self.S_base[dest],
+			limit: self.S_limit[dest],
+			key: self.S_key[dest],
+			flags: self.S_flags[dest]
+		});
+	}
+	
+	// selector watch: note the entry and warn, once per table and entry, about
+	// one that looks wrong
+	fn watch_selector(&mut self, bus: &Bus, selector: u8) {
+		let (base, len) = (self.SDTR_base, self.SDTR_len);
+		let why = match self.selectors.as_mut() {
+			Some(w) => w.check(bus, base, len, selector),
+			None => None,
+		};
+		if let Some(why) = why {
+			match self.stage {
+				Stage::Execute { base, pc, iword0, iword1 } => println!("@{:08X}::{:08X} 0x{:04X} SELECTOR 0x{:02X} {} in {}",
+					base, pc, iword0, selector, why, isa::disassemble(iword0, iword1)),
+				_ => println!("@{:08X}::{:08X} SELECTOR 0x{:02X} {}", self.S_base[PS], self.R[PC], selector, why),
+			}
+		}
+	}
+	
 	fn increment(&self, iword: u16) -> u32 {
 		if (iword >> 14) & 3 == 1 || (iword >> 14) & 3 == 3 {
 			4
@@ -390,6 +818,7 @@ impl SeriesQ {
 	}
 	
 	fn read_fault(&mut self, iword0: u16, addr: u32, err: BusError) {
+		self.fault_addr = Some(addr);
 		self.F[12] = (addr & 0xFF) as u8;
 		self.F[13] = ((addr & 0xFF00) >> 8) as u8;
 		self.F[14] = ((addr & 0xFF0000) >> 16) as u8;
@@ -402,6 +831,7 @@ impl SeriesQ {
 		}
 	}
 	fn write_fault(&mut self, iword0: u16, addr: u32, err: BusError) {
+		self.fault_addr = Some(addr);
 		self.F[12] = (addr & 0xFF) as u8;
 		self.F[13] = ((addr & 0xFF00) >> 8) as u8;
 		self.F[14] = ((addr & 0xFF0000) >> 16) as u8;
@@ -413,15 +843,83 @@ impl SeriesQ {
 			_ => self.app_fault(iword0, WRITE_FAULT as u32),
 		}
 	}
+	// whole words in address order, for the instructions with block operands;
+	// every word is checked before any is written
+	fn read_block(&mut self, bus: &Bus, iword0: u16, segment: usize, addr: u32, buf: &mut [u8]) -> bool {
+		for (n, chunk) in buf.chunks_mut(4).enumerate() {
+			let a = addr.wrapping_add(4 * n as u32);
+			if !self.access_check(segment, a, false, false) {
+				self.seg_fault(iword0, a);
+				return false;
+			}
+			match bus.read_w(a) {
+				Err(e) => {
+					self.read_fault(iword0, a, e);
+					return false;
+				},
+				Ok(x) => chunk.copy_from_slice(&x.to_le_bytes()),
+			}
+		}
+		true
+	}
+	fn write_block(&mut self, bus: &mut Bus, iword0: u16, segment: usize, addr: u32, buf: &[u8]) -> bool {
+		for n in 0..buf.len() as u32 / 4 {
+			if !self.access_check(segment, addr.wrapping_add(4 * n), true, false) {
+				self.seg_fault(iword0, addr.wrapping_add(4 * n));
+				return false;
+			}
+		}
+		for (n, chunk) in buf.chunks(4).enumerate() {
+			let a = addr.wrapping_add(4 * n as u32);
+			if let Err(e) = bus.write_w(a, u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])) {
+				self.write_fault(iword0, a, e);
+				return false;
+			}
+		}
+		true
+	}
+	// taint mode warning; each address is reported once
+	fn taint_report(&mut self, addr: u32) {
+		if self.taint.as_mut().map_or(false, |seen| seen.insert(addr)) {
+			self.memory_report("UNINITIALIZED READ", addr);
+		}
+	}
+	// self-modifying code mode warning; each address is reported once
+	fn smc_report(&mut self, addr: u32) {
+		if self.smc.as_mut().map_or(false, |seen| seen.insert(addr)) {
+			self.memory_report("STORE INTO CODE", addr);
+		}
+	}
+	fn memory_report(&self, what: &str, addr: u32) {
+		match self.stage {
+			Stage::Execute { base, pc, iword0, iword1 } => println!("@{:08X}::{:08X} 0x{:04X} {} 0x{:08X} in {}",
+				base, pc, iword0, what, addr, isa::disassemble(iword0, iword1)),
+			_ => println!("@{:08X}::{:08X} {} 0x{:08X} changing priority level", self.S_base[PS], self.R[PC], what, addr),
+		}
+	}
+	// the comparator interrupt is pending exactly while TOD is past the comparator
+	fn tod_check(&self) {
+		self.ipl[TOD_IPL].store(self.tod.passed(), Ordering::Relaxed);
+	}
+	// an access into a guard segment is a stack overflow, and carries the
+	// offset into the segment rather than the address
 	fn seg_fault(&mut self, iword0: u16, addr: u32) {
-		self.F[12] = (addr & 0xFF) as u8;
-		self.F[13] = ((addr & 0xFF00) >> 8) as u8;
-		self.F[14] = ((addr & 0xFF0000) >> 16) as u8;
-		self.F[15] = ((addr & 0xFF000000) >> 24) as u8;
-		self.app_fault(iword0, SEGMENTATION_FAULT as u32);
-		println!("@{:08X}::{:08X} 0x{:04X} SEGMENTATION FAULT 0x{:08X}", self.S_base[PS], self.R[PC], iword0, addr);
+		let (code, value) = match self.guard_hit.take() {
+			Some(offset) => (STACK_OVERFLOW, offset),
+			None => (SEGMENTATION_FAULT, addr),
+		};
+		self.fault_addr = Some(addr);
+		self.F[12] = (value & 0xFF) as u8;
+		self.F[13] = ((value & 0xFF00) >> 8) as u8;
+		self.F[14] = ((value & 0xFF0000) >> 16) as u8;
+		self.F[15] = ((value & 0xFF000000) >> 24) as u8;
+		self.app_fault(iword0, code as u32);
+		let fault = self.last_fault.as_ref().unwrap();
+		println!("@{:08X}::{:08X} 0x{:04X} {} 0x{:08X} {}", self.S_base[PS], self.R[PC], iword0,
+			crate::fault::name(code as u32), value, fault);
 	}
 	fn app_fault(&mut self, iword0: u16, error_code: u32) {
+		self.last_fault = Some(Fault::new(error_code, self.stage, self.fault_addr.take()));
 		if self.F[8] & 1 == 0 {
 			// we are in supervisor state
 			self.sys_fault(iword0, error_code);
@@ -444,7 +942,9 @@ impl SeriesQ {
 		}
 	}
 	fn sys_fault(&mut self, iword0: u16, error_code: u32) {
-		// println!("@{:08X}::{:08X} 0x{:04X} SYSTEM FAULT 0x{:08X}", self.S_base[PS], self.R[PC], iword0, error_code);
+		let fault = self.last_fault.as_ref().unwrap();
+		println!("@{:08X}::{:08X} 0x{:04X} SYSTEM FAULT 0x{:08X} {}", self.S_base[PS], self.R[PC], iword0, error_code, fault);
+		self.trace.dump();
 		self.F[10] = (iword0 & 0xFF) as u8;
 		self.F[11] = ((iword0 & 0xFF00) >> 8) as u8;
 		
@@ -486,15 +986,49 @@ impl SeriesQ {
 			
 			F: [0xFE; 16],
 			
+			FP: [0; 16],
+			
 			SDTR_base: 0,
 			SDTR_len: 0,
+			descriptors: BTreeMap::new(),
 			
 			PEBA_base: 0,
 			PLBA_base: 0,
 			
+			tod: Tod::default(),
+			
 			running: Arc::new(AtomicBool::new(false)),
 			waiting: Arc::new(AtomicBool::new(false)),
+			skip: false,
 			cycles: 0,
+			cycle_limit: 0,
+			dma_spacing: 1,
+			dma_grants: 0,
+			last_grant: 0,
+			breakpoints: Breakpoints::default(),
+			watches: Watches::default(),
+			hooks: Hooks::default(),
+			taint: None,
+			smc: None,
+			selectors: None,
+			coverage: None,
+			trace: Arc::new(TraceRing::default()),
+			trace_log: None,
+			heartbeat: watchdog::register(CPU_THREAD),
+			prefetch: Prefetch::default(),
+			checkpoint: None,
+			profiler: None,
+			clocks: None,
+			stage: Stage::Interrupt,
+			last_fault: None,
+			fault_addr: None,
+			assert_access: false,
+			approved: RefCell::new(Vec::new()),
+			align_fixup: false,
+			low_protect: 0,
+			supervisor_only: Vec::new(),
+			yield_stop: false,
+			guard_hit: Cell::new(None),
 			
 			bus: bus,
 			channels: Vec::new(),
@@ -502,7 +1036,12 @@ impl SeriesQ {
 			icode: Vec::new(),
 			
 			faultpl: Vec::new(),
-			faultcode: Vec::new()
+			faultcode: Vec::new(),
+			
+			attention: Arc::new(AtomicBool::new(false)),
+			attention_ipl: ATTENTION_IPL,
+			storm: None,
+			shown: None
 		};
 		
 		for _ in 0..16 {
@@ -520,7 +1059,100 @@ impl SeriesQ {
 		result
 	}
 	
-	fn pl_set(&mut self, pl: u8, ssr7: u8, bus: &mut Bus) {
+	// copy the registers out for the frontend, if it shows them
+	fn show(&self, running: bool) {
+		if let Some(s) = &self.shown {
+			*s.lock().unwrap() = Registers {
+				R: self.R,
+				F0: self.F[0],
+				F8: self.F[8],
+				ps_base: self.S_base[PS],
+				cycles: self.cycles,
+				running
+			};
+		}
+	}
+	
+	pub fn save_state(&self) -> CpuState {
+		let mut state = CpuState {
+			R: self.R,
+			
+			S_selector: self.S_selector,
+			S_base: self.S_base,
+			S_limit: self.S_limit,
+			S_key: self.S_key,
+			S_flags: self.S_flags,
+			
+			MPK: self.MPK,
+			F: self.F,
+			FP: self.FP,
+			
+			SDTR_base: self.SDTR_base,
+			SDTR_len: self.SDTR_len,
+			descriptors: self.descriptors.clone(),
+			
+			PEBA_base: self.PEBA_base,
+			PLBA_base: self.PLBA_base,
+			
+			tod: self.tod,
+			
+			waiting: self.waiting.load(Ordering::Relaxed),
+			skip: self.skip,
+			cycles: self.cycles,
+			
+			ipl: [false; 8],
+			icode: [0; 8],
+			faultpl: [false; 8],
+			faultcode: [0; 8],
+		};
+		
+		for n in 0..8 {
+			state.ipl[n] = self.ipl[n].load(Ordering::Relaxed);
+			state.icode[n] = self.icode[n].load(Ordering::Relaxed);
+			state.faultpl[n] = self.faultpl[n].load(Ordering::Relaxed);
+			state.faultcode[n] = self.faultcode[n].load(Ordering::Relaxed);
+		}
+		state
+	}
+	
+	pub fn load_state(&mut self, state: &CpuState) {
+		self.R = state.R;
+		
+		self.S_selector = state.S_selector;
+		self.S_base = state.S_base;
+		self.S_limit = state.S_limit;
+		self.S_key = state.S_key;
+		self.S_flags = state.S_flags;
+		
+		self.MPK = state.MPK;
+		self.F = state.F;
+		self.FP = state.FP;
+		
+		self.SDTR_base = state.SDTR_base;
+		self.SDTR_len = state.SDTR_len;
+		self.descriptors = state.descriptors.clone();
+		
+		self.PEBA_base = state.PEBA_base;
+		self.PLBA_base = state.PLBA_base;
+		
+		self.tod = state.tod;
+		
+		self.waiting.store(state.waiting, Ordering::Relaxed);
+		self.skip = state.skip;
+		self.cycles = state.cycles;
+		
+		// interrupt lines are shared with devices, so update them in place
+		for n in 0..8 {
+			self.ipl[n].store(state.ipl[n], Ordering::Relaxed);
+			self.icode[n].store(state.icode[n], Ordering::Relaxed);
+			self.faultpl[n].store(state.faultpl[n], Ordering::Relaxed);
+			self.faultcode[n].store(state.faultcode[n], Ordering::Relaxed);
+		}
+	}
+	
+	// enter priority level pl as an interrupt does; the monitor's pl command
+	// calls this directly
+	pub fn pl_set(&mut self, pl: u8, ssr7: u8, bus: &mut Bus) {
 		
 		let new_priority = pl & 0x7;
 		
@@ -648,7 +1280,7 @@ impl SeriesQ {
 		}
 	}
 	
-	fn pl_retn(&mut self, bus: &mut Bus) {
+	pub fn pl_retn(&mut self, bus: &mut Bus) {
 		// restore old priority level		
 		let mut error = false;
 		loop {
@@ -699,18 +1331,39 @@ impl SeriesQ {
 		}
 	}
 	
-	pub fn run(cpu: Arc<Mutex<SeriesQ>>) {
-		thread::spawn(move || {
+	pub fn run(cpu: Arc<Mutex<SeriesQ>>) -> thread::JoinHandle<()> {
+		// raise running before the thread starts so an early stop isn't lost
+		cpu.lock().unwrap().running.store(true, Ordering::Relaxed);
+		
+		thread::Builder::new().name(CPU_THREAD.to_string()).spawn(move || {
+			let _dump = PanicDump(Arc::clone(&cpu));
 			let mut cpu = cpu.lock().unwrap();
-			cpu.cycles = 0;
-			let mut skip = false;
 			
 			let mut our_bus = Arc::clone(&cpu.bus);
 			let mut held_bus = our_bus.lock().unwrap();
 			
 			println!("CPU START, {} devices attached to bus", held_bus.region.len());
-			cpu.running.store(true, Ordering::Relaxed);
+			cpu.heartbeat.idle(false);
+			cpu.show(true);
+			if let Some(c) = &cpu.clocks {
+				c.run(true);
+			}
+			held_bus.set_audit(cpu.assert_access);
+			held_bus.set_fixup(cpu.align_fixup);
+			let cycles = cpu.cycles;
+			if let Some(c) = cpu.checkpoint.as_mut() {
+				c.start(cycles);
+			}
+			if !cpu.watches.is_empty() {
+				Watches::start(&mut cpu, &held_bus);
+			}
+			// anything the monitor read while stopped isn't the guest's doing
+			held_bus.take_taint();
+			held_bus.take_modified();
+			held_bus.take_fixups();
 			while cpu.running.load(Ordering::Relaxed) {
+				cpu.heartbeat.beat();
+				
 				// clear zero register
 				cpu.R[0] = 0;
 				
@@ -720,6 +1373,24 @@ impl SeriesQ {
 				
 				if !(cpu.waiting.load(Ordering::Relaxed)) {
 				
+				// watches first, so the last instruction before a stop is reported
+				if !cpu.watches.is_empty() {
+					Watches::check(&mut cpu, &held_bus);
+				}
+				if !cpu.breakpoints.is_empty() && Breakpoints::check(&mut cpu, &mut held_bus) {
+					cpu.running.store(false, Ordering::Relaxed);
+					break;
+				}
+				let pc = cpu.R[PC];
+				cpu.stage = Stage::Fetch { base: cpu.S_base[PS], pc: pc };
+				if let Some(c) = cpu.coverage.as_mut() {
+					c.record(pc);
+				}
+				if cpu.assert_access {
+					held_bus.take_audit();
+					cpu.approved.borrow_mut().clear();
+				}
+				
 				// instruction fetch
 				let mut iword0: u16 = 0;
 				let mut iword1: u16 = 0;
@@ -727,7 +1398,7 @@ impl SeriesQ {
 				
 				let addr = cpu.R[PC].wrapping_add(cpu.S_base[PS]);
 				if cpu.access_check(PS, addr, false, true) {
-					match held_bus.read_h_big(cpu.R[PC].wrapping_add(cpu.S_base[PS])) {
+					match cpu.prefetch.fetch(&held_bus, addr) {
 						Err(e) => {
 							ifetch = false;
 							// for now
@@ -746,7 +1417,7 @@ impl SeriesQ {
 				if ifetch && cpu.increment(iword0) >= 4 {
 					let addr = cpu.R[PC].wrapping_add(cpu.S_base[PS]);
 					if cpu.access_check(PS, addr, false, true) {
-						match held_bus.read_h_big(cpu.R[PC].wrapping_add(cpu.S_base[PS])) {
+						match cpu.prefetch.fetch(&held_bus, addr) {
 							Err(e) => {
 								ifetch = false;
 								// for now
@@ -761,7 +1432,19 @@ impl SeriesQ {
 					}
 				}
 				
-				if ifetch && !skip {
+				if ifetch {
+					cpu.stage = Stage::Execute { base: cpu.S_base[PS], pc: pc, iword0: iword0, iword1: iword1 };
+				}
+				if ifetch && !cpu.skip {
+					let decoded = if cpu.hooks.is_empty() {
+						None
+					} else {
+						Some(Decoded::new(cpu.S_base[PS], pc, iword0, iword1))
+					};
+					if let Some(d) = &decoded {
+						Hooks::run(&mut cpu, When::Before, d, &held_bus);
+					}
+					
 					match (iword0 & 0xFF00) >> 8 {
 						
 						// RR
@@ -946,6 +1629,7 @@ impl SeriesQ {
 							} else {
 								cpu.SDTR_len = (cpu.R[rr_reg_r(iword0)] & 0xFF) as u8;
 								cpu.SDTR_base = cpu.R[rr_reg_d(iword0)];
+								cpu.descriptors.clear();
 							}
 							
 							let mut ok = true;
@@ -973,66 +1657,36 @@ impl SeriesQ {
 							}
 						},
 						
+						0b00101110 => { // LPBA, load priority entry and link block addresses
+							if cpu.F[8] & 0b00000001 != 0 {
+								cpu.app_fault(iword0, SUPERVISOR_ACCESS as u32);
+							} else {
+								cpu.R[rr_reg_d(iword0)] = cpu.PEBA_base;
+								cpu.R[rr_reg_r(iword0)] = cpu.PLBA_base;
+							}
+						},
+						0b00101111 => { // SPBA, set priority entry and link block addresses
+							let (entry, link) = (cpu.R[rr_reg_d(iword0)], cpu.R[rr_reg_r(iword0)]);
+							if cpu.F[8] & 0b00000001 != 0 {
+								cpu.app_fault(iword0, SUPERVISOR_ACCESS as u32);
+							} else if !held_bus.holds(entry, PRIORITY_TABLE_SIZE) || !held_bus.holds(link, PRIORITY_TABLE_SIZE) {
+								// both tables must be attached, so a bad address faults here and not at the next interrupt
+								cpu.app_fault(iword0, OUT_OF_BOUNDS as u32);
+							} else {
+								cpu.PEBA_base = entry;
+								cpu.PLBA_base = link;
+							}
+						},
+						
 						0b00100110 => { // LSEL, load segment selector
 							cpu.R[rr_reg_d(iword0)] = cpu.S_selector[rr_reg_r(iword0)] as u32;
 						}
 						0b00100111 => { // SSEL, set segment selector
 							if (cpu.F[8] & 0b00000001 != 0 && rr_reg_d(iword0) >= 8) {
 								cpu.app_fault(iword0, SUPERVISOR_ACCESS as u32);
-							} else if ((cpu.R[rr_reg_r(iword0)] & 0xFF) as u8) > cpu.SDTR_len {
-								cpu.app_fault(iword0, OUT_OF_BOUNDS as u32);
 							} else {
-								cpu.S_selector[rr_reg_d(iword0)] = (cpu.R[rr_reg_r(iword0)] & 0xFF) as u8;
-								
-								// ugh
-								let mut ok = true;
-								
-								// read S_base
-								let addr = cpu.SDTR_base + 12 * (cpu.R[rr_reg_r(iword0)] & 0xFF);
-								match held_bus.read_w(addr) {
-									Err(e) => {
-										cpu.read_fault(iword0, addr, e);
-										ok = false;
-									},
-									Ok(x) => { cpu.S_base[rr_reg_d(iword0)] = x; },
-								};
-								
-								if ok {
-									// read S_limit
-									let addr = cpu.SDTR_base + 12 * (cpu.R[rr_reg_r(iword0)] & 0xFF) + 4;
-									match held_bus.read_w(addr) {
-										Err(e) => {
-											cpu.read_fault(iword0, addr, e);
-											ok = false;
-										},
-										Ok(x) => { cpu.S_limit[rr_reg_d(iword0)] = x; },
-									};
-								}
-								
-								if ok {
-									// read S_key
-									let addr = cpu.SDTR_base + 12 * (cpu.R[rr_reg_r(iword0)] & 0xFF) + 8;
-									match held_bus.read_b(addr) {
-										Err(e) => {
-											cpu.read_fault(iword0, addr, e);
-											ok = false;
-										},
-										Ok(x) => { cpu.S_key[rr_reg_d(iword0)] = x; },
-									};
-								}
-								
-								if ok {
-									// read S_flags
-									let addr = cpu.SDTR_base + 12 * (cpu.R[rr_reg_r(iword0)] & 0xFF) + 9;
-									match held_bus.read_b(addr) {
-										Err(e) => {
-											cpu.read_fault(iword0, addr, e);
-											ok = false;
-										},
-										Ok(x) => { cpu.S_flags[rr_reg_d(iword0)] = x; },
-									};
-								}
-								
+								let selector = (cpu.R[rr_reg_r(iword0)] & 0xFF) as u8;
+								cpu.set_selector(&held_bus, iword0, rr_reg_d(iword0), selector);
 							}
 						}
 						
@@ -1051,6 +1705,24 @@ impl SeriesQ {
 							}
 						}
 						
+						0b00101100 => { // TKEY, test whether the key R[d] admits to segment r
+							if cpu.F[8] & 0b00000001 != 0 {
+								cpu.app_fault(iword0, SUPERVISOR_ACCESS as u32);
+							} else {
+								let key = cpu.R[rr_reg_d(iword0)] as u8;
+								cpu.R[rr_reg_d(iword0)] = (cpu.S_key[rr_reg_r(iword0)] == key) as u32;
+							}
+						}
+						
+						0b00101101 => { // INVSEL, drop selector R[r] from the descriptor cache; R[d] = 1 if it was there
+							if cpu.F[8] & 0b00000001 != 0 {
+								cpu.app_fault(iword0, SUPERVISOR_ACCESS as u32);
+							} else {
+								let selector = (cpu.R[rr_reg_r(iword0)] & 0xFF) as u8;
+								cpu.R[rr_reg_d(iword0)] = cpu.descriptors.remove(&selector).is_some() as u32;
+							}
+						}
+						
 						0b00101010 => { // CSEL, copy segment selector
 							if (cpu.F[8] & 0b00000001 != 0 && rr_reg_d(iword0) >= 8) {
 								cpu.app_fault(iword0, SUPERVISOR_ACCESS as u32);
@@ -1061,61 +1733,8 @@ impl SeriesQ {
 						0b00101011 => { // SSELHC, set segment selector
 							if (cpu.F[8] & 0b00000001 != 0 && rr_reg_d(iword0) >= 8) {
 								cpu.app_fault(iword0, SUPERVISOR_ACCESS as u32);
-							} else if ((rr_reg_r(iword0) & 0xFF) as u8) > cpu.SDTR_len {
-								cpu.app_fault(iword0, OUT_OF_BOUNDS as u32);
 							} else {
-								cpu.S_selector[rr_reg_d(iword0)] = ((rr_reg_r(iword0) as u32) & 0xFF) as u8;
-								
-								// ugh
-								let mut ok = true;
-								
-								// read S_base
-								let addr = cpu.SDTR_base + 12 * ((rr_reg_r(iword0) as u32) & 0xFF);
-								match held_bus.read_w(addr) {
-									Err(e) => {
-										cpu.read_fault(iword0, addr, e);
-										ok = false;
-										// println!("Error");
-									},
-									Ok(x) => { cpu.S_base[rr_reg_d(iword0)] = x; },
-								};
-								
-								if ok {
-									// read S_limit
-									let addr = cpu.SDTR_base + 12 * ((rr_reg_r(iword0) as u32) & 0xFF) + 4;
-									match held_bus.read_w(addr) {
-										Err(e) => {
-											cpu.read_fault(iword0, addr, e);
-											ok = false;
-										},
-										Ok(x) => { cpu.S_limit[rr_reg_d(iword0)] = x; },
-									};
-								}
-								
-								if ok {
-									// read S_key
-									let addr = cpu.SDTR_base + 12 * ((rr_reg_r(iword0) as u32) & 0xFF) + 8;
-									match held_bus.read_b(addr) {
-										Err(e) => {
-											cpu.read_fault(iword0, addr, e);
-											ok = false;
-										},
-										Ok(x) => { cpu.S_key[rr_reg_d(iword0)] = x; },
-									};
-								}
-								
-								if ok {
-									// read S_flags
-									let addr = cpu.SDTR_base + 12 * ((rr_reg_r(iword0) as u32) & 0xFF) + 9;
-									match held_bus.read_b(addr) {
-										Err(e) => {
-											cpu.read_fault(iword0, addr, e);
-											ok = false;
-										},
-										Ok(x) => { cpu.S_flags[rr_reg_d(iword0)] = x; },
-									};
-								}
-								
+								cpu.set_selector(&held_bus, iword0, rr_reg_d(iword0), rr_reg_r(iword0) as u8);
 							}
 						}
 						
@@ -1132,16 +1751,27 @@ impl SeriesQ {
 							// println!("now 0x{:02X}", cpu.S_selector[PS]);
 						},
 						
+						0b00110010 => { // YIELD, a point where the guest can stand to be paused
+							if cpu.yield_stop {
+								cpu.running.store(false, Ordering::Relaxed);
+							} else {
+								// let anything waiting on the bus have it, and the host the thread
+								drop(held_bus);
+								thread::yield_now();
+								held_bus = our_bus.lock().unwrap();
+							}
+						},
+						
 						0b00111110 => { // IF, conditionally execute next instruction
 							let mask = (iword0 & 0xFF) as u8;
 							if mask & cpu.F[0] == 0 {
-								skip = true;
+								cpu.skip = true;
 							}
 						},
 						0b00111111 => { // IFN, conditionally skip next instruction
 							let mask = (iword0 & 0xFF) as u8;
 							if mask & cpu.F[0] != 0 {
-								skip = true;
+								cpu.skip = true;
 							}
 						},
 						
@@ -1463,6 +2093,51 @@ impl SeriesQ {
 							}
 						},
 						
+						0b01101100 => { // RM LDMPK, load all 16 memory protection keys from memory
+							if cpu.F[8] & 0b00000001 != 0 {
+								cpu.app_fault(iword0, SUPERVISOR_ACCESS as u32);
+							} else {
+								let seg = rm_seg_s(iword1);
+								let addr = cpu.gen_addr_rm(seg, rr_reg_r(iword0), iword1);
+								let mut keys = [0u8; 16];
+								if cpu.read_block(&held_bus, iword0, seg, addr, &mut keys) {
+									cpu.MPK = keys;
+								}
+							}
+						},
+						0b01101101 => { // RM STMPK, store all 16 memory protection keys to memory
+							if cpu.F[8] & 0b00000001 != 0 {
+								cpu.app_fault(iword0, SUPERVISOR_ACCESS as u32);
+							} else {
+								let seg = rm_seg_s(iword1);
+								let addr = cpu.gen_addr_rm(seg, rr_reg_r(iword0), iword1);
+								let keys = cpu.MPK;
+								cpu.write_block(&mut held_bus, iword0, seg, addr, &keys);
+							}
+						},
+						0b01101110 => { // RM STCTX, store the whole architectural state to a context block
+							if cpu.F[8] & 0b00000001 != 0 {
+								cpu.app_fault(iword0, SUPERVISOR_ACCESS as u32);
+							} else {
+								let seg = rm_seg_s(iword1);
+								let addr = cpu.gen_addr_rm(seg, rr_reg_r(iword0), iword1);
+								let block = cpu.context();
+								cpu.write_block(&mut held_bus, iword0, seg, addr, &block);
+							}
+						},
+						0b01101111 => { // RM LDCTX, load the whole architectural state from a context block
+							if cpu.F[8] & 0b00000001 != 0 {
+								cpu.app_fault(iword0, SUPERVISOR_ACCESS as u32);
+							} else {
+								let seg = rm_seg_s(iword1);
+								let addr = cpu.gen_addr_rm(seg, rr_reg_r(iword0), iword1);
+								let mut block = [0u8; CONTEXT_SIZE];
+								if cpu.read_block(&held_bus, iword0, seg, addr, &mut block) {
+									cpu.set_context(&block);
+								}
+							}
+						},
+						
 						0b01111111 => { // RM BAL, branch and optionally link
 							if rr_reg_d(iword0) != 0 {
 								cpu.copy_segment(LS, PS);
@@ -1473,6 +2148,214 @@ impl SeriesQ {
 							cpu.R[PC] = cpu.gen_offset_rm(rm_seg_s(iword1), rr_reg_r(iword0), iword1);
 						},
 						
+						0b10010000 => { // LTOD, load time-of-day, both halves at once
+							let (low, high) = tod::split(cpu.tod.now());
+							cpu.R[rr_reg_d(iword0)] = low;
+							cpu.R[rr_reg_r(iword0)] = high;
+						},
+						0b10010001 => { // STOD, set time-of-day
+							if cpu.F[8] & 0b00000001 != 0 {
+								cpu.app_fault(iword0, SUPERVISOR_ACCESS as u32);
+							} else {
+								let value = tod::join(cpu.R[rr_reg_d(iword0)], cpu.R[rr_reg_r(iword0)]);
+								cpu.tod.set(value);
+								cpu.tod_check();
+							}
+						},
+						0b10010010 => { // LTODC, load time-of-day comparator
+							if cpu.F[8] & 0b00000001 != 0 {
+								cpu.app_fault(iword0, SUPERVISOR_ACCESS as u32);
+							} else {
+								let (low, high) = tod::split(cpu.tod.comparator);
+								cpu.R[rr_reg_d(iword0)] = low;
+								cpu.R[rr_reg_r(iword0)] = high;
+							}
+						},
+						0b10010011 => { // STODC, set time-of-day comparator
+							if cpu.F[8] & 0b00000001 != 0 {
+								cpu.app_fault(iword0, SUPERVISOR_ACCESS as u32);
+							} else {
+								cpu.tod.comparator = tod::join(cpu.R[rr_reg_d(iword0)], cpu.R[rr_reg_r(iword0)]);
+								cpu.tod_check();
+							}
+						},
+						
+						0b10100000 | 0b10100001 => { // MUL/MULU, multiply, signed/unsigned
+							let (x, flags) = alu_mul(cpu.R[rr_reg_d(iword0)], cpu.R[rr_reg_r(iword0)], cpu.F[0], iword0 >> 8 == 0b10100000);
+							cpu.R[rr_reg_d(iword0)] = x;
+							cpu.F[0] = flags;
+						},
+						0b10100010 | 0b10100011 => { // DIV/DIVU, divide, signed/unsigned, rounding toward zero
+							match alu_div(cpu.R[rr_reg_d(iword0)], cpu.R[rr_reg_r(iword0)], cpu.F[0], iword0 >> 8 == 0b10100010) {
+								Some((x, flags)) => {
+									cpu.R[rr_reg_d(iword0)] = x;
+									cpu.F[0] = flags;
+								},
+								None => cpu.app_fault(iword0, DIVIDE_BY_ZERO as u32),
+							}
+						},
+						0b10100100 | 0b10100101 => { // MULW/MULWU, widening multiply into a register pair, signed/unsigned
+							let d = rr_reg_d(iword0);
+							// the pair starts on an even register, and 14-15 (LR, PC) can't be written
+							if d % 2 != 0 || d == 14 {
+								cpu.app_fault(iword0, ILLEGAL_INSTRUCTION as u32);
+							} else {
+								let (x, flags) = alu_mul_wide(cpu.R[d], cpu.R[rr_reg_r(iword0)], cpu.F[0], iword0 >> 8 == 0b10100100);
+								cpu.R[d] = (x >> 32) as u32;
+								cpu.R[d + 1] = x as u32;
+								cpu.F[0] = flags;
+							}
+						},
+						0b10100110 | 0b10100111 => { // DIVR/MODR, signed divide with remainder into a register pair, truncated/floored
+							let d = rr_reg_d(iword0);
+							if d % 2 != 0 || d == 14 {
+								cpu.app_fault(iword0, ILLEGAL_INSTRUCTION as u32);
+							} else {
+								match alu_divr(cpu.R[d], cpu.R[rr_reg_r(iword0)], cpu.F[0], iword0 >> 8 == 0b10100111) {
+									Ok((q, r, flags)) => {
+										cpu.R[d] = q;
+										cpu.R[d + 1] = r;
+										cpu.F[0] = flags;
+									},
+									Err(code) => cpu.app_fault(iword0, code as u32),
+								}
+							}
+						},
+						
+						#[cfg(feature = "crypto")]
+						0b11000000 | 0b11000001 => { // RM AESE/AESD, AES-128 block encrypt/decrypt in place, key at s: R[d]
+							let seg = rm_seg_s(iword1);
+							let addr = cpu.gen_addr_rm(seg, rr_reg_r(iword0), iword1);
+							let key_addr = cpu.S_base[seg].wrapping_add(cpu.R[rr_reg_d(iword0)]);
+							let mut key = [0u8; 16];
+							let mut block = [0u8; 16];
+							if cpu.read_block(&held_bus, iword0, seg, key_addr, &mut key)
+								&& cpu.read_block(&held_bus, iword0, seg, addr, &mut block) {
+								if iword0 >> 8 == 0b11000000 {
+									crypto::aes128_encrypt(&key, &mut block);
+								} else {
+									crypto::aes128_decrypt(&key, &mut block);
+								}
+								cpu.write_block(&mut held_bus, iword0, seg, addr, &block);
+							}
+						},
+						#[cfg(feature = "crypto")]
+						0b11000010 => { // RM SHA256, compress the 64-byte block into the 8-word state at s: R[d]
+							let seg = rm_seg_s(iword1);
+							let addr = cpu.gen_addr_rm(seg, rr_reg_r(iword0), iword1);
+							let state_addr = cpu.S_base[seg].wrapping_add(cpu.R[rr_reg_d(iword0)]);
+							let mut state = [0u8; 32];
+							let mut block = [0u8; 64];
+							if cpu.read_block(&held_bus, iword0, seg, state_addr, &mut state)
+								&& cpu.read_block(&held_bus, iword0, seg, addr, &mut block) {
+								let mut words = [0u32; 8];
+								for (w, chunk) in words.iter_mut().zip(state.chunks(4)) {
+									*w = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+								}
+								crypto::sha256_compress(&mut words, &block);
+								for (chunk, w) in state.chunks_mut(4).zip(words.iter()) {
+									chunk.copy_from_slice(&w.to_le_bytes());
+								}
+								cpu.write_block(&mut held_bus, iword0, seg, state_addr, &state);
+							}
+						},
+						
+						op if decimal::is_decimal(op as u8) => { // RM AP/SP/ZAP, packed decimal field at the address with the one at s: R[d]
+							let seg = rm_seg_s(iword1);
+							let addr = cpu.gen_addr_rm(seg, rr_reg_r(iword0), iword1);
+							let second_addr = cpu.S_base[seg].wrapping_add(cpu.R[rr_reg_d(iword0)]);
+							let mut first = [0u8; DECIMAL_SIZE];
+							let mut second = [0u8; DECIMAL_SIZE];
+							if cpu.read_block(&held_bus, iword0, seg, addr, &mut first)
+								&& cpu.read_block(&held_bus, iword0, seg, second_addr, &mut second) {
+								match decimal::operate(op as u8, &first, &second) {
+									Ok(y) => {
+										if cpu.write_block(&mut held_bus, iword0, seg, addr, &decimal::pack(y)) {
+											cpu.F[0] = decimal_flags(y, cpu.F[0]);
+										}
+									},
+									Err(DecimalError::Overflow) => cpu.app_fault(iword0, DECIMAL_OVERFLOW as u32),
+									Err(DecimalError::Data) => cpu.app_fault(iword0, DECIMAL_DATA as u32),
+								}
+							}
+						},
+						
+						op if fpu::is_float(op as u8) => { // FADD..FGET, binary32 arithmetic on the FP registers
+							let (d, r) = (rr_reg_d(iword0), rr_reg_r(iword0));
+							let exceptions = match op as u8 & 0x0F {
+								0..=3 => {
+									let (x, exceptions) = fpu::arith(op as u8, cpu.FP[d], cpu.FP[r]);
+									cpu.FP[d] = x;
+									exceptions
+								},
+								4 => {
+									cpu.F[0] = fpu::compare(cpu.FP[d], cpu.FP[r], cpu.F[0]);
+									0
+								},
+								5 => {
+									let (x, exceptions) = fpu::from_int(cpu.R[r]);
+									cpu.FP[d] = x;
+									exceptions
+								},
+								6 => {
+									let (x, exceptions) = fpu::to_int(cpu.FP[r]);
+									cpu.R[d] = x;
+									exceptions
+								},
+								7 => {
+									cpu.FP[d] = cpu.FP[r];
+									0
+								},
+								8 => {
+									cpu.FP[d] = cpu.R[r];
+									0
+								},
+								_ => {
+									cpu.R[d] = cpu.FP[r];
+									0
+								},
+							};
+							cpu.F[FP_FLAGS] |= exceptions;
+						},
+						0b11010000 => { // RM FL, load FP register
+							let addr = cpu.gen_addr_rm(rm_seg_s(iword1), rr_reg_r(iword0), iword1);
+							if cpu.access_check(rm_seg_s(iword1), addr, false, false) {
+								match held_bus.read_w(addr) {
+									Err(e) => {
+										cpu.read_fault(iword0, addr, e);
+									},
+									Ok(x) => { cpu.FP[rr_reg_d(iword0)] = x; },
+								};
+							} else {
+								cpu.seg_fault(iword0, addr);
+							}
+						},
+						0b11010001 => { // RM FST, store FP register
+							let addr = cpu.gen_addr_rm(rm_seg_s(iword1), rr_reg_r(iword0), iword1);
+							if cpu.access_check(rm_seg_s(iword1), addr, true, false) {
+								match held_bus.write_w(addr, cpu.FP[rr_reg_d(iword0)]) {
+									Err(e) => {
+										cpu.write_fault(iword0, addr, e);
+									},
+									Ok(_) => { /* do nothing */ },
+								};
+							} else {
+								cpu.seg_fault(iword0, addr);
+							}
+						},
+						
+						op if simd::is_vector(op as u8) => { // VADDB..VMAXH, packed lanes across register quadruples
+							let (d, r) = (rr_reg_d(iword0), rr_reg_r(iword0));
+							// quadruples start on a multiple of 4, and 12-15 (LR, PC among them) can't be written
+							if d % 4 != 0 || r % 4 != 0 || d == 12 {
+								cpu.app_fault(iword0, ILLEGAL_INSTRUCTION as u32);
+							} else {
+								for i in 0..4 {
+									cpu.R[d + i] = simd::packed(op as u8, cpu.R[d + i], cpu.R[r + i]);
+								}
+							}
+						},
+						
 						0xFF => {
 							cpu.running.store(false, Ordering::Relaxed);
 						},
@@ -1482,14 +2365,62 @@ impl SeriesQ {
 							cpu.app_fault(0xFFFF, ILLEGAL_INSTRUCTION as u32);
 						},
 					};
-				} else if skip {
-					skip = false;
+					
+					if let Some(d) = &decoded {
+						Hooks::run(&mut cpu, When::After, d, &held_bus);
+					}
+					
+					let entry = TraceEntry {
+						base: cpu.S_base[PS],
+						pc: pc,
+						iword0: iword0,
+						iword1: iword1,
+						result: cpu.R[rr_reg_d(iword0)]
+					};
+					if let Some(log) = &cpu.trace_log {
+						log.retire(&entry);
+					}
+					cpu.trace.retire(entry);
+				} else if cpu.skip {
+					cpu.skip = false;
+				}
+				if cpu.assert_access {
+					cpu.audit_accesses(&held_bus, iword0);
+				}
+				let fixups = held_bus.take_fixups();
+				if fixups != 0 {
+					cpu.F[9] = cpu.F[9].saturating_add(fixups.min(0xFF) as u8);
+				}
+				if cpu.taint.is_some() {
+					if let Some(addr) = held_bus.take_taint() {
+						cpu.taint_report(addr);
+					}
+				}
+				if cpu.smc.is_some() {
+					if let Some(addr) = held_bus.take_modified() {
+						cpu.smc_report(addr);
+					}
 				}
 				
 				}
 				
 				// service interrupts
 				
+				cpu.stage = Stage::Interrupt;
+				
+				if cpu.cycles % TOD_INTERVAL == 0 || cpu.waiting.load(Ordering::Relaxed) {
+					cpu.tod_check();
+				}
+				if cpu.cycles % TOD_INTERVAL == 0 {
+					if let Some(p) = &cpu.profiler {
+						p.lock().unwrap().tick(cpu.S_base[PS], cpu.R[PC], &held_bus);
+					}
+					if let Some(c) = &cpu.clocks {
+						c.advance(TOD_INTERVAL);
+					}
+					cpu.show(true);
+				}
+				
 				let mut new_pl = 0;
 				for (index, state) in cpu.faultpl.iter().enumerate() {
 					if state.load(Ordering::Relaxed) && index > new_pl {
@@ -1503,30 +2434,102 @@ impl SeriesQ {
 					cpu.waiting.store(false, Ordering::Relaxed);
 				} else {
 					new_pl = 0;
+					// a device wins a tie with the storm on its level
+					let storm = cpu.storm.as_ref().map_or(0, |s| s.pending());
+					let mut from_storm = false;
 					for (index, state) in cpu.ipl.iter().enumerate() {
-						if state.load(Ordering::Relaxed) && index > new_pl {
+						let device = state.load(Ordering::Relaxed);
+						if (device || storm & (1 << index) != 0) && index > new_pl {
 							new_pl = index;
+							from_storm = !device;
 						}
 					}
-					let new_code = cpu.icode[new_pl].load(Ordering::Relaxed);
-					if cpu.pl_esc((new_pl & 0xFF) as u8, 0, &mut held_bus) {
+					let mut new_code = if from_storm { STORM_CODE } else { cpu.icode[new_pl].load(Ordering::Relaxed) };
+					
+					// attention wins a tie with a device on its level
+					let attention = cpu.attention.load(Ordering::Relaxed) && cpu.attention_ipl >= new_pl;
+					if attention {
+						new_pl = cpu.attention_ipl;
+						new_code = ATTENTION_CODE;
+						from_storm = false;
+					}
+					if cpu.pl_esc((new_pl & 0xFF) as u8, new_code, &mut held_bus) {
 						//println!("Interrupt {}", new_pl);
+						if attention {
+							cpu.attention.store(false, Ordering::Relaxed);
+						}
+						if from_storm {
+							if let Some(s) = &cpu.storm {
+								s.taken(new_pl);
+							}
+						}
 						cpu.waiting.store(false, Ordering::Relaxed);
 					}
 				}
 					
-				// service DMA
+				if cpu.taint.is_some() {
+					if let Some(addr) = held_bus.take_taint() {
+						cpu.taint_report(addr);
+					}
+				}
+				if cpu.smc.is_some() {
+					if let Some(addr) = held_bus.take_modified() {
+						cpu.smc_report(addr);
+					}
+				}
+				
+				// service DMA; channels get one grant each per round, and rounds are
+				// spaced so a busy device cannot starve instruction fetch
 				
-				for c in &cpu.channels {
-					if c.check_pending() {
+				let idle = cpu.waiting.load(Ordering::Relaxed);
+				if idle || cpu.cycles.wrapping_sub(cpu.last_grant) >= cpu.dma_spacing {
+					let mut grants = 0;
+					if cpu.channels.iter().any(|c| c.check_pending()) {
 						drop(held_bus);
-						c.open();
+						grants = bus::grant_pending(&cpu.channels);
 						held_bus = our_bus.lock().unwrap();
 					}
+					if grants != 0 {
+						cpu.dma_grants += grants as u64;
+						cpu.last_grant = cpu.cycles;
+						held_bus.take_taint();
+						held_bus.take_modified();
+						held_bus.take_fixups();
+					}
 				}
 				cpu.cycles = cpu.cycles.wrapping_add(1);
+				if cpu.cycle_limit != 0 && cpu.cycles >= cpu.cycle_limit {
+					cpu.running.store(false, Ordering::Relaxed);
+				}
+				
+				if cpu.checkpoint.as_ref().map_or(false, |c| c.due(cpu.cycles)) {
+					// quiesce first: DMA already requested and posted device writes
+					// land before the state is taken, not after
+					drop(held_bus);
+					cpu.dma_grants += bus::grant_pending(&cpu.channels) as u64;
+					held_bus = our_bus.lock().unwrap();
+					held_bus.drain();
+					held_bus.take_taint();
+					held_bus.take_modified();
+					held_bus.take_fixups();
+					let snap = Snapshot::new(cpu.save_state(), held_bus.snapshot());
+					let cycles = cpu.cycles;
+					cpu.checkpoint.as_mut().unwrap().write(&snap, cycles);
+				}
 			}
-			println!("@{:08X}::{:08X} CPU STOP - {} cycles", cpu.S_base[PS], cpu.R[PC], cpu.cycles);
-		});
+			if let Some(log) = &cpu.trace_log {
+				log.flush();
+			}
+			cpu.heartbeat.idle(true);
+			cpu.show(false);
+			if let Some(c) = &cpu.clocks {
+				c.run(false);
+			}
+			if cpu.dma_grants != 0 {
+				println!("@{:08X}::{:08X} CPU STOP - {} cycles, {} DMA grants", cpu.S_base[PS], cpu.R[PC], cpu.cycles, cpu.dma_grants);
+			} else {
+				println!("@{:08X}::{:08X} CPU STOP - {} cycles", cpu.S_base[PS], cpu.R[PC], cpu.cycles);
+			}
+		}).unwrap()
 	}
 }
\ No newline at end of file
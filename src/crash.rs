@@ -0,0 +1,187 @@
+use std::fs::{self, OpenOptions};
+use std::panic;
+use std::sync::{Arc, OnceLock, TryLockError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{thread, time};
+use crate::sync::Mutex;
+use crate::bus::{Bus, BusState};
+use crate::cpu::{CpuState, SeriesQ, CPU_THREAD};
+use crate::dasd::Dasd;
+use crate::machine::Machine;
+use crate::options::Options;
+use crate::ram::Ram;
+use crate::snapshot::Snapshot;
+use crate::tape::TapeDrive;
+
+// Crash: what a panic on any emulator thread leaves behind
+//
+// The hook runs on the panicking thread before it unwinds. It stops the CPU,
+// marks the checkpoint files as predating a crash (PATH.crashed, giving the
+// panic), writes back the disk's cache and syncs the disk image, the reel in
+// the tape drive and the printer, punch and transcript files to the host
+// disk, then dumps the trace ring and writes the machine state to CORE_FILE,
+// a snapshot --restore and the monitor can read. Each step gets WAIT to finish
+// on a thread of its own, so one that needs a lock the panic left held, or
+// trips over what it left broken, costs only itself; a step that times out is
+// left blocked, a thread leaked for the rest of the process. A panic on the
+// CPU thread holds the CPU and bus until it unwinds, so that thread writes the
+// trace and core itself on the way out.
+//
+// Any other thread (the monitor, a device, a DMA transfer) may panic holding
+// the bus, which it keeps until the hook returns, so the core step only tries
+// the locks it needs. Given up on after LOCK_WAIT, the bus leaves the core
+// with main memory alone, copied from the RAM directly, and a device region
+// whose lock is held is left out.
+
+pub const CORE_FILE: &str = "rustframe.core";
+
+const WAIT: time::Duration = time::Duration::from_secs(2);
+const LOCK_WAIT: time::Duration = time::Duration::from_millis(500);
+
+struct Crash {
+	cpu: Arc<Mutex<SeriesQ>>,
+	running: Arc<AtomicBool>,
+	dasd: Arc<Mutex<Dasd>>,
+	tape: Arc<Mutex<TapeDrive>>,
+	files: Vec<String>,
+	checkpoint: Option<String>,
+	ram: Arc<Ram>,
+	layout: Vec<(u32, u32)>
+}
+
+static CRASH: OnceLock<Crash> = OnceLock::new();
+
+// only the first panic is handled; the rest are usually its poisoned locks
+static CRASHED: AtomicBool = AtomicBool::new(false);
+
+// that one was on the CPU thread, which writes the core as it unwinds
+static CPU_PANICKED: AtomicBool = AtomicBool::new(false);
+
+// run step on a thread of its own, giving up on it after WAIT
+fn attempt<F: FnOnce() -> Result<(), String> + Send + 'static>(what: &str, step: F) {
+	let handle = thread::spawn(step);
+	let start = time::Instant::now();
+	while !handle.is_finished() && start.elapsed() < WAIT {
+		thread::sleep(time::Duration::from_millis(1));
+	}
+	if !handle.is_finished() {
+		println!("CRASH: {} TIMED OUT", what);
+		return;
+	}
+	match handle.join() {
+		Ok(Ok(_)) => { },
+		Ok(Err(e)) => println!("CRASH: {} FAILED: {}", what, e),
+		Err(_) => println!("CRASH: {} FAILED", what),
+	}
+}
+
+fn sync_file(path: &str) -> Result<(), String> {
+	OpenOptions::new().append(true).open(path).and_then(|f| f.sync_all()).map_err(|e| format!("{}: {}", path, e))
+}
+
+// f of m's contents, or None if whoever holds m keeps it past LOCK_WAIT
+fn try_with<T: ?Sized, U, F: FnOnce(&T) -> U>(m: &Mutex<T>, f: F) -> Option<U> {
+	let start = time::Instant::now();
+	loop {
+		match m.try_lock() {
+			Ok(x) => return Some(f(&x)),
+			Err(TryLockError::Poisoned(e)) => return Some(f(&e.into_inner())),
+			Err(TryLockError::WouldBlock) if start.elapsed() < LOCK_WAIT => thread::sleep(time::Duration::from_millis(1)),
+			Err(TryLockError::WouldBlock) => return None,
+		}
+	}
+}
+
+// the bus as a core keeps it when the bus itself can't be had: main memory,
+// attached at 0, and nothing for the other regions
+fn memory_only(ram: &Ram, layout: &[(u32, u32)]) -> BusState {
+	BusState {
+		base: layout.iter().map(|x| x.0).collect(),
+		size: layout.iter().map(|x| x.1).collect(),
+		region: layout.iter().map(|&(base, size)| {
+			if base == 0 && size == ram.size() { Some(ram.to_vec()) } else { None }
+		}).collect()
+	}
+}
+
+fn write_core(cpu: CpuState, bus: BusState) -> Result<(), String> {
+	Snapshot::new(cpu, bus).save(CORE_FILE).map_err(|e| format!("{}: {}", CORE_FILE, e))?;
+	println!("CRASH: MACHINE STATE WRITTEN TO {}", CORE_FILE);
+	Ok(())
+}
+
+fn save(crash: &Crash, why: String) {
+	crash.running.store(false, Ordering::Relaxed);
+	
+	if let Some(path) = &crash.checkpoint {
+		let mark = format!("{}.crashed", path);
+		let note = format!("{}\n{}.0 is the newest checkpoint from before the crash\n", why, path);
+		if let Err(e) = fs::write(&mark, note) {
+			println!("CRASH: {}: {}", mark, e);
+		}
+	}
+	
+	let dasd = Arc::clone(&crash.dasd);
+	attempt("DISK SYNC", move || dasd.lock().unwrap_or_else(|e| e.into_inner()).sync());
+	let tape = Arc::clone(&crash.tape);
+	attempt("TAPE SYNC", move || {
+		let drive = tape.lock().unwrap_or_else(|e| e.into_inner());
+		drive.reel.as_ref().map_or(Ok(()), |r| sync_file(&r.path))
+	});
+	let files = crash.files.clone();
+	attempt("OUTPUT SYNC", move || files.iter().try_for_each(|x| sync_file(x)));
+	
+	if thread::current().name() == Some(CPU_THREAD) {
+		CPU_PANICKED.store(true, Ordering::SeqCst);
+		return;
+	}
+	// the CPU lets go of its lock once it has stopped
+	let (cpu, ram, layout) = (Arc::clone(&crash.cpu), Arc::clone(&crash.ram), crash.layout.clone());
+	attempt("CORE", move || {
+		let (state, bus) = try_with(&*cpu, |cpu| {
+			cpu.trace.dump();
+			(cpu.save_state(), Arc::clone(&cpu.bus))
+		}).ok_or("CPU HELD BY THE PANICKING THREAD")?;
+		let bus = try_with(&*bus, Bus::try_snapshot).unwrap_or_else(|| {
+			println!("CRASH: BUS HELD BY THE PANICKING THREAD, CORE HAS MAIN MEMORY ONLY");
+			memory_only(&ram, &layout)
+		});
+		write_core(state, bus)
+	});
+}
+
+pub fn install(machine: &Machine, opt: &Options) {
+	let crash = Crash {
+		cpu: Arc::clone(&machine.cpu),
+		running: Arc::clone(&machine.cpu.lock().unwrap().running),
+		dasd: Arc::clone(&machine.dasd),
+		tape: Arc::clone(&machine.tape),
+		files: [&opt.print_out, &opt.punch_out, &opt.transcript].iter().filter_map(|x| (*x).clone()).collect(),
+		checkpoint: opt.checkpoint.clone(),
+		ram: Arc::clone(&machine.ram),
+		layout: machine.bus.lock().unwrap().layout()
+	};
+	if CRASH.set(crash).is_err() {
+		return;
+	}
+	
+	let previous = panic::take_hook();
+	panic::set_hook(Box::new(move |info| {
+		previous(info);
+		if !CRASHED.swap(true, Ordering::SeqCst) {
+			save(CRASH.get().unwrap(), info.to_string());
+		}
+	}));
+}
+
+// the CPU thread, unwinding from its panic with the CPU and bus let go; its
+// panic dump has already shown the trace ring
+pub fn cpu_unwound(cpu: &SeriesQ) {
+	if CPU_PANICKED.swap(false, Ordering::SeqCst) {
+		let (state, bus) = (cpu.save_state(), Arc::clone(&cpu.bus));
+		attempt("CORE", move || {
+			let bus = try_with(&*bus, Bus::try_snapshot).ok_or("BUS HELD")?;
+			write_core(state, bus)
+		});
+	}
+}
@@ -0,0 +1,177 @@
+// Crypto: AES-128 and SHA-256 primitives for the crypto instruction page
+//
+// Operands are blocks of memory, so byte order is the memory's own: AES keys
+// and blocks and SHA-256 message blocks are taken byte by byte in address
+// order, as the standards write them. The SHA-256 chaining state is eight
+// machine words, so the guest can load and store it with L and ST.
+
+const SBOX: [u8; 256] = [
+	0x63, 0x7C, 0x77, 0x7B, 0xF2, 0x6B, 0x6F, 0xC5, 0x30, 0x01, 0x67, 0x2B, 0xFE, 0xD7, 0xAB, 0x76,
+	0xCA, 0x82, 0xC9, 0x7D, 0xFA, 0x59, 0x47, 0xF0, 0xAD, 0xD4, 0xA2, 0xAF, 0x9C, 0xA4, 0x72, 0xC0,
+	0xB7, 0xFD, 0x93, 0x26, 0x36, 0x3F, 0xF7, 0xCC, 0x34, 0xA5, 0xE5, 0xF1, 0x71, 0xD8, 0x31, 0x15,
+	0x04, 0xC7, 0x23, 0xC3, 0x18, 0x96, 0x05, 0x9A, 0x07, 0x12, 0x80, 0xE2, 0xEB, 0x27, 0xB2, 0x75,
+	0x09, 0x83, 0x2C, 0x1A, 0x1B, 0x6E, 0x5A, 0xA0, 0x52, 0x3B, 0xD6, 0xB3, 0x29, 0xE3, 0x2F, 0x84,
+	0x53, 0xD1, 0x00, 0xED, 0x20, 0xFC, 0xB1, 0x5B, 0x6A, 0xCB, 0xBE, 0x39, 0x4A, 0x4C, 0x58, 0xCF,
+	0xD0, 0xEF, 0xAA, 0xFB, 0x43, 0x4D, 0x33, 0x85, 0x45, 0xF9, 0x02, 0x7F, 0x50, 0x3C, 0x9F, 0xA8,
+	0x51, 0xA3, 0x40, 0x8F, 0x92, 0x9D, 0x38, 0xF5, 0xBC, 0xB6, 0xDA, 0x21, 0x10, 0xFF, 0xF3, 0xD2,
+	0xCD, 0x0C, 0x13, 0xEC, 0x5F, 0x97, 0x44, 0x17, 0xC4, 0xA7, 0x7E, 0x3D, 0x64, 0x5D, 0x19, 0x73,
+	0x60, 0x81, 0x4F, 0xDC, 0x22, 0x2A, 0x90, 0x88, 0x46, 0xEE, 0xB8, 0x14, 0xDE, 0x5E, 0x0B, 0xDB,
+	0xE0, 0x32, 0x3A, 0x0A, 0x49, 0x06, 0x24, 0x5C, 0xC2, 0xD3, 0xAC, 0x62, 0x91, 0x95, 0xE4, 0x79,
+	0xE7, 0xC8, 0x37, 0x6D, 0x8D, 0xD5, 0x4E, 0xA9, 0x6C, 0x56, 0xF4, 0xEA, 0x65, 0x7A, 0xAE, 0x08,
+	0xBA, 0x78, 0x25, 0x2E, 0x1C, 0xA6, 0xB4, 0xC6, 0xE8, 0xDD, 0x74, 0x1F, 0x4B, 0xBD, 0x8B, 0x8A,
+	0x70, 0x3E, 0xB5, 0x66, 0x48, 0x03, 0xF6, 0x0E, 0x61, 0x35, 0x57, 0xB9, 0x86, 0xC1, 0x1D, 0x9E,
+	0xE1, 0xF8, 0x98, 0x11, 0x69, 0xD9, 0x8E, 0x94, 0x9B, 0x1E, 0x87, 0xE9, 0xCE, 0x55, 0x28, 0xDF,
+	0x8C, 0xA1, 0x89, 0x0D, 0xBF, 0xE6, 0x42, 0x68, 0x41, 0x99, 0x2D, 0x0F, 0xB0, 0x54, 0xBB, 0x16,
+];
+
+const INV_SBOX: [u8; 256] = [
+	0x52, 0x09, 0x6A, 0xD5, 0x30, 0x36, 0xA5, 0x38, 0xBF, 0x40, 0xA3, 0x9E, 0x81, 0xF3, 0xD7, 0xFB,
+	0x7C, 0xE3, 0x39, 0x82, 0x9B, 0x2F, 0xFF, 0x87, 0x34, 0x8E, 0x43, 0x44, 0xC4, 0xDE, 0xE9, 0xCB,
+	0x54, 0x7B, 0x94, 0x32, 0xA6, 0xC2, 0x23, 0x3D, 0xEE, 0x4C, 0x95, 0x0B, 0x42, 0xFA, 0xC3, 0x4E,
+	0x08, 0x2E, 0xA1, 0x66, 0x28, 0xD9, 0x24, 0xB2, 0x76, 0x5B, 0xA2, 0x49, 0x6D, 0x8B, 0xD1, 0x25,
+	0x72, 0xF8, 0xF6, 0x64, 0x86, 0x68, 0x98, 0x16, 0xD4, 0xA4, 0x5C, 0xCC, 0x5D, 0x65, 0xB6, 0x92,
+	0x6C, 0x70, 0x48, 0x50, 0xFD, 0xED, 0xB9, 0xDA, 0x5E, 0x15, 0x46, 0x57, 0xA7, 0x8D, 0x9D, 0x84,
+	0x90, 0xD8, 0xAB, 0x00, 0x8C, 0xBC, 0xD3, 0x0A, 0xF7, 0xE4, 0x58, 0x05, 0xB8, 0xB3, 0x45, 0x06,
+	0xD0, 0x2C, 0x1E, 0x8F, 0xCA, 0x3F, 0x0F, 0x02, 0xC1, 0xAF, 0xBD, 0x03, 0x01, 0x13, 0x8A, 0x6B,
+	0x3A, 0x91, 0x11, 0x41, 0x4F, 0x67, 0xDC, 0xEA, 0x97, 0xF2, 0xCF, 0xCE, 0xF0, 0xB4, 0xE6, 0x73,
+	0x96, 0xAC, 0x74, 0x22, 0xE7, 0xAD, 0x35, 0x85, 0xE2, 0xF9, 0x37, 0xE8, 0x1C, 0x75, 0xDF, 0x6E,
+	0x47, 0xF1, 0x1A, 0x71, 0x1D, 0x29, 0xC5, 0x89, 0x6F, 0xB7, 0x62, 0x0E, 0xAA, 0x18, 0xBE, 0x1B,
+	0xFC, 0x56, 0x3E, 0x4B, 0xC6, 0xD2, 0x79, 0x20, 0x9A, 0xDB, 0xC0, 0xFE, 0x78, 0xCD, 0x5A, 0xF4,
+	0x1F, 0xDD, 0xA8, 0x33, 0x88, 0x07, 0xC7, 0x31, 0xB1, 0x12, 0x10, 0x59, 0x27, 0x80, 0xEC, 0x5F,
+	0x60, 0x51, 0x7F, 0xA9, 0x19, 0xB5, 0x4A, 0x0D, 0x2D, 0xE5, 0x7A, 0x9F, 0x93, 0xC9, 0x9C, 0xEF,
+	0xA0, 0xE0, 0x3B, 0x4D, 0xAE, 0x2A, 0xF5, 0xB0, 0xC8, 0xEB, 0xBB, 0x3C, 0x83, 0x53, 0x99, 0x61,
+	0x17, 0x2B, 0x04, 0x7E, 0xBA, 0x77, 0xD6, 0x26, 0xE1, 0x69, 0x14, 0x63, 0x55, 0x21, 0x0C, 0x7D,
+];
+
+const RCON: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1B, 0x36];
+
+const K256: [u32; 64] = [
+	0x428A2F98, 0x71374491, 0xB5C0FBCF, 0xE9B5DBA5, 0x3956C25B, 0x59F111F1, 0x923F82A4, 0xAB1C5ED5,
+	0xD807AA98, 0x12835B01, 0x243185BE, 0x550C7DC3, 0x72BE5D74, 0x80DEB1FE, 0x9BDC06A7, 0xC19BF174,
+	0xE49B69C1, 0xEFBE4786, 0x0FC19DC6, 0x240CA1CC, 0x2DE92C6F, 0x4A7484AA, 0x5CB0A9DC, 0x76F988DA,
+	0x983E5152, 0xA831C66D, 0xB00327C8, 0xBF597FC7, 0xC6E00BF3, 0xD5A79147, 0x06CA6351, 0x14292967,
+	0x27B70A85, 0x2E1B2138, 0x4D2C6DFC, 0x53380D13, 0x650A7354, 0x766A0ABB, 0x81C2C92E, 0x92722C85,
+	0xA2BFE8A1, 0xA81A664B, 0xC24B8B70, 0xC76C51A3, 0xD192E819, 0xD6990624, 0xF40E3585, 0x106AA070,
+	0x19A4C116, 0x1E376C08, 0x2748774C, 0x34B0BCB5, 0x391C0CB3, 0x4ED8AA4A, 0x5B9CCA4F, 0x682E6FF3,
+	0x748F82EE, 0x78A5636F, 0x84C87814, 0x8CC70208, 0x90BEFFFA, 0xA4506CEB, 0xBEF9A3F7, 0xC67178F2,
+];
+
+// multiply by x in GF(2^8)
+fn xtime(a: u8) -> u8 {
+	(a << 1) ^ if a & 0x80 != 0 { 0x1B } else { 0 }
+}
+
+fn gmul(mut a: u8, mut b: u8) -> u8 {
+	let mut p = 0;
+	while b != 0 {
+		if b & 1 != 0 {
+			p ^= a;
+		}
+		a = xtime(a);
+		b >>= 1;
+	}
+	p
+}
+
+fn expand_key(key: &[u8; 16]) -> [[u8; 16]; 11] {
+	let mut rk = [[0u8; 16]; 11];
+	rk[0] = *key;
+	for round in 1..11 {
+		let prev = rk[round - 1];
+		let mut t = [prev[13], prev[14], prev[15], prev[12]];
+		for b in t.iter_mut() {
+			*b = SBOX[*b as usize];
+		}
+		t[0] ^= RCON[round - 1];
+		for i in 0..16 {
+			let w = if i < 4 { t[i] } else { rk[round][i - 4] };
+			rk[round][i] = prev[i] ^ w;
+		}
+	}
+	rk
+}
+
+fn add_round_key(state: &mut [u8; 16], rk: &[u8; 16]) {
+	for (s, k) in state.iter_mut().zip(rk.iter()) {
+		*s ^= k;
+	}
+}
+
+// the state is column-major: byte i is row i % 4 of column i / 4
+fn shift_rows(state: &mut [u8; 16], inverse: bool) {
+	let old = *state;
+	for col in 0..4 {
+		for row in 1..4 {
+			let from = if inverse { (col + 4 - row) % 4 } else { (col + row) % 4 };
+			state[col * 4 + row] = old[from * 4 + row];
+		}
+	}
+}
+
+fn mix_columns(state: &mut [u8; 16], inverse: bool) {
+	let m: [u8; 4] = if inverse { [14, 11, 13, 9] } else { [2, 3, 1, 1] };
+	for col in state.chunks_mut(4) {
+		let c = [col[0], col[1], col[2], col[3]];
+		for row in 0..4 {
+			col[row] = (0..4).fold(0, |acc, i| acc ^ gmul(c[(row + i) % 4], m[i]));
+		}
+	}
+}
+
+pub fn aes128_encrypt(key: &[u8; 16], block: &mut [u8; 16]) {
+	let rk = expand_key(key);
+	add_round_key(block, &rk[0]);
+	for round in 1..11 {
+		for b in block.iter_mut() {
+			*b = SBOX[*b as usize];
+		}
+		shift_rows(block, false);
+		if round != 10 {
+			mix_columns(block, false);
+		}
+		add_round_key(block, &rk[round]);
+	}
+}
+
+pub fn aes128_decrypt(key: &[u8; 16], block: &mut [u8; 16]) {
+	let rk = expand_key(key);
+	add_round_key(block, &rk[10]);
+	for round in (0..10).rev() {
+		shift_rows(block, true);
+		for b in block.iter_mut() {
+			*b = INV_SBOX[*b as usize];
+		}
+		add_round_key(block, &rk[round]);
+		if round != 0 {
+			mix_columns(block, true);
+		}
+	}
+}
+
+// one application of the compression function; padding is the guest's business
+pub fn sha256_compress(state: &mut [u32; 8], block: &[u8; 64]) {
+	let mut w = [0u32; 64];
+	for (i, chunk) in block.chunks(4).enumerate() {
+		w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+	}
+	for i in 16..64 {
+		let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+		let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+		w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+	}
+	
+	let mut v = *state;
+	for i in 0..64 {
+		let (a, b, c, d, e, f, g, h) = (v[0], v[1], v[2], v[3], v[4], v[5], v[6], v[7]);
+		let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+		let ch = (e & f) ^ (!e & g);
+		let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(K256[i]).wrapping_add(w[i]);
+		let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+		let maj = (a & b) ^ (a & c) ^ (b & c);
+		let t2 = s0.wrapping_add(maj);
+		v = [t1.wrapping_add(t2), a, b, c, d.wrapping_add(t1), e, f, g];
+	}
+	for (s, x) in state.iter_mut().zip(v.iter()) {
+		*s = s.wrapping_add(*x);
+	}
+}
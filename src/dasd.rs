@@ -0,0 +1,244 @@
+use std::io;
+use crate::bus::{Memory32, BusError};
+use crate::diskimage::{DiskImage, DiskError, SyncPolicy, MAX_BLOCK_SIZE};
+use crate::transcript;
+use serde::{Serialize, Deserialize};
+
+// DASD: a disk drive holding one volume, read and written a block at a time
+//
+// Registers: command at 0 (word), status at 4, cylinder at 8 (halfword), head
+// at 10, sector at 11, the volume's geometry at 12 in the same layout, block
+// size at 16 (halfword), block buffer at DASD_BUFFER (past the ID block).
+//
+//   1 READ   read the block at the cylinder, head and sector into the buffer
+//   2 WRITE  write the buffer to the block
+//   3 FLUSH  write any blocks held in the write-back cache to the volume
+//
+// Commands complete at once. Status: EQDN..PR (Error, eQuipment check, Data
+// check, No record, ..., write Protected, Ready). The check bits say why the
+// last command failed and stay until the next one; a write to a protected
+// volume sets Error alone. A data check comes from
+// the volume's bad block map (see diskimage.rs) or a block failed from the
+// monitor; the buffer is left as it was, so a guest can retry.
+//
+// The drive's saved state names the volume it has mounted, so restoring it
+// mounts that volume again if another has been put in since.
+
+pub const DASD_BUFFER: u32 = 0x100;
+pub const DASD_REGION_SIZE: u32 = DASD_BUFFER + MAX_BLOCK_SIZE as u32;
+
+pub const DASD_STATUS: u32 = 4;
+
+pub const DASD_READ: u32 = 1;
+pub const DASD_WRITE: u32 = 2;
+pub const DASD_FLUSH: u32 = 3;
+
+pub const DASD_READY: u8 = 0b00000001;
+pub const DASD_PROTECTED: u8 = 0b00000010;
+pub const DASD_NO_RECORD: u8 = 0b00010000;
+pub const DASD_DATA_CHECK: u8 = 0b00100000;
+pub const DASD_EQUIPMENT_CHECK: u8 = 0b01000000;
+pub const DASD_ERROR: u8 = 0b10000000;
+
+// how a volume was mounted, enough to mount it again
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Mount {
+	pub path: String,
+	pub overlay: Option<String>,
+	pub cache: Option<usize>,
+	pub sync: SyncPolicy
+}
+
+impl Mount {
+	pub fn new(path: &str) -> Mount {
+		Mount { path: path.to_string(), overlay: None, cache: None, sync: SyncPolicy::Flush }
+	}
+	
+	pub fn open(&self) -> Result<DiskImage, String> {
+		// a file the host will not let us write is mounted write-protected
+		let mut image = match DiskImage::open(&self.path, self.overlay.is_some()) {
+			Err(e) if e.kind() == io::ErrorKind::PermissionDenied => DiskImage::open(&self.path, true),
+			x => x,
+		}.map_err(|e| format!("{}: {}", self.path, e))?;
+		if let Some(overlay) = &self.overlay {
+			image.overlay(overlay).map_err(|e| format!("{}: {}", overlay, e))?;
+		}
+		image.cache = self.cache;
+		image.sync = self.sync;
+		Ok(image)
+	}
+	
+	pub fn name(&self) -> String {
+		match &self.overlay {
+			Some(overlay) => format!("{} over {}", overlay, self.path),
+			None => self.path.clone(),
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+struct DasdState {
+	regs: Vec<u8>,
+	mount: Option<Mount>
+}
+
+pub struct Dasd {
+	pub regs: Vec<u8>,
+	pub volume: Option<(Mount, DiskImage)>
+}
+
+impl Dasd {
+	pub fn new() -> Dasd {
+		Dasd {
+			regs: vec![0 as u8; DASD_REGION_SIZE as usize],
+			volume: None
+		}
+	}
+	
+	// put a volume in the drive, writing back the cache of the one it replaces
+	pub fn mount(&mut self, mount: Option<Mount>) -> Result<(), String> {
+		self.flush()?;
+		self.volume = match mount {
+			Some(m) => {
+				let image = m.open()?;
+				transcript::record("DASD", &format!("MOUNTED {}", m.name()));
+				Some((m, image))
+			},
+			None => None,
+		};
+		self.show_volume();
+		Ok(())
+	}
+	
+	// the geometry registers and ready bits for whatever is mounted
+	fn show_volume(&mut self) {
+		let (geometry, mounted) = match &self.volume {
+			Some((_, image)) => {
+				let g = image.geometry;
+				([g.cylinders, g.heads as u16 | (g.sectors as u16) << 8, g.block_size],
+					DASD_READY | if image.read_only { DASD_PROTECTED } else { 0 })
+			},
+			None => ([0, 0, 0], 0),
+		};
+		for (n, x) in geometry.iter().enumerate() {
+			self.regs.write_h(12 + 2 * n as u32, *x).unwrap();
+		}
+		let s = &mut self.regs[DASD_STATUS as usize];
+		*s = (*s & !(DASD_READY | DASD_PROTECTED)) | mounted;
+	}
+	
+	fn status(&mut self, check: u8) {
+		let s = &mut self.regs[DASD_STATUS as usize];
+		*s = (*s & (DASD_READY | DASD_PROTECTED)) | if check != 0 { DASD_ERROR | check } else { 0 };
+	}
+	
+	fn command(&mut self, command: u32) {
+		let (mount, image) = match &mut self.volume {
+			Some(x) => x,
+			None => {
+				self.regs[DASD_STATUS as usize] = DASD_ERROR;
+				return;
+			},
+		};
+		let size = image.geometry.block_size as usize;
+		let block = image.geometry.block(self.regs.read_h(8).unwrap(), self.regs[10], self.regs[11]);
+		let buffer = &mut self.regs[DASD_BUFFER as usize..DASD_BUFFER as usize + size];
+		let result = match (command, block) {
+			(DASD_READ, None) | (DASD_WRITE, None) => Err(DiskError::NoRecord),
+			(DASD_READ, Some(n)) => image.read(n, buffer),
+			(DASD_WRITE, Some(n)) => image.write(n, buffer),
+			(DASD_FLUSH, _) => image.flush().map_err(DiskError::Host),
+			_ => {
+				self.status(DASD_ERROR);
+				return;
+			},
+		};
+		let check = match result {
+			Ok(_) => 0,
+			Err(DiskError::NoRecord) => DASD_NO_RECORD,
+			Err(DiskError::DataCheck) => DASD_DATA_CHECK,
+			Err(DiskError::WriteProtected) => DASD_PROTECTED,
+			Err(DiskError::Host(e)) => {
+				transcript::record("DASD", &format!("{}: {}", mount.name(), e));
+				DASD_EQUIPMENT_CHECK
+			},
+		};
+		self.status(check);
+	}
+	
+	// write back what the cache holds, before the emulator goes away
+	pub fn flush(&mut self) -> Result<(), String> {
+		match &mut self.volume {
+			Some((mount, image)) if image.dirty() > 0 => image.flush().map_err(|e| format!("{}: {}", mount.name(), e)),
+			_ => Ok(()),
+		}
+	}
+	
+	// write back the cache and sync the image to the host disk whatever the
+	// policy, for when the emulator is going down unexpectedly
+	pub fn sync(&mut self) -> Result<(), String> {
+		match &mut self.volume {
+			Some((mount, image)) => image.flush().and_then(|_| image.sync_file()).map_err(|e| format!("{}: {}", mount.name(), e)),
+			None => Ok(()),
+		}
+	}
+	
+	// the buffer and the cylinder, head and sector registers are the guest's
+	fn writable(addr: u32, width: u32) -> bool {
+		addr >= 8 && (addr as u64 + width as u64 <= 12 || addr >= DASD_BUFFER)
+	}
+}
+
+impl Memory32<u32, BusError> for Dasd {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.regs.read_b(addr)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h(addr)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h_big(addr)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		self.regs.read_w(addr)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		if !Dasd::writable(addr, 1) {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_b(addr, data)
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		if !Dasd::writable(addr, 2) {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_h(addr, data)
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		match addr {
+			0 => self.command(data),
+			_ if Dasd::writable(addr, 4) => self.regs.write_w(addr, data)?,
+			_ => return Err(BusError::InvalidAddress),
+		}
+		Ok(())
+	}
+	
+	// the volume's contents belong to its file, not the machine
+	fn save_state(&self) -> Option<Vec<u8>> {
+		let state = DasdState { regs: self.regs.clone(), mount: self.volume.as_ref().map(|(m, _)| m.clone()) };
+		bincode::serialize(&state).ok()
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		let saved: DasdState = bincode::deserialize(state).map_err(|_| BusError::InvalidState)?;
+		if saved.mount != self.volume.as_ref().map(|(m, _)| m.clone()) {
+			if let Err(e) = self.mount(saved.mount) {
+				println!("{}", e);
+				return Err(BusError::InvalidState);
+			}
+		}
+		self.regs = saved.regs;
+		self.show_volume();
+		Ok(())
+	}
+}
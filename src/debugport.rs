@@ -0,0 +1,98 @@
+use std::io::{self, Write};
+use std::time::Instant;
+use crate::bus::{Memory32, BusError};
+use crate::charset::CodePage;
+use crate::transcript;
+
+// DebugPort: any byte stored here goes straight to the host's stderr, each line
+// prefixed with seconds since power-on; reads return zero
+
+pub const DEBUGPORT_SIZE: u32 = 4;
+
+pub struct DebugPort {
+	pub codepage: CodePage,
+	start: Instant,
+	line_start: bool,
+	line: String		// the current line, for the transcript
+}
+
+impl DebugPort {
+	pub fn new() -> DebugPort {
+		DebugPort {
+			codepage: CodePage::Latin1,
+			start: Instant::now(),
+			line_start: true,
+			line: String::new()
+		}
+	}
+	
+	fn put(&mut self, b: u8) {
+		let mut err = io::stderr().lock();
+		if self.line_start {
+			let t = self.start.elapsed();
+			let _ = write!(err, "[{:5}.{:06}] ", t.as_secs(), t.subsec_micros());
+			self.line_start = false;
+		}
+		
+		match self.codepage.to_char(b) {
+			'\n' => {
+				let _ = writeln!(err);
+				self.line_start = true;
+				transcript::record("DEBUG", &std::mem::take(&mut self.line));
+			},
+			'\r' => { },
+			c => {
+				let _ = write!(err, "{}", c);
+				self.line.push(c);
+			},
+		}
+		let _ = err.flush();
+	}
+}
+
+// the port is one word, and takes naturally aligned accesses to it
+fn check(addr: u32, width: u32) -> Result<(), BusError> {
+	if addr as u64 + width as u64 > DEBUGPORT_SIZE as u64 {
+		Err(BusError::InvalidAddress)
+	} else if addr % width != 0 {
+		Err(BusError::AlignmentCheck)
+	} else {
+		Ok(())
+	}
+}
+
+impl Memory32<u32, BusError> for DebugPort {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		check(addr, 1).map(|_| 0)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		check(addr, 2).map(|_| 0)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		check(addr, 2).map(|_| 0)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		check(addr, 4).map(|_| 0)
+	}
+	
+	// wider stores send only their low byte
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		check(addr, 1)?;
+		self.put(data);
+		Ok(())
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		check(addr, 2)?;
+		self.put((data & 0xFF) as u8);
+		Ok(())
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		check(addr, 4)?;
+		self.put((data & 0xFF) as u8);
+		Ok(())
+	}
+	
+	fn idempotent(&self, _addr: u32) -> bool {
+		true
+	}
+}
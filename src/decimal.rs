@@ -0,0 +1,72 @@
+// Packed decimal for the decimal instructions (opcodes 0xC8-0xCA)
+//
+// A field is DECIMAL_SIZE bytes in memory: fifteen digits, most significant
+// first and two to a byte, then a sign in the low nibble of the last byte. A,
+// C, E and F are plus and B and D are minus; results are signed C or D, and a
+// zero result is always plus. A digit above 9 or a sign below A in a field
+// that is read for its value is a data error.
+//
+//   +0 AP   add the second field to the first
+//   +1 SP   subtract the second field from the first
+//   +2 ZAP  the first field = the second; the first isn't looked at
+//
+// A result of more than fifteen digits is a decimal overflow. Either error
+// leaves the first field as it was.
+
+pub const DECIMAL_SIZE: usize = 8;
+
+// one more than the largest fifteen-digit value
+const LIMIT: i64 = 1_000_000_000_000_000;
+
+const PLUS: u8 = 0xC;
+const MINUS: u8 = 0xD;
+
+pub enum DecimalError {
+	Data,
+	Overflow
+}
+
+pub fn is_decimal(opcode: u8) -> bool {
+	matches!(opcode, 0xC8..=0xCA)
+}
+
+pub fn unpack(field: &[u8; DECIMAL_SIZE]) -> Result<i64, DecimalError> {
+	let mut x: i64 = 0;
+	for n in 0..2 * DECIMAL_SIZE - 1 {
+		let digit = (field[n / 2] >> if n % 2 == 0 { 4 } else { 0 }) & 0xF;
+		if digit > 9 {
+			return Err(DecimalError::Data);
+		}
+		x = 10 * x + digit as i64;
+	}
+	match field[DECIMAL_SIZE - 1] & 0xF {
+		0xB | 0xD => Ok(-x),
+		0xA | 0xC | 0xE | 0xF => Ok(x),
+		_ => Err(DecimalError::Data),
+	}
+}
+
+pub fn pack(x: i64) -> [u8; DECIMAL_SIZE] {
+	let mut field = [0u8; DECIMAL_SIZE];
+	let mut digits = x.unsigned_abs();
+	field[DECIMAL_SIZE - 1] = if x < 0 { MINUS } else { PLUS };
+	for n in (0..2 * DECIMAL_SIZE - 1).rev() {
+		field[n / 2] |= ((digits % 10) as u8) << if n % 2 == 0 { 4 } else { 0 };
+		digits /= 10;
+	}
+	field
+}
+
+// the value the instruction leaves in the first field
+pub fn operate(opcode: u8, first: &[u8; DECIMAL_SIZE], second: &[u8; DECIMAL_SIZE]) -> Result<i64, DecimalError> {
+	let y = match opcode & 0x03 {
+		0 => unpack(first)? + unpack(second)?,
+		1 => unpack(first)? - unpack(second)?,
+		_ => unpack(second)?,
+	};
+	if y.abs() >= LIMIT {
+		Err(DecimalError::Overflow)
+	} else {
+		Ok(y)
+	}
+}
@@ -0,0 +1,133 @@
+use std::sync::Arc;
+use crate::sync::Mutex;
+use crate::bus::{Memory32, BusError};
+use crate::discovery::*;
+use crate::machine::Region;
+
+// Identified: a device region with the standard ID block at ID_BLOCK
+//
+// Words: vendor at 0xF0 (VENDOR, "SQ"), model at 0xF4 (the discovery type in
+// the low half, the product number if the device has one in the high half),
+// revision at 0xF8, capability bits at 0xFC:
+//
+//   0	the device interrupts (the discovery table gives its IPL)
+//   1	the device masters the bus over a DMA channel
+//   2	writes are posted to a device thread (reads still wait for it)
+//
+// The block is read-only. The machine puts one around every device it
+// attaches, making its region at least ID_REGION_SIZE bytes, so a device's
+// own registers are below 0xF0 or from 0x100 on. A driver checks the model
+// and revision it was written for before touching anything else.
+
+pub const ID_BLOCK: u32 = 0xF0;
+pub const ID_REGION_SIZE: u32 = 0x100;
+
+pub const VENDOR: u32 = 0x5153;
+
+pub const CAP_INTERRUPT: u32 = 0b001;
+pub const CAP_DMA: u32 = 0b010;
+pub const CAP_QUEUED: u32 = 0b100;
+
+// a device's revision goes up when its registers change
+fn revision(kind: u32) -> u32 {
+	match kind {
+		DT_PRINTER => 2,		// geometry register and page printing
+		DT_DATAPORT => 2,		// FIFOs, loopback and the UART
+		_ => 1,
+	}
+}
+
+// the ID block for what the machine attached as region
+pub fn ident(region: &Region) -> Vec<u8> {
+	// the product number is the model name's leading number, as in "1204 line printer"
+	let product = region.name.split(' ').next().and_then(|x| x.parse::<u16>().ok()).unwrap_or(0);
+	let mut caps = 0;
+	if region.ipl.is_some() {
+		caps |= CAP_INTERRUPT;
+	}
+	if region.kind == DT_PRINTER {
+		caps |= CAP_DMA;
+	}
+	if region.backing == "queued" {
+		caps |= CAP_QUEUED;
+	}
+	let mut out = Vec::with_capacity(16);
+	for word in [VENDOR, (product as u32) << 16 | region.kind, revision(region.kind), caps] {
+		out.extend_from_slice(&word.to_le_bytes());
+	}
+	out
+}
+
+pub struct Identified {
+	device: Arc<Mutex<dyn Memory32<u32, BusError> + Send>>,
+	id: Vec<u8>
+}
+
+impl Identified {
+	pub fn new(device: Arc<Mutex<dyn Memory32<u32, BusError> + Send>>, id: Vec<u8>) -> Identified {
+		Identified { device: device, id: id }
+	}
+}
+
+fn in_block(addr: u32) -> bool {
+	addr >= ID_BLOCK && addr < ID_REGION_SIZE
+}
+
+impl Memory32<u32, BusError> for Identified {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		if in_block(addr) {
+			return self.id.read_b(addr - ID_BLOCK);
+		}
+		self.device.lock().unwrap().read_b(addr)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		if in_block(addr) {
+			return self.id.read_h(addr - ID_BLOCK);
+		}
+		self.device.lock().unwrap().read_h(addr)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		if in_block(addr) {
+			return self.id.read_h_big(addr - ID_BLOCK);
+		}
+		self.device.lock().unwrap().read_h_big(addr)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		if in_block(addr) {
+			return self.id.read_w(addr - ID_BLOCK);
+		}
+		self.device.lock().unwrap().read_w(addr)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		if in_block(addr) {
+			return Err(BusError::InvalidAddress);
+		}
+		self.device.lock().unwrap().write_b(addr, data)
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		if in_block(addr) {
+			return Err(BusError::InvalidAddress);
+		}
+		self.device.lock().unwrap().write_h(addr, data)
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		if in_block(addr) {
+			return Err(BusError::InvalidAddress);
+		}
+		self.device.lock().unwrap().write_w(addr, data)
+	}
+	
+	fn save_state(&self) -> Option<Vec<u8>> {
+		self.device.lock().unwrap().save_state()
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		self.device.lock().unwrap().load_state(state)
+	}
+	fn drain(&self) {
+		self.device.lock().unwrap().drain()
+	}
+	fn idempotent(&self, addr: u32) -> bool {
+		in_block(addr) || self.device.lock().unwrap().idempotent(addr)
+	}
+}
@@ -0,0 +1,50 @@
+use crate::machine::Region;
+
+// Discovery: the table telling guest software where everything is attached
+//
+// The table is read-only and always at DISCOVERY_BASE; everything else may
+// move. It is made of words:
+//   0		number of entries
+//   4...	entries of four words each: type, base, size, IPL (all ones for none)
+// Entries are in the machine's attach order, one per region, the table
+// itself included. A type identifies the kind of device, not its instance.
+
+pub const DISCOVERY_BASE: u32 = 0xE0000;
+pub const DISCOVERY_SIZE: u32 = 0x1000;
+
+pub const DT_RAM: u32 = 0x01;
+pub const DT_ROM: u32 = 0x02;
+pub const DT_DISCOVERY: u32 = 0x03;
+pub const DT_FONT: u32 = 0x04;
+pub const DT_PRINTER: u32 = 0x10;
+pub const DT_DATAPORT: u32 = 0x11;
+pub const DT_READER: u32 = 0x12;
+pub const DT_PUNCH: u32 = 0x13;
+pub const DT_DEBUGPORT: u32 = 0x14;
+pub const DT_SEMIHOST: u32 = 0x15;
+pub const DT_PROFILER: u32 = 0x16;
+pub const DT_REMOTE: u32 = 0x17;
+pub const DT_SHARED: u32 = 0x18;
+pub const DT_HOSTCMD: u32 = 0x19;
+pub const DT_OPCONSOLE: u32 = 0x1A;
+pub const DT_DASD: u32 = 0x1B;
+pub const DT_TAPE: u32 = 0x1C;
+pub const DT_PANEL: u32 = 0x1D;
+pub const DT_CLIPBOARD: u32 = 0x1E;
+pub const DT_TRACE: u32 = 0x1F;
+pub const DT_IOMMU: u32 = 0x20;
+pub const DT_DISPLAY: u32 = 0x21;
+
+const ENTRY: usize = 16;
+
+// the table's contents for a set of regions
+pub fn table(regions: &[Region]) -> Vec<u8> {
+	let mut out = Vec::with_capacity(4 + regions.len() * ENTRY);
+	out.extend_from_slice(&(regions.len() as u32).to_le_bytes());
+	for r in regions {
+		for word in [r.kind, r.base, r.size, r.ipl.map_or(u32::MAX, |x| x as u32)] {
+			out.extend_from_slice(&word.to_le_bytes());
+		}
+	}
+	out
+}
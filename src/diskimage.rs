@@ -0,0 +1,459 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use crate::transport::{self, BlockTransport, FileTransport, MemoryTransport, Mode};
+use serde::{Serialize, Deserialize};
+
+// DiskImage: a DASD volume kept in a host file
+//
+// An image is a header, a bad block map and the blocks, all little-endian:
+//   0		"SQDK"
+//   4		version (halfword, 1)
+//   6		flags (halfword): IMAGE_COMPRESSED
+//   8		cylinders (halfword), heads at 10 (byte), sectors per track at 11
+//   12		block size in bytes (halfword, 1 to MAX_BLOCK_SIZE)
+//   16		bad block map entries (word)
+//   20-31	zero
+// A map entry is two words: the block number, (cylinder * heads + head) *
+// sectors + sector, and how many more reads of it fail, 0 for every read. The
+// blocks follow the map in order. In a compressed image each block is a tag
+// byte instead: 0 for a block of zeros, or 1 followed by the block. Compressed
+// images are expanded into memory and can only be read.
+//
+// Writes can be held in a write-back cache of up to so many dirty blocks, which
+// goes to the file when it fills, when the guest asks for a flush and when the
+// emulator finishes. The sync policy says when the file is also synced to the
+// host's disk: never, on each flush, or after every block written to it.
+//
+// An overlay takes every write instead of the image, which is then only read,
+// so one pristine image can be the base of any number of experiments. An
+// overlay file is a header like an image's, with "SQOV" and no map, followed
+// by records of a block number (word) and the block, one for every block that
+// has been written; a block read comes from its record if it has one.
+//
+// Images and overlays are reached through a BlockTransport (transport.rs), so
+// one can be another process's, given as tcp:HOST:PORT.
+
+pub const MAX_BLOCK_SIZE: usize = 512;
+pub const IMAGE_COMPRESSED: u16 = 0x0001;
+
+const MAGIC: &[u8; 4] = b"SQDK";
+const OVERLAY_MAGIC: &[u8; 4] = b"SQOV";
+const VERSION: u16 = 1;
+const HEADER_SIZE: u64 = 32;
+const ENTRY_SIZE: u64 = 8;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Geometry {
+	pub cylinders: u16,
+	pub heads: u8,
+	pub sectors: u8,
+	pub block_size: u16
+}
+
+impl Geometry {
+	// CYLINDERS,HEADS,SECTORS[,BLOCKSIZE], the block size defaulting to 256;
+	// for sqdisk, as are creating and rewriting images
+	#[allow(dead_code)]
+	pub fn parse(s: &str) -> Option<Geometry> {
+		let v: Vec<u32> = s.split(',').map(|x| x.trim().parse().ok()).collect::<Option<_>>()?;
+		let geometry = match v[..] {
+			[c, h, s] => Geometry { cylinders: c as u16, heads: h as u8, sectors: s as u8, block_size: 256 },
+			[c, h, s, b] => Geometry { cylinders: c as u16, heads: h as u8, sectors: s as u8, block_size: b as u16 },
+			_ => return None,
+		};
+		if v.iter().zip([0xFFFF, 0xFF, 0xFF, MAX_BLOCK_SIZE as u32]).any(|(&x, max)| x == 0 || x > max) {
+			return None;
+		}
+		Some(geometry)
+	}
+	
+	pub fn blocks(&self) -> u32 {
+		self.cylinders as u32 * self.heads as u32 * self.sectors as u32
+	}
+	
+	// the block number of a cylinder, head and sector, if the volume has it
+	pub fn block(&self, cylinder: u16, head: u8, sector: u8) -> Option<u32> {
+		if cylinder >= self.cylinders || head >= self.heads || sector >= self.sectors {
+			return None;
+		}
+		Some((cylinder as u32 * self.heads as u32 + head as u32) * self.sectors as u32 + sector as u32)
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BadBlock {
+	pub block: u32,
+	pub reads: u32		// reads still to fail, 0 for all of them
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum SyncPolicy {
+	Never,
+	Flush,
+	Always
+}
+
+impl SyncPolicy {
+	pub fn parse(s: &str) -> Option<SyncPolicy> {
+		match s {
+			"never" => Some(SyncPolicy::Never),
+			"flush" => Some(SyncPolicy::Flush),
+			"always" => Some(SyncPolicy::Always),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct CacheStats {
+	pub reads: u64,
+	pub read_hits: u64,			// reads of a dirty block, from the cache
+	pub writes: u64,
+	pub write_hits: u64,		// writes of a block already dirty
+	pub flushes: u64,
+	pub written_back: u64,		// dirty blocks written to the file
+	pub syncs: u64
+}
+
+#[derive(Debug)]
+pub enum DiskError {
+	NoRecord,			// no such block on the volume
+	DataCheck,			// the block is in the bad block map
+	WriteProtected,
+	Host(io::Error)
+}
+
+impl From<io::Error> for DiskError {
+	fn from(e: io::Error) -> DiskError {
+		DiskError::Host(e)
+	}
+}
+
+struct Overlay {
+	file: Box<dyn BlockTransport>,
+	records: HashMap<u32, u64>		// file offset of each block written
+}
+
+pub struct DiskImage {
+	pub geometry: Geometry,
+	pub bad: Vec<BadBlock>,
+	pub read_only: bool,
+	pub compressed: bool,
+	pub sync: SyncPolicy,
+	pub cache: Option<usize>,	// most dirty blocks to hold, None to write through
+	pub stats: CacheStats,
+	dirty: BTreeMap<u32, Vec<u8>>,
+	data: u64,			// offset of block 0 in blocks
+	blocks: Box<dyn BlockTransport>,
+	overlay: Option<Overlay>
+}
+
+fn invalid(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn header(geometry: &Geometry, flags: u16, bad: &[BadBlock]) -> Vec<u8> {
+	let mut out = Vec::with_capacity(HEADER_SIZE as usize + bad.len() * ENTRY_SIZE as usize);
+	out.extend_from_slice(MAGIC);
+	out.extend_from_slice(&VERSION.to_le_bytes());
+	out.extend_from_slice(&flags.to_le_bytes());
+	out.extend_from_slice(&geometry.cylinders.to_le_bytes());
+	out.push(geometry.heads);
+	out.push(geometry.sectors);
+	out.extend_from_slice(&geometry.block_size.to_le_bytes());
+	out.extend_from_slice(&[0, 0]);
+	out.extend_from_slice(&(bad.len() as u32).to_le_bytes());
+	out.resize(HEADER_SIZE as usize, 0);
+	for b in bad {
+		out.extend_from_slice(&b.block.to_le_bytes());
+		out.extend_from_slice(&b.reads.to_le_bytes());
+	}
+	out
+}
+
+fn word(buf: &[u8], offset: usize) -> u32 {
+	u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+fn half(buf: &[u8], offset: usize) -> u16 {
+	u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+impl DiskImage {
+	// a new uncompressed volume of zeros with no bad blocks
+	#[allow(dead_code)]
+	pub fn create(path: &str, geometry: Geometry) -> io::Result<()> {
+		let mut file = FileTransport::create(path)?;
+		file.write_at(0, &header(&geometry, 0, &[]))?;
+		file.set_size(HEADER_SIZE + geometry.blocks() as u64 * geometry.block_size as u64)
+	}
+	
+	pub fn open(path: &str, read_only: bool) -> io::Result<DiskImage> {
+		let mut file = transport::open(path, if read_only { Mode::Read } else { Mode::ReadWrite })?;
+		let mut head = [0 as u8; HEADER_SIZE as usize];
+		file.read_at(0, &mut head).map_err(|_| invalid("not a disk image"))?;
+		if &head[0..4] != MAGIC {
+			return Err(invalid("not a disk image"));
+		}
+		if half(&head, 4) != VERSION {
+			return Err(invalid(&format!("disk image version {} is not supported", half(&head, 4))));
+		}
+		let flags = half(&head, 6);
+		let geometry = Geometry { cylinders: half(&head, 8), heads: head[10], sectors: head[11], block_size: half(&head, 12) };
+		if geometry.blocks() == 0 || geometry.block_size == 0 || geometry.block_size as usize > MAX_BLOCK_SIZE {
+			return Err(invalid("bad geometry in disk image header"));
+		}
+		
+		let entries = word(&head, 16) as u64;
+		let mut map = vec![0 as u8; (entries * ENTRY_SIZE) as usize];
+		file.read_at(HEADER_SIZE, &mut map).map_err(|_| invalid("bad block map is cut short"))?;
+		let bad = map.chunks(ENTRY_SIZE as usize).map(|x| BadBlock { block: word(x, 0), reads: word(x, 4) }).collect();
+		
+		let data = HEADER_SIZE + entries * ENTRY_SIZE;
+		let size = geometry.block_size as usize;
+		let compressed = flags & IMAGE_COMPRESSED != 0;
+		let blocks: Box<dyn BlockTransport> = if compressed {
+			let mut packed = vec![0 as u8; file.size()?.saturating_sub(data) as usize];
+			file.read_at(data, &mut packed)?;
+			let mut out = Vec::with_capacity(geometry.blocks() as usize * size);
+			let mut n = 0;
+			for _ in 0..geometry.blocks() {
+				match packed.get(n) {
+					Some(0) => { out.resize(out.len() + size, 0); n += 1; },
+					Some(1) if n + 1 + size <= packed.len() => { out.extend_from_slice(&packed[n + 1..n + 1 + size]); n += 1 + size; },
+					_ => return Err(invalid("compressed blocks are cut short or corrupt")),
+				}
+			}
+			Box::new(MemoryTransport::new(out))
+		} else {
+			if file.size()? < data + geometry.blocks() as u64 * size as u64 {
+				return Err(invalid("disk image is shorter than its geometry"));
+			}
+			file
+		};
+		Ok(DiskImage {
+			geometry: geometry,
+			bad: bad,
+			read_only: read_only || compressed,
+			compressed: compressed,
+			sync: SyncPolicy::Flush,
+			cache: None,
+			stats: CacheStats::default(),
+			dirty: BTreeMap::new(),
+			data: if compressed { 0 } else { data },
+			blocks: blocks,
+			overlay: None
+		})
+	}
+	
+	// send writes to the overlay at path, making it if it is not there, and
+	// read the blocks it already holds from it
+	pub fn overlay(&mut self, path: &str) -> io::Result<()> {
+		let mut file = transport::open(path, Mode::Create)?;
+		let head = header(&self.geometry, 0, &[]);
+		let mut records = HashMap::new();
+		if file.size()? == 0 {
+			file.write_at(0, OVERLAY_MAGIC)?;
+			file.write_at(4, &head[4..])?;
+		} else {
+			let mut theirs = [0 as u8; HEADER_SIZE as usize];
+			file.read_at(0, &mut theirs).map_err(|_| invalid("not an overlay"))?;
+			if &theirs[0..4] != OVERLAY_MAGIC {
+				return Err(invalid("not an overlay"));
+			}
+			if theirs[4..] != head[4..] {
+				return Err(invalid("overlay was made for a volume of another geometry"));
+			}
+			let len = file.size()?;
+			let record = 4 + self.geometry.block_size as u64;
+			let mut offset = HEADER_SIZE;
+			let mut number = [0 as u8; 4];
+			while offset < len {
+				if offset + record > len {
+					return Err(invalid("overlay ends in the middle of a block"));
+				}
+				file.read_at(offset, &mut number)?;
+				let block = u32::from_le_bytes(number);
+				if block >= self.geometry.blocks() {
+					return Err(invalid("overlay holds a block the volume does not have"));
+				}
+				records.insert(block, offset + 4);
+				offset += record;
+			}
+		}
+		self.overlay = Some(Overlay { file: file, records: records });
+		self.read_only = false;
+		Ok(())
+	}
+	
+	fn offset(&self, block: u32) -> u64 {
+		block as u64 * self.geometry.block_size as u64
+	}
+	
+	// a block as it is stored, whatever the bad block map says
+	pub fn read_raw(&mut self, block: u32, buf: &mut [u8]) -> io::Result<()> {
+		if let Some(o) = &mut self.overlay {
+			if let Some(&offset) = o.records.get(&block) {
+				return o.file.read_at(offset, buf);
+			}
+		}
+		let offset = self.data + self.offset(block);
+		self.blocks.read_at(offset, buf)
+	}
+	
+	// read a block into buf, which is the block size; a bad block fails, and a
+	// block that fails only so many times counts the failure
+	pub fn read(&mut self, block: u32, buf: &mut [u8]) -> Result<(), DiskError> {
+		if block >= self.geometry.blocks() {
+			return Err(DiskError::NoRecord);
+		}
+		if let Some(n) = self.bad.iter().position(|b| b.block == block) {
+			match self.bad[n].reads {
+				0 => return Err(DiskError::DataCheck),
+				1 => { self.bad.remove(n); return Err(DiskError::DataCheck); },
+				_ => { self.bad[n].reads -= 1; return Err(DiskError::DataCheck); },
+			}
+		}
+		self.stats.reads += 1;
+		if let Some(x) = self.dirty.get(&block) {
+			buf.copy_from_slice(x);
+			self.stats.read_hits += 1;
+			return Ok(());
+		}
+		Ok(self.read_raw(block, buf)?)
+	}
+	
+	pub fn write(&mut self, block: u32, buf: &[u8]) -> Result<(), DiskError> {
+		if block >= self.geometry.blocks() {
+			return Err(DiskError::NoRecord);
+		}
+		if self.read_only {
+			return Err(DiskError::WriteProtected);
+		}
+		self.stats.writes += 1;
+		match self.cache {
+			Some(limit) => {
+				if self.dirty.insert(block, buf.to_vec()).is_some() {
+					self.stats.write_hits += 1;
+				}
+				if self.dirty.len() > limit {
+					self.flush()?;
+				}
+			},
+			None => {
+				self.write_raw(block, buf)?;
+				if self.sync == SyncPolicy::Always {
+					self.sync_file()?;
+				}
+			},
+		}
+		Ok(())
+	}
+	
+	fn write_raw(&mut self, block: u32, buf: &[u8]) -> io::Result<()> {
+		if let Some(o) = &mut self.overlay {
+			let offset = match o.records.get(&block) {
+				Some(&x) => x,
+				None => {
+					let end = o.file.size()?;
+					o.file.write_at(end, &block.to_le_bytes())?;
+					o.records.insert(block, end + 4);
+					end + 4
+				},
+			};
+			return o.file.write_at(offset, buf);
+		}
+		let offset = self.data + self.offset(block);
+		self.blocks.write_at(offset, buf)
+	}
+	
+	pub fn sync_file(&mut self) -> io::Result<()> {
+		// a compressed volume is only in memory
+		if let Some(o) = &mut self.overlay {
+			o.file.flush()?;
+			self.stats.syncs += 1;
+		} else if !self.compressed {
+			self.blocks.flush()?;
+			self.stats.syncs += 1;
+		}
+		Ok(())
+	}
+	
+	pub fn dirty(&self) -> usize {
+		self.dirty.len()
+	}
+	
+	// write the dirty blocks to the file, syncing as the policy says; a block
+	// that cannot be written stays dirty
+	pub fn flush(&mut self) -> io::Result<()> {
+		self.stats.flushes += 1;
+		while let Some((&block, _)) = self.dirty.iter().next() {
+			let buf = self.dirty.remove(&block).unwrap();
+			if let Err(e) = self.write_raw(block, &buf) {
+				self.dirty.insert(block, buf);
+				return Err(e);
+			}
+			self.stats.written_back += 1;
+			if self.sync == SyncPolicy::Always {
+				self.sync_file()?;
+			}
+		}
+		if self.sync == SyncPolicy::Flush {
+			self.sync_file()?;
+		}
+		Ok(())
+	}
+	
+	// make a block fail its next reads reads, or every read for 0
+	pub fn inject(&mut self, block: u32, reads: u32) {
+		self.bad.retain(|b| b.block != block);
+		self.bad.push(BadBlock { block: block, reads: reads });
+	}
+	
+	pub fn repair(&mut self, block: u32) -> bool {
+		let before = self.bad.len();
+		self.bad.retain(|b| b.block != block);
+		self.bad.len() != before
+	}
+	
+	// write the whole volume, with its current bad block map, to a new image
+	#[allow(dead_code)]
+	pub fn save_as(&mut self, path: &str, compress: bool) -> io::Result<()> {
+		let flags = if compress { IMAGE_COMPRESSED } else { 0 };
+		let mut out = header(&self.geometry, flags, &self.bad);
+		let mut buf = vec![0 as u8; self.geometry.block_size as usize];
+		for block in 0..self.geometry.blocks() {
+			self.read_raw(block, &mut buf)?;
+			if !compress {
+				out.extend_from_slice(&buf);
+			} else if buf.iter().all(|&x| x == 0) {
+				out.push(0);
+			} else {
+				out.push(1);
+				out.extend_from_slice(&buf);
+			}
+		}
+		FileTransport::create(path)?.write_at(0, &out)
+	}
+	
+	// write the bad block map back into an uncompressed image's file, which
+	// has to move the blocks when the map changes size
+	#[allow(dead_code)]
+	pub fn save_map(&mut self) -> io::Result<()> {
+		let mut blocks = Vec::with_capacity(self.geometry.blocks() as usize * self.geometry.block_size as usize);
+		let mut buf = vec![0 as u8; self.geometry.block_size as usize];
+		for block in 0..self.geometry.blocks() {
+			self.read_raw(block, &mut buf)?;
+			blocks.extend_from_slice(&buf);
+		}
+		let head = header(&self.geometry, 0, &self.bad);
+		if self.read_only || self.compressed || self.overlay.is_some() {
+			return Err(io::Error::new(io::ErrorKind::PermissionDenied, "image is read-only"));
+		}
+		self.blocks.write_at(0, &head)?;
+		self.blocks.write_at(head.len() as u64, &blocks)?;
+		self.blocks.set_size(head.len() as u64 + blocks.len() as u64)?;
+		self.data = head.len() as u64;
+		Ok(())
+	}
+}
@@ -0,0 +1,171 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
+use crate::bus::{Memory32, BusError};
+use crate::transcript;
+
+// Dma: the part of the bus a channel's device may reach, and how
+//
+// A channel hands its device a Master in place of the bus. With translation on
+// (iommu.rs) the device's addresses are first translated through the channel's
+// table. Each access is then checked against the channel's window: one that
+// falls outside all of its ranges, or writes to a range that is read-only,
+// doesn't reach the bus and fails with InvalidAddress as a missing address
+// would. The violation, or a failed translation, is raised as a fault on the
+// device's level with DMA_VIOLATION_CODE as the PS selector, so the guest
+// hears that its device went astray rather than finding memory quietly
+// changed. A channel without a window reaches the whole bus.
+
+pub const DMA_VIOLATION_CODE: u8 = 0xD7;
+
+pub struct DmaRange {
+	pub base: u32,
+	pub size: u32,
+	pub write: bool
+}
+
+impl DmaRange {
+	pub fn read(base: u32, size: u32) -> DmaRange {
+		DmaRange { base: base, size: size, write: false }
+	}
+	
+	fn covers(&self, addr: u32, width: u32) -> bool {
+		addr >= self.base && (addr - self.base) as u64 + width as u64 <= self.size as u64
+	}
+}
+
+pub struct Window {
+	pub name: &'static str,
+	pub ranges: Vec<DmaRange>,
+	fault: Option<(Arc<AtomicBool>, Arc<AtomicU8>)>,
+	pub violations: AtomicU64
+}
+
+impl Window {
+	// fault is the CPU's fault line and code for the device's level, or None
+	// to refuse violations without interrupting
+	pub fn new(name: &'static str, ranges: Vec<DmaRange>, fault: Option<(&Arc<AtomicBool>, &Arc<AtomicU8>)>) -> Window {
+		Window {
+			name: name,
+			ranges: ranges,
+			fault: fault.map(|(line, code)| (Arc::clone(line), Arc::clone(code))),
+			violations: AtomicU64::new(0)
+		}
+	}
+	
+	fn allows(&self, addr: u32, width: u32, write: bool) -> bool {
+		self.ranges.iter().any(|r| r.covers(addr, width) && (r.write || !write))
+	}
+	
+	fn raise(&self) {
+		self.violations.fetch_add(1, Ordering::Relaxed);
+		if let Some((line, code)) = &self.fault {
+			code.store(DMA_VIOLATION_CODE, Ordering::Relaxed);
+			line.store(true, Ordering::Relaxed);
+		}
+	}
+}
+
+// where a channel's I/O translation table is, and its last selector
+#[derive(Clone, Copy)]
+pub struct Translation {
+	pub table: u32,
+	pub last: u8
+}
+
+// SDT descriptor flags for reading and writing
+const READABLE: u8 = 0x80;
+const WRITABLE: u8 = 0x40;
+
+// the bus as a device sees it during a grant
+pub struct Master<'a, T> {
+	bus: &'a mut T,
+	window: Option<&'a Window>,
+	translation: Option<Translation>
+}
+
+impl<'a, T: Memory32<u32, BusError>> Master<'a, T> {
+	pub fn new(bus: &'a mut T, window: Option<&'a Window>, translation: Option<Translation>) -> Master<'a, T> {
+		Master { bus: bus, window: window, translation: translation }
+	}
+	
+	fn refuse(&self, addr: u32, write: bool, why: &str) -> BusError {
+		let name = self.window.map_or("device", |w| w.name);
+		let what = format!("{} {} {:08X}", name.to_uppercase(), if write { "WRITE TO" } else { "READ OF" }, addr);
+		println!("DMA: {} {}", what, why);
+		transcript::record("DMA VIOLATION", &what);
+		if let Some(w) = self.window {
+			w.raise();
+		}
+		BusError::InvalidAddress
+	}
+	
+	// the descriptor a selector picks, as base, limit and flags
+	fn descriptor(&self, t: Translation, selector: u8) -> Option<(u32, u32, u8)> {
+		if selector > t.last {
+			return None;
+		}
+		let entry = t.table.wrapping_add(12 * selector as u32);
+		match (self.bus.read_w(entry), self.bus.read_w(entry.wrapping_add(4)), self.bus.read_b(entry.wrapping_add(9))) {
+			(Ok(base), Ok(limit), Ok(flags)) => Some((base, limit, flags)),
+			_ => None,
+		}
+	}
+	
+	// the bus address a device address reaches, or the address refused and why
+	fn reach(&self, addr: u32, width: u32, write: bool) -> Result<u32, (u32, &'static str)> {
+		let phys = match self.translation {
+			None => addr,
+			Some(t) => {
+				let offset = addr & 0xFFFFFF;
+				match self.descriptor(t, (addr >> 24) as u8) {
+					Some((base, limit, flags)) if base as u64 + offset as u64 + width as u64 <= limit as u64
+						&& flags & if write { WRITABLE } else { READABLE } != 0 => base + offset,
+					_ => return Err((addr, "NOT TRANSLATED")),
+				}
+			},
+		};
+		match self.window {
+			Some(w) if !w.allows(phys, width, write) => Err((phys, "OUTSIDE ITS WINDOW")),
+			_ => Ok(phys),
+		}
+	}
+	
+	fn resolve(&self, addr: u32, width: u32, write: bool) -> Result<u32, BusError> {
+		self.reach(addr, width, write).map_err(|(at, why)| self.refuse(at, write, why))
+	}
+	
+	// a byte as the device would read it, but with no violation raised if it
+	// can't, for the monitor to look over what a guest has given a device
+	pub fn peek(&self, addr: u32) -> Result<u8, String> {
+		let phys = self.reach(addr, 1, false).map_err(|(at, why)| format!("{:08X} {}", at, why))?;
+		self.bus.read_b(phys).map_err(|e| format!("{:08X} {:?}", phys, e))
+	}
+}
+
+impl<'a, T: Memory32<u32, BusError>> Memory32<u32, BusError> for Master<'a, T> {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.bus.read_b(self.resolve(addr, 1, false)?)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.bus.read_h(self.resolve(addr, 2, false)?)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		self.bus.read_h_big(self.resolve(addr, 2, false)?)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		self.bus.read_w(self.resolve(addr, 4, false)?)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		let addr = self.resolve(addr, 1, true)?;
+		self.bus.write_b(addr, data)
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		let addr = self.resolve(addr, 2, true)?;
+		self.bus.write_h(addr, data)
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		let addr = self.resolve(addr, 4, true)?;
+		self.bus.write_w(addr, data)
+	}
+}
@@ -0,0 +1,141 @@
+// ELF: reading ELF32 executables for the SeriesQ; sqasm writes them with
+// elfout.rs
+//
+// Images are little-endian ELF32 with e_machine EM_SERIESQ. PT_LOAD segments are
+// placed at their physical address, zero filled out to p_memsz. A PT_NOTE owned
+// by "SeriesQ" with type NT_SQ_SEGMENTS may carry initial segment descriptors,
+// 12 bytes each: register, selector, key, flags, base, limit.
+
+pub const EM_SERIESQ: u16 = 0x5351;
+pub const NT_SQ_SEGMENTS: u32 = 1;
+pub const NOTE_OWNER: &[u8] = b"SeriesQ\0";
+
+pub const ET_EXEC: u16 = 2;
+pub const PT_LOAD: u32 = 1;
+pub const PT_NOTE: u32 = 4;
+pub const EHDR_SIZE: usize = 52;
+pub const PHDR_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SegmentDescriptor {
+	pub reg: u8,
+	pub selector: u8,
+	pub key: u8,
+	pub flags: u8,
+	pub base: u32,
+	pub limit: u32
+}
+
+pub struct ElfImage {
+	pub entry: u32,
+	pub segments: Vec<(u32, Vec<u8>)>,
+	pub descriptors: Vec<SegmentDescriptor>
+}
+
+fn h(b: &[u8], at: usize) -> Result<u16, String> {
+	match b.get(at..at + 2) {
+		Some(x) => Ok(u16::from_le_bytes([x[0], x[1]])),
+		None => Err("truncated file".to_string()),
+	}
+}
+
+fn w(b: &[u8], at: usize) -> Result<u32, String> {
+	match b.get(at..at + 4) {
+		Some(x) => Ok(u32::from_le_bytes([x[0], x[1], x[2], x[3]])),
+		None => Err("truncated file".to_string()),
+	}
+}
+
+fn slice(b: &[u8], at: u32, len: u32) -> Result<&[u8], String> {
+	let end = (at as usize).checked_add(len as usize).ok_or("bad offset")?;
+	b.get(at as usize..end).ok_or("segment extends past end of file".to_string())
+}
+
+pub fn align4(x: usize) -> usize {
+	(x + 3) & !3
+}
+
+fn parse_notes(notes: &[u8], out: &mut Vec<SegmentDescriptor>) -> Result<(), String> {
+	let mut at = 0;
+	while at + 12 <= notes.len() {
+		let namesz = w(notes, at)? as usize;
+		let descsz = w(notes, at + 4)? as usize;
+		let kind = w(notes, at + 8)?;
+		let name_at = at + 12;
+		let desc_at = name_at + align4(namesz);
+		let desc = notes.get(desc_at..desc_at + descsz).ok_or("truncated note")?;
+		
+		if notes.get(name_at..name_at + namesz) == Some(NOTE_OWNER) && kind == NT_SQ_SEGMENTS {
+			if descsz % 12 != 0 {
+				return Err("bad segment descriptor note".to_string());
+			}
+			for d in desc.chunks(12) {
+				if d[0] > 15 {
+					return Err(format!("bad segment register {} in note", d[0]));
+				}
+				out.push(SegmentDescriptor {
+					reg: d[0],
+					selector: d[1],
+					key: d[2],
+					flags: d[3],
+					base: w(d, 4)?,
+					limit: w(d, 8)?
+				});
+			}
+		}
+		at = desc_at + align4(descsz);
+	}
+	Ok(())
+}
+
+// fits says whether len bytes at a physical address are somewhere to load
+// them, so a segment is checked before its memory is allocated
+pub fn parse<F: Fn(u32, u32) -> bool>(b: &[u8], fits: F) -> Result<ElfImage, String> {
+	if b.len() < EHDR_SIZE || &b[0..4] != b"\x7FELF" {
+		return Err("not an ELF file".to_string());
+	}
+	if b[4] != 1 || b[5] != 1 {
+		return Err("not a little-endian ELF32 file".to_string());
+	}
+	if h(b, 18)? != EM_SERIESQ {
+		return Err(format!("e_machine 0x{:04X} is not SeriesQ", h(b, 18)?));
+	}
+	if h(b, 16)? != ET_EXEC {
+		return Err("not an executable".to_string());
+	}
+	
+	let entry = w(b, 24)?;
+	let phoff = w(b, 28)? as usize;
+	let phentsize = h(b, 42)? as usize;
+	let phnum = h(b, 44)? as usize;
+	if phnum > 0 && phentsize < PHDR_SIZE {
+		return Err("bad program header size".to_string());
+	}
+	
+	let mut image = ElfImage { entry: entry, segments: Vec::new(), descriptors: Vec::new() };
+	for n in 0..phnum {
+		let ph = phoff + n * phentsize;
+		let p_type = w(b, ph)?;
+		let offset = w(b, ph + 4)?;
+		let paddr = w(b, ph + 12)?;
+		let filesz = w(b, ph + 16)?;
+		let memsz = w(b, ph + 20)?;
+		
+		match p_type {
+			PT_LOAD => {
+				if filesz > memsz {
+					return Err("segment file size exceeds memory size".to_string());
+				}
+				if !fits(paddr, memsz) {
+					return Err(format!("segment of {} bytes at 0x{:08X} does not fit in memory", memsz, paddr));
+				}
+				let mut data = slice(b, offset, filesz)?.to_vec();
+				data.resize(memsz as usize, 0);
+				image.segments.push((paddr, data));
+			},
+			PT_NOTE => parse_notes(slice(b, offset, filesz)?, &mut image.descriptors)?,
+			_ => { },
+		}
+	}
+	Ok(image)
+}
@@ -0,0 +1,56 @@
+use crate::elf::{SegmentDescriptor, align4, EHDR_SIZE, EM_SERIESQ, ET_EXEC, NOTE_OWNER, NT_SQ_SEGMENTS, PHDR_SIZE, PT_LOAD, PT_NOTE};
+
+// Elfout: writing ELF32 executables, as elf.rs reads them, for sqasm -f elf
+
+// an executable with one PT_LOAD per chunk and a descriptor note if any are given
+pub fn build(entry: u32, chunks: &[(u32, Vec<u8>)], descriptors: &[SegmentDescriptor]) -> Vec<u8> {
+	let mut note = Vec::new();
+	if !descriptors.is_empty() {
+		note.extend_from_slice(&(NOTE_OWNER.len() as u32).to_le_bytes());
+		note.extend_from_slice(&((descriptors.len() * 12) as u32).to_le_bytes());
+		note.extend_from_slice(&NT_SQ_SEGMENTS.to_le_bytes());
+		note.extend_from_slice(NOTE_OWNER);
+		note.resize(align4(note.len()), 0);
+		for d in descriptors {
+			note.extend_from_slice(&[d.reg, d.selector, d.key, d.flags]);
+			note.extend_from_slice(&d.base.to_le_bytes());
+			note.extend_from_slice(&d.limit.to_le_bytes());
+		}
+	}
+	
+	let phnum = chunks.len() + if note.is_empty() { 0 } else { 1 };
+	let mut b = Vec::new();
+	b.extend_from_slice(b"\x7FELF\x01\x01\x01\0\0\0\0\0\0\0\0\0");
+	b.extend_from_slice(&ET_EXEC.to_le_bytes());
+	b.extend_from_slice(&EM_SERIESQ.to_le_bytes());
+	b.extend_from_slice(&1u32.to_le_bytes());			// e_version
+	b.extend_from_slice(&entry.to_le_bytes());
+	b.extend_from_slice(&(EHDR_SIZE as u32).to_le_bytes());	// e_phoff
+	b.extend_from_slice(&0u32.to_le_bytes());			// e_shoff
+	b.extend_from_slice(&0u32.to_le_bytes());			// e_flags
+	b.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+	b.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+	b.extend_from_slice(&(phnum as u16).to_le_bytes());
+	b.extend_from_slice(&[0; 6]);						// no sections
+	
+	let mut offset = EHDR_SIZE + phnum * PHDR_SIZE;
+	let mut phdr = |b: &mut Vec<u8>, p_type: u32, addr: u32, len: usize, flags: u32| {
+		for x in &[p_type, offset as u32, addr, addr, len as u32, len as u32, flags, 4] {
+			b.extend_from_slice(&x.to_le_bytes());
+		}
+		offset = align4(offset + len);
+	};
+	for (addr, data) in chunks {
+		phdr(&mut b, PT_LOAD, *addr, data.len(), 7);
+	}
+	if !note.is_empty() {
+		phdr(&mut b, PT_NOTE, 0, note.len(), 4);
+	}
+	
+	for (_, data) in chunks {
+		b.extend_from_slice(data);
+		b.resize(align4(b.len()), 0);
+	}
+	b.extend_from_slice(&note);
+	b
+}
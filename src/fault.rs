@@ -0,0 +1,87 @@
+use std::fmt;
+use crate::cpu::{PS, SUPERVISOR_ACCESS, OUT_OF_BOUNDS, ILLEGAL_INSTRUCTION, SEGMENTATION_FAULT,
+	READ_FAULT, WRITE_FAULT, READ_ALIGN, READ_ADDR, WRITE_ALIGN, WRITE_ADDR, STACK_OVERFLOW,
+	DIVIDE_BY_ZERO, DIVIDE_OVERFLOW, DECIMAL_OVERFLOW, DECIMAL_DATA};
+use crate::isa;
+
+// Fault: what the CPU knew when it raised a fault, for diagnostics
+//
+// The guest only sees the code and the F10-F15 fault registers; this adds the
+// instruction that faulted, the segment register its memory operand used and
+// the address involved, so messages can say what went wrong by name.
+
+// where the CPU was when the fault was raised
+#[derive(Clone, Copy, Debug)]
+pub enum Stage {
+	Fetch { base: u32, pc: u32 },
+	Execute { base: u32, pc: u32, iword0: u16, iword1: u16 },
+	Interrupt
+}
+
+#[derive(Clone, Debug)]
+pub struct Fault {
+	pub code: u32,
+	pub stage: Stage,
+	pub segment: Option<usize>,
+	pub addr: Option<u32>
+}
+
+pub fn name(code: u32) -> String {
+	match code as i32 {
+		SUPERVISOR_ACCESS => "SUPERVISOR ACCESS".to_string(),
+		OUT_OF_BOUNDS => "OUT OF BOUNDS".to_string(),
+		ILLEGAL_INSTRUCTION => "ILLEGAL INSTRUCTION".to_string(),
+		SEGMENTATION_FAULT => "SEGMENTATION FAULT".to_string(),
+		READ_FAULT => "READ FAULT".to_string(),
+		WRITE_FAULT => "WRITE FAULT".to_string(),
+		READ_ALIGN => "READ ALIGNMENT".to_string(),
+		READ_ADDR => "READ ADDRESS".to_string(),
+		WRITE_ALIGN => "WRITE ALIGNMENT".to_string(),
+		WRITE_ADDR => "WRITE ADDRESS".to_string(),
+		STACK_OVERFLOW => "STACK OVERFLOW".to_string(),
+		DIVIDE_BY_ZERO => "DIVIDE BY ZERO".to_string(),
+		DIVIDE_OVERFLOW => "DIVIDE OVERFLOW".to_string(),
+		DECIMAL_OVERFLOW => "DECIMAL OVERFLOW".to_string(),
+		DECIMAL_DATA => "DECIMAL DATA".to_string(),
+		x => format!("CODE {}", x),
+	}
+}
+
+impl Fault {
+	pub fn new(code: u32, stage: Stage, addr: Option<u32>) -> Fault {
+		// only memory operands name a segment register; fetches always use PS
+		let segment = match stage {
+			Stage::Fetch { .. } => Some(PS),
+			Stage::Execute { iword0, iword1, .. } => match isa::by_opcode((iword0 >> 8) as u8).map(|x| x.format) {
+				Some(isa::Format::RM) | Some(isa::Format::RMX) => Some((iword1 >> 12) as usize),
+				_ => None,
+			},
+			Stage::Interrupt => None,
+		};
+		Fault {
+			code: code,
+			stage: stage,
+			segment: segment,
+			addr: addr
+		}
+	}
+}
+
+impl fmt::Display for Fault {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", name(self.code))?;
+		match self.stage {
+			Stage::Fetch { base, pc } => write!(f, " fetching @{:08X}::{:08X}", base, pc)?,
+			Stage::Execute { base, pc, iword0, iword1 } =>
+				write!(f, " in @{:08X}::{:08X} {}", base, pc, isa::disassemble(iword0, iword1))?,
+			Stage::Interrupt => write!(f, " changing priority level")?,
+		}
+		if let Some(s) = self.segment {
+			write!(f, ", SSR{}", s)?;
+		}
+		if let Some(a) = self.addr {
+			write!(f, ", address 0x{:08X}", a)?;
+		}
+		Ok(())
+	}
+}
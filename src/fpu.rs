@@ -0,0 +1,119 @@
+// Single-precision floating point for the FP instructions (opcodes 0xB0-0xB9,
+// 0xD0 and 0xD1)
+//
+// There are sixteen FP registers, FP0-FP15, each an IEEE 754 binary32 value
+// kept as its bits, so loads, stores and moves leave NaN payloads alone.
+// Arithmetic rounds to nearest, ties to even.
+//
+//   +0 FADD  FP[d] += FP[r]		+5 FLT   FP[d] = R[r], a signed integer
+//   +1 FSUB  FP[d] -= FP[r]		+6 FIX   R[d] = FP[r], toward zero
+//   +2 FMUL  FP[d] *= FP[r]		+7 FMV   FP[d] = FP[r]
+//   +3 FDIV  FP[d] /= FP[r]		+8 FPUT  FP[d] = R[r]'s bits
+//   +4 FCMP  compare FP[d], FP[r]	+9 FGET  R[d] = FP[r]'s bits
+//
+// FL and FST (RM) load and store an FP register as a word. FCMP sets L, G or
+// E in F0 as FP[d] is less than, greater than or equal to FP[r], or V alone
+// if either is a NaN; no other instruction changes F0.
+//
+// Exceptions don't fault. Each sets its bit in F1, where it stays until the
+// guest clears it with SF:
+//
+//   F1: ...IZOUX (Invalid, divide by Zero, Overflow, Underflow, ineXact)
+//
+// Invalid is an operation with no sensible result (0/0, inf - inf, 0 * inf)
+// or a FIX of a NaN or of a value out of range, which gives 0 for a NaN and
+// the nearest integer otherwise. Underflow is a result that is inexact and
+// smaller than the least normal number.
+
+pub const FP_INVALID: u8 = 0b00010000;
+pub const FP_ZERO_DIVIDE: u8 = 0b00001000;
+pub const FP_OVERFLOW: u8 = 0b00000100;
+pub const FP_UNDERFLOW: u8 = 0b00000010;
+pub const FP_INEXACT: u8 = 0b00000001;
+
+pub const FP_FLAGS: usize = 1;
+
+pub fn is_float(opcode: u8) -> bool {
+	matches!(opcode, 0xB0..=0xB9)
+}
+
+// FADD-FDIV: the result's bits and the exceptions it raised. Each is done in
+// double precision, which holds a binary32 product exactly and rounds sums
+// and quotients so that rounding again to single gives the right answer.
+pub fn arith(opcode: u8, a: u32, b: u32) -> (u32, u8) {
+	let (x, y) = (f32::from_bits(a) as f64, f32::from_bits(b) as f64);
+	let (wide, exact) = match opcode & 0x0F {
+		0 | 1 => {
+			let y = if opcode & 0x0F == 1 { -y } else { y };
+			// Knuth's two-sum: the part of the sum double precision lost
+			let s = x + y;
+			let z = s - x;
+			(s, (x - (s - z)) + (y - z) == 0.0)
+		},
+		2 => (x * y, true),
+		_ => (x / y, true),
+	};
+	let result = wide as f32;
+	let mut exceptions = 0;
+	if result.is_nan() {
+		if !x.is_nan() && !y.is_nan() {
+			exceptions |= FP_INVALID;
+		}
+	} else if opcode & 0x0F == 3 && y == 0.0 && x.is_finite() {
+		exceptions |= FP_ZERO_DIVIDE;
+	} else if result.is_infinite() {
+		if x.is_finite() && y.is_finite() {
+			exceptions |= FP_OVERFLOW | FP_INEXACT;
+		}
+	} else {
+		let exact = exact && if opcode & 0x0F == 3 {
+			result as f64 * y == x
+		} else {
+			result as f64 == wide
+		};
+		if !exact {
+			exceptions |= FP_INEXACT;
+			if result.abs() < f32::MIN_POSITIVE {
+				exceptions |= FP_UNDERFLOW;
+			}
+		}
+	}
+	(result.to_bits(), exceptions)
+}
+
+// FCMP: F0 with L, G, E and V set for the comparison
+pub fn compare(a: u32, b: u32, flags: u8) -> u8 {
+	let (x, y) = (f32::from_bits(a), f32::from_bits(b));
+	let mut new_flags = flags & 0b10000111;
+	// PLGEVCSB
+	if x < y {
+		new_flags |= 0b01000000;
+	} else if x > y {
+		new_flags |= 0b00100000;
+	} else if x == y {
+		new_flags |= 0b00010000;
+	} else {
+		new_flags |= 0b00001000;
+	}
+	new_flags
+}
+
+// FLT
+pub fn from_int(x: u32) -> (u32, u8) {
+	let result = x as i32 as f32;
+	(result.to_bits(), if result as f64 != x as i32 as f64 { FP_INEXACT } else { 0 })
+}
+
+// FIX
+pub fn to_int(a: u32) -> (u32, u8) {
+	let x = f32::from_bits(a);
+	let whole = x.trunc();
+	let exceptions = if x.is_nan() || whole < i32::MIN as f32 || whole >= 2147483648.0 {
+		FP_INVALID
+	} else if whole != x {
+		FP_INEXACT
+	} else {
+		0
+	};
+	(x as i32 as u32, exceptions)
+}
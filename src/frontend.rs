@@ -0,0 +1,111 @@
+use std::sync::{Arc, Mutex};
+
+// Frontend: what the operator sees of the machine, and the operator's input
+// that doesn't come through the monitor
+//
+// The devices that talk to the operator hand their output to the frontend
+// instead of printing it: the printer's and punch's text when it isn't going
+// to a file, the operator console's messages, attention presses and the
+// panel's switches and lamps. A frontend that shows the registers gets them
+// from the CPU, which copies them out while it runs. A frontend hands back what
+// the operator did as events, which the machine carries out while it waits on
+// the CPU (Machine::wait_limit).
+//
+// One frontend serves the process, installed before the machine starts; the
+// terminal until one is. It prints everything as the emulator always has and
+// has no input of its own, the monitor being its keyboard. --window installs
+// the window (window.rs) instead.
+
+// something the operator did, for the machine to carry out; only the window
+// makes any
+#[cfg_attr(not(feature = "window"), allow(dead_code))]
+pub enum Event {
+	Reply(u32, String),		// answer message id, or 0 for none in particular
+	Attention,
+	Switches(u32),
+	Stop
+}
+
+// the registers as the CPU last copied them out, every TOD_INTERVAL
+// instructions and when it stops; only the window reads them
+#[allow(non_snake_case)]
+#[cfg_attr(not(feature = "window"), allow(dead_code))]
+#[derive(Clone, Copy, Default)]
+pub struct Registers {
+	pub R: [u32; 16],
+	pub F0: u8,
+	pub F8: u8,
+	pub ps_base: u32,
+	pub cycles: u64,
+	pub running: bool
+}
+
+pub type Shown = Arc<Mutex<Registers>>;
+
+pub trait Frontend: Send {
+	// text a device printed, ending its own lines; device is its transcript tag
+	fn output(&mut self, device: &str, text: &str);
+	// message id from the guest to the operator
+	fn message(&mut self, id: u32, text: &str);
+	// the attention key was pressed, from source
+	fn attention(&mut self, source: &str);
+	// the panel's switches or lamps changed
+	fn panel(&mut self, switches: u32, lamps: u32);
+	// what the operator did since the last poll; called every millisecond or
+	// so from the thread that waits on the CPU
+	fn poll(&mut self) -> Vec<Event>;
+}
+
+pub struct Terminal;
+
+impl Frontend for Terminal {
+	fn output(&mut self, _device: &str, text: &str) {
+		// not print!, which would put the text in the transcript a second
+		// time; devices record their own output there
+		std::print!("{}", text);
+	}
+	fn message(&mut self, id: u32, text: &str) {
+		println!("OPERATOR MESSAGE {:X}: {}", id, text);
+	}
+	fn attention(&mut self, source: &str) {
+		println!("ATTENTION FROM {}", source);
+	}
+	// the monitor's panel command shows them
+	fn panel(&mut self, _switches: u32, _lamps: u32) { }
+	fn poll(&mut self) -> Vec<Event> {
+		Vec::new()
+	}
+}
+
+static FRONTEND: Mutex<Option<Box<dyn Frontend>>> = Mutex::new(None);
+
+// devices report from panicking threads too, so never give up on the lock
+fn with<T>(f: impl FnOnce(&mut dyn Frontend) -> T) -> T {
+	let mut guard = FRONTEND.lock().unwrap_or_else(|e| e.into_inner());
+	f(guard.get_or_insert_with(|| Box::new(Terminal)).as_mut())
+}
+
+#[cfg_attr(not(feature = "window"), allow(dead_code))]
+pub fn install(frontend: Box<dyn Frontend>) {
+	*FRONTEND.lock().unwrap_or_else(|e| e.into_inner()) = Some(frontend);
+}
+
+pub fn output(device: &str, text: &str) {
+	with(|f| f.output(device, text));
+}
+
+pub fn message(id: u32, text: &str) {
+	with(|f| f.message(id, text));
+}
+
+pub fn attention(source: &str) {
+	with(|f| f.attention(source));
+}
+
+pub fn panel(switches: u32, lamps: u32) {
+	with(|f| f.panel(switches, lamps));
+}
+
+pub fn poll() -> Vec<Event> {
+	with(|f| f.poll())
+}
@@ -0,0 +1,112 @@
+// Hexfmt: Intel HEX and Motorola S-record images, and absolute load decks
+//
+// HEX and S-records parse to address/data chunks plus an optional start address,
+// and both writers (hexout.rs, for sqasm) emit 16 data bytes per record with
+// 32-bit addressing (I32HEX, S3/S7). Load decks are card images for the firmware loader: `D`, address, count
+// and data in hex per card, then `R`, count and the addresses of words to
+// relocate for a relocatable deck, then `E` and the entry address.
+
+pub struct HexImage {
+	pub chunks: Vec<(u32, Vec<u8>)>,
+	pub entry: Option<u32>
+}
+
+fn hex_bytes(s: &str, line: usize) -> Result<Vec<u8>, String> {
+	if s.len() % 2 != 0 || !s.is_ascii() {
+		return Err(format!("line {}: odd number of hex digits", line));
+	}
+	(0..s.len()).step_by(2).map(|i| {
+		u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| format!("line {}: bad hex digit", line))
+	}).collect()
+}
+
+fn push(chunks: &mut Vec<(u32, Vec<u8>)>, addr: u32, data: &[u8]) {
+	if let Some((start, run)) = chunks.last_mut() {
+		if start.wrapping_add(run.len() as u32) == addr {
+			run.extend_from_slice(data);
+			return;
+		}
+	}
+	chunks.push((addr, data.to_vec()));
+}
+
+pub fn parse_ihex(text: &str) -> Result<HexImage, String> {
+	let mut image = HexImage { chunks: Vec::new(), entry: None };
+	let mut base: u32 = 0;
+	
+	for (n, raw) in text.lines().enumerate() {
+		let line = n + 1;
+		let rec = raw.trim();
+		if rec.is_empty() {
+			continue;
+		}
+		if !rec.starts_with(':') {
+			return Err(format!("line {}: record does not start with ':'", line));
+		}
+		let b = hex_bytes(&rec[1..], line)?;
+		if b.len() < 5 || b.len() != 5 + b[0] as usize {
+			return Err(format!("line {}: bad record length", line));
+		}
+		if b.iter().fold(0u8, |acc, x| acc.wrapping_add(*x)) != 0 {
+			return Err(format!("line {}: checksum mismatch", line));
+		}
+		
+		let offset = ((b[1] as u32) << 8) | b[2] as u32;
+		let data = &b[4..b.len() - 1];
+		match b[3] {
+			0x00 => push(&mut image.chunks, base.wrapping_add(offset), data),
+			0x01 => break,
+			0x02 if data.len() == 2 => base = (((data[0] as u32) << 8) | data[1] as u32) << 4,
+			0x04 if data.len() == 2 => base = (((data[0] as u32) << 8) | data[1] as u32) << 16,
+			0x03 if data.len() == 4 => {
+				let cs = ((data[0] as u32) << 8) | data[1] as u32;
+				let ip = ((data[2] as u32) << 8) | data[3] as u32;
+				image.entry = Some((cs << 4).wrapping_add(ip));
+			},
+			0x05 if data.len() == 4 => image.entry = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]])),
+			x => return Err(format!("line {}: bad record type {:02X}", line, x)),
+		}
+	}
+	Ok(image)
+}
+
+pub fn parse_srec(text: &str) -> Result<HexImage, String> {
+	let mut image = HexImage { chunks: Vec::new(), entry: None };
+	
+	for (n, raw) in text.lines().enumerate() {
+		let line = n + 1;
+		let rec = raw.trim();
+		if rec.is_empty() {
+			continue;
+		}
+		if rec.len() < 4 || !rec.starts_with('S') {
+			return Err(format!("line {}: record does not start with 'S'", line));
+		}
+		let kind = rec.as_bytes()[1];
+		let b = hex_bytes(&rec[2..], line)?;
+		if b.is_empty() || b.len() != 1 + b[0] as usize {
+			return Err(format!("line {}: bad record length", line));
+		}
+		if b.iter().fold(0u8, |acc, x| acc.wrapping_add(*x)) != 0xFF {
+			return Err(format!("line {}: checksum mismatch", line));
+		}
+		
+		let width = match kind {
+			b'0' | b'1' | b'5' | b'9' => 2,
+			b'2' | b'6' | b'8' => 3,
+			b'3' | b'7' => 4,
+			_ => return Err(format!("line {}: bad record type S{}", line, kind as char)),
+		};
+		if b.len() < 2 + width {
+			return Err(format!("line {}: record too short", line));
+		}
+		let addr = b[1..1 + width].iter().fold(0u32, |acc, x| (acc << 8) | *x as u32);
+		let data = &b[1 + width..b.len() - 1];
+		match kind {
+			b'1' | b'2' | b'3' => push(&mut image.chunks, addr, data),
+			b'7' | b'8' | b'9' => image.entry = Some(addr),
+			_ => { },	// header and record counts
+		}
+	}
+	Ok(image)
+}
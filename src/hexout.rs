@@ -0,0 +1,98 @@
+// Hexout: writing Intel HEX, S-record and load deck images, as hexfmt.rs
+// describes them, for sqasm
+
+pub const DECK_BYTES: usize = 32;
+pub const DECK_RELOCATIONS: usize = 9;
+
+pub fn write_ihex(chunks: &[(u32, Vec<u8>)], entry: Option<u32>) -> String {
+	let mut out = String::new();
+	let mut record = |kind: u8, offset: u16, data: &[u8]| {
+		let mut b = vec![data.len() as u8, (offset >> 8) as u8, offset as u8, kind];
+		b.extend_from_slice(data);
+		let sum = b.iter().fold(0u8, |acc, x| acc.wrapping_add(*x));
+		b.push(sum.wrapping_neg());
+		out.push(':');
+		for x in b {
+			out.push_str(&format!("{:02X}", x));
+		}
+		out.push('\n');
+	};
+	
+	let mut upper: Option<u16> = None;
+	for (addr, data) in chunks {
+		for (i, piece) in data.chunks(16).enumerate() {
+			let mut at = addr.wrapping_add(16 * i as u32);
+			let mut piece = piece;
+			// split where a record would cross a 64K boundary
+			while !piece.is_empty() {
+				if upper != Some((at >> 16) as u16) {
+					upper = Some((at >> 16) as u16);
+					record(0x04, 0, &((at >> 16) as u16).to_be_bytes());
+				}
+				let room = (0x10000 - (at & 0xFFFF)) as usize;
+				let n = piece.len().min(room);
+				record(0x00, at as u16, &piece[..n]);
+				piece = &piece[n..];
+				at = at.wrapping_add(n as u32);
+			}
+		}
+	}
+	if let Some(e) = entry {
+		record(0x05, 0, &e.to_be_bytes());
+	}
+	record(0x01, 0, &[]);
+	out
+}
+
+pub fn write_srec(chunks: &[(u32, Vec<u8>)], entry: Option<u32>, header: &str) -> String {
+	let mut out = String::new();
+	let mut record = |kind: char, addr: &[u8], data: &[u8]| {
+		let mut b = vec![(addr.len() + data.len() + 1) as u8];
+		b.extend_from_slice(addr);
+		b.extend_from_slice(data);
+		let sum = b.iter().fold(0u8, |acc, x| acc.wrapping_add(*x));
+		b.push(!sum);
+		out.push('S');
+		out.push(kind);
+		for x in b {
+			out.push_str(&format!("{:02X}", x));
+		}
+		out.push('\n');
+	};
+	
+	record('0', &[0, 0], &header.as_bytes()[..header.len().min(64)]);
+	let mut count: u32 = 0;
+	for (addr, data) in chunks {
+		for (i, piece) in data.chunks(16).enumerate() {
+			record('3', &addr.wrapping_add(16 * i as u32).to_be_bytes(), piece);
+			count += 1;
+		}
+	}
+	if count <= 0xFFFF {
+		record('5', &(count as u16).to_be_bytes(), &[]);
+	}
+	record('7', &entry.unwrap_or(0).to_be_bytes(), &[]);
+	out
+}
+
+pub fn write_deck(chunks: &[(u32, Vec<u8>)], relocations: &[u32], entry: u32) -> String {
+	let mut out = String::new();
+	for (addr, data) in chunks {
+		for (i, piece) in data.chunks(DECK_BYTES).enumerate() {
+			out.push_str(&format!("D{:08X}{:02X}", addr.wrapping_add((DECK_BYTES * i) as u32), piece.len()));
+			for x in piece {
+				out.push_str(&format!("{:02X}", x));
+			}
+			out.push('\n');
+		}
+	}
+	for piece in relocations.chunks(DECK_RELOCATIONS) {
+		out.push_str(&format!("R{:02X}", piece.len()));
+		for x in piece {
+			out.push_str(&format!("{:08X}", x));
+		}
+		out.push('\n');
+	}
+	out.push_str(&format!("E{:08X}\n", entry));
+	out
+}
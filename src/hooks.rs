@@ -0,0 +1,112 @@
+use crate::bus::Bus;
+use crate::cpu::SeriesQ;
+use crate::isa::{self, Format, Op};
+
+// Hooks: callbacks run around the execution of selected instructions
+//
+// Tools register a hook for an opcode byte or for any instruction a predicate
+// accepts, to run before the instruction executes or after it has (faulted or
+// not). Hooks see the decoded instruction and can read the CPU and bus, but
+// change nothing; a skipped instruction (IF, IFN) runs no hooks. With no
+// hooks registered the CPU does not decode anything for them.
+
+pub enum Select {
+	Opcode(u8),
+	// for tools; --log-op selects by opcode alone
+	#[allow(dead_code)]
+	Predicate(Box<dyn Fn(&Decoded) -> bool + Send>)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum When {
+	Before,
+	After
+}
+
+pub type HookFn = Box<dyn FnMut(&Decoded, &SeriesQ, &Bus) + Send>;
+
+struct Hook {
+	select: Select,
+	when: When,
+	f: HookFn
+}
+
+// one instruction, with its operand fields pulled out by format; --log-op
+// reads only the disassembly and the destination, the rest are for tools
+#[allow(dead_code)]
+pub struct Decoded {
+	pub base: u32,
+	pub pc: u32,
+	pub iword0: u16,
+	pub iword1: u16,
+	pub opcode: u8,
+	pub op: Option<&'static Op>,
+	pub d: usize,
+	pub r: usize,
+	pub s: Option<usize>,		// segment register, RM and RMX
+	pub x: Option<usize>,		// index register, RMX
+	pub index: Option<u16>		// i12 (RM) or i8 (RMX)
+}
+
+impl Decoded {
+	pub fn new(base: u32, pc: u32, iword0: u16, iword1: u16) -> Decoded {
+		let opcode = (iword0 >> 8) as u8;
+		let op = isa::by_opcode(opcode);
+		let (s, x, index) = match op.map(|o| o.format) {
+			Some(Format::RM) => (Some((iword1 >> 12) as usize), None, Some(iword1 & 0xFFF)),
+			Some(Format::RMX) => (Some((iword1 >> 12) as usize), Some(((iword1 >> 8) & 0xF) as usize), Some(iword1 & 0xFF)),
+			_ => (None, None, None),
+		};
+		Decoded {
+			base: base,
+			pc: pc,
+			iword0: iword0,
+			iword1: iword1,
+			opcode: opcode,
+			op: op,
+			d: ((iword0 >> 4) & 0xF) as usize,
+			r: (iword0 & 0xF) as usize,
+			s: s,
+			x: x,
+			index: index
+		}
+	}
+	
+	pub fn disassemble(&self) -> String {
+		isa::disassemble(self.iword0, self.iword1)
+	}
+}
+
+#[derive(Default)]
+pub struct Hooks {
+	list: Vec<Hook>
+}
+
+impl Hooks {
+	pub fn is_empty(&self) -> bool {
+		self.list.is_empty()
+	}
+	
+	pub fn add(&mut self, select: Select, when: When, f: HookFn) {
+		self.list.push(Hook {
+			select: select,
+			when: when,
+			f: f
+		});
+	}
+	
+	// called by the CPU around each instruction it executes
+	pub fn run(cpu: &mut SeriesQ, when: When, decoded: &Decoded, bus: &Bus) {
+		let mut hooks = std::mem::take(&mut cpu.hooks);
+		for h in hooks.list.iter_mut().filter(|h| h.when == when) {
+			let selected = match &h.select {
+				Select::Opcode(x) => *x == decoded.opcode,
+				Select::Predicate(p) => p(decoded),
+			};
+			if selected {
+				(h.f)(decoded, cpu, bus);
+			}
+		}
+		cpu.hooks = hooks;
+	}
+}
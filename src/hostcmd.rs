@@ -0,0 +1,195 @@
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use crate::bus::{Memory32, BusError};
+use crate::ram::Ram;
+use crate::transcript;
+
+// HostCommand: lets guest jobs run host programs the operator has allowed,
+// disabled unless some are named on the command line
+//
+// Registers (words): run at 0, command line at 4, input file at 8, output file
+// at 12, result at 16. Storing any value to run starts the command on a host
+// thread, and run reads 1 until it has finished; the result is then its exit
+// status, or -1 if it was refused or could not be started. A store to run while
+// a command is still going fails with InvalidState. The command line is a
+// NUL-terminated string in main memory, split at blanks; the first word must
+// be one of the allowed programs and the rest are passed to it with no shell
+// in between, and must be plain file names in the spool directory like the
+// file registers, which are otherwise 0 for no input and discarded output.
+// Commands run in the spool directory with an empty environment apart from
+// PATH.
+
+pub const HOSTCMD_REGION_SIZE: u32 = 20;
+
+const HOSTCMD_FAIL: u32 = 0xFFFFFFFF;
+
+pub struct HostCommand {
+	pub allowed: Vec<String>,
+	pub spool: PathBuf,
+	
+	regs: Arc<Mutex<Vec<u8>>>,
+	ram: Arc<Ram>
+}
+
+// a name that stays inside the spool directory
+fn plain_name(name: &str) -> bool {
+	!name.is_empty() && name != "." && name != ".." && !name.contains(|c| c == '/' || c == '\\' || c == ':')
+}
+
+impl HostCommand {
+	pub fn new(ram: Arc<Ram>) -> HostCommand {
+		HostCommand {
+			allowed: Vec::new(),
+			spool: PathBuf::from("spool"),
+			
+			regs: Arc::new(Mutex::new(vec![0 as u8; HOSTCMD_REGION_SIZE as usize])),
+			ram: ram
+		}
+	}
+	
+	pub fn enabled(&self) -> bool {
+		!self.allowed.is_empty()
+	}
+	
+	fn reg(&self, offset: u32) -> u32 {
+		self.regs.lock().unwrap().read_w(offset).unwrap()
+	}
+	
+	fn guest_string(&self, addr: u32) -> Option<String> {
+		let len = (addr..self.ram.size()).position(|a| matches!(self.ram.read_b(a), Ok(0)))?;
+		String::from_utf8(self.ram.read_bytes(addr, len as u32)?).ok()
+	}
+	
+	// a spool file named by a register, or None for a bad name
+	fn spool_file(&self, offset: u32) -> Option<Option<PathBuf>> {
+		match self.reg(offset) {
+			0 => Some(None),
+			addr => {
+				let name = self.guest_string(addr)?;
+				if plain_name(&name) { Some(Some(self.spool.join(name))) } else { None }
+			},
+		}
+	}
+	
+	// everything the command needs is read from guest memory here, so the
+	// host thread running it never has to touch the bus
+	fn prepare_command(&self) -> Result<(String, Command), u32> {
+		let line = self.guest_string(self.reg(4)).ok_or(HOSTCMD_FAIL)?;
+		let mut words = line.split_whitespace();
+		let program = words.next().filter(|x| self.allowed.iter().any(|a| a == x));
+		let args: Vec<&str> = words.collect();
+		let program = match program {
+			Some(x) if args.iter().all(|a| plain_name(a)) => x,
+			_ => {
+				transcript::record("HOSTCMD", &format!("REFUSED {}", line));
+				return Err(HOSTCMD_FAIL);
+			},
+		};
+		let (input, output) = match (self.spool_file(8), self.spool_file(12)) {
+			(Some(i), Some(o)) => (i, o),
+			_ => return Err(HOSTCMD_FAIL),
+		};
+		
+		let mut cmd = Command::new(program);
+		cmd.args(args).current_dir(&self.spool).env_clear();
+		if let Some(path) = std::env::var_os("PATH") {
+			cmd.env("PATH", path);
+		}
+		cmd.stdin(match input.map(File::open) {
+			Some(Ok(f)) => Stdio::from(f),
+			Some(Err(_)) => return Err(HOSTCMD_FAIL),
+			None => Stdio::null(),
+		});
+		cmd.stdout(match output.map(File::create) {
+			Some(Ok(f)) => Stdio::from(f),
+			Some(Err(_)) => return Err(HOSTCMD_FAIL),
+			None => Stdio::null(),
+		});
+		cmd.stderr(Stdio::null());
+		Ok((line, cmd))
+	}
+	
+	// called with the bus held, so the wait for the command happens on its own
+	// thread, which posts the result and clears run when it is done
+	fn run(&self) -> Result<(), BusError> {
+		if self.reg(0) != 0 {
+			return Err(BusError::InvalidState);
+		}
+		let (line, mut cmd) = match self.prepare_command() {
+			Ok(x) => x,
+			Err(result) => return self.regs.lock().unwrap().write_w(16, result),
+		};
+		self.regs.lock().unwrap().write_w(0, 1)?;
+		
+		transcript::record("HOSTCMD", &line);
+		let regs = Arc::clone(&self.regs);
+		thread::spawn(move || {
+			let result = match cmd.status() {
+				Ok(status) => status.code().map_or(HOSTCMD_FAIL, |x| x as u32),
+				Err(_) => HOSTCMD_FAIL,
+			};
+			let mut regs = regs.lock().unwrap();
+			regs.write_w(16, result).unwrap();
+			regs.write_w(0, 0).unwrap();
+		});
+		Ok(())
+	}
+	
+	// the spool directory has to exist before the first command
+	pub fn prepare(&self) -> std::io::Result<()> {
+		fs::create_dir_all(&self.spool)
+	}
+}
+
+impl Memory32<u32, BusError> for HostCommand {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		if !self.enabled() {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.lock().unwrap().read_b(addr)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		if !self.enabled() {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.lock().unwrap().read_h(addr)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		if !self.enabled() {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.lock().unwrap().read_h_big(addr)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		if !self.enabled() {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.lock().unwrap().read_w(addr)
+	}
+	
+	// commands are run with a word store to the run register
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		if !self.enabled() || addr < 4 {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.lock().unwrap().write_b(addr, data)
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		if !self.enabled() || addr < 4 {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.lock().unwrap().write_h(addr, data)
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		if !self.enabled() {
+			return Err(BusError::InvalidAddress);
+		}
+		if addr == 0 {
+			return self.run();
+		}
+		self.regs.lock().unwrap().write_w(addr, data)
+	}
+}
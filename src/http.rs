@@ -0,0 +1,84 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::thread;
+use std::time::Duration;
+use crate::capture::{self, RECORD_SECONDS};
+use crate::screen::Screen;
+use crate::sync::Mutex;
+
+// Http: pictures of the text display for a browser or curl, with --http
+//
+//   GET /screen.png			a screenshot
+//   GET /screen.apng?seconds=N	a recording of N seconds (RECORD_SECONDS by
+//					default, at most MAX_SECONDS), sent when done
+//
+// Just enough HTTP/1.0 for that: one request a connection, which is closed
+// after the answer. Nothing else about the machine is served.
+
+const MAX_SECONDS: u64 = 60;
+
+// longest request header taken
+const MAX_HEADER: usize = 8192;
+
+pub fn listen(addr: &str, screen: Arc<Mutex<Screen>>) -> io::Result<()> {
+	let listener = TcpListener::bind(addr)?;
+	thread::spawn(move || {
+		for stream in listener.incoming().flatten() {
+			let screen = Arc::clone(&screen);
+			thread::spawn(move || {
+				// the client going away is no concern of the machine's
+				let _ = serve(stream, &screen);
+			});
+		}
+	});
+	Ok(())
+}
+
+fn serve(mut stream: TcpStream, screen: &Mutex<Screen>) -> io::Result<()> {
+	let target = match request(&stream)? {
+		Some(x) => x,
+		None => return answer(&mut stream, "400 Bad Request", "text/plain", b"bad request\n"),
+	};
+	let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+	match path {
+		"/screen.png" => answer(&mut stream, "200 OK", "image/png", &capture::screenshot(screen)),
+		"/screen.apng" => {
+			let seconds = query.split('&').find_map(|x| x.strip_prefix("seconds="))
+				.map_or(Some(RECORD_SECONDS), |x| x.parse::<u64>().ok().filter(|&x| x > 0 && x <= MAX_SECONDS));
+			match seconds {
+				Some(x) => {
+					let frames = capture::record(screen, Duration::from_secs(x), &AtomicBool::new(false));
+					answer(&mut stream, "200 OK", "image/apng", &frames.png())
+				},
+				None => answer(&mut stream, "400 Bad Request", "text/plain", format!("seconds is 1 to {}\n", MAX_SECONDS).as_bytes()),
+			}
+		},
+		_ => answer(&mut stream, "404 Not Found", "text/plain", b"try /screen.png or /screen.apng\n"),
+	}
+}
+
+// the target of a GET, once the whole header is in
+fn request(stream: &TcpStream) -> io::Result<Option<String>> {
+	let mut reader = BufReader::new(Read::take(stream, MAX_HEADER as u64));
+	let mut first = String::new();
+	reader.read_line(&mut first)?;
+	loop {
+		let mut line = String::new();
+		if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+			break;
+		}
+	}
+	let words: Vec<&str> = first.split_whitespace().collect();
+	match words.as_slice() {
+		["GET", target, version] if version.starts_with("HTTP/") => Ok(Some(target.to_string())),
+		_ => Ok(None),
+	}
+}
+
+fn answer(stream: &mut TcpStream, status: &str, kind: &str, body: &[u8]) -> io::Result<()> {
+	write!(stream, "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, kind, body.len())?;
+	stream.write_all(body)?;
+	stream.flush()
+}
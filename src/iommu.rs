@@ -0,0 +1,102 @@
+use crate::bus::{Bus, BusError, Channel, Memory32};
+use crate::dma::Translation;
+
+// Iommu: I/O translation tables, so devices can be given guest-virtual buffers
+//
+// Registers (words), two for each channel, channel n's at 8n:
+//
+//   table    where the channel's translation table is
+//   control  bit 8 on: the channel's device addresses are translated; the low
+//            byte is the last selector in the table
+//
+// A translated device address is a selector in its top byte and an offset in
+// the rest. The selector picks a descriptor from the table, which is laid out
+// as the segment descriptor table is (base, limit, key and flags in 12 bytes),
+// and the access goes to base + offset if it ends by the limit and the flags
+// let it read or write as it needs to; anything else is a DMA violation
+// (dma.rs). Keys aren't checked: the table itself is what the device may
+// reach. Descriptors are read as each access is made, so a changed entry
+// takes effect at once. A supervisor can give a program a device buffer by
+// putting a descriptor for it in the table and the program only the selector.
+// The CPU refuses application-state accesses to the region.
+
+pub const TRANSLATE_ON: u32 = 0x100;
+
+pub struct Iommu {
+	channels: Vec<Channel<Bus>>,
+	regs: Vec<u8>
+}
+
+impl Iommu {
+	pub fn new(channels: &[Channel<Bus>]) -> Iommu {
+		Iommu {
+			channels: channels.iter().map(Channel::clone).collect(),
+			regs: vec![0 as u8; channels.len() * 8]
+		}
+	}
+	
+	pub fn size(&self) -> u32 {
+		self.regs.len() as u32
+	}
+	
+	// pass channel n's registers on to it
+	fn apply(&self, n: usize) {
+		let table = self.regs.read_w(8 * n as u32).unwrap();
+		let control = self.regs.read_w(8 * n as u32 + 4).unwrap();
+		self.channels[n].set_translation(if control & TRANSLATE_ON != 0 {
+			Some(Translation { table: table, last: control as u8 })
+		} else {
+			None
+		});
+	}
+	
+	fn store<F: FnOnce(&mut Vec<u8>) -> Result<(), BusError>>(&mut self, addr: u32, f: F) -> Result<(), BusError> {
+		f(&mut self.regs)?;
+		self.apply(addr as usize / 8);
+		Ok(())
+	}
+}
+
+impl Memory32<u32, BusError> for Iommu {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.regs.read_b(addr)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h(addr)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h_big(addr)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		self.regs.read_w(addr)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		self.store(addr, |r| r.write_b(addr, data))
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		self.store(addr, |r| r.write_h(addr, data))
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		self.store(addr, |r| r.write_w(addr, data))
+	}
+	
+	fn save_state(&self) -> Option<Vec<u8>> {
+		bincode::serialize(&self.regs).ok()
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		let regs: Vec<u8> = bincode::deserialize(state).map_err(|_| BusError::InvalidState)?;
+		if regs.len() != self.regs.len() {
+			return Err(BusError::InvalidState);
+		}
+		self.regs = regs;
+		for n in 0..self.channels.len() {
+			self.apply(n);
+		}
+		Ok(())
+	}
+	
+	fn idempotent(&self, _addr: u32) -> bool {
+		true
+	}
+}
@@ -0,0 +1,176 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::{thread, time};
+use rand::Rng;
+use crate::batch;
+use crate::machine::{Machine, Stop};
+use crate::options::Options;
+use crate::sync::Mutex;
+
+// IrqStorm: run headless while interrupts arrive at random, and report how
+// long each level took to deliver them
+//
+// A host thread raises an interrupt on a level from 1 to 7, picked at random,
+// at random intervals averaging the given number of microseconds. The storm is
+// a source of its own, ORed into the CPU's scan of the device lines; it loses
+// a tie with a device on its level, and its code is STORM_CODE. Each interrupt
+// is held until the CPU enters its level for it, and the time from raising it
+// to then is its latency. A level that already has one outstanding isn't
+// raised again, as a device can't post a second status before the first is
+// taken. One outstanding for LOST_AFTER is lost: the line is dropped and
+// counted against the level.
+//
+// Without a program on the command line the built-in workload runs, the
+// irqstorm example: a loop at level 0 with a handler on every level that
+// counts its entries, run for STORM_SECONDS unless --max-seconds says
+// otherwise. Its counts are reported beside the storm's.
+
+pub const STORM_CODE: u8 = 0x5A;
+
+pub const EXIT_LOST: i32 = 1;
+
+const LOST_AFTER: time::Duration = time::Duration::from_secs(1);
+const STORM_SECONDS: f64 = 2.0;
+
+#[derive(Clone, Copy, Default)]
+struct Level {
+	raised: u64,
+	taken: u64,
+	lost: u64,
+	outstanding: Option<time::Instant>,
+	worst: time::Duration,
+	total: time::Duration
+}
+
+pub struct Storm {
+	pending: AtomicU8, // bit n: level n is raised
+	levels: Mutex<[Level; 8]>,
+	done: AtomicBool
+}
+
+impl Storm {
+	fn new() -> Storm {
+		Storm {
+			pending: AtomicU8::new(0),
+			levels: Mutex::new([Level::default(); 8]),
+			done: AtomicBool::new(false)
+		}
+	}
+	
+	// the levels raised, for the CPU's interrupt scan
+	pub fn pending(&self) -> u8 {
+		self.pending.load(Ordering::Relaxed)
+	}
+	
+	// the CPU entered level for the storm's interrupt
+	pub fn taken(&self, level: usize) {
+		let mut levels = self.levels.lock().unwrap();
+		let l = &mut levels[level];
+		if let Some(since) = l.outstanding.take() {
+			let latency = since.elapsed();
+			l.taken += 1;
+			l.total += latency;
+			l.worst = l.worst.max(latency);
+		}
+		self.pending.fetch_and(!(1 << level), Ordering::Relaxed);
+	}
+	
+	// drop interrupts outstanding too long
+	fn expire(&self, levels: &mut [Level; 8]) {
+		for (n, l) in levels.iter_mut().enumerate() {
+			if l.outstanding.map_or(false, |x| x.elapsed() >= LOST_AFTER) {
+				l.outstanding = None;
+				l.lost += 1;
+				self.pending.fetch_and(!(1 << n), Ordering::Relaxed);
+			}
+		}
+	}
+	
+	fn fire(storm: Arc<Storm>, mean: u64) -> thread::JoinHandle<()> {
+		thread::spawn(move || {
+			let mut rng = rand::thread_rng();
+			while !storm.done.load(Ordering::Relaxed) {
+				thread::sleep(time::Duration::from_micros(rng.gen_range(0..=2 * mean)));
+				let level = rng.gen_range(1..8);
+				let mut levels = storm.levels.lock().unwrap();
+				storm.expire(&mut levels);
+				let l = &mut levels[level];
+				if l.outstanding.is_none() {
+					l.raised += 1;
+					l.outstanding = Some(time::Instant::now());
+					storm.pending.fetch_or(1 << level, Ordering::Relaxed);
+				}
+			}
+		})
+	}
+}
+
+pub fn load_workload(machine: &mut Machine) {
+	machine.load_example("irqstorm").unwrap();
+	// as many passes as there are, so the time limit ends it
+	machine.cpu.lock().unwrap().R[10] = u32::MAX;
+}
+
+pub fn run(machine: &mut Machine, opt: &Options, mean: u64, workload: bool) -> i32 {
+	let storm = Arc::new(Storm::new());
+	machine.cpu.lock().unwrap().storm = Some(Arc::clone(&storm));
+	machine.cpu.lock().unwrap().cycle_limit = opt.max_cycles.unwrap_or(batch::BATCH_CYCLE_LIMIT);
+	let limit = match opt.time_limit() {
+		None if workload => Some(time::Duration::from_secs_f64(STORM_SECONDS)),
+		x => x,
+	};
+	
+	let start = time::Instant::now();
+	let thread = Storm::fire(Arc::clone(&storm), mean);
+	machine.start();
+	let stop = machine.wait_limit(limit);
+	storm.done.store(true, Ordering::Relaxed);
+	thread.join().unwrap();
+	let seconds = start.elapsed().as_secs_f64();
+	
+	let mut cpu = machine.cpu.lock().unwrap();
+	cpu.storm = None;
+	let mut levels = storm.levels.lock().unwrap();
+	storm.expire(&mut levels);
+	let (mut raised, mut lost) = (0, 0);
+	let mut worst = (time::Duration::ZERO, 0);
+	for (n, l) in levels.iter().enumerate().skip(1) {
+		let mean = if l.taken != 0 { l.total.as_micros() / l.taken as u128 } else { 0 };
+		let mut line = format!("STORM: LEVEL {} RAISED {} TAKEN {} LOST {} WORST {}US MEAN {}US",
+			n, l.raised, l.taken, l.lost, l.worst.as_micros(), mean);
+		if l.outstanding.is_some() {
+			line.push_str(" PENDING 1");
+		}
+		// the example's handler for level n counts in R(n + 1)
+		if workload {
+			line.push_str(&format!(" GUEST {}", cpu.R[n + 1]));
+		}
+		println!("{}", line);
+		raised += l.raised;
+		lost += l.lost;
+		if l.worst > worst.0 {
+			worst = (l.worst, n);
+		}
+	}
+	println!("STORM: {} INTERRUPTS IN {:.3} SECONDS, {} INSTRUCTIONS, WORST {}US ON LEVEL {}",
+		raised, seconds, cpu.cycles, worst.0.as_micros(), worst.1);
+	drop(levels);
+	drop(cpu);
+	
+	if lost != 0 {
+		println!("STORM: {} INTERRUPTS LOST", lost);
+		return EXIT_LOST;
+	}
+	match stop {
+		Stop::Halted => 0,
+		_ if workload => 0,
+		Stop::CycleLimit => {
+			println!("STORM: CYCLE LIMIT EXCEEDED");
+			batch::EXIT_LIMIT
+		},
+		Stop::TimeLimit => {
+			println!("STORM: TIME LIMIT EXCEEDED");
+			batch::EXIT_LIMIT
+		},
+	}
+}
@@ -0,0 +1,288 @@
+// ISA: SeriesQ instruction formats and opcode table, shared by the assembler and tools
+//
+// The first halfword holds the opcode in its high byte. RR instructions carry two
+// register (or 4-bit immediate) fields d and r in the low byte; RM and RMX add a
+// second halfword with segment register s in the top nibble and either a 12-bit
+// index (RM) or index register x and 8-bit index (RMX) below it.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+	None,		// no operands; the low byte is ignored
+	RR,			// d, r
+	Shift(u32),	// d, count; r holds count minus the bias
+	Imm8,		// 8-bit immediate in the low byte
+	RMX,		// d, s: r, x, i8
+	RM			// d, s: r, i12
+}
+
+// who may run an instruction; the rest fault with SUPERVISOR ACCESS outside
+// the supervisor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Privilege {
+	Anyone,
+	Supervisor,
+	HighD,		// the supervisor only when d is 8 or above
+	HighR		// the supervisor only when r is 8 or above
+}
+
+// what an instruction does with memory at its operand address
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+	None,
+	Read,
+	Write,
+	ReadWrite
+}
+
+// F0 bits, high to low
+pub const FLAG_NAMES: &str = "PLGEVCSB";
+
+const ARITH: u8 = 0xFF;
+const SHIFT: u8 = 0x84;
+const MULTIPLY: u8 = 0x8C;
+const DIVIDE: u8 = 0x88;
+const DECIMAL: u8 = 0x70;
+const FLOAT_COMPARE: u8 = 0x78;
+
+#[derive(Debug)]
+pub struct Op {
+	pub mnemonic: &'static str,
+	pub opcode: u8,
+	pub format: Format,
+	
+	// for the reference card: what it does, the F0 bits it sets, and what it can
+	// fault on beyond its privilege and memory access
+	pub summary: &'static str,
+	pub flags: u8,
+	pub privilege: Privilege,
+	pub access: Access,
+	pub faults: &'static str
+}
+
+const fn op(mnemonic: &'static str, opcode: u8, format: Format) -> Op {
+	Op {
+		mnemonic: mnemonic,
+		opcode: opcode,
+		format: format,
+		summary: "",
+		flags: 0,
+		privilege: Privilege::Anyone,
+		access: Access::None,
+		faults: ""
+	}
+}
+
+impl Op {
+	const fn doc(mut self, summary: &'static str) -> Op {
+		self.summary = summary;
+		self
+	}
+	const fn flags(mut self, flags: u8) -> Op {
+		self.flags = flags;
+		self
+	}
+	const fn privilege(mut self, privilege: Privilege) -> Op {
+		self.privilege = privilege;
+		self
+	}
+	const fn access(mut self, access: Access) -> Op {
+		self.access = access;
+		self
+	}
+	const fn faults(mut self, faults: &'static str) -> Op {
+		self.faults = faults;
+		self
+	}
+}
+
+// The CPU decodes with its own match in cpu.rs; summary, flags, privilege and
+// access here are kept to what its arms do.
+pub static OPS: &[Op] = &[
+	op("MV", 0x00, Format::RR).doc("R[d] = R[r]"),
+	op("LQ", 0x01, Format::RR).doc("R[d] = r"),
+	op("BTR", 0x02, Format::RR).doc("R[d] = low byte of R[r]"),
+	op("HTR", 0x03, Format::RR).doc("R[d] = low half of R[r]"),
+	op("BSF", 0x04, Format::RR).doc("R[d] = low byte of R[r], sign extended"),
+	op("HSF", 0x05, Format::RR).doc("R[d] = low half of R[r], sign extended"),
+	op("BNS", 0x06, Format::RR).doc("low byte of R[d] = low byte of R[r]"),
+	op("HNS", 0x07, Format::RR).doc("low half of R[d] = low half of R[r]"),
+	op("A", 0x08, Format::RR).doc("R[d] += R[r]").flags(ARITH),
+	op("AC", 0x09, Format::RR).doc("R[d] += R[r] + C").flags(ARITH),
+	op("S", 0x0A, Format::RR).doc("R[d] -= R[r]").flags(ARITH),
+	op("SC", 0x0B, Format::RR).doc("R[d] -= R[r] + C").flags(ARITH),
+	op("AQ", 0x0C, Format::RR).doc("R[d] += r").flags(ARITH),
+	op("AQC", 0x0D, Format::RR).doc("R[d] += r + C").flags(ARITH),
+	op("SQ", 0x0E, Format::RR).doc("R[d] -= r").flags(ARITH),
+	op("SQC", 0x0F, Format::RR).doc("R[d] -= r + C").flags(ARITH),
+	op("AN", 0x10, Format::RR).doc("R[d] &= R[r]"),
+	op("O", 0x11, Format::RR).doc("R[d] |= R[r]"),
+	op("X", 0x12, Format::RR).doc("R[d] ^= R[r]"),
+	op("XN", 0x13, Format::RR).doc("R[d] = !(R[d] ^ R[r])"),
+	op("ANQ", 0x14, Format::RR).doc("R[d] &= r"),
+	op("OQ", 0x15, Format::RR).doc("R[d] |= r"),
+	op("XQ", 0x16, Format::RR).doc("R[d] ^= r"),
+	op("XNQ", 0x17, Format::RR).doc("R[d] = !(R[d] ^ r)"),
+	op("SL", 0x18, Format::RR).doc("R[d] <<= R[r] mod 32").flags(SHIFT),
+	op("SR", 0x19, Format::RR).doc("R[d] >>= R[r] mod 32, logical").flags(SHIFT),
+	op("ASL", 0x1A, Format::RR).doc("R[d] <<= R[r] mod 32, arithmetic").flags(SHIFT),
+	op("ASR", 0x1B, Format::RR).doc("R[d] >>= R[r] mod 32, arithmetic").flags(SHIFT),
+	op("SLQ", 0x1C, Format::Shift(1)).doc("R[d] <<= count (1-16)").flags(SHIFT),
+	op("SRQ", 0x1D, Format::Shift(1)).doc("R[d] >>= count (1-16), logical").flags(SHIFT),
+	op("SLQL", 0x1E, Format::Shift(16)).doc("R[d] <<= count (16-31)").flags(SHIFT),
+	op("SRQL", 0x1F, Format::Shift(16)).doc("R[d] >>= count (16-31), logical").flags(SHIFT),
+	op("C", 0x20, Format::RR).doc("set flags as for R[d] - R[r]").flags(ARITH),
+	op("LF", 0x22, Format::RR).doc("R[d] = F[r]"),
+	op("SF", 0x23, Format::RR).doc("F[d] = low byte of R[r]").flags(ARITH).privilege(Privilege::HighR),
+	op("LSDTR", 0x24, Format::RR).doc("R[d] = SDT base, R[r] = SDT length").privilege(Privilege::Supervisor),
+	op("SSDTR", 0x25, Format::RR).doc("SDT base = R[d], length = R[r]; reload PEBA and PLBA from the SDT")
+		.privilege(Privilege::Supervisor).faults("READ ADDRESS, READ ALIGNMENT or READ FAULT reading the SDT"),
+	op("LSEL", 0x26, Format::RR).doc("R[d] = selector in segment register r"),
+	op("SSEL", 0x27, Format::RR).doc("load segment register d with selector R[r]").privilege(Privilege::HighD),
+	op("LMPK", 0x28, Format::RR).doc("R[d] = MPK[r]").privilege(Privilege::Supervisor),
+	op("SMPK", 0x29, Format::RR).doc("MPK[d] = R[r]").privilege(Privilege::Supervisor),
+	op("CSEL", 0x2A, Format::RR).doc("segment register d = segment register r").privilege(Privilege::HighD),
+	op("SSELHC", 0x2B, Format::RR).doc("load segment register d with selector r").privilege(Privilege::HighD),
+	op("TKEY", 0x2C, Format::RR).doc("R[d] = 1 if key R[d] admits to segment r, else 0").privilege(Privilege::Supervisor),
+	op("INVSEL", 0x2D, Format::RR).doc("drop selector R[r] from the descriptor cache; R[d] = 1 if it was there")
+		.privilege(Privilege::Supervisor),
+	op("LPBA", 0x2E, Format::RR).doc("R[d] = PEBA, R[r] = PLBA").privilege(Privilege::Supervisor),
+	op("SPBA", 0x2F, Format::RR).doc("PEBA = R[d], PLBA = R[r]").privilege(Privilege::Supervisor)
+		.faults("OUT OF BOUNDS if either table is not attached"),
+	op("PLR", 0x30, Format::None).doc("return from the priority level, restoring its caller's state").flags(ARITH),
+	op("SVC", 0x31, Format::Imm8).doc("fault with the code in the immediate").faults("always, with the immediate's code"),
+	op("YIELD", 0x32, Format::None).doc("let the host and other threads have the machine"),
+	op("IF", 0x3E, Format::Imm8).doc("skip the next instruction unless F0 has a bit of the mask"),
+	op("IFN", 0x3F, Format::Imm8).doc("skip the next instruction if F0 has a bit of the mask"),
+	
+	op("L", 0x40, Format::RMX).doc("R[d] = word at the address").access(Access::Read),
+	op("LA", 0x41, Format::RMX).doc("R[d] = the address"),
+	op("BTR", 0x42, Format::RMX).doc("R[d] = byte at the address").access(Access::Read),
+	op("HTR", 0x43, Format::RMX).doc("R[d] = half at the address").access(Access::Read),
+	op("BSF", 0x44, Format::RMX).doc("R[d] = byte at the address, sign extended").access(Access::Read),
+	op("HSF", 0x45, Format::RMX).doc("R[d] = half at the address, sign extended").access(Access::Read),
+	op("BNS", 0x46, Format::RMX).doc("low byte of R[d] = byte at the address").access(Access::Read),
+	op("HNS", 0x47, Format::RMX).doc("low half of R[d] = half at the address").access(Access::Read),
+	op("ST", 0x48, Format::RMX).doc("word at the address = R[d]").access(Access::Write),
+	op("BST", 0x49, Format::RMX).doc("byte at the address = low byte of R[d]").access(Access::Write),
+	op("HST", 0x4A, Format::RMX).doc("half at the address = low half of R[d]").access(Access::Write),
+	op("BAL", 0x5F, Format::RMX).doc("branch to the address in segment s; unless d is 0, R[d] and LS link back"),
+	
+	op("L", 0x60, Format::RM).doc("R[d] = word at the address").access(Access::Read),
+	op("LA", 0x61, Format::RM).doc("R[d] = the address"),
+	op("BTR", 0x62, Format::RM).doc("R[d] = byte at the address").access(Access::Read),
+	op("HTR", 0x63, Format::RM).doc("R[d] = half at the address").access(Access::Read),
+	op("BSF", 0x64, Format::RM).doc("R[d] = byte at the address, sign extended").access(Access::Read),
+	op("HSF", 0x65, Format::RM).doc("R[d] = half at the address, sign extended").access(Access::Read),
+	op("BNS", 0x66, Format::RM).doc("low byte of R[d] = byte at the address").access(Access::Read),
+	op("HNS", 0x67, Format::RM).doc("low half of R[d] = half at the address").access(Access::Read),
+	op("ST", 0x68, Format::RM).doc("word at the address = R[d]").access(Access::Write),
+	op("BST", 0x69, Format::RM).doc("byte at the address = low byte of R[d]").access(Access::Write),
+	op("HST", 0x6A, Format::RM).doc("half at the address = low half of R[d]").access(Access::Write),
+	op("LDMPK", 0x6C, Format::RM).doc("load all 16 memory protection keys from the address")
+		.privilege(Privilege::Supervisor).access(Access::Read),
+	op("STMPK", 0x6D, Format::RM).doc("store all 16 memory protection keys at the address")
+		.privilege(Privilege::Supervisor).access(Access::Write),
+	op("STCTX", 0x6E, Format::RM).doc("store the whole architectural state in the context block at the address")
+		.privilege(Privilege::Supervisor).access(Access::Write),
+	op("LDCTX", 0x6F, Format::RM).doc("load the whole architectural state from the context block at the address")
+		.flags(ARITH).privilege(Privilege::Supervisor).access(Access::Read),
+	op("BAL", 0x7F, Format::RM).doc("branch to the address in segment s; unless d is 0, R[d] and LS link back"),
+	
+	op("VADDB", 0x80, Format::RR).doc("add byte lanes of R[r..r+3] into R[d..d+3]").faults(VECTOR),
+	op("VSUBB", 0x81, Format::RR).doc("subtract byte lanes of R[r..r+3] from R[d..d+3]").faults(VECTOR),
+	op("VCEQB", 0x82, Format::RR).doc("byte lanes of R[d..d+3] = all ones where equal to R[r..r+3], else 0").faults(VECTOR),
+	op("VCGTB", 0x83, Format::RR).doc("byte lanes of R[d..d+3] = all ones where greater than R[r..r+3], else 0").faults(VECTOR),
+	op("VMINB", 0x84, Format::RR).doc("byte lanes of R[d..d+3] = the lesser of them and R[r..r+3]").faults(VECTOR),
+	op("VMAXB", 0x85, Format::RR).doc("byte lanes of R[d..d+3] = the greater of them and R[r..r+3]").faults(VECTOR),
+	op("VADDH", 0x88, Format::RR).doc("add half lanes of R[r..r+3] into R[d..d+3]").faults(VECTOR),
+	op("VSUBH", 0x89, Format::RR).doc("subtract half lanes of R[r..r+3] from R[d..d+3]").faults(VECTOR),
+	op("VCEQH", 0x8A, Format::RR).doc("half lanes of R[d..d+3] = all ones where equal to R[r..r+3], else 0").faults(VECTOR),
+	op("VCGTH", 0x8B, Format::RR).doc("half lanes of R[d..d+3] = all ones where greater than R[r..r+3], else 0").faults(VECTOR),
+	op("VMINH", 0x8C, Format::RR).doc("half lanes of R[d..d+3] = the lesser of them and R[r..r+3]").faults(VECTOR),
+	op("VMAXH", 0x8D, Format::RR).doc("half lanes of R[d..d+3] = the greater of them and R[r..r+3]").faults(VECTOR),
+	op("LTOD", 0x90, Format::RR).doc("R[d] = time-of-day low word, R[r] = high word"),
+	op("STOD", 0x91, Format::RR).doc("time-of-day = R[r]:R[d]").privilege(Privilege::Supervisor),
+	op("LTODC", 0x92, Format::RR).doc("R[d] = time-of-day comparator low word, R[r] = high word").privilege(Privilege::Supervisor),
+	op("STODC", 0x93, Format::RR).doc("time-of-day comparator = R[r]:R[d]").privilege(Privilege::Supervisor),
+	
+	op("MUL", 0xA0, Format::RR).doc("R[d] *= R[r], signed; V and C if the product doesn't fit").flags(MULTIPLY),
+	op("MULU", 0xA1, Format::RR).doc("R[d] *= R[r], unsigned; V and C if the product doesn't fit").flags(MULTIPLY),
+	op("DIV", 0xA2, Format::RR).doc("R[d] /= R[r], signed, toward zero; V for 0x80000000 / -1, which leaves 0x80000000")
+		.flags(DIVIDE).faults(ZERO),
+	op("DIVU", 0xA3, Format::RR).doc("R[d] /= R[r], unsigned").flags(DIVIDE).faults(ZERO),
+	op("MULW", 0xA4, Format::RR).doc("R[d]:R[d+1] = R[d] * R[r], signed, high word in R[d]").flags(MULTIPLY).faults(PAIR),
+	op("MULWU", 0xA5, Format::RR).doc("R[d]:R[d+1] = R[d] * R[r], unsigned, high word in R[d]").flags(MULTIPLY).faults(PAIR),
+	op("DIVR", 0xA6, Format::RR).doc("R[d] /= R[r], signed, toward zero; R[d+1] = the remainder, with R[d]'s sign")
+		.flags(DIVIDE).faults(QUOTIENT),
+	op("MODR", 0xA7, Format::RR).doc("R[d] /= R[r], signed, toward minus infinity; R[d+1] = the remainder, with R[r]'s sign")
+		.flags(DIVIDE).faults(QUOTIENT),
+	op("FADD", 0xB0, Format::RR).doc("FP[d] += FP[r]; F1 for the exceptions raised"),
+	op("FSUB", 0xB1, Format::RR).doc("FP[d] -= FP[r]; F1 for the exceptions raised"),
+	op("FMUL", 0xB2, Format::RR).doc("FP[d] *= FP[r]; F1 for the exceptions raised"),
+	op("FDIV", 0xB3, Format::RR).doc("FP[d] /= FP[r]; F1 for the exceptions raised"),
+	op("FCMP", 0xB4, Format::RR).doc("set L, G or E as for FP[d] - FP[r], or V if either is a NaN").flags(FLOAT_COMPARE),
+	op("FLT", 0xB5, Format::RR).doc("FP[d] = R[r] as a signed integer; F1 if inexact"),
+	op("FIX", 0xB6, Format::RR).doc("R[d] = FP[r] as a signed integer, toward zero; F1 if inexact or out of range"),
+	op("FMV", 0xB7, Format::RR).doc("FP[d] = FP[r]"),
+	op("FPUT", 0xB8, Format::RR).doc("FP[d] = the bits of R[r]"),
+	op("FGET", 0xB9, Format::RR).doc("R[d] = the bits of FP[r]"),
+	
+	op("AESE", 0xC0, Format::RM).doc("AES-128 encrypt the block at the address in place, key at s: R[d]")
+		.access(Access::ReadWrite).faults(CRYPTO),
+	op("AESD", 0xC1, Format::RM).doc("AES-128 decrypt the block at the address in place, key at s: R[d]")
+		.access(Access::ReadWrite).faults(CRYPTO),
+	op("SHA256", 0xC2, Format::RM).doc("compress the 64-byte block at the address into the 8-word state at s: R[d]")
+		.access(Access::ReadWrite).faults(CRYPTO),
+	op("AP", 0xC8, Format::RM).doc("packed decimal field at the address += field at s: R[d]; L, G or E for the sum's sign")
+		.flags(DECIMAL).access(Access::ReadWrite).faults(PACKED),
+	op("SP", 0xC9, Format::RM).doc("packed decimal field at the address -= field at s: R[d]; L, G or E for the difference's sign")
+		.flags(DECIMAL).access(Access::ReadWrite).faults(PACKED),
+	op("ZAP", 0xCA, Format::RM).doc("packed decimal field at the address = field at s: R[d]; L, G or E for its sign")
+		.flags(DECIMAL).access(Access::Write).faults(PACKED),
+	op("FL", 0xD0, Format::RM).doc("FP[d] = word at the address").access(Access::Read),
+	op("FST", 0xD1, Format::RM).doc("word at the address = FP[d]").access(Access::Write),
+	
+	op("HLT", 0xFF, Format::None).doc("stop the CPU"),
+];
+
+const VECTOR: &str = "ILLEGAL INSTRUCTION unless d and r are multiples of 4 and d is not 12";
+const ZERO: &str = "DIVIDE BY ZERO if R[r] is 0";
+const PAIR: &str = "ILLEGAL INSTRUCTION unless d is even and not 14";
+const QUOTIENT: &str = "ILLEGAL INSTRUCTION unless d is even and not 14; DIVIDE BY ZERO if R[r] is 0; DIVIDE OVERFLOW for 0x80000000 / -1";
+const PACKED: &str = "DECIMAL DATA for a bad digit or sign in a field read; DECIMAL OVERFLOW past 15 digits";
+pub const CRYPTO: &str = "ILLEGAL INSTRUCTION unless built with crypto";
+
+// instruction length in bytes, from the first halfword
+pub fn length(iword0: u16) -> u32 {
+	if (iword0 >> 14) & 3 == 1 || (iword0 >> 14) & 3 == 3 {
+		4
+	} else {
+		2
+	}
+}
+
+pub fn by_opcode(opcode: u8) -> Option<&'static Op> {
+	OPS.iter().find(|x| x.opcode == opcode)
+}
+
+// all table entries for a mnemonic; RR and memory forms may share one
+pub fn by_mnemonic(mnemonic: &str) -> impl Iterator<Item = &'static Op> + '_ {
+	OPS.iter().filter(move |x| x.mnemonic.eq_ignore_ascii_case(mnemonic))
+}
+
+// one instruction in assembler syntax; iword1 is only used by RM and RMX
+pub fn disassemble(iword0: u16, iword1: u16) -> String {
+	let op = match by_opcode((iword0 >> 8) as u8) {
+		Some(x) => x,
+		None => return format!("??? 0x{:04X}", iword0),
+	};
+	let (d, r) = ((iword0 >> 4) & 0xF, iword0 & 0xF);
+	match op.format {
+		Format::None => op.mnemonic.to_string(),
+		Format::RR => format!("{} {}, {}", op.mnemonic, d, r),
+		Format::Shift(bias) => format!("{} {}, {}", op.mnemonic, d, r as u32 + bias),
+		Format::Imm8 => format!("{} 0x{:02X}", op.mnemonic, iword0 & 0xFF),
+		Format::RMX => format!("{} {}, {}: {}, {}, 0x{:02X}", op.mnemonic, d, iword1 >> 12, r, (iword1 >> 8) & 0xF, iword1 & 0xFF),
+		Format::RM => format!("{} {}, {}: {}, 0x{:03X}", op.mnemonic, d, iword1 >> 12, r, iword1 & 0xFFF),
+	}
+}
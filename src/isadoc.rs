@@ -0,0 +1,108 @@
+use crate::isa::{Access, Format, Op, Privilege, FLAG_NAMES, OPS};
+
+// IsaDoc: the instruction set reference card, written from the opcode table
+//
+// One entry per opcode in table order: assembler syntax, the encoding's bits,
+// what it does, the F0 flags it sets and what it can fault on. Text is for the
+// terminal and listings, HTML a single page with no outside references.
+
+const PREAMBLE: &[&str] = &[
+	"Flags are F0 bits, high to low: P odd result, L r below d, G r above d,",
+	"E r equal to d, V overflow, C carry, S r below d signed, B r above d",
+	"signed; - is left alone. Shifts set C to the last bit shifted out.",
+	"RM and RMX address s: R[r] + i12 or s: R[r] + R[x] + i8, s being a segment",
+	"register. Any instruction can fault on its fetch, and opcodes not listed",
+	"fault with ILLEGAL INSTRUCTION.",
+];
+
+fn syntax(op: &Op) -> String {
+	match op.format {
+		Format::None => op.mnemonic.to_string(),
+		Format::RR => format!("{} d, r", op.mnemonic),
+		Format::Shift(bias) => format!("{} d, count (r = count - {})", op.mnemonic, bias),
+		Format::Imm8 => format!("{} i8", op.mnemonic),
+		Format::RMX => format!("{} d, s: r, x, i8", op.mnemonic),
+		Format::RM => format!("{} d, s: r, i12", op.mnemonic),
+	}
+}
+
+fn encoding(op: &Op) -> String {
+	let operands = match op.format {
+		Format::None => "--------",
+		Format::RR | Format::Shift(_) => "dddd rrrr",
+		Format::Imm8 => "iiiiiiii",
+		Format::RMX => "dddd rrrr ssss xxxx iiiiiiii",
+		Format::RM => "dddd rrrr ssss iiiiiiiiiiii",
+	};
+	format!("{:08b} {}", op.opcode, operands)
+}
+
+fn flags(op: &Op) -> String {
+	FLAG_NAMES.chars().enumerate()
+		.map(|(n, x)| if op.flags & (0x80 >> n) != 0 { x } else { '-' })
+		.collect()
+}
+
+fn faults(op: &Op) -> Vec<String> {
+	let mut out = Vec::new();
+	match op.privilege {
+		Privilege::Anyone => { },
+		Privilege::Supervisor => out.push("SUPERVISOR ACCESS outside the supervisor".to_string()),
+		Privilege::HighD => out.push("SUPERVISOR ACCESS outside the supervisor if d is 8 or above".to_string()),
+		Privilege::HighR => out.push("SUPERVISOR ACCESS outside the supervisor if r is 8 or above".to_string()),
+	}
+	if op.access != Access::None {
+		out.push("SEGMENTATION FAULT or STACK OVERFLOW outside the segment".to_string());
+	}
+	if op.access == Access::Read || op.access == Access::ReadWrite {
+		out.push("READ ADDRESS, READ ALIGNMENT or READ FAULT from the bus".to_string());
+	}
+	if op.access == Access::Write || op.access == Access::ReadWrite {
+		out.push("WRITE ADDRESS, WRITE ALIGNMENT or WRITE FAULT from the bus".to_string());
+	}
+	if !op.faults.is_empty() {
+		out.push(op.faults.to_string());
+	}
+	out
+}
+
+pub fn text() -> String {
+	let mut out = String::from("SERIESQ INSTRUCTION SET\n\n");
+	for line in PREAMBLE {
+		out += &format!("{}\n", line);
+	}
+	for op in OPS {
+		out += &format!("\n{:02X}  {:<24} {}\n", op.opcode, syntax(op), encoding(op));
+		out += &format!("    {}\n", op.summary);
+		out += &format!("    flags {}\n", flags(op));
+		for x in faults(op) {
+			out += &format!("    faults {}\n", x);
+		}
+	}
+	out
+}
+
+fn escape(x: &str) -> String {
+	x.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+pub fn html() -> String {
+	let mut out = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+		<title>SeriesQ instruction set</title>\n<style>\n\
+		body { font-family: sans-serif; }\n\
+		table { border-collapse: collapse; }\n\
+		th, td { border: 1px solid #999; padding: 2px 6px; text-align: left; vertical-align: top; }\n\
+		code { white-space: nowrap; }\n\
+		</style>\n</head>\n<body>\n<h1>SeriesQ instruction set</h1>\n<p>\n");
+	for line in PREAMBLE {
+		out += &format!("{}\n", escape(line));
+	}
+	out += "</p>\n<table>\n<tr><th>Op</th><th>Syntax</th><th>Encoding</th><th>Operation</th><th>Flags</th><th>Faults</th></tr>\n";
+	for op in OPS {
+		let faults: Vec<String> = faults(op).iter().map(|x| escape(x)).collect();
+		out += &format!("<tr><td><code>{:02X}</code></td><td><code>{}</code></td><td><code>{}</code></td><td>{}</td><td><code>{}</code></td><td>{}</td></tr>\n",
+			op.opcode, escape(&syntax(op)), encoding(op), escape(op.summary), flags(op), faults.join("<br>"));
+	}
+	out += "</table>\n</body>\n</html>\n";
+	out
+}
@@ -0,0 +1,159 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::{thread, time};
+use crate::sync::Mutex;
+use crate::batch::{BATCH_CYCLE_LIMIT, EXIT_HOST_ERROR};
+use crate::machine::{Machine, Stop};
+use crate::options::{self, Options};
+use crate::sink::Sink;
+use crate::snapshot::Snapshot;
+use crate::transcript;
+
+// Jobs: a small batch service running job files submitted to a queue directory
+//
+// A job is a text file NAME.job whose first line is a control card
+//   $JOB JOBNAME [CYCLES=N]
+// and whose other lines are the deck. Jobs run one at a time in order of file
+// name, each from the machine as it stood when the service started, with its
+// deck in the card reader and the CPU started in the firmware loader, as with
+// --boot. A job ends when the CPU halts or reaches its cycle limit (CYCLES, or
+// --max-cycles, or the batch default). Its printer and punch output go to
+// output/JOBNAME.lst and output/JOBNAME.pun in the queue directory, and the
+// job file is then moved to done/. The directory is looked at every
+// POLL_INTERVAL for new jobs; the service runs until it is killed.
+
+const POLL_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+struct Job {
+	name: String,
+	cycles: Option<u64>,
+	deck: String
+}
+
+fn parse(text: &str) -> Result<Job, String> {
+	let (card, deck) = text.split_once('\n').unwrap_or((text, ""));
+	let mut words = card.split_whitespace();
+	if words.next() != Some("$JOB") {
+		return Err("no $JOB card".to_string());
+	}
+	let name = match words.next() {
+		Some(x) if x.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') => x.to_string(),
+		Some(x) => return Err(format!("bad job name {}", x)),
+		None => return Err("$JOB card without a job name".to_string()),
+	};
+	let mut cycles = None;
+	for word in words {
+		match word.strip_prefix("CYCLES=").and_then(options::parse_number) {
+			Some(x) if x > 0 => cycles = Some(x),
+			_ => return Err(format!("bad $JOB option {}", word)),
+		}
+	}
+	Ok(Job { name: name, cycles: cycles, deck: deck.to_string() })
+}
+
+// job files waiting in the queue, in the order they will run
+fn submitted(dir: &Path) -> io::Result<Vec<PathBuf>> {
+	let mut jobs: Vec<PathBuf> = fs::read_dir(dir)?
+		.filter_map(|e| e.ok().map(|e| e.path()))
+		.filter(|p| p.is_file() && p.extension().map_or(false, |x| x == "job"))
+		.collect();
+	jobs.sort();
+	Ok(jobs)
+}
+
+// Spool: the printer and punch outputs, redirected to each job's files in turn
+
+pub struct Spool {
+	printer: Arc<Mutex<Sink>>,
+	punch: Arc<Mutex<Sink>>
+}
+
+impl Spool {
+	// before the printer is queued, which takes its output with it
+	pub fn attach(machine: &Machine) -> Spool {
+		let spool = Spool {
+			printer: Arc::new(Mutex::new(Sink::Frontend("PRINTER"))),
+			punch: Arc::new(Mutex::new(Sink::Frontend("PUNCH")))
+		};
+		machine.printer.lock().unwrap().output = Sink::Shared(Arc::clone(&spool.printer));
+		machine.punch.lock().unwrap().output = Sink::Shared(Arc::clone(&spool.punch));
+		spool
+	}
+	
+	fn open(&self, output: &Path, name: &str) -> io::Result<()> {
+		let printer = Sink::file(&output.join(format!("{}.lst", name)).to_string_lossy())?;
+		let punch = Sink::file(&output.join(format!("{}.pun", name)).to_string_lossy())?;
+		*self.printer.lock().unwrap() = printer;
+		*self.punch.lock().unwrap() = punch;
+		Ok(())
+	}
+	
+	fn close(&self) {
+		*self.printer.lock().unwrap() = Sink::Frontend("PRINTER");
+		*self.punch.lock().unwrap() = Sink::Frontend("PUNCH");
+	}
+}
+
+// run a job from the initial state, returning how it ended
+fn run_job(machine: &mut Machine, opt: &Options, initial: &Snapshot, spool: &Spool, output: &Path, job: &Job) -> String {
+	if let Err(e) = machine.restore(initial) {
+		return format!("JOB {} NOT RUN: cannot reset the machine: {}", job.name, e);
+	}
+	if let Err(e) = spool.open(output, &job.name) {
+		return format!("JOB {} NOT RUN: {}", job.name, e);
+	}
+	println!("JOB {} STARTED", job.name);
+	machine.reader.lock().unwrap().load_cards(&job.deck);
+	machine.boot();
+	machine.cpu.lock().unwrap().cycle_limit = job.cycles.or(opt.max_cycles).unwrap_or(BATCH_CYCLE_LIMIT);
+	machine.start();
+	let stop = machine.wait_limit(opt.time_limit());
+	spool.close();
+	if let Err(e) = machine.dasd.lock().unwrap().flush() {
+		println!("{}", e);
+	}
+	match stop {
+		Stop::Halted => format!("JOB {} ENDED", job.name),
+		Stop::CycleLimit => format!("JOB {} CYCLE LIMIT EXCEEDED", job.name),
+		Stop::TimeLimit => format!("JOB {} TIME LIMIT EXCEEDED", job.name),
+	}
+}
+
+pub fn run(machine: &mut Machine, opt: &Options, spool: Spool, dir: &str) -> i32 {
+	let dir = Path::new(dir);
+	let (output, done) = (dir.join("output"), dir.join("done"));
+	if let Err(e) = fs::create_dir_all(&output).and_then(|_| fs::create_dir_all(&done)) {
+		println!("{}: {}", dir.display(), e);
+		return EXIT_HOST_ERROR;
+	}
+	let initial = machine.snapshot();
+	println!("JOB QUEUE {}", dir.display());
+	
+	loop {
+		let queue = match submitted(dir) {
+			Ok(x) => x,
+			Err(e) => {
+				println!("{}: {}", dir.display(), e);
+				return EXIT_HOST_ERROR;
+			},
+		};
+		for path in queue {
+			let file = path.file_name().unwrap().to_string_lossy().to_string();
+			let result = match fs::read_to_string(&path).map_err(|e| e.to_string()).and_then(|x| parse(&x)) {
+				Ok(job) => run_job(machine, opt, &initial, &spool, &output, &job),
+				Err(e) => format!("{} REJECTED: {}", file, e),
+			};
+			println!("{}", result);
+			transcript::record("JOBS", &result);
+			
+			// a job that cannot be moved out of the queue would run forever
+			if let Err(e) = fs::rename(&path, done.join(&file)) {
+				println!("{}: {}", path.display(), e);
+				return EXIT_HOST_ERROR;
+			}
+		}
+		thread::sleep(POLL_INTERVAL);
+	}
+}
@@ -0,0 +1,628 @@
+use std::fs;
+use std::io;
+use std::sync::Arc;
+use crate::sync::Mutex;
+use std::sync::atomic::{AtomicU8, AtomicU64, AtomicBool, Ordering};
+use std::thread;
+use crate::bus::{Bus, BusError, Channel, Memory32};
+use crate::charset::CodePage;
+use crate::clock::Domain;
+use crate::mmio::Device;
+use crate::sink::Sink;
+use crate::transcript;
+use crate::watchdog;
+use serde::{Serialize, Deserialize};
+
+// LP1204: line printer of 144 columns, or 80, 132 or 160 (--print-width),
+// with one line buffer or two (--print-buffers); command at 144, channel at
+// 145, buffer number at 146, execute at 148, page address at 152 (word), line
+// count at 156 (word), geometry at 160 (word: the width, the number of buffers
+// in the third byte and the offset of buffer 0 in the top byte, in units of
+// 256 bytes; put back as each command finishes, should a guest store there),
+// interrupt control at 164, interrupt status at 165 and printer status at
+// 166.
+// The buffers follow each other from offset 0 when they fit below the command,
+// as one 144 column buffer does, or else from 0x100.
+//
+// Commands:
+//   0	print the buffer and space one line
+//   1	skip to the next line punched for the channel, without printing
+//   2	print the buffer, then skip to the channel
+//   3	load the carriage tape: the channel byte is the form length in lines
+//		(1-72), and the buffer holds a halfword of punches for each line, bit
+//		n - 1 for channel n; the paper is then at the top of the form
+//   4	print a page: read line count records from the page address over the
+//		printer's DMA channel and carry out each as command 0, 1 or 2, a
+//		record being a line of the width, the command and the channel; the
+//		count is left holding the records not printed, which is not 0 if the
+//		page ran into memory that could not be read; the channel reaches only
+//		main memory and the ROM, and a page elsewhere also faults on IPL 4
+//		(dma.rs). With the channel's translation on, the page address is a
+//		selector and offset (iommu.rs)
+// Commands 0 to 3 work on the buffer the buffer number names; with two, a
+// guest can fill one while the other is printed. A buffer number past the
+// last makes the command do nothing.
+// Skipping to a channel punched on no line spaces one line instead. The
+// output is text; a skip that passes the bottom of the form writes a form feed
+// in place of the rest of the page. A page of up to PAGE_LINES records takes
+// the time of one printed line, not one each. Only the polled printer prints
+// pages: the queued one could not wait for the bus without stalling a CPU
+// that reads it, so it ignores command 4.
+//
+// As a command finishes, once its line is out, the interrupt status is set to
+// PRINT_DONE_CODE, or PRINT_ERROR_CODE if the command could not be carried
+// out: an unknown command or buffer number, a carriage tape of no lines or
+// more than 72, or a page not printed to the end. With bit 0 of interrupt
+// control on, the printer interrupts on IPL 4 with the status as the PS
+// selector for as long as the status is not 0; a driver stores 0 there to
+// acknowledge it, and the polled printer drops the line when it next looks at
+// its registers. A driver can then start a command and wait for the interrupt
+// rather than polling the execute byte.
+//
+// Printer status: bit 0 out of paper, bit 1 jammed. The printer holds --paper
+// pages of forms (endless by default) and runs out once the carriage has fed
+// that many past the bottom of the form, at the end of the command that does
+// it. A jam comes only from the operator (the monitor's printer jam). While
+// either bit is up every command fails without moving the paper, with
+// PRINT_ERROR_CODE as its status, until the operator clears it (printer fix).
+
+// the printer's clock (clock.rs), and the ticks of it a printed line takes
+pub const PRINTER_HZ: u64 = 1000;
+const PRINT_TICKS: u64 = 90;
+
+pub const PRINTER_CHANNEL: usize = 4;
+pub const PAGE_LINES: u32 = 256;
+
+pub const WIDTHS: [u32; 4] = [80, 132, 144, 160];
+pub const MAX_BUFFERS: u32 = 2;
+
+pub const GEOMETRY: usize = 160;
+const INTERRUPTS: usize = 164;
+const INTERRUPT_STATUS: usize = 165;
+const PRINTER_STATUS: usize = 166;
+
+pub const INTERRUPT_ENABLE: u8 = 0b00000001;
+pub const PRINT_DONE_CODE: u8 = 0x44;
+pub const PRINT_ERROR_CODE: u8 = 0x45;
+
+pub const PAPER_OUT: u8 = 0b00000001;
+pub const JAMMED: u8 = 0b00000010;
+
+// Geometry: the line width and number of buffers, and so the region's layout
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Geometry {
+	pub width: u32,
+	pub buffers: u32
+}
+
+impl Default for Geometry {
+	fn default() -> Geometry {
+		Geometry { width: 144, buffers: 1 }
+	}
+}
+
+impl Geometry {
+	fn base(&self) -> u32 {
+		if self.width * self.buffers <= 144 { 0 } else { 0x100 }
+	}
+	
+	// offset of buffer n, if there is one
+	pub fn buffer(&self, n: u8) -> Option<usize> {
+		if (n as u32) < self.buffers {
+			Some((self.base() + n as u32 * self.width) as usize)
+		} else {
+			None
+		}
+	}
+	
+	// the region, in whole 256 byte pages
+	pub fn size(&self) -> u32 {
+		(self.base() + self.width * self.buffers).max(PRINTER_STATUS as u32 + 1).next_multiple_of(0x100)
+	}
+	
+	fn register(&self) -> u32 {
+		self.width | self.buffers << 16 | (self.base() >> 8) << 24
+	}
+	
+	// a page record: the line, then the command and channel
+	fn record(&self) -> u32 {
+		self.width + 2
+	}
+	
+	// as the geometry register gives it, if it is one
+	pub fn from_register(x: u32) -> Option<Geometry> {
+		let geometry = Geometry { width: x & 0xFFFF, buffers: (x >> 16) & 0xFF };
+		if geometry.width != 0 && geometry.buffers != 0 && geometry.register() == x {
+			Some(geometry)
+		} else {
+			None
+		}
+	}
+}
+
+pub const CHANNELS: u8 = 12;
+
+// 11 inch forms at 6 lines per inch, top of form on channel 1 and the
+// overflow line on channel 12
+const FORM_LINES: usize = 66;
+const OVERFLOW_LINE: usize = 60;
+
+const TAPE_MAX: usize = 72;
+
+// Carriage: the carriage control tape, one word of channel punches for each
+// line of the form, and the line the paper stands at
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Carriage {
+	pub tape: Vec<u16>,
+	pub line: usize,
+	#[serde(default)]
+	pub pages: u64		// forms fed past the bottom, for the paper supply
+}
+
+impl Default for Carriage {
+	fn default() -> Carriage {
+		let mut tape = vec![0 as u16; FORM_LINES];
+		tape[0] = 1;
+		tape[OVERFLOW_LINE] = 1 << (CHANNELS - 1);
+		Carriage { tape: tape, line: 0, pages: 0 }
+	}
+}
+
+impl Carriage {
+	// a tape from a text file with a line for each line of the form, listing
+	// the channels punched in it
+	pub fn load(path: &str) -> io::Result<Carriage> {
+		let bad = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+		let mut tape = Vec::new();
+		for (n, text) in fs::read_to_string(path)?.lines().enumerate() {
+			let mut punches = 0;
+			for word in text.split(|c: char| c == ',' || c.is_whitespace()).filter(|x| !x.is_empty()) {
+				match word.parse::<u8>() {
+					Ok(x) if x >= 1 && x <= CHANNELS => punches |= 1 << (x - 1),
+					_ => return Err(bad(format!("line {}: bad channel {}", n + 1, word))),
+				}
+			}
+			tape.push(punches);
+		}
+		if tape.is_empty() {
+			return Err(bad("empty carriage tape".to_string()));
+		}
+		Ok(Carriage { tape: tape, line: 0, pages: 0 })
+	}
+	
+	// move the paper; spacing just ends lines, but a skip past the bottom of
+	// the form ejects the page
+	fn feed(&mut self, output: &mut Sink, lines: usize, skip: bool) {
+		let to = self.line + lines;
+		let form = self.tape.len();
+		self.pages += (to / form) as u64;
+		if skip && to >= form {
+			self.line = to % form;
+			output.write(&format!("\n\x0C{}", "\n".repeat(self.line)));
+		} else {
+			self.line = to % form;
+			output.write(&"\n".repeat(lines));
+		}
+	}
+	
+	fn skip(&mut self, output: &mut Sink, channel: u8) {
+		let form = self.tape.len();
+		let punch = if channel >= 1 && channel <= CHANNELS { 1 << (channel - 1) } else { 0 };
+		match (1..=form).find(|n| self.tape[(self.line + n) % form] & punch != 0) {
+			Some(n) => self.feed(output, n, true),
+			None => self.feed(output, 1, false),
+		}
+	}
+	
+	// false for a form length out of range, leaving the tape as it was
+	fn load_tape(&mut self, buf: &[u8], lines: u8) -> bool {
+		let lines = lines as usize;
+		if lines == 0 || lines > TAPE_MAX {
+			return false;
+		}
+		self.tape = buf[..2 * lines].chunks(2).map(|x| u16::from_le_bytes([x[0], x[1]])).collect();
+		self.line = 0;
+		true
+	}
+}
+
+pub const ENDLESS: u64 = u64::MAX;
+
+// Paper: the forms left in the printer and whether they have jammed; shared
+// with the operator, since a running printer thread holds the printer itself
+pub struct Paper {
+	left: AtomicU64,		// pages, or ENDLESS
+	jammed: AtomicBool
+}
+
+impl Paper {
+	pub fn new() -> Paper {
+		Paper {
+			left: AtomicU64::new(ENDLESS),
+			jammed: AtomicBool::new(false)
+		}
+	}
+	
+	pub fn status(&self) -> u8 {
+		let mut status = 0;
+		if self.left.load(Ordering::Relaxed) == 0 {
+			status |= PAPER_OUT;
+		}
+		if self.jammed.load(Ordering::Relaxed) {
+			status |= JAMMED;
+		}
+		status
+	}
+	
+	pub fn left(&self) -> u64 {
+		self.left.load(Ordering::Relaxed)
+	}
+	
+	pub fn jam(&self) {
+		self.jammed.store(true, Ordering::Relaxed);
+		transcript::record("PRINTER", "JAMMED");
+	}
+	
+	// clear a jam and load pages of forms
+	pub fn fix(&self, pages: u64) {
+		self.jammed.store(false, Ordering::Relaxed);
+		self.left.store(pages, Ordering::Relaxed);
+	}
+	
+	fn ready(&self) -> bool {
+		self.status() == 0
+	}
+	
+	// use up the pages a command fed
+	fn feed(&self, pages: u64) {
+		let used = self.left.fetch_update(Ordering::Relaxed, Ordering::Relaxed,
+			|x| if x == ENDLESS || pages == 0 { None } else { Some(x.saturating_sub(pages)) });
+		if used.is_ok() && self.left() == 0 {
+			transcript::record("PRINTER", "OUT OF PAPER");
+		}
+	}
+}
+
+fn print(text: &[u8], codepage: CodePage, output: &mut Sink) {
+	let line = render(codepage, text);
+	output.write(&line);
+	transcript::record("PRINTER", line.trim_end());
+}
+
+// carry out a line's command, 0 to 2, without waiting for the mechanism
+fn line(text: &[u8], command: u8, channel: u8, carriage: &mut Carriage, codepage: CodePage, output: &mut Sink) {
+	match command {
+		0 => { // Print Buffer
+			print(text, codepage, output);
+			carriage.feed(output, 1, false);
+		},
+		1 => carriage.skip(output, channel), // Skip to Channel
+		2 => { // Print and Skip
+			print(text, codepage, output);
+			carriage.skip(output, channel);
+		},
+		_ => { },
+	}
+}
+
+// Page check, for the monitor's page command: command 4's records decoded a
+// line each, with what the printer would make of any that are wrong. byte is
+// the byte at a device address as the printer's channel reads it, or why it
+// can't. Gives the lines and how many problems they show.
+pub fn check_page<F: Fn(u32) -> Result<u8, String>>(addr: u32, count: u32, geometry: Geometry, codepage: CodePage, byte: F) -> (Vec<String>, u32) {
+	let (record, width) = (geometry.record(), geometry.width as usize);
+	let mut out = vec![format!("PAGE @{:08X}, {} RECORDS OF {} BYTES", addr, count, record)];
+	let mut problems = 0;
+	if count > PAGE_LINES {
+		out.push(format!("  COUNT IS PAST {}; THE REST ARE LEFT UNPRINTED", PAGE_LINES));
+		problems += 1;
+	}
+	for n in 0..count.min(PAGE_LINES) {
+		let at = addr.wrapping_add(n * record);
+		let mut r = Vec::with_capacity(record as usize);
+		for i in 0..record {
+			match byte(at.wrapping_add(i)) {
+				Ok(x) => r.push(x),
+				Err(why) => {
+					out.push(format!("{:>4} @{:08X} UNREADABLE AT {}; PRINTING STOPS HERE", n, at, why));
+					return (out, problems + 1);
+				},
+			}
+		}
+		let text = render(codepage, &r[..width]).trim_end().to_string();
+		let (command, channel) = (r[width], r[width + 1]);
+		let skip = format!("SKIP TO {}", channel);
+		let (what, wrong) = match command {
+			0 => (format!("PRINT \"{}\"", text), None),
+			1 => (skip, None),
+			2 => (format!("PRINT \"{}\", {}", text, skip), None),
+			x => (format!("COMMAND {}", x), Some("NOT 0, 1 OR 2; IGNORED")),
+		};
+		let wrong = match wrong {
+			None if command != 0 && (channel < 1 || channel > CHANNELS) => Some("NO SUCH CHANNEL; SPACES ONE LINE"),
+			None if at.checked_add(record - 1).is_none() => Some("WRAPS PAST FFFFFFFF"),
+			x => x,
+		};
+		match wrong {
+			Some(why) => {
+				out.push(format!("{:>4} @{:08X} {} -- {}", n, at, what, why));
+				problems += 1;
+			},
+			None => out.push(format!("{:>4} @{:08X} {}", n, at, what)),
+		}
+	}
+	(out, problems)
+}
+
+// carry out the command in a region whose execute byte is set; false if it
+// could not be
+fn execute(regs: &[u8], geometry: Geometry, carriage: &mut Carriage, codepage: CodePage, output: &mut Sink, paper: &Paper, clock: &Domain) -> bool {
+	let start = match geometry.buffer(regs[146]) {
+		Some(x) if paper.ready() => x,
+		_ => return false,
+	};
+	let text = &regs[start..start + geometry.width as usize];
+	let before = carriage.pages;
+	let ok = match regs[144] {
+		0 | 2 => {
+			line(text, regs[144], regs[145], carriage, codepage, output);
+			clock.wait(PRINT_TICKS);
+			true
+		},
+		1 => {
+			line(text, 1, regs[145], carriage, codepage, output);
+			true
+		},
+		3 => carriage.load_tape(&regs[start..], regs[145]), // Load Carriage Tape
+		_ => false,
+	};
+	paper.feed(carriage.pages - before);
+	ok
+}
+
+// a region for the geometry, with the geometry register filled in
+pub fn region(geometry: Geometry) -> Vec<u8> {
+	let mut regs = vec![0 as u8; geometry.size() as usize];
+	finish(&mut regs, geometry);
+	regs
+}
+
+// clear the execute byte once a command is done
+fn finish(regs: &mut [u8], geometry: Geometry) {
+	regs[148] = 0;
+	regs[GEOMETRY..GEOMETRY + 4].copy_from_slice(&geometry.register().to_le_bytes());
+}
+
+// finish a command and post its interrupt status
+fn complete(regs: &mut [u8], geometry: Geometry, ok: bool) {
+	finish(regs, geometry);
+	regs[INTERRUPT_STATUS] = if ok { PRINT_DONE_CODE } else { PRINT_ERROR_CODE };
+}
+
+// hold the interrupt line up while a status is posted and interrupts are on
+fn signal(regs: &[u8], ipl: &AtomicBool, icode: &AtomicU8) {
+	let status = regs[INTERRUPT_STATUS];
+	let pending = status != 0 && regs[INTERRUPTS] & INTERRUPT_ENABLE != 0;
+	if pending {
+		icode.store(status, Ordering::Relaxed);
+	}
+	ipl.store(pending, Ordering::Relaxed);
+}
+
+fn render(codepage: CodePage, line: &[u8]) -> String {
+	line.iter().map(|&x| {
+		match codepage.to_char(x) {
+			'\u{00A0}' => ' ',
+			'\u{00AD}' => '-',
+			c if c.is_control() => ' ',
+			c => c
+		}
+	}).collect()
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LP1204 {
+	#[serde(skip)]
+	pub ipl: Arc<AtomicBool>,
+	#[serde(skip)]
+	pub icode: Arc<AtomicU8>,
+	
+	pub buffer: Arc<Mutex<Vec<u8>>>,
+	pub geometry: Geometry,
+	pub codepage: CodePage,
+	#[serde(skip)]
+	pub output: Sink,
+	pub carriage: Carriage,
+	
+	#[serde(skip)]
+	pub channel: Option<Channel<Bus>>,		// for printing pages
+	#[serde(skip)]
+	pub running: Arc<AtomicBool>,
+	#[serde(skip, default = "fresh_paper")]
+	pub paper: Arc<Paper>,
+	#[serde(skip, default = "host_clock")]
+	pub clock: Arc<Domain>
+}
+
+fn fresh_paper() -> Arc<Paper> {
+	Arc::new(Paper::new())
+}
+
+// a clock on host time alone, until the machine gives the printer one of its
+// domains; the queued printer keeps it, as the CPU may be waiting on it for a
+// read and would deliver no ticks until it is answered
+pub fn host_clock() -> Arc<Domain> {
+	Domain::host("printer", PRINTER_HZ)
+}
+
+impl LP1204 {
+	pub fn new(ipl_line: Arc<AtomicBool>, ipl_code: Arc<AtomicU8>) -> LP1204 {
+		let buf = Arc::new(Mutex::new(region(Geometry::default())));
+		
+		LP1204 {
+			ipl: ipl_line,
+			icode: ipl_code,
+			buffer: buf,
+			geometry: Geometry::default(),
+			codepage: CodePage::Latin1,
+			output: Sink::Frontend("PRINTER"),
+			carriage: Carriage::default(),
+			channel: None,
+			running: Arc::new(AtomicBool::new(false)),
+			paper: fresh_paper(),
+			clock: host_clock()
+		}
+	}
+	
+	// command 4; returns the records left unprinted
+	fn print_page(&mut self, addr: u32, count: u32) -> u32 {
+		let ch = match &self.channel {
+			Some(x) if self.paper.ready() => x,
+			_ => return count,
+		};
+		let record = self.geometry.record();
+		let len = count.min(PAGE_LINES) * record;
+		let mut page = Vec::with_capacity(len as usize);
+		ch.in_channel(|bus| {
+			for i in 0..len {
+				match bus.read_b(addr.wrapping_add(i)) {
+					Ok(x) => page.push(x),
+					Err(_) => break,
+				}
+			}
+		});
+		let (records, width) = (page.chunks_exact(record as usize), self.geometry.width as usize);
+		let (printed, before) = (records.len() as u32, self.carriage.pages);
+		for r in records {
+			line(&r[..width], r[width], r[width + 1], &mut self.carriage, self.codepage, &mut self.output);
+		}
+		self.paper.feed(self.carriage.pages - before);
+		self.clock.wait(PRINT_TICKS);
+		count - printed
+	}
+	
+	pub fn run(prt: Arc<Mutex<LP1204>>) {
+		let heart = watchdog::register("printer");
+		thread::spawn(move || {
+			let mut guard = prt.lock().unwrap();
+			let prt = &mut *guard;
+			
+			prt.running.store(true, Ordering::Relaxed);
+			heart.idle(false);
+			
+			while prt.running.load(Ordering::Relaxed) {
+				heart.beat();
+				let mut buf = prt.buffer.lock().unwrap();
+				let mut exec: u8 = 0;
+				
+				match buf.read_b(148) {
+					Err(e) => {
+						println!("FATAL PRINTER ERROR");
+						break;
+					},
+					Ok(x) => { exec = x; },
+				};
+				
+				// a guest acknowledges by clearing the status, or turns
+				// interrupts off, with a store the printer only sees here
+				buf[PRINTER_STATUS] = prt.paper.status();
+				signal(&buf, &prt.ipl, &prt.icode);
+				
+				if exec != 0 {
+					if buf[144] == 4 {
+						// the CPU grants the bus only between instructions, and may be
+						// in one reading this buffer, so let go of it while waiting
+						let (addr, count) = (buf.read_w(152).unwrap(), buf.read_w(156).unwrap());
+						drop(buf);
+						let left = prt.print_page(addr, count);
+						buf = prt.buffer.lock().unwrap();
+						buf.write_w(156, left).unwrap();
+						complete(&mut buf, prt.geometry, left == 0);
+					} else {
+						// print from a copy, so a guest can fill another buffer meanwhile
+						let regs = buf.clone();
+						drop(buf);
+						let ok = execute(&regs, prt.geometry, &mut prt.carriage, prt.codepage, &mut prt.output, &prt.paper, &prt.clock);
+						buf = prt.buffer.lock().unwrap();
+						complete(&mut buf, prt.geometry, ok);
+					}
+					buf[PRINTER_STATUS] = prt.paper.status();
+					signal(&buf, &prt.ipl, &prt.icode);
+				}
+			}
+			heart.idle(true);
+		});
+	}
+}
+
+// QueuedLP1204: the same printer as a queued MMIO device (see mmio.rs); the
+// device thread owns the buffer, so printing a line never holds the bus
+pub struct QueuedLP1204 {
+	buffer: Vec<u8>,
+	geometry: Geometry,
+	codepage: CodePage,
+	output: Sink,
+	carriage: Carriage,
+	ipl: Arc<AtomicBool>,
+	icode: Arc<AtomicU8>,
+	paper: Arc<Paper>,
+	clock: Arc<Domain>
+}
+
+impl QueuedLP1204 {
+	pub fn new(buffer: Vec<u8>, geometry: Geometry, codepage: CodePage, output: Sink, carriage: Carriage,
+		ipl_line: Arc<AtomicBool>, ipl_code: Arc<AtomicU8>, paper: Arc<Paper>) -> QueuedLP1204 {
+		QueuedLP1204 {
+			buffer: buffer,
+			geometry: geometry,
+			codepage: codepage,
+			output: output,
+			carriage: carriage,
+			ipl: ipl_line,
+			icode: ipl_code,
+			paper: paper,
+			clock: host_clock()
+		}
+	}
+}
+
+impl Device for QueuedLP1204 {
+	fn read(&mut self, offset: u32, width: u32) -> Result<u32, BusError> {
+		self.buffer[PRINTER_STATUS] = self.paper.status();
+		match width {
+			1 => self.buffer.read_b(offset).map(|x| x as u32),
+			2 => self.buffer.read_h(offset).map(|x| x as u32),
+			_ => self.buffer.read_w(offset),
+		}
+	}
+	
+	fn write(&mut self, offset: u32, width: u32, data: u32) {
+		let result = match width {
+			1 => self.buffer.write_b(offset, data as u8),
+			2 => self.buffer.write_h(offset, data as u16),
+			_ => self.buffer.write_w(offset, data),
+		};
+		if result.is_err() {
+			println!("FATAL PRINTER ERROR");
+			return;
+		}
+		
+		// any store that sets the execute byte starts the command
+		if self.buffer[148] != 0 {
+			let ok = execute(&self.buffer, self.geometry, &mut self.carriage, self.codepage, &mut self.output, &self.paper, &self.clock);
+			complete(&mut self.buffer, self.geometry, ok);
+		}
+		signal(&self.buffer, &self.ipl, &self.icode);
+	}
+	
+	fn save_state(&self) -> Option<Vec<u8>> {
+		Some(self.buffer.clone())
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		self.buffer.load_state(state)?;
+		signal(&self.buffer, &self.ipl, &self.icode);
+		Ok(())
+	}
+}
@@ -0,0 +1,996 @@
+use std::sync::Arc;
+use crate::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{fs, io, thread, time};
+use std::io::Read;
+use std::path::Path;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand::seq::index;
+use crate::bus::{self, Bus, BusError, Channel, Memory32};
+use crate::cpu::{SeriesQ, PC};
+use crate::dma::{DmaRange, Window};
+use crate::elf;
+use crate::attention;
+use crate::frontend::{self, Event};
+use crate::hexfmt;
+use crate::debugport::{DebugPort, DEBUGPORT_SIZE};
+use crate::discovery::{self, DISCOVERY_BASE, DISCOVERY_SIZE, DT_RAM, DT_ROM, DT_DISCOVERY, DT_PRINTER,
+	DT_DATAPORT, DT_READER, DT_PUNCH, DT_DEBUGPORT, DT_SEMIHOST, DT_PROFILER, DT_REMOTE, DT_SHARED, DT_HOSTCMD,
+	DT_OPCONSOLE, DT_DASD, DT_TAPE, DT_PANEL, DT_CLIPBOARD, DT_TRACE, DT_IOMMU, DT_DISPLAY, DT_FONT};
+use crate::ram::Ram;
+use crate::rom::Rom;
+use crate::semihost::{Semihost, SEMIHOST_REGION_SIZE};
+use crate::profiler::{Profiler, PROFILE_REGION_SIZE};
+use crate::hostcmd::{HostCommand, HOSTCMD_REGION_SIZE};
+use crate::opconsole::{OperatorConsole, OPCON_REGION_SIZE, OPCON_IPL};
+use crate::dasd::{Dasd, DASD_REGION_SIZE};
+use crate::tape::{TapeDrive, TAPE_REGION_SIZE};
+use crate::panel::{Panel, PANEL_REGION_SIZE};
+use crate::clipboard::{Clipboard, CLIP_REGION_SIZE};
+use crate::tracectl::{TraceControl, TRACE_REGION_SIZE};
+use crate::iommu::Iommu;
+use crate::screen::{Screen, SCREEN_REGION_SIZE, FONT_SIZE};
+use crate::remote::{self, RemoteRegion};
+use crate::shared::SharedRegion;
+use crate::card::{CardReader, CardPunch, CARD_REGION_SIZE};
+use crate::charset::CodePage;
+use crate::lp1204::{self, LP1204, QueuedLP1204, Geometry, Paper, PRINTER_CHANNEL, PRINTER_HZ};
+use crate::clock::Clocks;
+use crate::mmio::QueuedRegion;
+use crate::devid::{self, Identified, ID_REGION_SIZE};
+use crate::contract::Contract;
+use crate::port::{self, Port};
+use crate::periph;
+use crate::snapshot::{Config, Snapshot, DeviceSnapshot};
+use crate::symbols::SymbolTable;
+
+pub const ROM_BASE: u32 = 0xF0000;
+pub const ROM_SIZE: u32 = 0x1000;
+
+static LOADER: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/loader.bin"));
+
+// name, origin and image of each example program the build assembled
+pub static EXAMPLES: &[(&str, u32, &[u8])] = include!(concat!(env!("OUT_DIR"), "/examples.rs"));
+
+// and of each guest conformance test, for --selftest
+pub static SELFTESTS: &[(&str, u32, &[u8])] = include!(concat!(env!("OUT_DIR"), "/selftests.rs"));
+
+// a randomized layout gives each device its own slot between main memory and
+// the discovery table, at a random multiple of SLOT_ALIGN within it
+const SLOT: u32 = 0x1000;
+const SLOT_ALIGN: u32 = 0x100;
+
+// differences listed when a snapshot won't restore
+const RESTORE_PROBLEMS: usize = 4;
+
+// Stop: why a run came to an end
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stop {
+	Halted,
+	CycleLimit,
+	TimeLimit
+}
+
+// Region: what the machine attached at a bus address, for maps and checks
+
+pub struct Region {
+	pub name: &'static str,
+	pub kind: u32,				// discovery table type
+	pub base: u32,
+	pub size: u32,
+	pub access: &'static str,	// RW or RO
+	pub backing: &'static str,	// ram, rom, device, queued (a device on its own thread), remote or shared
+	pub ipl: Option<usize>
+}
+
+impl Region {
+	fn new(name: &'static str, kind: u32, base: u32, size: u32, access: &'static str, backing: &'static str, ipl: Option<usize>) -> Region {
+		Region { name: name, kind: kind, base: base, size: size, access: access, backing: backing, ipl: ipl }
+	}
+}
+
+// Image: where part of a loaded file landed
+
+pub struct Image {
+	pub path: String,
+	pub base: u32,
+	pub len: u32
+}
+
+// Machine: a SeriesQ wired to main memory and the standard devices
+//
+//   0x00000 - 0x0FFFF	main memory (64K)
+//   0x10000 - 0x100FF	1204 line printer, IPL 4 (larger with --print-width or
+//   			--print-buffers)
+//   0x20000 - 0x200FF	2200 data port interface, IPL 6
+//   0x30000 - 0x300FF	card reader
+//   0x30100 - 0x301FF	card punch
+//   0x40000 - 0x400FF	debug output port
+//   0x41000 - 0x410FF	semihosting interface (when enabled)
+//   0x42000 - 0x420FF	profiler
+//   0x43000 - 0x430FF	host command interface (when enabled)
+//   0x44000 - 0x440FF	operator console, IPL 3
+//   0x45000 - 0x452FF	disk drive
+//   0x46000 - 0x468FF	tape drive
+//   0x47000 - 0x470FF	front panel switches and lamps
+//   0x48000 - 0x48EFF	host clipboard (when enabled)
+//   0x49000 - 0x490FF	trace control (supervisor state only)
+//   0x4A000 - 0x4A0FF	I/O translation tables (supervisor state only)
+//   0x4B000 - 0x4BFFF	text display
+//   0x4C000 - 0x4C7FF	font ROM
+//   0xE0000 - 0xE0FFF	discovery table
+//   0xF0000 - 0xF0FFF	firmware ROM: absolute loader at 0xF0000
+//
+// Every device has the standard ID block at 0xF0 of its region (devid.rs).
+// The printer's channel may only read main memory and the ROM (dma.rs).
+// With a randomized layout only main memory, the discovery table and the
+// ROM stay where they are.
+
+pub struct Machine {
+	pub cpu: Arc<Mutex<SeriesQ>>,
+	pub bus: Arc<Mutex<Bus>>,
+	pub ram: Arc<Ram>,
+	pub printer: Arc<Mutex<LP1204>>,
+	pub printer_buffer: Arc<Mutex<Vec<u8>>>,
+	pub paper: Arc<Paper>,			// the printer's, for the operator
+	pub clocks: Arc<Clocks>,
+	pub dataport: Arc<Mutex<Port>>,
+	pub reader: Arc<Mutex<CardReader>>,
+	pub punch: Arc<Mutex<CardPunch>>,
+	pub debugport: Arc<Mutex<DebugPort>>,
+	pub semihost: Arc<Mutex<Semihost>>,
+	pub hostcmd: Arc<Mutex<HostCommand>>,
+	pub opconsole: Arc<Mutex<OperatorConsole>>,
+	pub dasd: Arc<Mutex<Dasd>>,
+	pub tape: Arc<Mutex<TapeDrive>>,
+	pub panel: Arc<Mutex<Panel>>,
+	pub clipboard: Arc<Mutex<Clipboard>>,
+	pub tracectl: Arc<Mutex<TraceControl>>,
+	pub iommu: Arc<Mutex<Iommu>>,
+	pub screen: Arc<Mutex<Screen>>,
+	pub attention: Arc<AtomicBool>,
+	pub symbols: SymbolTable,
+	pub regions: Vec<Region>,
+	pub images: Vec<Image>,
+	
+	running: Arc<AtomicBool>,
+	cpu_thread: Option<thread::JoinHandle<()>>,
+	devices_started: bool,
+	queued_printer: bool,
+	linked: bool				// data port cabled to another machine or a peripheral process
+}
+
+impl Machine {
+	pub fn new() -> Machine {
+		let ram = Arc::new(Ram::new(65536));
+		let mut b = Bus::new();
+		b.attach_ram(0, Arc::clone(&ram));
+		
+		let bus = Arc::new(Mutex::new(b));
+		let mut cpu = SeriesQ::new(Arc::clone(&bus));
+		let clocks = Arc::new(Clocks::new());
+		cpu.clocks = Some(Arc::clone(&clocks));
+		
+		let mut prt = LP1204::new(Arc::clone(&cpu.ipl[4]), Arc::clone(&cpu.icode[4]));
+		prt.clock = clocks.domain("printer", PRINTER_HZ);
+		cpu.channels[PRINTER_CHANNEL].set_window(Some(Window::new("printer",
+			vec![DmaRange::read(0, ram.size()), DmaRange::read(ROM_BASE, ROM_SIZE)],
+			Some((&cpu.faultpl[4], &cpu.faultcode[4])))));
+		prt.channel = Some(Channel::clone(&cpu.channels[PRINTER_CHANNEL]));
+		let printer_buffer = Arc::clone(&prt.buffer);
+		let paper = Arc::clone(&prt.paper);
+		bus.lock().unwrap().attach(0x10000, Geometry::default().size(), Arc::clone(&printer_buffer) as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		let mut dp = Port::new(Arc::clone(&cpu.ipl[6]));
+		dp.clock = clocks.domain("data port", port::UART_CLOCK);
+		let dataport = Arc::new(Mutex::new(dp));
+		bus.lock().unwrap().attach(0x20000, 4, Arc::clone(&dataport) as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		let reader = Arc::new(Mutex::new(CardReader::new()));
+		bus.lock().unwrap().attach(0x30000, CARD_REGION_SIZE, Arc::clone(&reader) as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		let punch = Arc::new(Mutex::new(CardPunch::new()));
+		bus.lock().unwrap().attach(0x30100, CARD_REGION_SIZE, Arc::clone(&punch) as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		let debugport = Arc::new(Mutex::new(DebugPort::new()));
+		bus.lock().unwrap().attach(0x40000, DEBUGPORT_SIZE, Arc::clone(&debugport) as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		let semihost = Arc::new(Mutex::new(Semihost::new(Arc::clone(&ram), Arc::clone(&cpu.running))));
+		bus.lock().unwrap().attach(0x41000, SEMIHOST_REGION_SIZE, Arc::clone(&semihost) as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		let profiler = Arc::new(Mutex::new(Profiler::new()));
+		bus.lock().unwrap().attach(0x42000, PROFILE_REGION_SIZE, Arc::clone(&profiler) as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		cpu.profiler = Some(profiler);
+		
+		let hostcmd = Arc::new(Mutex::new(HostCommand::new(Arc::clone(&ram))));
+		bus.lock().unwrap().attach(0x43000, HOSTCMD_REGION_SIZE, Arc::clone(&hostcmd) as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		let opconsole = Arc::new(Mutex::new(OperatorConsole::new(Arc::clone(&cpu.ipl[OPCON_IPL]))));
+		bus.lock().unwrap().attach(0x44000, OPCON_REGION_SIZE, Arc::clone(&opconsole) as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		let dasd = Arc::new(Mutex::new(Dasd::new()));
+		bus.lock().unwrap().attach(0x45000, DASD_REGION_SIZE, Arc::clone(&dasd) as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		let tape = Arc::new(Mutex::new(TapeDrive::new()));
+		bus.lock().unwrap().attach(0x46000, TAPE_REGION_SIZE, Arc::clone(&tape) as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		let panel = Arc::new(Mutex::new(Panel::new()));
+		bus.lock().unwrap().attach(0x47000, PANEL_REGION_SIZE, Arc::clone(&panel) as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		let clipboard = Arc::new(Mutex::new(Clipboard::new()));
+		bus.lock().unwrap().attach(0x48000, CLIP_REGION_SIZE, Arc::clone(&clipboard) as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		let tracectl = Arc::new(Mutex::new(TraceControl::new()));
+		bus.lock().unwrap().attach(0x49000, TRACE_REGION_SIZE, Arc::clone(&tracectl) as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		let iommu = Arc::new(Mutex::new(Iommu::new(&cpu.channels)));
+		let iommu_size = iommu.lock().unwrap().size();
+		bus.lock().unwrap().attach(0x4A000, iommu_size, Arc::clone(&iommu) as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		let screen = Arc::new(Mutex::new(Screen::new()));
+		bus.lock().unwrap().attach(0x4B000, SCREEN_REGION_SIZE, Arc::clone(&screen) as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		let font = Arc::new(Mutex::new(Rom::new(&screen.lock().unwrap().font, FONT_SIZE)));
+		bus.lock().unwrap().attach(0x4C000, FONT_SIZE, font as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		let rom = Arc::new(Mutex::new(Rom::new(LOADER, ROM_SIZE)));
+		bus.lock().unwrap().attach(ROM_BASE, ROM_SIZE, rom as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		// filled in once the regions are known
+		let table = Arc::new(Mutex::new(Rom::new(&[], DISCOVERY_SIZE)));
+		bus.lock().unwrap().attach(DISCOVERY_BASE, DISCOVERY_SIZE, table as Arc<Mutex<dyn Memory32<u32, BusError> + Send>>);
+		
+		let running = Arc::clone(&cpu.running);
+		let attention = Arc::clone(&cpu.attention);
+		
+		let mut machine = Machine {
+			cpu: Arc::new(Mutex::new(cpu)),
+			bus: bus,
+			ram: ram,
+			printer: Arc::new(Mutex::new(prt)),
+			printer_buffer: printer_buffer,
+			paper: paper,
+			clocks: clocks,
+			dataport: dataport,
+			reader: reader,
+			punch: punch,
+			debugport: debugport,
+			semihost: semihost,
+			hostcmd: hostcmd,
+			opconsole: opconsole,
+			dasd: dasd,
+			tape: tape,
+			panel: panel,
+			clipboard: clipboard,
+			tracectl: tracectl,
+			iommu: iommu,
+			screen: screen,
+			attention: attention,
+			symbols: SymbolTable::default(),
+			regions: vec![
+				Region::new("main memory", DT_RAM, 0, 65536, "RW", "ram", None),
+				Region::new("1204 line printer", DT_PRINTER, 0x10000, Geometry::default().size(), "RW", "device", Some(4)),
+				Region::new("2200 data port", DT_DATAPORT, 0x20000, 4, "RW", "device", Some(6)),
+				Region::new("card reader", DT_READER, 0x30000, CARD_REGION_SIZE, "RW", "device", None),
+				Region::new("card punch", DT_PUNCH, 0x30100, CARD_REGION_SIZE, "RW", "device", None),
+				Region::new("debug output port", DT_DEBUGPORT, 0x40000, DEBUGPORT_SIZE, "RW", "device", None),
+				Region::new("semihosting interface", DT_SEMIHOST, 0x41000, SEMIHOST_REGION_SIZE, "RW", "device", None),
+				Region::new("profiler", DT_PROFILER, 0x42000, PROFILE_REGION_SIZE, "RW", "device", None),
+				Region::new("host command interface", DT_HOSTCMD, 0x43000, HOSTCMD_REGION_SIZE, "RW", "device", None),
+				Region::new("operator console", DT_OPCONSOLE, 0x44000, OPCON_REGION_SIZE, "RW", "device", Some(OPCON_IPL)),
+				Region::new("disk drive", DT_DASD, 0x45000, DASD_REGION_SIZE, "RW", "device", None),
+				Region::new("tape drive", DT_TAPE, 0x46000, TAPE_REGION_SIZE, "RW", "device", None),
+				Region::new("front panel", DT_PANEL, 0x47000, PANEL_REGION_SIZE, "RW", "device", None),
+				Region::new("host clipboard", DT_CLIPBOARD, 0x48000, CLIP_REGION_SIZE, "RW", "device", None),
+				Region::new("trace control", DT_TRACE, 0x49000, TRACE_REGION_SIZE, "RW", "device", None),
+				Region::new("I/O translation", DT_IOMMU, 0x4A000, iommu_size, "RW", "device", None),
+				Region::new("text display", DT_DISPLAY, 0x4B000, SCREEN_REGION_SIZE, "RW", "device", None),
+				Region::new("font ROM", DT_FONT, 0x4C000, FONT_SIZE, "RO", "rom", None),
+				Region::new("firmware ROM", DT_ROM, ROM_BASE, ROM_SIZE, "RO", "rom", None),
+				Region::new("discovery table", DT_DISCOVERY, DISCOVERY_BASE, DISCOVERY_SIZE, "RO", "rom", None),
+			],
+			images: Vec::new(),
+			
+			running: running,
+			cpu_thread: None,
+			devices_started: false,
+			queued_printer: false,
+			linked: false
+		};
+		machine.identify_devices();
+		machine.update_discovery();
+		machine.protect_devices();
+		machine
+	}
+	
+	// put the ID block on every device, growing any region too small for it
+	fn identify_devices(&mut self) {
+		let mut bus = self.bus.lock().unwrap();
+		for r in self.regions.iter_mut().filter(|r| r.backing == "device") {
+			let device = bus.region_at(r.base).unwrap();
+			r.size = r.size.max(ID_REGION_SIZE);
+			bus.replace(r.base, Arc::new(Mutex::new(Identified::new(device, devid::ident(r)))));
+			bus.resize(r.base, r.size);
+		}
+	}
+	
+	// put a Contract around every device, to report one that breaks it; after
+	// the printer is queued, if it is going to be
+	pub fn enforce_contracts(&mut self) {
+		let mut bus = self.bus.lock().unwrap();
+		for r in self.regions.iter().filter(|r| r.backing == "device" || r.backing == "queued") {
+			let device = bus.region_at(r.base).unwrap();
+			bus.replace(r.base, Arc::new(Mutex::new(Contract::new(r.name, device))));
+		}
+	}
+	
+	// the regions application state may not touch at all
+	fn protect_devices(&self) {
+		let kinds = [DT_TRACE, DT_IOMMU];
+		self.cpu.lock().unwrap().supervisor_only = self.regions.iter()
+			.filter(|r| kinds.contains(&r.kind)).map(|r| (r.base, r.size)).collect();
+	}
+	
+	fn update_discovery(&self) {
+		let table = Rom::new(&discovery::table(&self.regions), DISCOVERY_SIZE);
+		self.bus.lock().unwrap().replace(DISCOVERY_BASE, Arc::new(Mutex::new(table)));
+	}
+	
+	// where the (first) region of a discovery type is attached
+	pub fn base_of(&self, kind: u32) -> u32 {
+		self.regions.iter().find(|r| r.kind == kind).map(|r| r.base).unwrap()
+	}
+	
+	// put another font in the font ROM, for the display and the guest alike
+	pub fn load_font(&mut self, font: Vec<u8>) {
+		let rom = Rom::new(&font, FONT_SIZE);
+		self.bus.lock().unwrap().replace(self.base_of(DT_FONT), Arc::new(Mutex::new(rom)));
+		self.screen.lock().unwrap().font = font;
+	}
+	
+	// move every device to a random slot, for checking that guest software
+	// finds them through the discovery table; a seed always gives the same
+	// layout, which a snapshot restored into this machine has to share
+	pub fn randomize_layout(&mut self, seed: u64) {
+		let mut rng = StdRng::seed_from_u64(seed);
+		let low = (self.ram.size() + SLOT - 1) / SLOT * SLOT;
+		let slots = ((DISCOVERY_BASE - low) / SLOT) as usize;
+		let fixed = [DT_RAM, DT_ROM, DT_DISCOVERY];
+		let movable: Vec<usize> = (0..self.regions.len()).filter(|&n| !fixed.contains(&self.regions[n].kind)).collect();
+		
+		let chosen = index::sample(&mut rng, slots, movable.len());
+		let mut moves = Vec::new();
+		for (n, slot) in movable.into_iter().zip(chosen.into_iter()) {
+			let r = &mut self.regions[n];
+			let offset = rng.gen_range(0..=(SLOT - r.size) / SLOT_ALIGN) * SLOT_ALIGN;
+			let base = low + slot as u32 * SLOT + offset;
+			moves.push((r.base, base));
+			r.base = base;
+		}
+		self.bus.lock().unwrap().relocate(&moves);
+		self.update_discovery();
+		self.protect_devices();
+	}
+	
+	// segment tables, interrupt blocks and the port/printer test program
+	pub fn load_demo(&self) -> Result<(), BusError> {
+		let printer = self.base_of(DT_PRINTER);
+		let dataport = self.base_of(DT_DATAPORT);
+		let mut bus = self.bus.lock().unwrap();
+		
+		// set EBA
+		bus.write_w(0xF00, 0x0000C100)?;
+		bus.write_w(0xF04, 0x0000C180)?;
+		bus.write_b(0xF08, 0xEB)?;
+		bus.write_b(0xF09, 0x01)?;
+		
+		// set LBA
+		bus.write_w(0xF0C, 0x0000C000)?;
+		bus.write_w(0xF10, 0x0000C080)?;
+		bus.write_b(0xF14, 0xEB)?;
+		bus.write_b(0xF15, 0x01)?;
+		
+		// flat memory model with code and data segment
+		bus.write_w(0xF18, 0x00000000)?;
+		bus.write_w(0xF1C, 0x00010000)?;
+		bus.write_b(0xF20, 0xEB)?;
+		bus.write_b(0xF21, 0x00)?;
+		
+		bus.write_w(0xF24, 0x00000000)?;
+		bus.write_w(0xF28, 0x00010000)?;
+		bus.write_b(0xF2C, 0xEB)?;
+		bus.write_b(0xF2D, 0x01)?;
+		
+		// user program segment
+		bus.write_w(0xF30, 0x00004000)?;
+		bus.write_w(0xF34, 0x00007000)?;
+		bus.write_b(0xF38, 0x0E)?;
+		bus.write_b(0xF39, 0xE0)?;
+		
+		// service dispatch table segment
+		bus.write_w(0xF3C, 0x00000000)?;
+		bus.write_w(0xF40, 0x00000200)?;
+		bus.write_b(0xF44, 0xEB)?;
+		bus.write_b(0xF45, 0x01)?;
+		
+		// 1204 line printer
+		bus.write_w(0xF48, printer)?;
+		bus.write_w(0xF4C, printer + 0x98)?;
+		bus.write_b(0xF50, 0x0E)?;
+		bus.write_b(0xF51, 0xE0)?;
+		
+		// 2200 data port interface
+		bus.write_w(0xF54, dataport)?;
+		bus.write_w(0xF58, dataport + 4)?;
+		bus.write_b(0xF5C, 0x0E)?;
+		bus.write_b(0xF5D, 0xE0)?;
+		
+		// user exit trampoline
+		bus.write_w(0xC000, 0x00002000)?;
+		bus.write_w(0xC004, 0x00003000)?;
+		bus.write_b(0xC008, 0xFF)?;
+		bus.write_b(0xC009, 0x00)?;
+		bus.write_b(0xC00A, 0x7E)?;
+		bus.write_b(0xC00B, 0x01)?;
+		bus.write_w(0xC00C, 0x00000000)?;
+		
+		// test LBA entry
+		bus.write_w(0xC070, 0x00004000)?;
+		bus.write_w(0xC074, 0x00007000)?;
+		bus.write_b(0xC078, 0x0E)?;
+		bus.write_b(0xC079, 0xE0)?;
+		bus.write_b(0xC07A, 0x71)?;
+		bus.write_b(0xC07B, 0x04)?;
+		bus.write_w(0xC07C, 0x00000000)?;
+		
+		// test EBA entry
+		bus.write_w(0xC170, 0x00002000)?;
+		bus.write_w(0xC174, 0x00003000)?;
+		bus.write_b(0xC178, 0xFF)?;
+		bus.write_b(0xC179, 0x00)?;
+		bus.write_b(0xC17A, 0x7E)?;
+		bus.write_b(0xC17B, 0x00)?;
+		bus.write_w(0xC17C, 0x00000100)?;
+		
+		bus.write_h(0x1000, 0x1F_01)?;	// LFI 1, 15
+		bus.write_h(0x1002, 0x17_1C)?;  // SLFI 1, 7
+		bus.write_h(0x1004, 0x27_01)?;	// LFI 2, 6
+		bus.write_h(0x1006, 0x12_25)?;	// SSBA 1, 2
+		bus.write_h(0x1008, 0x72_2B)?;	// SLSFI 7, 2
+		bus.write_h(0x100A, 0x03_2B)?;	// SLSFI 0, 3
+		bus.write_h(0x100C, 0xB6_2B)?;	// SLSFI 11, 6
+		bus.write_h(0x100E, 0xC7_2B)?;	// SLSFI 11, 7
+		bus.write_h(0x1010, 0x3E_01)?;	// LFI 3, 14
+		bus.write_h(0x1012, 0x03_29)?;	// SMPK 0, 3
+		bus.write_h(0x1014, 0x00_30)?;	// PLR
+		
+		bus.write_w(0x2000, 0xFF_FF)?;			// HLT
+		
+		bus.write_w(0x2100, 0xFC_70_1F_68)?;	// ST 1, 7, 15, +@R1SAVE
+		bus.write_w(0x2104, 0xFC_70_2F_68)?;	// ST 2, 7, 15, +@R2SAVE
+		bus.write_w(0x2108, 0xFC_70_3F_68)?;	// ST 3, 7, 15, +@R3SAVE
+		bus.write_w(0x210C, 0xFC_70_EF_68)?;	// ST 14, 7, 15, +@R4SAVE
+		
+		bus.write_h(0x2110, 0x17_26)?;			// LSS 1, 7
+		bus.write_h(0x2112, 0x10_1C)?;			// SLFI 1, 0
+		bus.write_h(0x2114, 0x85_2B)?;			// SLSFI 8, 5
+		bus.write_h(0x2116, 0x00_00)?;			// NOP
+		bus.write_w(0x2118, 0x00_80_11_63)?;	// HTR 1, 8, 1
+		bus.write_w(0x211C, 0x02_70_EF_61)?;	// SBALR 1					(LA 14, 7, 15, X'2')
+		bus.write_h(0x2120, 0xF1_00)?;			// 							(MV 15, 1)
+		
+		bus.write_h(0x2122, 0x00_00)?;			// NOP
+		bus.write_w(0x2124, 0xD8_70_1F_60)?;	// L 1, 7, 15, +@R1SAVE
+		bus.write_w(0x2128, 0xD8_70_2F_60)?;	// L 2, 7, 15, +@R2SAVE
+		bus.write_w(0x212C, 0xD8_70_3F_60)?;	// L 3, 7, 15, +@R3SAVE
+		bus.write_w(0x2130, 0xD8_70_EF_60)?;	// L 14, 7, 15, +@R4SAVE
+		bus.write_h(0x2134, 0x00_30)?;			// PLR
+		
+		bus.write_w(0x2200, 0)?; // 0x2200: R1SAVE
+		bus.write_w(0x2204, 0)?; // 0x2204: R2SAVE
+		bus.write_w(0x2208, 0)?; // 0x2208: R3SAVE
+		bus.write_w(0x220C, 0)?; // 0x220C: LKSAVE
+		
+		bus.write_w(0x4000, 0x00_71_10_61)?;	// LA 1, 7: 0, X'100'
+		bus.write_h(0x4004, 0x20_00)?;			// MV 2, 0
+		bus.write_h(0x4006, 0x00_00)?;			// NOP
+		bus.write_w(0x4008, 0x90_00_30_61)?;	// LA 3, 0: 0, X'90'
+		
+		bus.write_w(0x400C, 0x00_72_41_42)?;	// BTR 4, 7: 1, 2
+		
+		bus.write_h(0x4010, 0x23_20)?;			// C 2, 3
+		bus.write_h(0x4012, 0x10_3E)?;			// IFEQ
+		bus.write_w(0x4014, 0x18_70_FF_61)?;	// LA 15, 7: 15, +@PRINT
+		
+		bus.write_h(0x4018, 0x40_20)?;			// C 4, 0
+		bus.write_h(0x401A, 0x10_3E)?;			// IFEQ
+		bus.write_w(0x401C, 0x10_70_FF_61)?;	// LA 15, 7: 15, +@PRINT
+		
+		bus.write_w(0x4020, 0x00_B0_42_69)?;	// BST 4, 11: 2
+		bus.write_h(0x4024, 0x21_0C)?;			// AFI 2, 1
+		bus.write_h(0x4026, 0x00_00)?;			// NOP
+		bus.write_w(0x4028, 0x00_C0_40_6A)?;	// HST 4, 12: 0
+		bus.write_w(0x402C, 0xDC_7F_FF_61)?;	// LA 15, 7: 15, X'100'
+		
+		// PRINT:
+		bus.write_h(0x4030, 0x11_01)?;			// LFI 1, 1
+		bus.write_h(0x4032, 0x00_00)?;			// NOP
+		bus.write_w(0x4034, 0x94_B0_10_69)?;	// BST 1, 11: 0, X'94'
+		bus.write_h(0x4038, 0xFF_FF)?;			// NOP
+		
+		let string = CodePage::Latin1.encode_strict("0123456789 PORT TEST\0").unwrap();
+		for (i, c) in string.iter().enumerate() {
+			bus.write_b(0x4100 + (i as u32), *c)?;
+		}
+		Ok(())
+	}
+	
+	fn write_chunks(&mut self, path: &str, chunks: &[(u32, Vec<u8>)]) -> io::Result<()> {
+		let mut bus = self.bus.lock().unwrap();
+		for (addr, data) in chunks {
+			for (i, b) in data.iter().enumerate() {
+				if bus.write_b(addr.wrapping_add(i as u32), *b).is_err() {
+					return Err(io::Error::new(io::ErrorKind::InvalidInput,
+						format!("image does not fit at 0x{:08X}", addr)));
+				}
+			}
+			
+			// hex records come a line at a time, so run adjacent chunks together
+			match self.images.last_mut() {
+				Some(x) if x.path == path && x.base.wrapping_add(x.len) == *addr => x.len += data.len() as u32,
+				_ => self.images.push(Image { path: path.to_string(), base: *addr, len: data.len() as u32 }),
+			}
+		}
+		Ok(())
+	}
+	
+	// copy a raw binary image into memory, returning its length
+	pub fn load_image(&mut self, path: &str, addr: u32) -> io::Result<usize> {
+		let image = fs::read(path)?;
+		self.write_chunks(path, &[(addr, image.clone())])?;
+		Ok(image.len())
+	}
+	
+	// place an ELF executable's segments, then take its entry point and descriptors
+	pub fn load_elf(&mut self, path: &str) -> io::Result<u32> {
+		let data = fs::read(path)?;
+		let image = {
+			let bus = self.bus.lock().unwrap();
+			elf::parse(&data, |addr, len| bus.holds(addr, len))
+		}.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		self.write_chunks(path, &image.segments)?;
+		
+		let mut cpu = self.cpu.lock().unwrap();
+		for d in &image.descriptors {
+			let n = d.reg as usize;
+			cpu.S_selector[n] = d.selector;
+			cpu.S_base[n] = d.base;
+			cpu.S_limit[n] = d.limit;
+			cpu.S_key[n] = d.key;
+			cpu.S_flags[n] = d.flags;
+		}
+		cpu.R[PC] = image.entry;
+		Ok(image.entry)
+	}
+	
+	// Intel HEX or S-record by extension; a start address record sets the PC
+	pub fn load_hex(&mut self, path: &str) -> io::Result<Option<u32>> {
+		let text = fs::read_to_string(path)?;
+		let ext = Path::new(path).extension().map(|x| x.to_string_lossy().to_ascii_lowercase());
+		let image = match ext.as_deref() {
+			Some("srec") | Some("s19") | Some("s28") | Some("s37") | Some("mot") => hexfmt::parse_srec(&text),
+			_ => hexfmt::parse_ihex(&text),
+		}.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+		
+		self.write_chunks(path, &image.chunks)?;
+		if let Some(entry) = image.entry {
+			self.cpu.lock().unwrap().R[PC] = entry;
+		}
+		Ok(image.entry)
+	}
+	
+	// a built-in example, started at its origin
+	pub fn load_example(&mut self, name: &str) -> io::Result<()> {
+		self.load_builtin("example", EXAMPLES, name)
+	}
+	
+	// a built-in conformance test, started at its origin
+	pub fn load_selftest(&mut self, name: &str) -> io::Result<()> {
+		self.load_builtin("self-test", SELFTESTS, name)
+	}
+	
+	fn load_builtin(&mut self, kind: &str, table: &[(&str, u32, &[u8])], name: &str) -> io::Result<()> {
+		let (_, origin, image) = table.iter().find(|x| x.0 == name).ok_or_else(|| {
+			let names: Vec<_> = table.iter().map(|x| x.0).collect();
+			io::Error::new(io::ErrorKind::NotFound, format!("no such {} (there are {})", kind, names.join(", ")))
+		})?;
+		self.write_chunks(&format!("{} {}", kind, name), &[(*origin, image.to_vec())])?;
+		self.cpu.lock().unwrap().R[PC] = *origin;
+		Ok(())
+	}
+	
+	// any supported image format; addr only places raw binaries
+	pub fn load_file(&mut self, path: &str, addr: u32) -> io::Result<()> {
+		let ext = Path::new(path).extension().map(|x| x.to_string_lossy().to_ascii_lowercase());
+		match ext.as_deref() {
+			Some("hex") | Some("ihx") | Some("srec") | Some("s19") | Some("s28") | Some("s37") | Some("mot") => {
+				self.load_hex(path)?;
+			},
+			_ => {
+				let mut magic = [0 as u8; 4];
+				let is_elf = fs::File::open(path).and_then(|mut f| f.read_exact(&mut magic)).is_ok()
+					&& &magic == b"\x7FELF";
+				if is_elf {
+					self.load_elf(path)?;
+				} else {
+					self.load_image(path, addr)?;
+				}
+			},
+		}
+		Ok(())
+	}
+	
+	// the code page the text device named (as --codepage names them) translates
+	// with, or every one's for None
+	pub fn set_codepage(&self, device: Option<&str>, codepage: CodePage) {
+		let named = |name| device.map_or(true, |d| d == name);
+		if named("printer") {
+			self.printer.lock().unwrap().codepage = codepage;
+		}
+		if named("reader") {
+			self.reader.lock().unwrap().codepage = codepage;
+		}
+		if named("punch") {
+			self.punch.lock().unwrap().codepage = codepage;
+		}
+		if named("console") {
+			self.opconsole.lock().unwrap().codepage = codepage;
+		}
+		if named("clipboard") {
+			self.clipboard.lock().unwrap().codepage = codepage;
+		}
+		if named("debug") {
+			self.debugport.lock().unwrap().codepage = codepage;
+		}
+	}
+	
+	// change the printer's line width and buffers, which resizes its region;
+	// before the printer is queued or anything is restored
+	pub fn set_printer_geometry(&mut self, geometry: Geometry) {
+		let base = self.base_of(DT_PRINTER);
+		self.printer.lock().unwrap().geometry = geometry;
+		*self.printer_buffer.lock().unwrap() = lp1204::region(geometry);
+		self.bus.lock().unwrap().resize(base, geometry.size());
+		if let Some(r) = self.regions.iter_mut().find(|r| r.kind == DT_PRINTER) {
+			r.size = geometry.size();
+		}
+		self.update_discovery();
+	}
+	
+	// run the printer as a queued MMIO device instead of polling its buffer; it
+	// takes over the current buffer contents, code page, output and carriage
+	pub fn queue_printer(&mut self) {
+		if self.queued_printer {
+			return;
+		}
+		let (geometry, codepage, output, carriage, ipl, icode) = {
+			let mut prt = self.printer.lock().unwrap();
+			(prt.geometry, prt.codepage, std::mem::take(&mut prt.output), prt.carriage.clone(),
+				Arc::clone(&prt.ipl), Arc::clone(&prt.icode))
+		};
+		let buffer = self.printer_buffer.lock().unwrap().clone();
+		let region = QueuedRegion::spawn(geometry.size(),
+			QueuedLP1204::new(buffer, geometry, codepage, output, carriage, ipl, icode, Arc::clone(&self.paper)));
+		let r = self.regions.iter_mut().find(|r| r.kind == DT_PRINTER).unwrap();
+		r.backing = "queued";
+		let id = devid::ident(r);
+		self.bus.lock().unwrap().replace(self.base_of(DT_PRINTER), Arc::new(Mutex::new(Identified::new(Arc::new(Mutex::new(region)), id))));
+		self.queued_printer = true;
+	}
+	
+	// a region served by another process; it is not moved by a randomized layout
+	pub fn attach_remote(&mut self, base: u32, region: RemoteRegion) {
+		let size = region.size;
+		self.bus.lock().unwrap().attach(base, size, Arc::new(Mutex::new(region)));
+		self.regions.push(Region::new("remote region", DT_REMOTE, base, size, "RW", "remote", None));
+		self.update_discovery();
+	}
+	
+	// a region backed by a file host tools can use too; also not moved
+	pub fn attach_shared(&mut self, base: u32, region: SharedRegion) {
+		let size = region.size;
+		self.bus.lock().unwrap().attach(base, size, Arc::new(Mutex::new(region)));
+		self.regions.push(Region::new("shared region", DT_SHARED, base, size, "RW", "shared", None));
+		self.update_discovery();
+	}
+	
+	// let other emulators attach this machine's main memory as a remote region;
+	// they reach it without waiting for the bus
+	pub fn serve_memory(&self, addr: &str) -> io::Result<()> {
+		remote::serve(addr, self.ram.size(), Arc::new(Mutex::new(Arc::clone(&self.ram))))
+	}
+	
+	// cable this machine's data port to another's instead of the echo device
+	pub fn link(&mut self, peer: &mut Machine) {
+		port::null_modem(Arc::clone(&self.dataport), Arc::clone(&peer.dataport));
+		self.linked = true;
+		peer.linked = true;
+	}
+	
+	// give the data port's peripheral side to the process serving addr
+	pub fn attach_peripheral(&mut self, addr: &str) -> io::Result<()> {
+		periph::connect(addr, Arc::clone(&self.dataport))?;
+		self.linked = true;
+		Ok(())
+	}
+	
+	// worst-case DMA load: a device thread that takes every grant channel 0 offers
+	pub fn dma_storm(&self) {
+		let ch = Channel::clone(&self.cpu.lock().unwrap().channels[0]);
+		ch.set_window(Some(Window::new("dma storm", vec![DmaRange::read(0, self.ram.size())], None)));
+		thread::spawn(move || {
+			loop {
+				ch.in_channel(|bus| {
+					bus.read_w(0).ok();
+				});
+			}
+		});
+	}
+	
+	// IPL through the firmware loader from the card reader
+	pub fn boot(&self) {
+		self.cpu.lock().unwrap().R[PC] = ROM_BASE;
+	}
+	
+	pub fn start(&mut self) {
+		if !self.devices_started {
+			if !self.linked {
+				port::echo(Arc::clone(&self.dataport));
+			}
+			if !self.queued_printer {
+				LP1204::run(Arc::clone(&self.printer));
+			}
+			self.devices_started = true;
+		}
+		// reap a CPU thread that halted on its own
+		if self.cpu_thread.as_ref().map_or(false, |h| h.is_finished()) {
+			self.wait();
+		}
+		if self.cpu_thread.is_none() {
+			self.cpu_thread = Some(SeriesQ::run(Arc::clone(&self.cpu)));
+		}
+	}
+	
+	pub fn is_running(&self) -> bool {
+		self.running.load(Ordering::Relaxed)
+	}
+	
+	// block until the CPU halts on its own
+	pub fn wait(&mut self) {
+		if let Some(handle) = self.cpu_thread.take() {
+			handle.join().unwrap();
+		}
+	}
+	
+	// block until the CPU stops, pausing it if the wall-clock limit passes
+	// first, and carry out the operator's input at the frontend meanwhile
+	pub fn wait_limit(&mut self, limit: Option<time::Duration>) -> Stop {
+		let start = time::Instant::now();
+		while self.is_running() {
+			if let Some(l) = limit {
+				if start.elapsed() >= l {
+					self.pause();
+					return Stop::TimeLimit;
+				}
+			}
+			for event in frontend::poll() {
+				self.carry_out(event);
+			}
+			thread::sleep(time::Duration::from_millis(1));
+		}
+		self.pause();
+		
+		let cpu = self.cpu.lock().unwrap();
+		if cpu.cycle_limit != 0 && cpu.cycles >= cpu.cycle_limit {
+			Stop::CycleLimit
+		} else {
+			Stop::Halted
+		}
+	}
+	
+	// what the operator did at the frontend
+	fn carry_out(&mut self, event: Event) {
+		match event {
+			Event::Reply(id, text) => {
+				if let Err(e) = self.opconsole.lock().unwrap().reply(id, &text) {
+					println!("REPLY: {}", e);
+				}
+			},
+			Event::Attention => attention::press(&self.attention, "FRONTEND"),
+			Event::Switches(x) => self.panel.lock().unwrap().set_switches(x),
+			Event::Stop => self.running.store(false, Ordering::Relaxed),
+		}
+	}
+	
+	// stop at an instruction boundary and let in-flight device work finish
+	pub fn pause(&mut self) {
+		self.running.store(false, Ordering::Relaxed);
+		self.wait();
+		
+		// the printer clears its execute byte once the current line is out; a
+		// queued printer answers the read only after its earlier writes are done
+		if self.queued_printer {
+			let printer = self.base_of(DT_PRINTER);
+			self.bus.lock().unwrap().read_b(printer + 148).ok();
+		} else if self.devices_started {
+			// a page being printed may be waiting on the bus
+			while self.printer_buffer.lock().unwrap()[148] != 0 {
+				self.grant_pending();
+				thread::sleep(time::Duration::from_millis(1));
+			}
+		}
+	}
+	
+	// grant the bus to channels waiting on it while the CPU is stopped
+	fn grant_pending(&self) {
+		let channels: Vec<Channel<Bus>> = self.cpu.lock().unwrap().channels.iter().map(Channel::clone).collect();
+		let grants = bus::grant_pending(&channels);
+		self.cpu.lock().unwrap().dma_grants += grants as u64;
+	}
+	
+	// pause, then settle the bus so a snapshot sees nothing in flight: channel
+	// requests already up get their grant, and queued devices finish the writes
+	// posted to them
+	pub fn quiesce(&mut self) {
+		self.pause();
+		self.grant_pending();
+		self.bus.lock().unwrap().drain();
+	}
+	
+	// Memory map, one record per line, hexadecimal numbers, the free text last:
+	//   REGION BASE SIZE ACCESS BACKING IPL NAME	(IPL is - for none)
+	//   IMAGE BASE LENGTH PATH
+	//   DMA CHANNEL BASE SIZE ACCESS VIOLATIONS NAME	(one per range of a window)
+	// Regions come lowest base first, images in the order they were loaded,
+	// windows by channel.
+	pub fn memory_map(&self) -> String {
+		let mut regions: Vec<&Region> = self.regions.iter().collect();
+		regions.sort_by_key(|r| r.base);
+		let mut out = String::new();
+		for r in regions {
+			let ipl = r.ipl.map_or("-".to_string(), |x| x.to_string());
+			out.push_str(&format!("REGION {:08X} {:08X} {} {} {} {}\n", r.base, r.size, r.access, r.backing, ipl, r.name));
+		}
+		for i in &self.images {
+			out.push_str(&format!("IMAGE {:08X} {:08X} {}\n", i.base, i.len, i.path));
+		}
+		for (n, ch) in self.cpu.lock().unwrap().channels.iter().enumerate() {
+			if let Some(w) = ch.window() {
+				for r in &w.ranges {
+					out.push_str(&format!("DMA {:X} {:08X} {:08X} {} {:X} {}\n", n, r.base, r.size,
+						if r.write { "RW" } else { "RO" }, w.violations.load(Ordering::Relaxed), w.name));
+				}
+			}
+		}
+		out
+	}
+	
+	pub fn snapshot(&self) -> Snapshot {
+		Snapshot::new(self.cpu.lock().unwrap().save_state(), self.bus.lock().unwrap().snapshot())
+	}
+	
+	// why a snapshot's machine isn't this one, where restoring would go wrong
+	fn incompatible(&self, config: &Config) -> Vec<String> {
+		let ours = Config::new(self.bus.lock().unwrap().layout());
+		let mut out = Vec::new();
+		for f in config.features.iter().filter(|f| !ours.features.contains(f)) {
+			out.push(format!("it needs the {} feature, which this build lacks", f));
+		}
+		let mut sizes: Vec<u32> = config.regions.iter().map(|r| r.1).collect();
+		let mut here: Vec<u32> = ours.regions.iter().map(|r| r.1).collect();
+		sizes.sort();
+		here.sort();
+		if sizes == here && config.regions != ours.regions {
+			out.push("its devices are at other addresses (a different --random-layout?)".to_string());
+			return out;
+		}
+		let name = |base: u32| self.regions.iter().find(|r| r.base == base).map_or("region", |r| r.name);
+		for &(base, size) in &config.regions {
+			match ours.regions.iter().find(|r| r.0 == base) {
+				None => out.push(format!("it has a region of 0x{:X} bytes at 0x{:08X}, where this machine has none", size, base)),
+				Some(&(_, x)) if x != size => out.push(format!("its {} at 0x{:08X} is 0x{:X} bytes, here 0x{:X}", name(base), base, size, x)),
+				_ => { },
+			}
+		}
+		for &(base, _) in ours.regions.iter().filter(|r| !config.regions.iter().any(|x| x.0 == r.0)) {
+			out.push(format!("it has no {} at 0x{:08X}", name(base), base));
+		}
+		if out.is_empty() && ours.regions != config.regions {
+			out.push("its regions were attached in another order".to_string());
+		}
+		out
+	}
+	
+	pub fn restore(&mut self, snap: &Snapshot) -> Result<(), String> {
+		let problems = self.incompatible(&snap.config);
+		if !problems.is_empty() {
+			let more = match problems.len() {
+				n if n > RESTORE_PROBLEMS => format!("; and {} more", n - RESTORE_PROBLEMS),
+				_ => String::new(),
+			};
+			let shown = &problems[..problems.len().min(RESTORE_PROBLEMS)];
+			return Err(format!("snapshot of another machine (emulator {}): {}{}", snap.config.emulator, shown.join("; "), more));
+		}
+		self.bus.lock().unwrap().restore(&snap.bus).map_err(|e| format!("cannot restore: {:?}", e))?;
+		self.cpu.lock().unwrap().load_state(&snap.cpu);
+		Ok(())
+	}
+	
+	// the region a monitor argument names: its base in hex, or a word of its name
+	fn find_region(&self, spec: &str) -> Result<&Region, String> {
+		if let Ok(base) = u32::from_str_radix(spec, 16) {
+			if let Some(r) = self.regions.iter().find(|r| r.base == base) {
+				return Ok(r);
+			}
+		}
+		let word = spec.to_lowercase();
+		let found: Vec<&Region> = self.regions.iter().filter(|r| r.name.split(' ').any(|w| w == word)).collect();
+		match found[..] {
+			[r] => Ok(r),
+			[] => Err(format!("no device {}", spec)),
+			_ => Err(format!("{} names more than one device; give its address", spec)),
+		}
+	}
+	
+	pub fn save_device(&self, spec: &str) -> Result<DeviceSnapshot, String> {
+		let r = self.find_region(spec)?;
+		let state = self.bus.lock().unwrap().save_region(r.base).ok_or(format!("the {} has no state to save", r.name))?;
+		Ok(DeviceSnapshot { name: r.name.to_string(), kind: r.kind, base: r.base, size: r.size, state: state })
+	}
+	
+	// restore a device into the region of its kind and size, preferring the
+	// one at its old base, and leave the rest of the machine alone
+	pub fn restore_device(&self, snap: &DeviceSnapshot) -> Result<&'static str, String> {
+		let same = |r: &&Region| r.kind == snap.kind && r.size == snap.size;
+		let r = self.regions.iter().filter(same).find(|r| r.base == snap.base)
+			.or_else(|| self.regions.iter().find(same))
+			.ok_or(format!("no {} of the same size to restore into", snap.name))?;
+		self.bus.lock().unwrap().load_region(r.base, &snap.state).map_err(|e| format!("cannot restore the {}: {:?}", r.name, e))?;
+		Ok(r.name)
+	}
+	
+	pub fn dump(&self) {
+		let c = self.cpu.lock().unwrap();
+		println!("R1   : 0x{:08X}", c.R[1]);
+		println!("R2   : 0x{:08X}", c.R[2]);
+		println!("R3   : 0x{:08X}", c.R[3]);
+		println!("R4   : 0x{:08X}", c.R[4]);
+		println!("R5   : 0x{:08X}", c.R[5]);
+		println!("R6   : 0x{:08X}", c.R[6]);
+		println!("R7   : 0x{:08X}", c.R[7]);
+		println!("R8   : 0x{:08X}", c.R[8]);
+		println!("R9   : 0x{:08X}", c.R[9]);
+		println!("R10  : 0x{:08X}", c.R[10]);
+		println!("R11  : 0x{:08X}", c.R[11]);
+		println!("R12  : 0x{:08X}", c.R[12]);
+		println!("R13  : 0x{:08X}", c.R[13]);
+		println!("LR   : 0x{:08X}", c.R[14]);
+		println!("PC   : 0x{:08X}", c.R[15]);
+		
+		println!("SR0  : 0b{:08b}", c.F[0]);
+		println!("SR1  : 0b{:08b}", c.F[1]);
+		println!("SR8  : 0b{:08b}", c.F[8]);
+		
+		for x in 0..16 {
+			println!("FP{:<3}: 0x{:08X} ({})", x, c.FP[x], f32::from_bits(c.FP[x]));
+		}
+		
+		for x in 0..15 {
+			println!("SSR{:<2}: 0x{:02X} (0x{:08X}->0x{:08X}; 0x{:02X}, 0x{:02X})", x, c.S_selector[x], c.S_base[x], c.S_limit[x], c.S_key[x], c.S_flags[x]);
+		}
+		if let Some(f) = &c.last_fault {
+			println!("LAST FAULT: {}", f);
+		}
+	}
+}
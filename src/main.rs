@@ -1,428 +1,489 @@
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicU8, AtomicU16, AtomicBool, Ordering};
-use std::{thread, time};
-mod bus;
-mod cpu;
-use crate::bus::{Memory32, BusError};
-use crate::cpu::{SeriesQ, SQAddr};
+use std::{env, fs, process, thread, time};
+use std::collections::HashSet;
+use std::sync::Arc;
 
-extern crate encoding;
-use encoding::{Encoding, EncoderTrap, DecoderTrap};
-use encoding::all::ISO_8859_1;
+// Console output also goes to the session transcript when one is open; these
+// stand in for the std macros everywhere in the emulator.
+macro_rules! println {
+	() => {{
+		std::println!();
+		crate::transcript::console("\n");
+	}};
+	($($arg:tt)*) => {{
+		let text = format!($($arg)*);
+		std::println!("{}", text);
+		crate::transcript::console(&text);
+		crate::transcript::console("\n");
+	}};
+}
 
-struct LP1204 {
-	pub ipl: Arc<AtomicBool>,
-	pub icode: Arc<AtomicU8>,
-	
-	pub buffer: Arc<Mutex<Vec<u8>>>,
-	
-	pub running: Arc<AtomicBool>
+macro_rules! print {
+	($($arg:tt)*) => {{
+		let text = format!($($arg)*);
+		std::print!("{}", text);
+		crate::transcript::console(&text);
+	}};
 }
 
-impl LP1204 {
-	pub fn new(ipl_line: Arc<AtomicBool>, ipl_code: Arc<AtomicU8>) -> LP1204 {
-		let buf = Arc::new(Mutex::new(vec![0 as u8; 256]));
-		
-		LP1204 {
-			ipl: ipl_line,
-			icode: ipl_code,
-			buffer: buf,
-			running: Arc::new(AtomicBool::new(false))
+mod transcript;
+mod sync;
+mod bus;
+mod dma;
+mod iommu;
+mod screen;
+mod capture;
+mod http;
+mod cpu;
+mod breakpoint;
+mod watch;
+mod selwatch;
+mod hooks;
+mod charset;
+mod sink;
+mod mmio;
+mod devid;
+mod lp1204;
+mod port;
+mod periph;
+mod card;
+mod ram;
+mod rom;
+mod elf;
+mod hexfmt;
+mod debugport;
+mod discovery;
+mod semihost;
+mod hostcmd;
+mod profiler;
+mod machine;
+mod snapshot;
+mod snapdiff;
+mod migrate;
+mod options;
+mod batch;
+mod pair;
+mod remote;
+mod shared;
+mod bench;
+mod irqstorm;
+mod check;
+mod contract;
+mod opcheck;
+mod selftest;
+mod checkpoint;
+mod crash;
+mod monitor;
+mod symbols;
+mod coverage;
+mod isa;
+mod trace;
+mod fault;
+mod prefetch;
+mod simd;
+mod decimal;
+mod fpu;
+mod tod;
+mod clock;
+mod attention;
+mod frontend;
+mod opconsole;
+mod jobs;
+mod diskimage;
+mod transport;
+mod dasd;
+mod tape;
+mod panel;
+mod clipboard;
+mod tracectl;
+mod watchdog;
+#[cfg(feature = "crypto")]
+mod crypto;
+mod font;
+#[cfg(feature = "window")]
+mod window;
+use crate::machine::{Machine, Stop};
+use crate::options::Options;
+use crate::sink::Sink;
+use crate::lp1204::Carriage;
+use crate::symbols::SymbolTable;
+use crate::coverage::Coverage;
+use crate::checkpoint::{Checkpoint, CHECKPOINT_KEEP};
+use crate::snapshot::Snapshot;
+use crate::bus::Bus;
+use crate::cpu::SeriesQ;
+use crate::hooks::{Decoded, Select, When};
+use crate::remote::RemoteRegion;
+use crate::shared::SharedRegion;
+use crate::dasd::Mount;
+use crate::selwatch::SelectorWatch;
+use crate::lp1204::Geometry;
+use crate::trace::TraceLog;
+use crate::tracectl::TRACE_ON;
+
+// attach job input and output files named on the command line
+fn setup(machine: &mut Machine, opt: &Options) -> Result<(), String> {
+	// before anything is loaded, so that everything loaded counts as written
+	if opt.taint {
+		machine.ram.track_taint(opt.taint_fault);
+		machine.cpu.lock().unwrap().taint = Some(HashSet::new());
+	}
+	if opt.smc {
+		machine.ram.track_code(opt.smc_fault);
+		machine.cpu.lock().unwrap().smc = Some(HashSet::new());
+	}
+	if opt.watch_selectors {
+		machine.cpu.lock().unwrap().selectors = Some(SelectorWatch::default());
+	}
+	// before restoring, since a snapshot includes the bus layout
+	if opt.print_width.is_some() || opt.print_buffers.is_some() {
+		let mut geometry = Geometry::default();
+		geometry.width = opt.print_width.unwrap_or(geometry.width);
+		geometry.buffers = opt.print_buffers.unwrap_or(geometry.buffers);
+		machine.set_printer_geometry(geometry);
+	}
+	for (addr, base) in &opt.remote {
+		let region = RemoteRegion::connect(addr).map_err(|e| format!("{}: {}", addr, e))?;
+		machine.attach_remote(*base, region);
+	}
+	for (path, base) in &opt.shared {
+		let region = SharedRegion::open(path).map_err(|e| format!("{}: {}", path, e))?;
+		machine.attach_shared(*base, region);
+	}
+	if let Some(addr) = &opt.serve_memory {
+		machine.serve_memory(addr).map_err(|e| format!("{}: {}", addr, e))?;
+	}
+	if let Some(path) = &opt.restore {
+		let snap = Snapshot::load(path).map_err(|e| format!("{}: {}", path, e))?;
+		machine.restore(&snap).map_err(|e| format!("{}: {}", path, e))?;
+	}
+	for (path, addr) in &opt.load {
+		machine.load_file(path, *addr).map_err(|e| format!("{}: {}", path, e))?;
+	}
+	if let Some(path) = &opt.elf {
+		machine.load_elf(path).map_err(|e| format!("{}: {}", path, e))?;
+	}
+	if let Some(name) = &opt.example {
+		machine.load_example(name).map_err(|e| format!("{}: {}", name, e))?;
+	}
+	if let Some(name) = &opt.selftest_case {
+		machine.load_selftest(name).map_err(|e| format!("{}: {}", name, e))?;
+	}
+	// after restoring, which brings back the devices' own, and before the deck
+	// is read through the reader's
+	for (device, codepage) in &opt.codepages {
+		machine.set_codepage(device.as_deref(), *codepage);
+	}
+	if let Some(path) = &opt.deck {
+		machine.reader.lock().unwrap().load_deck(path).map_err(|e| format!("{}: {}", path, e))?;
+	}
+	if let Some(path) = &opt.dasd {
+		let mut mount = Mount::new(path);
+		mount.overlay = opt.dasd_overlay.clone();
+		mount.cache = opt.dasd_cache;
+		if let Some(x) = opt.dasd_sync {
+			mount.sync = x;
 		}
+		machine.dasd.lock().unwrap().mount(Some(mount))?;
 	}
-	
-	pub fn run(prt: Arc<Mutex<LP1204>>) {
-		thread::spawn(move || {
-			let prt = prt.lock().unwrap();
-			
-			prt.running.store(true, Ordering::Relaxed);
-			
-			while prt.running.load(Ordering::Relaxed) {
-				let mut buf = prt.buffer.lock().unwrap();
-				let mut exec: u8 = 0;
-				
-				match buf.read_b(148) {
-					Err(e) => {
-						println!("FATAL PRINTER ERROR");
-						break;
-					},
-					Ok(x) => { exec = x; },
-				};
-				
-				if exec != 0 {
-					if buf[144] == 0 { // Print Buffer
-						let cleaned: Vec<u8> = buf[0..144].iter().map(|&x| {
-							match x {
-								0x00..=0x1F => 0x20,
-								0x7F..=0xA0 => 0x20,
-								0xAD => 0x2D,
-								_ => x
-							}
-						}).collect();
-						
-						let line = ISO_8859_1.decode(&cleaned, DecoderTrap::Replace).unwrap();
-						
-						println!("{}", line);
-						thread::sleep(time::Duration::from_millis(90));
-					}
-					
-					match buf.write_b(148, 0) {
-					Err(e) => {
-						println!("FATAL PRINTER ERROR");
-						break;
-					},
-					Ok(_) => { },
-				};
-				}
-			}
-		});
+	if !opt.tapes.is_empty() {
+		let mut tape = machine.tape.lock().unwrap();
+		tape.length = opt.tape_length;
+		tape.load_magazine(&opt.tapes).map_err(|e| e.to_string())?;
 	}
-}
-
-struct Port {
-	pub tx: AtomicU16,
-	pub rx: AtomicU16,
-	pub lines: AtomicU8, // [3210IEAR] - Device Specific Lines, Inbound, Error, Acknowledge, Ready
-	pub imask: AtomicU8,
-	pub strobe: AtomicBool,
-	
-	pub ipl: Arc<AtomicBool>
-}
-
-impl Port {
-	pub fn new(ipl_line: Arc<AtomicBool>) -> Port {
-		Port {
-			tx: AtomicU16::new(0),
-			rx: AtomicU16::new(0),
-			lines: AtomicU8::new(0),
-			imask: AtomicU8::new(0),
-			strobe: AtomicBool::new(false),
-			
-			ipl: ipl_line
+	if let Some(x) = opt.switches {
+		machine.panel.lock().unwrap().set_switches(x);
+	}
+	if let Some(n) = opt.port_depth {
+		machine.dataport.lock().unwrap().set_depth(n);
+	}
+	if let Some(addr) = &opt.port_socket {
+		machine.attach_peripheral(addr).map_err(|e| format!("{}: {}", addr, e))?;
+	}
+	if let Some(path) = &opt.map {
+		machine.symbols = SymbolTable::load(path).map_err(|e| format!("{}: {}", path, e))?;
+	}
+	if opt.assert_access {
+		machine.cpu.lock().unwrap().assert_access = true;
+	}
+	if opt.align_fixup {
+		let mut cpu = machine.cpu.lock().unwrap();
+		cpu.align_fixup = true;
+		cpu.F[9] = 0;
+	}
+	if opt.yield_stop {
+		machine.cpu.lock().unwrap().yield_stop = true;
+	}
+	if let Some(x) = opt.protect_low {
+		machine.cpu.lock().unwrap().low_protect = x;
+	}
+	if opt.no_host_time {
+		if let Some(p) = &machine.cpu.lock().unwrap().profiler {
+			p.lock().unwrap().clock = None;
 		}
 	}
-	
-	
-	// peripheral side
-	pub fn send(&self, data: u16) {
-		self.rx.store(data, Ordering::SeqCst);
+	if opt.coverage.is_some() {
+		machine.cpu.lock().unwrap().coverage = Some(Coverage::default());
 	}
-	
-	pub fn recv(&self) -> u16 {
-		if self.strobe.load(Ordering::SeqCst) {
-			self.strobe.store(false, Ordering::SeqCst);
-			self.tx.load(Ordering::SeqCst)
-		} else {
-			0
+	for mnemonic in &opt.log_ops {
+		let ops: Vec<_> = isa::by_mnemonic(mnemonic).collect();
+		if ops.is_empty() {
+			return Err(format!("Unknown instruction {}", mnemonic));
+		}
+		let mut cpu = machine.cpu.lock().unwrap();
+		for op in ops {
+			cpu.hooks.add(Select::Opcode(op.opcode), When::After, Box::new(|d: &Decoded, cpu: &SeriesQ, _: &Bus| {
+				println!("OP @{:08X}::{:08X}  {:<24} R{}={:08X}", d.base, d.pc, d.disassemble(), d.d, cpu.R[d.d]);
+			}));
 		}
 	}
-	
-	pub fn flag(&self, data: u8) {
-		self.lines.store(data, Ordering::SeqCst);
-		if data & self.imask.load(Ordering::SeqCst) != 0 {
-			self.ipl.store(true, Ordering::SeqCst);
+	if let Some(path) = &opt.trace {
+		let log = Arc::new(TraceLog::create(path).map_err(|e| format!("{}: {}", path, e))?);
+		machine.cpu.lock().unwrap().trace_log = Some(Arc::clone(&log));
+		let mut control = machine.tracectl.lock().unwrap();
+		control.log = Some(log);
+		if opt.trace_on {
+			control.set_control(TRACE_ON);
 		}
 	}
-	
-	// bus side
-	pub fn write(&self, data: u16) {
-		self.tx.store(data, Ordering::SeqCst);
-		self.strobe.store(true, Ordering::SeqCst);
+	if opt.boot {
+		machine.boot();
 	}
-	
-	pub fn read(&self) -> u16 {
-		self.ipl.store(false, Ordering::SeqCst);
-		self.rx.load(Ordering::SeqCst)
+	if opt.semihost {
+		let mut sh = machine.semihost.lock().unwrap();
+		sh.enabled = true;
+		sh.args = opt.guest_args.clone();
 	}
-	
-}
-
-impl Memory32<u32, BusError> for Port {
-	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
-		match addr {
-			0 => Ok((self.read() & 0xFF) as u8),
-			1 => Ok(((self.read() & 0xFF00) >> 8) as u8),
-			2 => Ok({
-				let x = self.lines.load(Ordering::SeqCst);
-				self.lines.store(0, Ordering::SeqCst);
-				x
-				}),
-			3 => Ok(self.imask.load(Ordering::SeqCst)),
-			_ => Err(BusError::InvalidAddress)
-		}
+	if opt.clipboard {
+		machine.clipboard.lock().unwrap().enabled = true;
 	}
-	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
-		match addr {
-			0 => Ok(self.read()),
-			_ => Err(BusError::InvalidAddress)
-		}
+	if let Some(n) = opt.attention_ipl {
+		machine.cpu.lock().unwrap().attention_ipl = n;
 	}
-	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
-		Err(BusError::InvalidAddress)
+	if let Some(addr) = &opt.attention_port {
+		attention::listen(addr, Arc::clone(&machine.attention)).map_err(|e| format!("{}: {}", addr, e))?;
 	}
-	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
-		Err(BusError::InvalidAddress)
+	if let Some(addr) = &opt.http {
+		http::listen(addr, Arc::clone(&machine.screen)).map_err(|e| format!("{}: {}", addr, e))?;
 	}
-	
-	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
-		match addr {
-			// 2 => Ok(self.lines.store(data, Ordering::SeqCst)),
-			3 => Ok(self.imask.store(data, Ordering::SeqCst)),
-			_ => Err(BusError::InvalidAddress)
+	// before the replies, which it translates
+	if let Some(path) = &opt.keymap {
+		machine.opconsole.lock().unwrap().keymap = opconsole::load_keymap(path)?;
+	}
+	for text in &opt.replies {
+		machine.opconsole.lock().unwrap().reply(0, text)?;
+	}
+	if let Some(x) = opt.paste_delay {
+		machine.opconsole.lock().unwrap().paste_delay = x;
+	}
+	if let Some(path) = &opt.paste {
+		let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+		opconsole::paste(Arc::clone(&machine.opconsole), text);
+	}
+	if !opt.host_commands.is_empty() {
+		let mut hc = machine.hostcmd.lock().unwrap();
+		hc.allowed = opt.host_commands.clone();
+		if let Some(dir) = &opt.spool {
+			hc.spool = dir.into();
 		}
+		hc.prepare().map_err(|e| format!("{}: {}", hc.spool.display(), e))?;
 	}
-	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
-		match addr {
-			0 => {
-				self.strobe.store(true, Ordering::SeqCst);
-				Ok(self.tx.store(data, Ordering::SeqCst))
-			},
-			_ => Err(BusError::InvalidAddress)
+	if let Some(path) = &opt.print_out {
+		machine.printer.lock().unwrap().output = Sink::file(path).map_err(|e| format!("{}: {}", path, e))?;
+	}
+	if let Some(path) = &opt.vfu {
+		machine.printer.lock().unwrap().carriage = Carriage::load(path).map_err(|e| format!("{}: {}", path, e))?;
+	}
+	if let Some(path) = &opt.display_font {
+		machine.load_font(screen::load_font(path).map_err(|e| format!("{}: {}", path, e))?);
+	}
+	if let Some(pages) = opt.paper {
+		machine.paper.fix(pages);
+	}
+	if let Some(path) = &opt.punch_out {
+		machine.punch.lock().unwrap().output = Sink::file(path).map_err(|e| format!("{}: {}", path, e))?;
+	}
+	if opt.queued_printer {
+		machine.queue_printer();
+	}
+	if let Some(path) = &opt.checkpoint {
+		let (cycles, every) = opt.checkpoint_interval();
+		let keep = opt.checkpoint_keep.unwrap_or(CHECKPOINT_KEEP);
+		machine.cpu.lock().unwrap().checkpoint = Some(Checkpoint::new(path, keep, cycles, every));
+	}
+	if let Some(n) = opt.dma_spacing {
+		machine.cpu.lock().unwrap().dma_spacing = n;
+	}
+	if opt.dma_storm {
+		machine.dma_storm();
+	}
+	if let Some(hz) = opt.cpu_hz {
+		machine.clocks.set_cpu_hz(hz);
+	}
+	machine.clocks.set_deterministic(opt.deterministic);
+	if opt.contracts {
+		machine.enforce_contracts();
+	}
+	Ok(())
+}
+
+// write what the run leaves behind: the coverage report and the disk cache
+fn finish(machine: &Machine, opt: &Options) {
+	if let Some(path) = &opt.coverage {
+		let cpu = machine.cpu.lock().unwrap();
+		let report = cpu.coverage.as_ref().map(|c| c.report(&machine.symbols)).unwrap_or_default();
+		if let Err(e) = fs::write(path, report) {
+			println!("{}: {}", path, e);
 		}
 	}
-	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
-		Err(BusError::InvalidAddress)
+	if let Err(e) = machine.dasd.lock().unwrap().flush() {
+		println!("{}", e);
 	}
+	// a recording the monitor left going is written with what it has
+	capture::stop();
 }
 
 fn main() {
-	let mem = Arc::new(Mutex::new(vec![0 as u8; 65536]));
-	let mem_clone = Arc::clone(&mem);
-	let mut b = bus::Bus::new();
-	b.attach(0, 65536, mem_clone);
-	
-	let bus = Arc::new(Mutex::new(b));
-	let bus2 = Arc::clone(&bus);
-	
-	let mut cpu = cpu::SeriesQ::new(bus);
-	let channel = bus::Channel::clone(&cpu.channels[0]);
-	
-	let prt = LP1204::new( Arc::clone(&cpu.ipl[4]), Arc::clone(&cpu.icode[4]) );
-	let prt_buf = Arc::clone(&prt.buffer);
-	bus2.lock().unwrap().attach(65536, 256, prt_buf);
-	
-	let prt_runnable = Arc::new(Mutex::new(prt));
-	
-	let dataport = Arc::new(Mutex::new(Port::new(Arc::clone(&cpu.ipl[6]))));
-	let dp2 = Arc::clone(&dataport);
-	let dp3 = Arc::clone(&dataport);
-	bus2.lock().unwrap().attach(0x20000, 4, dp2);
-	
-	///*
-	let mut bus3 = bus2.lock().unwrap();
-	
-	{
-	
-	// set EBA
-	bus3.write_w(0xF00, 0x0000C100);
-	bus3.write_w(0xF04, 0x0000C180);
-	bus3.write_b(0xF08, 0xEB);
-	bus3.write_b(0xF09, 0x01);
-	
-	// set LBA
-	bus3.write_w(0xF0C, 0x0000C000);
-	bus3.write_w(0xF10, 0x0000C080);
-	bus3.write_b(0xF14, 0xEB);
-	bus3.write_b(0xF15, 0x01);
-	
-	// flat memory model with code and data segment
-	bus3.write_w(0xF18, 0x00000000);
-	bus3.write_w(0xF1C, 0x00010000);
-	bus3.write_b(0xF20, 0xEB);
-	bus3.write_b(0xF21, 0x00);
-	
-	bus3.write_w(0xF24, 0x00000000);
-	bus3.write_w(0xF28, 0x00010000);
-	bus3.write_b(0xF2C, 0xEB);
-	bus3.write_b(0xF2D, 0x01);
-		
-	// user program segment
-	bus3.write_w(0xF30, 0x00004000);
-	bus3.write_w(0xF34, 0x00007000);
-	bus3.write_b(0xF38, 0x0E);
-	bus3.write_b(0xF39, 0xE0);
-	
-	// service dispatch table segment
-	bus3.write_w(0xF3C, 0x00000000);
-	bus3.write_w(0xF40, 0x00000200);
-	bus3.write_b(0xF44, 0xEB);
-	bus3.write_b(0xF45, 0x01);
-	
-	// 1204 line printer
-	bus3.write_w(0xF48, 0x00010000);
-	bus3.write_w(0xF4C, 0x00010098);
-	bus3.write_b(0xF50, 0x0E);
-	bus3.write_b(0xF51, 0xE0);
-	
-	// 2200 data port interface
-	bus3.write_w(0xF54, 0x00020000);
-	bus3.write_w(0xF58, 0x00020004);
-	bus3.write_b(0xF5C, 0x0E);
-	bus3.write_b(0xF5D, 0xE0);
-	
-	// user exit trampoline
-	bus3.write_w(0xC000, 0x00002000);
-	bus3.write_w(0xC004, 0x00003000);
-	bus3.write_b(0xC008, 0xFF);
-	bus3.write_b(0xC009, 0x00);
-	bus3.write_b(0xC00A, 0x7E);
-	bus3.write_b(0xC00B, 0x01);
-	bus3.write_w(0xC00C, 0x00000000);
-	
-	// test LBA entry
-	bus3.write_w(0xC070, 0x00004000);
-	bus3.write_w(0xC074, 0x00007000);
-	bus3.write_b(0xC078, 0x0E);
-	bus3.write_b(0xC079, 0xE0);
-	bus3.write_b(0xC07A, 0x71);
-	bus3.write_b(0xC07B, 0x04);
-	bus3.write_w(0xC07C, 0x00000000);
-	
-	// test EBA entry
-	bus3.write_w(0xC170, 0x00002000);
-	bus3.write_w(0xC174, 0x00003000);
-	bus3.write_b(0xC178, 0xFF);
-	bus3.write_b(0xC179, 0x00);
-	bus3.write_b(0xC17A, 0x7E);
-	bus3.write_b(0xC17B, 0x00);
-	bus3.write_w(0xC17C, 0x00000100);
-	
-	bus3.write_h(0x1000, 0x1F_01);	// LFI 1, 15
-	bus3.write_h(0x1002, 0x17_1C);  // SLFI 1, 7
-	bus3.write_h(0x1004, 0x27_01);	// LFI 2, 6
-	bus3.write_h(0x1006, 0x12_25);	// SSBA 1, 2
-	bus3.write_h(0x1008, 0x72_2B);	// SLSFI 7, 2
-	bus3.write_h(0x100A, 0x03_2B);	// SLSFI 0, 3
-	bus3.write_h(0x100C, 0xB6_2B);	// SLSFI 11, 6
-	bus3.write_h(0x100E, 0xC7_2B);	// SLSFI 11, 7
-	bus3.write_h(0x1010, 0x3E_01);	// LFI 3, 14
-	bus3.write_h(0x1012, 0x03_29);	// SMPK 0, 3
-	bus3.write_h(0x1014, 0x00_30);	// PLR
-	
-	bus3.write_w(0x2000, 0xFF_FF);			// HLT
-	
-	bus3.write_w(0x2100, 0xFC_70_1F_68);	// ST 1, 7, 15, +@R1SAVE
-	bus3.write_w(0x2104, 0xFC_70_2F_68);	// ST 2, 7, 15, +@R2SAVE
-	bus3.write_w(0x2108, 0xFC_70_3F_68);	// ST 3, 7, 15, +@R3SAVE
-	bus3.write_w(0x210C, 0xFC_70_EF_68);	// ST 14, 7, 15, +@R4SAVE
-	
-	bus3.write_h(0x2110, 0x17_26);			// LSS 1, 7
-	bus3.write_h(0x2112, 0x10_1C);			// SLFI 1, 0
-	bus3.write_h(0x2114, 0x85_2B);			// SLSFI 8, 5
-	bus3.write_h(0x2116, 0x00_00);			// NOP
-	bus3.write_w(0x2118, 0x00_80_11_63);	// HTR 1, 8, 1
-	bus3.write_w(0x211C, 0x02_70_EF_61);	// SBALR 1					(LA 14, 7, 15, X'2')
-	bus3.write_h(0x2120, 0xF1_00);			// 							(MV 15, 1)
-	
-	bus3.write_h(0x2122, 0x00_00);			// NOP
-	bus3.write_w(0x2124, 0xD8_70_1F_60);	// L 1, 7, 15, +@R1SAVE
-	bus3.write_w(0x2128, 0xD8_70_2F_60);	// L 2, 7, 15, +@R2SAVE
-	bus3.write_w(0x212C, 0xD8_70_3F_60);	// L 3, 7, 15, +@R3SAVE
-	bus3.write_w(0x2130, 0xD8_70_EF_60);	// L 14, 7, 15, +@R4SAVE
-	bus3.write_h(0x2134, 0x00_30);			// PLR
-	
-	bus3.write_w(0x2200, 0); // 0x2200: R1SAVE
-	bus3.write_w(0x2204, 0); // 0x2204: R2SAVE
-	bus3.write_w(0x2208, 0); // 0x2208: R3SAVE
-	bus3.write_w(0x220C, 0); // 0x220C: LKSAVE
-		
-	bus3.write_w(0x4000, 0x00_71_10_61);	// LA 1, 7: 0, X'100'
-	bus3.write_h(0x4004, 0x20_00);			// MV 2, 0
-	bus3.write_h(0x4006, 0x00_00);			// NOP
-	bus3.write_w(0x4008, 0x90_00_30_61);	// LA 3, 0: 0, X'90'
-	
-	bus3.write_w(0x400C, 0x00_72_41_42);	// BTR 4, 7: 1, 2
-	
-	bus3.write_h(0x4010, 0x23_20);			// C 2, 3
-	bus3.write_h(0x4012, 0x10_3E);			// IFEQ
-	bus3.write_w(0x4014, 0x18_70_FF_61);	// LA 15, 7: 15, +@PRINT
-	
-	bus3.write_h(0x4018, 0x40_20);			// C 4, 0
-	bus3.write_h(0x401A, 0x10_3E);			// IFEQ
-	bus3.write_w(0x401C, 0x10_70_FF_61);	// LA 15, 7: 15, +@PRINT
-	
-	bus3.write_w(0x4020, 0x00_B0_42_69);	// BST 4, 11: 2
-	bus3.write_h(0x4024, 0x21_0C);			// AFI 2, 1
-	bus3.write_h(0x4026, 0x00_00);			// NOP
-	bus3.write_w(0x4028, 0x00_C0_40_6A);	// HST 4, 12: 0
-	bus3.write_w(0x402C, 0xDC_7F_FF_61);	// LA 15, 7: 15, X'100'
-	
-	// PRINT:
-	bus3.write_h(0x4030, 0x11_01);			// LFI 1, 1
-	bus3.write_h(0x4032, 0x00_00);			// NOP
-	bus3.write_w(0x4034, 0x94_B0_10_69);	// BST 1, 11: 0, X'94'
-	bus3.write_h(0x4038, 0xFF_FF);			// NOP
-	
-	
-	
-	let string = ISO_8859_1.encode("0123456789 PORT TEST\0", EncoderTrap::Strict).unwrap();
-	for (i, c) in string.iter().enumerate() {
-		bus3.write_b(0x4100 + (i as u32), *c);
+	let args: Vec<String> = env::args().collect();
+	let opt = match Options::parse(&args) {
+		Ok(x) => x,
+		Err(e) => {
+			println!("{}", e);
+			println!("{}", options::USAGE);
+			process::exit(2);
+		},
+	};
+	if opt.selftest {
+		process::exit(selftest::run(&args));
 	}
 	
-	drop(bus3);
-	
+	if let Some(path) = &opt.transcript {
+		if let Err(e) = transcript::open(path) {
+			println!("{}: {}", path, e);
+			process::exit(batch::EXIT_HOST_ERROR);
+		}
 	}
-	//*/
-	
-	
 	
-	let mut running = Arc::clone(&cpu.running);
-		
-	let arc = Arc::new(Mutex::new(cpu));
-	
-	thread::spawn(move || {
-		let port = dp3.lock().unwrap();
-		port.flag(0b00000001);
-		drop(port);
-		
-		loop {
-			// wait for port data
-			loop {
-				let port = dp3.lock().unwrap();
-				if port.strobe.load(Ordering::SeqCst) {
-					break;
-				}
+	let mut machine = Machine::new();
+	if let Some(seed) = opt.random_layout {
+		machine.randomize_layout(seed);
+	}
+	// the storm reports on the built-in workload's own counts
+	let mut workload = false;
+	match &opt.migrate_listen {
+		Some(addr) => {
+			if let Err(e) = migrate::receive(&mut machine, addr) {
+				println!("MIGRATION FAILED: {}", e);
+				return;
 			}
-			let port = dp3.lock().unwrap();
-			println!("Got data {:04X}", port.recv());
-			port.flag(0b00000011);
+		},
+		None if opt.load.is_empty() && opt.elf.is_none() && opt.example.is_none() && opt.selftest_case.is_none() && !opt.boot && opt.job_queue.is_none()
+			&& opt.restore.is_none() => {
+			if opt.bench {
+				bench::load_workload(&machine);
+			} else if opt.irq_storm.is_some() {
+				irqstorm::load_workload(&mut machine);
+				workload = true;
+			} else {
+				machine.load_demo().expect("demo fits in RAM");
+			}
+		},
+		None => { },
+	}
+	if opt.check {
+		process::exit(check::run(&mut machine, &opt));
+	}
+	if opt.check_devices {
+		process::exit(contract::run(&machine));
+	}
+	if opt.check_decode {
+		process::exit(opcheck::run(&mut machine));
+	}
+	let spool = opt.job_queue.as_ref().map(|_| jobs::Spool::attach(&machine));
+	if let Err(e) = setup(&mut machine, &opt) {
+		println!("{}", e);
+		process::exit(batch::EXIT_HOST_ERROR);
+	}
+	#[cfg(feature = "window")]
+	if opt.window {
+		if let Err(e) = window::open(&machine) {
+			println!("{}", e);
+			process::exit(batch::EXIT_HOST_ERROR);
 		}
-	});
-	
-	SeriesQ::run(Arc::clone(&arc));
-	LP1204::run(prt_runnable);
-	thread::sleep(time::Duration::from_millis(2000));
-	
-	// let mut x = 0;
-	// channel.in_channel(|bus: &mut bus::Bus| -> () {
-		// x = bus.read_w(0x0000F000).unwrap();
-	// });
-	// println!("DMA: Got 0x{:08X}", x);
-	running.store(false, Ordering::Relaxed);
-	thread::sleep(time::Duration::from_millis(50));
-	
-	let c = arc.lock().unwrap();
-	println!("R1   : 0x{:08X}", c.R[1]);
-	println!("R2   : 0x{:08X}", c.R[2]);
-	println!("R3   : 0x{:08X}", c.R[3]);
-	println!("R4   : 0x{:08X}", c.R[4]);
-	println!("R5   : 0x{:08X}", c.R[5]);
-	println!("R6   : 0x{:08X}", c.R[6]);
-	println!("R7   : 0x{:08X}", c.R[7]);
-	println!("R8   : 0x{:08X}", c.R[8]);
-	println!("R9   : 0x{:08X}", c.R[9]);
-	println!("R10  : 0x{:08X}", c.R[10]);
-	println!("R11  : 0x{:08X}", c.R[11]);
-	println!("R12  : 0x{:08X}", c.R[12]);
-	println!("R13  : 0x{:08X}", c.R[13]);
-	println!("LR   : 0x{:08X}", c.R[14]);
-	println!("PC   : 0x{:08X}", c.R[15]);
+	}
+	crash::install(&machine, &opt);
+	if let Some(interval) = opt.watchdog_interval() {
+		watchdog::start(&machine, interval);
+	}
+	if opt.memory_map {
+		print!("{}", machine.memory_map());
+		return;
+	}
+	let mut peer = match pair::start(&mut machine, &opt) {
+		Ok(x) => x,
+		Err(e) => {
+			println!("{}", e);
+			process::exit(batch::EXIT_HOST_ERROR);
+		},
+	};
+	
+	if opt.bench {
+		let code = bench::run(&mut machine, &opt);
+		finish(&machine, &opt);
+		process::exit(code);
+	}
+	if let Some(mean) = opt.irq_storm {
+		let code = irqstorm::run(&mut machine, &opt, mean, workload);
+		finish(&machine, &opt);
+		process::exit(code);
+	}
+	if let (Some(spool), Some(dir)) = (spool, &opt.job_queue) {
+		process::exit(jobs::run(&mut machine, &opt, spool, dir));
+	}
+	if opt.batch {
+		let mut code = batch::run(&mut machine, &opt);
+		if let Some(p) = peer.as_mut() {
+			if !pair::wait(p, &opt) {
+				code = batch::EXIT_LIMIT;
+			}
+		}
+		finish(&machine, &opt);
+		process::exit(code);
+	}
+	if opt.monitor {
+		monitor::run(&mut machine);
+		finish(&machine, &opt);
+		return;
+	}
 	
-	println!("SR0  : 0b{:08b}", c.F[0]);
-	println!("SR8  : 0b{:08b}", c.F[8]);
+	if let Some(n) = opt.max_cycles {
+		machine.cpu.lock().unwrap().cycle_limit = n;
+	}
+	machine.start();
+	
+	if let Some(addr) = &opt.migrate_to {
+		thread::sleep(time::Duration::from_millis(opt.migrate_after));
+		match migrate::send(&mut machine, addr) {
+			Ok(_) => {
+				println!("Machine migrated to {}", addr);
+				return;
+			},
+			Err(e) => {
+				// keep the guest running here rather than losing it
+				println!("MIGRATION FAILED: {}", e);
+				machine.start();
+			},
+		}
+	}
 	
-	for x in 0..15 {
-		println!("SSR{:<2}: 0x{:02X} (0x{:08X}->0x{:08X}; 0x{:02X}, 0x{:02X})", x, c.S_selector[x], c.S_base[x], c.S_limit[x], c.S_key[x], c.S_flags[x]);
+	match machine.wait_limit(opt.time_limit()) {
+		Stop::Halted => { },
+		Stop::CycleLimit => println!("CYCLE LIMIT EXCEEDED"),
+		Stop::TimeLimit => println!("TIME LIMIT EXCEEDED"),
 	}
-}
\ No newline at end of file
+	machine.dump();
+	finish(&machine, &opt);
+	#[cfg(feature = "window")]
+	if opt.window {
+		window::linger();
+	}
+}
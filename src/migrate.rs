@@ -0,0 +1,80 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use crate::machine::Machine;
+use crate::snapshot::Snapshot;
+
+// Live migration: hand a paused machine to another emulator instance over TCP
+//
+// The sender writes "SQMG", a u64 length and the snapshot bytes. The receiver
+// restores them into its own, identically configured, machine and answers with
+// a single status byte, so the sender only gives up the guest once it lives on.
+
+const MIGRATE_MAGIC: &[u8; 4] = b"SQMG";
+const MIGRATE_OK: u8 = 0;
+const MIGRATE_REJECTED: u8 = 1;
+
+// the most the receiver takes for a snapshot; a machine's is far smaller, so
+// a length past this is garbage, or a peer out to make us allocate it
+const MIGRATE_LIMIT: u64 = 1 << 30;
+
+pub fn send(machine: &mut Machine, addr: &str) -> io::Result<()> {
+	machine.quiesce();
+	let data = machine.snapshot().to_bytes();
+	
+	let mut stream = TcpStream::connect(addr)?;
+	stream.write_all(MIGRATE_MAGIC)?;
+	stream.write_all(&(data.len() as u64).to_le_bytes())?;
+	stream.write_all(&data)?;
+	stream.flush()?;
+	
+	let mut status = [0 as u8; 1];
+	stream.read_exact(&mut status)?;
+	if status[0] == MIGRATE_OK {
+		Ok(())
+	} else {
+		Err(io::Error::new(io::ErrorKind::Other, "destination rejected the machine state"))
+	}
+}
+
+pub fn receive(machine: &mut Machine, addr: &str) -> io::Result<()> {
+	let listener = TcpListener::bind(addr)?;
+	println!("Waiting for incoming machine on {}", addr);
+	let (mut stream, peer) = listener.accept()?;
+	
+	let mut magic = [0 as u8; 4];
+	stream.read_exact(&mut magic)?;
+	if &magic != MIGRATE_MAGIC {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, "not a migration stream"));
+	}
+	let mut len = [0 as u8; 8];
+	stream.read_exact(&mut len)?;
+	let len = u64::from_le_bytes(len);
+	if len > MIGRATE_LIMIT {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, format!("machine state of {} bytes is too large", len)));
+	}
+	// grown as the bytes arrive, not allocated up front on the peer's word
+	let mut data = Vec::new();
+	(&mut stream).take(len).read_to_end(&mut data)?;
+	if data.len() as u64 != len {
+		return Err(io::ErrorKind::UnexpectedEof.into());
+	}
+	
+	let restored = Snapshot::from_bytes(&data).and_then(|snap| {
+		match machine.restore(&snap) {
+			Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+			Ok(_) => Ok(()),
+		}
+	});
+	
+	match restored {
+		Err(e) => {
+			stream.write_all(&[MIGRATE_REJECTED])?;
+			Err(e)
+		},
+		Ok(_) => {
+			stream.write_all(&[MIGRATE_OK])?;
+			println!("Machine received from {}", peer);
+			Ok(())
+		},
+	}
+}
@@ -0,0 +1,125 @@
+use std::sync::mpsc::{self, Sender, SyncSender};
+use std::thread;
+use crate::bus::{BusError, Memory32};
+use crate::watchdog;
+
+// Queued MMIO: devices that run on their own thread behind a message queue
+//
+// Writes are posted: the bus access returns once the write is queued, so a slow
+// device never holds up the CPU (or anything else waiting on the bus lock).
+// Reads wait for the device thread to reach them, which also means a read sees
+// every write posted before it. Bounds and alignment are checked on the bus
+// side so those faults stay synchronous; anything else a device rejects on a
+// posted write is its own to report.
+
+pub trait Device: Send + 'static {
+	fn read(&mut self, offset: u32, width: u32) -> Result<u32, BusError>;
+	fn write(&mut self, offset: u32, width: u32, data: u32);
+	
+	fn save_state(&self) -> Option<Vec<u8>> {
+		None
+	}
+	fn load_state(&mut self, _state: &[u8]) -> Result<(), BusError> {
+		Ok(())
+	}
+}
+
+enum Request {
+	Read(u32, u32, SyncSender<Result<u32, BusError>>),
+	Write(u32, u32, u32),
+	Save(SyncSender<Option<Vec<u8>>>),
+	Load(Vec<u8>, SyncSender<Result<(), BusError>>),
+	Drain(SyncSender<()>)
+}
+
+pub struct QueuedRegion {
+	size: u32,
+	queue: Sender<Request>
+}
+
+impl QueuedRegion {
+	// the device thread runs until the region is dropped
+	pub fn spawn<D: Device>(size: u32, mut device: D) -> QueuedRegion {
+		let (queue, requests) = mpsc::channel();
+		let heart = watchdog::register("queued device");
+		thread::spawn(move || {
+			for r in requests {
+				heart.idle(false);
+				match r {
+					Request::Read(offset, width, reply) => { reply.send(device.read(offset, width)).ok(); },
+					Request::Write(offset, width, data) => device.write(offset, width, data),
+					Request::Save(reply) => { reply.send(device.save_state()).ok(); },
+					Request::Load(state, reply) => { reply.send(device.load_state(&state)).ok(); },
+					Request::Drain(reply) => { reply.send(()).ok(); },
+				}
+				heart.idle(true);
+			}
+		});
+		QueuedRegion { size: size, queue: queue }
+	}
+	
+	fn check(&self, addr: u32, width: u32) -> Result<(), BusError> {
+		if addr.checked_add(width).map_or(true, |end| end > self.size) {
+			Err(BusError::InvalidAddress)
+		} else if addr % width != 0 {
+			Err(BusError::AlignmentCheck)
+		} else {
+			Ok(())
+		}
+	}
+	
+	fn read(&self, addr: u32, width: u32) -> Result<u32, BusError> {
+		self.check(addr, width)?;
+		let (reply, response) = mpsc::sync_channel(1);
+		self.queue.send(Request::Read(addr, width, reply)).map_err(|_| BusError::InvalidState)?;
+		response.recv().map_err(|_| BusError::InvalidState)?
+	}
+	
+	fn post(&mut self, addr: u32, width: u32, data: u32) -> Result<(), BusError> {
+		self.check(addr, width)?;
+		self.queue.send(Request::Write(addr, width, data)).map_err(|_| BusError::InvalidState)
+	}
+}
+
+impl Memory32<u32, BusError> for QueuedRegion {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.read(addr, 1).map(|x| x as u8)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.read(addr, 2).map(|x| x as u16)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		self.read(addr, 2).map(|x| (x as u16).swap_bytes())
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		self.read(addr, 4)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		self.post(addr, 1, data as u32)
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		self.post(addr, 2, data as u32)
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		self.post(addr, 4, data)
+	}
+	
+	fn save_state(&self) -> Option<Vec<u8>> {
+		let (reply, response) = mpsc::sync_channel(1);
+		self.queue.send(Request::Save(reply)).ok()?;
+		response.recv().ok()?
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		let (reply, response) = mpsc::sync_channel(1);
+		self.queue.send(Request::Load(state.to_vec(), reply)).map_err(|_| BusError::InvalidState)?;
+		response.recv().map_err(|_| BusError::InvalidState)?
+	}
+	// the device thread answers once every write posted before has been done
+	fn drain(&self) {
+		let (reply, response) = mpsc::sync_channel(1);
+		if self.queue.send(Request::Drain(reply)).is_ok() {
+			response.recv().ok();
+		}
+	}
+}
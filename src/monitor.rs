@@ -0,0 +1,790 @@
+use std::io::{self, BufRead, Write};
+use std::fs;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::attention;
+use crate::capture;
+use crate::bus::Memory32;
+use crate::charset::CodePage;
+use crate::cpu::{LR, LS, PC, PS, PRIORITY_TABLE_SIZE};
+use crate::dasd::Mount;
+use crate::dma::Master;
+use crate::lp1204;
+use crate::snapdiff;
+use crate::snapshot::{Snapshot, DeviceSnapshot};
+use crate::symbols::SymbolTable;
+use crate::machine::Machine;
+use crate::opconsole;
+use crate::panel;
+use crate::transcript;
+
+// Monitor: operator console on stdin; numbers are hexadecimal
+//
+// Memory can only be examined while the CPU is stopped, since a running CPU
+// holds the bus.
+
+const HELP: &str = "\
+  r                   show registers
+  d ADDR [LEN]        dump memory
+  e ADDR BYTE...      enter bytes at ADDR
+  find ADDR LEN PAT   search memory for a pattern
+  fill ADDR LEN PAT   fill memory with a repeated pattern
+  load FILE [ADDR]    load a raw, Intel HEX, S-record or ELF image
+  g [ADDR]            go, optionally from ADDR
+  until ADDR          go until PC reaches ADDR or a breakpoint stops it
+  n | next            run one instruction, or a whole call if it is a BAL
+  bt                  guess the call chain from BAL linkage
+  trace               show the last instructions executed
+  sym FILE            read symbols from an sqasm map file
+  map                 show the memory map and where images were loaded
+  pb [N]              decode the priority entry and link blocks for level N,
+                      or for all eight
+  page [ADDR [COUNT]] decode and check the printer page (command 4) of COUNT
+                      records at ADDR as the printer's DMA channel reads it,
+                      by default the one in the printer's registers
+  pl N [CODE]         switch to priority level N as an interrupt does, with
+                      CODE (0) as the SSR7 selector
+  plr                 return from the current priority level, as PLR does
+  sel                 list the descriptor table entries loaded so far, with
+                      --watch-selectors
+  devsave DEV FILE    save one device's state to FILE; DEV is its address
+                      or a word of its name in the map, like disk or printer
+  devload FILE        restore a device saved with devsave, leaving the rest
+                      of the machine as it is
+  snap FILE           save the whole machine to FILE, as --restore reads
+  diff FILE [FILE]    list the registers and memory that differ between the
+                      snapshot FILE and the machine now, or a second FILE
+  transcript [FILE|off]
+                      log the session to FILE, stop logging, or show where
+  b ADDR [if COND] [do ACTION; ...]
+                      break at ADDR, optionally only when COND holds
+  bl                  list breakpoints
+  bc [ID]             clear one breakpoint, or all of them
+  w EXPR              report EXPR whenever its value changes
+  wl                  list watches
+  wc [ID]             clear one watch, or all of them
+  attn                press the attention key, interrupting the guest
+  msgs                list operator messages waiting for a reply
+  reply ID TEXT       answer operator message ID (0 for none in particular)
+  paste FILE          type FILE in as replies, a line each time the guest
+                      takes the last
+  dasd                show the mounted volume, its cache and its bad blocks
+  dasd mount FILE [OVERLAY]
+                      put another volume in the disk drive
+  dasd unmount        take the volume out of the disk drive
+  dasd flush          write the cache back to the volume
+  dasd bad BLOCK [N]  fail the next N reads of BLOCK, or every read
+  dasd good BLOCK     take BLOCK out of the bad block map
+  tape                show the tape magazine and the reel in the drive
+  printer             show the printer's paper and whether it is jammed
+  printer jam         jam the printer, failing its commands until fixed
+  printer fix [N]     clear a jam and load N pages of forms, or endless
+  clocks              show the CPU clock and the ticks each device clock
+                      domain has had
+  screenshot FILE     save a PNG of the text display to FILE
+  record FILE [SECS]  record the text display to FILE as an animated PNG,
+                      for SECS seconds (10) or until record stop
+  record stop         end the recording and write it
+  panel [VALUE]       show the front panel, or set its switches to VALUE
+  panel deposit ADDR  store the switches as a word at ADDR
+  panel examine ADDR  show the word at ADDR in the lamps
+  s                   stop the CPU
+  q                   stop and leave the monitor";
+
+const BREAK_HELP: &str = "\
+  COND uses decimal or 0x numbers, R0-R15, LR, PC, F0-F15, the F0 flags
+  P L G E V C S B, CYCLES, and memory as [ADDR], H[ADDR] or B[ADDR]:
+    b 4010 if R3 == 0x90 && E
+  ACTION is log, log \"TEXT\", set REG = EXPR, set [ADDR] = EXPR or continue:
+    b 4010 if R1 > 100 do log; set R1 = 0; continue
+  w takes the same expressions, and w:ADDR, h:ADDR or b:ADDR for memory:
+    w R7
+    w w:0x2200";
+
+const PATTERN_HELP: &str = "\
+  PAT is hex bytes, h or w followed by halfwords or words (little-endian,
+  found only at aligned addresses), \"TEXT\" in ASCII or e\"TEXT\" in EBCDIC:
+    find 0 10000 w DEADBEEF
+    fill 5000 100 e\"EMPTY \"";
+
+// longest find output before it gives up listing matches
+const FIND_LIMIT: usize = 64;
+
+pub fn parse_hex(s: &str) -> Result<u32, String> {
+	let digits = s.strip_prefix("0x").or(s.strip_prefix("0X")).unwrap_or(s);
+	u32::from_str_radix(digits, 16).map_err(|_| format!("bad number {}", s))
+}
+
+// the text of a command line after its first n words
+fn rest(line: &str, n: usize) -> &str {
+	let mut s = line.trim_start();
+	for _ in 0..n {
+		s = s[s.find(char::is_whitespace).unwrap_or(s.len())..].trim_start();
+	}
+	s.trim_end()
+}
+
+// parse PAT into bytes and the alignment matches must have
+fn pattern(spec: &str) -> Result<(Vec<u8>, u32), String> {
+	let (codepage, text) = match spec.strip_prefix('"') {
+		Some(x) => (CodePage::Ascii, Some(x)),
+		None => (CodePage::Ebcdic037, spec.strip_prefix("e\"").or(spec.strip_prefix("E\""))),
+	};
+	if let Some(text) = text {
+		let text = text.strip_suffix('"').ok_or("unterminated string")?;
+		let bytes = codepage.encode_strict(text)
+			.ok_or(format!("\"{}\" has characters outside {}", text, codepage.name()))?;
+		return Ok((bytes, 1));
+	}
+	
+	let words: Vec<&str> = spec.split_whitespace().collect();
+	let (width, values) = match words.first().map(|x| x.to_ascii_lowercase()) {
+		Some(x) if x == "h" => (2, &words[1..]),
+		Some(x) if x == "w" => (4, &words[1..]),
+		_ => (1, &words[..]),
+	};
+	let mut bytes = Vec::new();
+	for x in values {
+		let v = parse_hex(x)?;
+		if width < 4 && v >> (8 * width) != 0 {
+			return Err(format!("{} does not fit in {} byte(s)", x, width));
+		}
+		bytes.extend_from_slice(&v.to_le_bytes()[..width]);
+	}
+	if bytes.is_empty() {
+		return Err("empty pattern".to_string());
+	}
+	Ok((bytes, width as u32))
+}
+
+// the parts of [addr, addr + len) that are attached to the bus, split at region edges
+fn mapped(machine: &Machine, addr: u32, len: u32) -> Vec<(u32, u32)> {
+	let end = addr as u64 + len as u64;
+	let mut result = Vec::new();
+	for (base, size) in machine.bus.lock().unwrap().regions() {
+		let lo = (base as u64).max(addr as u64);
+		let hi = (base as u64 + size as u64).min(end);
+		if lo < hi {
+			result.push((lo as u32, (hi - lo) as u32));
+		}
+	}
+	result
+}
+
+fn find(machine: &Machine, addr: u32, len: u32, pat: &[u8], align: u32) {
+	let regions = mapped(machine, addr, len);
+	let bus = machine.bus.lock().unwrap();
+	let mut found = 0;
+	// matches never straddle two regions
+	for (start, size) in regions {
+		let bytes: Vec<u8> = (start..start + size).map(|a| bus.read_b(a).unwrap_or(0)).collect();
+		for (n, window) in bytes.windows(pat.len()).enumerate() {
+			let a = start + n as u32;
+			if a % align != 0 || window != pat {
+				continue;
+			}
+			if found == FIND_LIMIT {
+				println!("... more matches not shown");
+				return;
+			}
+			println!("{:08X}", a);
+			found += 1;
+		}
+	}
+	if found == 0 {
+		println!("NOT FOUND");
+	}
+}
+
+fn fill(machine: &Machine, addr: u32, len: u32, pat: &[u8]) -> Result<(), String> {
+	let regions = mapped(machine, addr, len);
+	let mut bus = machine.bus.lock().unwrap();
+	let mut filled: u32 = 0;
+	for (start, size) in regions {
+		for a in start..start + size {
+			// keep the pattern in step with ADDR across gaps
+			let b = pat[(a - addr) as usize % pat.len()];
+			bus.write_b(a, b).map_err(|e| format!("{:08X}: {:?}", a, e))?;
+			filled += 1;
+		}
+	}
+	println!("FILLED {} BYTES", filled);
+	if filled != len {
+		println!("{} BYTES NOT ATTACHED TO THE BUS", len - filled);
+	}
+	Ok(())
+}
+
+// a BAL (RMX or RM) that links, and so returns to the instruction after it
+fn linking_bal(iword0: u16) -> bool {
+	let op = iword0 >> 8;
+	(op == 0x5F || op == 0x7F) && (iword0 >> 4) & 0xF != 0
+}
+
+// does the instruction before ret look like a BAL that linked?
+fn after_bal(machine: &Machine, base: u32, ret: u32) -> bool {
+	let bus = machine.bus.lock().unwrap();
+	match bus.read_h_big(base.wrapping_add(ret).wrapping_sub(4)) {
+		Ok(iword0) => linking_bal(iword0),
+		Err(_) => false,
+	}
+}
+
+// step over a call by stopping where it returns; anything else runs alone
+fn next(machine: &mut Machine) {
+	{
+		let mut cpu = machine.cpu.lock().unwrap();
+		let addr = cpu.R[PC].wrapping_add(cpu.S_base[PS]);
+		let call = match machine.bus.lock().unwrap().read_h_big(addr) {
+			Ok(iword0) => linking_bal(iword0),
+			Err(_) => false,
+		};
+		if call {
+			let ret = cpu.R[PC].wrapping_add(4);
+			cpu.breakpoints.until(ret);
+		} else {
+			let cycles = cpu.cycles;
+			cpu.breakpoints.step(cycles);
+		}
+	}
+	machine.start();
+}
+
+// There are no stack frames to walk, so this trusts the linkage BAL leaves
+// behind: LR with its caller's segment copied into LS, plus any register
+// that still holds a return address, as when LR is saved before a nested call.
+fn backtrace(machine: &Machine) {
+	let (regs, ps_base, ls_base) = {
+		let cpu = machine.cpu.lock().unwrap();
+		(cpu.R, cpu.S_base[PS], cpu.S_base[LS])
+	};
+	let symbols = &machine.symbols;
+	
+	println!("#0  {:08X}  {}", regs[PC], symbols.describe(regs[PC]));
+	
+	let mut frame = 1;
+	if after_bal(machine, ls_base, regs[LR]) {
+		println!("#{}  {:08X}  {:<24} LR, called from {:08X}", frame, regs[LR],
+			symbols.describe(regs[LR]), regs[LR].wrapping_sub(4));
+		frame += 1;
+	}
+	for r in (1..LR).rev() {
+		if regs[r] != regs[LR] && after_bal(machine, ps_base, regs[r]) {
+			println!("#{}  {:08X}  {:<24} R{}, called from {:08X}", frame, regs[r],
+				symbols.describe(regs[r]), r, regs[r].wrapping_sub(4));
+			frame += 1;
+		}
+	}
+	if symbols.len() == 0 {
+		println!("(no symbols loaded; see sym)");
+	}
+}
+
+// An entry block is the base, limit, key/flags/SR8 word and PC a level starts
+// with; a link block holds the same for what it interrupted, with SSR7's
+// selector in the top byte of the third word.
+fn priority_block(machine: &Machine, addr: u32, link: bool) -> String {
+	let bus = machine.bus.lock().unwrap();
+	let mut w = [0 as u32; 4];
+	for (i, x) in w.iter_mut().enumerate() {
+		match bus.read_w(addr.wrapping_add(4 * i as u32)) {
+			Ok(v) => *x = v,
+			Err(e) => return format!("@{:08X} UNREADABLE: {:?}", addr, e),
+		}
+	}
+	let selector = if link { format!(" SSR7 0x{:02X}", w[2] >> 24) } else { String::new() };
+	format!("@{:08X} 0x{:08X}->0x{:08X}; KEY 0x{:02X} FLAGS 0x{:02X} SR8 0b{:08b}{} PC 0x{:08X}",
+		addr, w[0], w[1], w[2] & 0xFF, (w[2] >> 8) & 0xFF, (w[2] >> 16) & 0xFF, selector, w[3])
+}
+
+fn priority_blocks(machine: &Machine, levels: Range<u32>) {
+	let (peba, plba, current) = {
+		let cpu = machine.cpu.lock().unwrap();
+		(cpu.PEBA_base, cpu.PLBA_base, ((cpu.F[8] & 0xE) >> 1) as u32)
+	};
+	for n in levels {
+		let mark = if n == current { '*' } else { ' ' };
+		println!("{}PL{} ENTRY {}", mark, n, priority_block(machine, peba.wrapping_add(n * PRIORITY_TABLE_SIZE / 8), false));
+		println!("     LINK  {}", priority_block(machine, plba.wrapping_add(n * PRIORITY_TABLE_SIZE / 8), true));
+	}
+}
+
+// the page command; lines are shown in the printer's code page, Latin-1
+fn page(machine: &Machine, addr: u32, count: u32, geometry: lp1204::Geometry) {
+	let (window, translation) = {
+		let cpu = machine.cpu.lock().unwrap();
+		let ch = &cpu.channels[lp1204::PRINTER_CHANNEL];
+		(ch.window(), ch.translation())
+	};
+	match translation {
+		Some(t) => println!("CHANNEL {}, TRANSLATED THROUGH @{:08X} TO SELECTOR {:02X}", lp1204::PRINTER_CHANNEL, t.table, t.last),
+		None => println!("CHANNEL {}, UNTRANSLATED", lp1204::PRINTER_CHANNEL),
+	}
+	let codepage = machine.printer.lock().unwrap().codepage;
+	let mut bus = machine.bus.lock().unwrap();
+	let master = Master::new(&mut *bus, window.as_deref(), translation);
+	let (lines, problems) = lp1204::check_page(addr, count, geometry, codepage, |a| master.peek(a));
+	for l in lines {
+		println!("{}", l);
+	}
+	println!("{} PROBLEMS", problems);
+}
+
+fn dump(machine: &Machine, addr: u32, len: u32) {
+	let bus = machine.bus.lock().unwrap();
+	// the end can be past FFFFFFFF
+	let end = addr as u64 + len as u64;
+	let mut line = addr & !0xF;
+	while (line as u64) < end {
+		let mut hex = String::new();
+		let mut text = String::new();
+		for i in 0..16u32 {
+			let a = line.wrapping_add(i);
+			if a < addr || a as u64 >= end {
+				hex.push_str("   ");
+				text.push(' ');
+				continue;
+			}
+			match bus.read_b(a) {
+				Ok(b) => {
+					hex.push_str(&format!("{:02X} ", b));
+					text.push(if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' });
+				},
+				Err(_) => {
+					hex.push_str("-- ");
+					text.push(' ');
+				},
+			}
+		}
+		println!("{:08X}  {} {}", line, hex, text);
+		match line.checked_add(16) {
+			Some(x) => line = x,
+			None => break,
+		}
+	}
+}
+
+// run one command line; Ok(false) leaves the monitor
+pub fn command(machine: &mut Machine, line: &str) -> Result<bool, String> {
+	let words: Vec<&str> = line.split_whitespace().collect();
+	let cmd = match words.first() {
+		Some(x) => x.to_ascii_lowercase(),
+		None => return Ok(true),
+	};
+	let args = &words[1..];
+	
+	let needs_stop = !matches!(cmd.as_str(), "s" | "stop" | "q" | "quit" | "help" | "?" | "map" | "transcript" | "attn" | "msgs" | "reply" | "paste" | "dasd" | "tape" | "printer" | "clocks" | "screenshot" | "record" | "panel");
+	if needs_stop && machine.is_running() {
+		return Err("CPU running; stop it first".to_string());
+	}
+	
+	match cmd.as_str() {
+		"help" | "?" => println!("{}\n{}\n{}", HELP, BREAK_HELP, PATTERN_HELP),
+		"r" => machine.dump(),
+		"d" => {
+			let addr = parse_hex(args.get(0).ok_or("d needs an address")?)?;
+			let len = match args.get(1) {
+				Some(x) => parse_hex(x)?,
+				None => 0x80,
+			};
+			dump(machine, addr, len);
+		},
+		"e" => {
+			let addr = parse_hex(args.get(0).ok_or("e needs an address")?)?;
+			let mut bus = machine.bus.lock().unwrap();
+			for (i, x) in args[1..].iter().enumerate() {
+				let b = parse_hex(x)?;
+				if b > 0xFF {
+					return Err(format!("{} is not a byte", x));
+				}
+				let a = addr.wrapping_add(i as u32);
+				bus.write_b(a, b as u8).map_err(|e| format!("{:08X}: {:?}", a, e))?;
+			}
+		},
+		"find" | "fill" => {
+			if args.len() < 3 {
+				return Err(format!("{} needs ADDR LEN PAT", cmd));
+			}
+			let addr = parse_hex(args[0])?;
+			let len = parse_hex(args[1])?;
+			let (pat, align) = pattern(rest(line, 3))?;
+			if cmd == "find" {
+				find(machine, addr, len, &pat, align);
+			} else {
+				fill(machine, addr, len, &pat)?;
+			}
+		},
+		"load" => {
+			let path = args.get(0).ok_or("load needs a file")?;
+			let addr = match args.get(1) {
+				Some(x) => parse_hex(x)?,
+				None => 0,
+			};
+			machine.load_file(path, addr).map_err(|e| format!("{}: {}", path, e))?;
+			println!("PC   : 0x{:08X}", machine.cpu.lock().unwrap().R[PC]);
+		},
+		"g" => {
+			let mut cpu = machine.cpu.lock().unwrap();
+			if let Some(x) = args.get(0) {
+				cpu.R[PC] = parse_hex(x)?;
+			}
+			cpu.breakpoints.cancel_temporary();
+			drop(cpu);
+			machine.start();
+		},
+		"until" => {
+			let addr = parse_hex(args.get(0).ok_or("until needs an address")?)?;
+			machine.cpu.lock().unwrap().breakpoints.until(addr);
+			machine.start();
+		},
+		"n" | "next" => next(machine),
+		"b" => {
+			let spec = line.trim_start()[words[0].len()..].trim();
+			if spec.is_empty() {
+				return Err("b needs an address".to_string());
+			}
+			let id = machine.cpu.lock().unwrap().breakpoints.add(spec)?;
+			println!("BREAKPOINT {}", id);
+		},
+		"bl" => {
+			for bp in &machine.cpu.lock().unwrap().breakpoints.list {
+				println!("{}", bp);
+			}
+		},
+		"bc" => {
+			let mut cpu = machine.cpu.lock().unwrap();
+			match args.get(0) {
+				Some(x) => {
+					let id = x.parse().map_err(|_| format!("bad breakpoint {}", x))?;
+					if !cpu.breakpoints.remove(id) {
+						return Err(format!("no breakpoint {}", id));
+					}
+				},
+				None => cpu.breakpoints.clear(),
+			}
+		},
+		"w" => {
+			let spec = rest(line, 1);
+			if spec.is_empty() {
+				return Err("w needs an expression".to_string());
+			}
+			let mut cpu = machine.cpu.lock().unwrap();
+			let bus = machine.bus.lock().unwrap();
+			let mut watches = std::mem::take(&mut cpu.watches);
+			let result = watches.add(spec, &cpu, &bus).map(|w| println!("WATCH {}", w));
+			cpu.watches = watches;
+			result?;
+		},
+		"wl" => {
+			for w in &machine.cpu.lock().unwrap().watches.list {
+				println!("{}", w);
+			}
+		},
+		"wc" => {
+			let mut cpu = machine.cpu.lock().unwrap();
+			match args.get(0) {
+				Some(x) => {
+					let id = x.parse().map_err(|_| format!("bad watch {}", x))?;
+					if !cpu.watches.remove(id) {
+						return Err(format!("no watch {}", id));
+					}
+				},
+				None => cpu.watches.clear(),
+			}
+		},
+		"bt" => backtrace(machine),
+		"trace" => machine.cpu.lock().unwrap().trace.dump(),
+		"map" => print!("{}", machine.memory_map()),
+		"pb" => {
+			let levels = match args.get(0) {
+				Some(x) => match x.parse::<u32>() {
+					Ok(n) if n < 8 => n..n + 1,
+					_ => return Err(format!("no priority level {}", x)),
+				},
+				None => 0..8,
+			};
+			priority_blocks(machine, levels);
+		},
+		"page" => {
+			let regs = machine.printer_buffer.lock().unwrap().clone();
+			let addr = match args.get(0) {
+				Some(x) => parse_hex(x)?,
+				None => regs.read_w(152).unwrap(),
+			};
+			let count = match args.get(1) {
+				Some(x) => parse_hex(x)?,
+				None => regs.read_w(156).unwrap(),
+			};
+			let geometry = lp1204::Geometry::from_register(regs.read_w(lp1204::GEOMETRY as u32).unwrap())
+				.ok_or("the printer's geometry register has been overwritten")?;
+			page(machine, addr, count, geometry);
+		},
+		"pl" | "plr" => {
+			let mut cpu = machine.cpu.lock().unwrap();
+			let mut bus = machine.bus.lock().unwrap();
+			if cmd == "plr" {
+				cpu.pl_retn(&mut bus);
+			} else {
+				let pl = match args.get(0).map(|x| x.parse::<u8>()) {
+					Some(Ok(n)) if n < 8 => n,
+					_ => return Err("pl needs a priority level from 0 to 7".to_string()),
+				};
+				let code = match args.get(1) {
+					Some(x) if parse_hex(x)? > 0xFF => return Err(format!("{} is not a selector", x)),
+					Some(x) => parse_hex(x)?,
+					None => 0,
+				};
+				cpu.pl_set(pl, code as u8, &mut bus);
+			}
+			println!("PRIORITY LEVEL {}, @{:08X}::{:08X}", (cpu.F[8] & 0xE) >> 1, cpu.S_base[PS], cpu.R[PC]);
+		},
+		"sel" => {
+			let cpu = machine.cpu.lock().unwrap();
+			let w = cpu.selectors.as_ref().ok_or("the selector watch is off")?;
+			let loaded: Vec<String> = w.loaded.iter().map(|x| format!("{:02X}", x)).collect();
+			println!("{} DESCRIPTOR TABLE ENTRIES LOADED: {}", loaded.len(), loaded.join(" "));
+		},
+		"transcript" => {
+			match args.get(0) {
+				Some(&"off") => {
+					match transcript::close() {
+						Some(path) => println!("TRANSCRIPT {} CLOSED", path),
+						None => return Err("no transcript open".to_string()),
+					}
+				},
+				Some(path) => {
+					transcript::open(path).map_err(|e| format!("{}: {}", path, e))?;
+					println!("TRANSCRIPT TO {}", path);
+				},
+				None => match transcript::path() {
+					Some(path) => println!("TRANSCRIPT TO {}", path),
+					None => println!("NO TRANSCRIPT"),
+				},
+			}
+		},
+		"sym" => {
+			let path = args.get(0).ok_or("sym needs a file")?;
+			machine.symbols = SymbolTable::load(path).map_err(|e| format!("{}: {}", path, e))?;
+			println!("{} SYMBOLS", machine.symbols.len());
+		},
+		"devsave" => {
+			let (dev, path) = match args {
+				[dev, path] => (dev, path),
+				_ => return Err("devsave needs a device and a file".to_string()),
+			};
+			machine.quiesce();
+			let snap = machine.save_device(dev)?;
+			snap.save(path).map_err(|e| format!("{}: {}", path, e))?;
+			println!("SAVED {} AT {:08X}", snap.name.to_uppercase(), snap.base);
+		},
+		"devload" => {
+			let path = args.get(0).ok_or("devload needs a file")?;
+			let snap = DeviceSnapshot::load(path).map_err(|e| format!("{}: {}", path, e))?;
+			let name = machine.restore_device(&snap)?;
+			println!("RESTORED {}", name.to_uppercase());
+		},
+		"snap" => {
+			let path = args.get(0).ok_or("snap needs a file")?;
+			machine.quiesce();
+			machine.snapshot().save(path).map_err(|e| format!("{}: {}", path, e))?;
+			println!("SAVED MACHINE");
+		},
+		"diff" => {
+			let path = args.get(0).ok_or("diff needs a snapshot file")?;
+			let before = Snapshot::load(path).map_err(|e| format!("{}: {}", path, e))?;
+			let after = match args.get(1) {
+				Some(other) => Snapshot::load(other).map_err(|e| format!("{}: {}", other, e))?,
+				None => {
+					machine.quiesce();
+					machine.snapshot()
+				},
+			};
+			for line in snapdiff::diff(&before, &after, &machine.regions, &machine.symbols)? {
+				println!("{}", line);
+			}
+		},
+		"attn" => attention::press(&machine.attention, "CONSOLE"),
+		"msgs" => {
+			let con = machine.opconsole.lock().unwrap();
+			for m in &con.outstanding {
+				println!("MESSAGE {:X}: {}", m.id, m.text.trim_end());
+			}
+			println!("{} REPLIES QUEUED", con.replies.len());
+		},
+		"reply" => {
+			let id = parse_hex(args.get(0).ok_or("reply needs a message number")?)?;
+			machine.opconsole.lock().unwrap().reply(id, rest(line, 2))?;
+		},
+		"paste" => {
+			let path = args.get(0).ok_or("paste needs a file")?;
+			let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+			opconsole::paste(Arc::clone(&machine.opconsole), text);
+		},
+		"dasd" => {
+			let mut dasd = machine.dasd.lock().unwrap();
+			if let Some(path) = args.get(1).filter(|_| args.get(0) == Some(&"mount")) {
+				// the new volume keeps the old one's cache settings
+				let mut mount = dasd.volume.as_ref().map_or(Mount::new(path), |(m, _)| Mount { path: path.to_string(), ..m.clone() });
+				mount.overlay = args.get(2).map(|x| x.to_string());
+				return dasd.mount(Some(mount)).map(|_| true);
+			}
+			if args.get(0) == Some(&"unmount") {
+				return dasd.mount(None).map(|_| true);
+			}
+			let (mount, image) = dasd.volume.as_mut().ok_or("no volume mounted")?;
+			let path = mount.name();
+			match (args.get(0).copied(), args.get(1)) {
+				(None, _) => {
+					let g = image.geometry;
+					println!("{}: {} CYLINDERS, {} HEADS, {} SECTORS OF {} BYTES{}", path, g.cylinders, g.heads, g.sectors,
+						g.block_size, if image.read_only { ", READ-ONLY" } else { "" });
+					let s = image.stats;
+					println!("{} READS ({} FROM CACHE), {} WRITES ({} TO DIRTY BLOCKS), {} DIRTY", s.reads, s.read_hits,
+						s.writes, s.write_hits, image.dirty());
+					println!("{} FLUSHES WROTE BACK {} BLOCKS, {} SYNCS, SYNC POLICY {:?}", s.flushes, s.written_back,
+						s.syncs, image.sync);
+					for b in &image.bad {
+						match b.reads {
+							0 => println!("BAD BLOCK {:X}", b.block),
+							n => println!("BAD BLOCK {:X} FOR {} READS", b.block, n),
+						}
+					}
+				},
+				(Some("flush"), None) => image.flush().map_err(|e| format!("{}: {}", path, e))?,
+				(Some("bad"), Some(block)) => {
+					let reads = args.get(2).map(|x| x.parse::<u32>().map_err(|_| format!("bad read count {}", x))).transpose()?;
+					image.inject(parse_hex(block)?, reads.unwrap_or(0));
+				},
+				(Some("good"), Some(block)) => {
+					if !image.repair(parse_hex(block)?) {
+						return Err(format!("block {} is not bad", block));
+					}
+				},
+				_ => return Err("dasd takes flush, bad BLOCK [N] or good BLOCK".to_string()),
+			}
+		},
+		"tape" => {
+			let tape = machine.tape.lock().unwrap();
+			for (n, path) in tape.magazine.iter().enumerate() {
+				let state = match &tape.reel {
+					Some(r) if n + 1 == tape.next => format!("IN THE DRIVE AT BYTE {}", r.pos),
+					_ if n < tape.next => "USED".to_string(),
+					_ => "WAITING".to_string(),
+				};
+				println!("{:>2} {} {}", n + 1, path, state);
+			}
+			if tape.magazine.is_empty() {
+				println!("MAGAZINE EMPTY");
+			}
+		},
+		"printer" => {
+			let paper = &machine.paper;
+			match args {
+				[] => { },
+				["jam"] => paper.jam(),
+				["fix"] => paper.fix(lp1204::ENDLESS),
+				["fix", pages] => paper.fix(pages.parse::<u64>().ok().filter(|&x| x != lp1204::ENDLESS)
+					.ok_or(format!("bad number of pages {}", pages))?),
+				_ => return Err("printer takes jam or fix [N]".to_string()),
+			}
+			let left = match paper.left() {
+				lp1204::ENDLESS => "ENDLESS".to_string(),
+				n => n.to_string(),
+			};
+			let state = match paper.status() {
+				0 => "READY",
+				x if x & lp1204::JAMMED != 0 => "JAMMED",
+				_ => "OUT OF PAPER",
+			};
+			println!("PRINTER {}, PAPER {}", state, left);
+		},
+		"clocks" => {
+			let clocks = &machine.clocks;
+			println!("CPU CLOCK {} HZ, DEVICE CLOCKS ON {}", clocks.cpu_hz(),
+				if clocks.deterministic() { "CPU CYCLES" } else { "HOST TIME" });
+			for d in clocks.domains() {
+				println!("{:<12} {:>10} HZ {:>14} TICKS", d.name.to_ascii_uppercase(), d.hz, d.ticks());
+			}
+		},
+		"screenshot" => {
+			let path = args.get(0).ok_or("screenshot needs a file")?;
+			fs::write(path, capture::screenshot(&machine.screen)).map_err(|e| format!("{}: {}", path, e))?;
+		},
+		"record" => {
+			match args {
+				["stop"] => {
+					if !capture::stop() {
+						return Err("not recording".to_string());
+					}
+				},
+				[path] | [path, _] => {
+					let seconds = match args.get(1) {
+						Some(x) => x.parse::<u64>().ok().filter(|&x| x > 0).ok_or(format!("bad number of seconds {}", x))?,
+						None => capture::RECORD_SECONDS,
+					};
+					capture::start(Arc::clone(&machine.screen), path, Duration::from_secs(seconds))?;
+					println!("RECORDING TO {}", path);
+				},
+				_ => return Err("record takes FILE [SECS] or stop".to_string()),
+			}
+		},
+		"panel" => {
+			let mut panel = machine.panel.lock().unwrap();
+			match args {
+				[] => { },
+				[op @ ("deposit" | "examine"), addr] => {
+					if machine.is_running() {
+						return Err("CPU running; stop it first".to_string());
+					}
+					let addr = parse_hex(addr)?;
+					let mut bus = machine.bus.lock().unwrap();
+					if *op == "deposit" {
+						bus.write_w(addr, panel.switches()).map_err(|e| format!("{:08X}: {:?}", addr, e))?;
+					} else {
+						panel.set_lamps(bus.read_w(addr).map_err(|e| format!("{:08X}: {:?}", addr, e))?);
+					}
+				},
+				[value] => panel.set_switches(parse_hex(value)?),
+				_ => return Err("panel takes VALUE, deposit ADDR or examine ADDR".to_string()),
+			}
+			println!("SWITCHES {}", panel::row(panel.switches()));
+			println!("LAMPS    {}", panel::row(panel.lamps()));
+		},
+		"s" | "stop" => {
+			machine.pause();
+			let cpu = machine.cpu.lock().unwrap();
+			println!("STOPPED AT 0x{:08X}", cpu.R[PC]);
+		},
+		"q" | "quit" => {
+			machine.pause();
+			return Ok(false);
+		},
+		x => return Err(format!("unknown command {}; try help", x)),
+	}
+	Ok(true)
+}
+
+pub fn run(machine: &mut Machine) {
+	let stdin = io::stdin();
+	loop {
+		// the prompt stays out of the transcript; the command goes in as INPUT
+		std::print!("* ");
+		io::stdout().flush().ok();
+		
+		let mut line = String::new();
+		match stdin.lock().read_line(&mut line) {
+			Ok(0) | Err(_) => {
+				machine.pause();
+				return;
+			},
+			Ok(_) => { },
+		}
+		transcript::record("INPUT", line.trim_end());
+		match command(machine, &line) {
+			Ok(true) => { },
+			Ok(false) => return,
+			Err(e) => println!("? {}", e),
+		}
+	}
+}
@@ -0,0 +1,77 @@
+use std::time;
+use crate::bus::Memory32;
+use crate::cpu::{CpuState, PC, ILLEGAL_INSTRUCTION};
+use crate::isa::{self, OPS};
+use crate::machine::Machine;
+
+// OpCheck: execute every opcode once and check the CPU decodes exactly the
+// opcodes in the ISA table
+//
+// The CPU decodes with its own match, so nothing else keeps it and OPS in
+// step. Each opcode runs alone from a fresh CPU state, with every operand
+// field zero, in supervisor state at level 7 so a fault stops the CPU rather
+// than entering a handler. Only the match's default arm faults with ILLEGAL
+// INSTRUCTION and no instruction word; an arm that rejects its operands, or
+// faults on the memory they address, still names the instruction. Opcodes
+// the table marks as needing crypto are only expected without it when built
+// with the crypto feature.
+
+pub const EXIT_MISMATCH: i32 = 1;
+
+const ORIGIN: u32 = 0x1000;
+const STEP_LIMIT: time::Duration = time::Duration::from_secs(1);
+
+// whether the table expects the CPU to decode op in this build
+fn expected(op: &isa::Op) -> bool {
+	cfg!(feature = "crypto") || op.faults != isa::CRYPTO
+}
+
+// run opcode alone, and say whether the CPU has an arm for it
+fn decodes(machine: &mut Machine, fresh: &CpuState, opcode: u8) -> bool {
+	let iword0 = (opcode as u16) << 8;
+	{
+		let mut bus = machine.bus.lock().unwrap();
+		for i in 0..isa::length(iword0) {
+			let b = if i == 0 { opcode } else { 0 };
+			bus.write_b(ORIGIN + i, b).unwrap();
+		}
+	}
+	{
+		let mut cpu = machine.cpu.lock().unwrap();
+		cpu.load_state(fresh);
+		cpu.R[PC] = ORIGIN;
+		cpu.last_fault = None;
+		cpu.cycle_limit = cpu.cycles + 1;
+	}
+	machine.start();
+	machine.wait_limit(Some(STEP_LIMIT));
+	
+	let cpu = machine.cpu.lock().unwrap();
+	let illegal = cpu.last_fault.as_ref().map_or(false, |x| x.code == ILLEGAL_INSTRUCTION as u32);
+	!(illegal && cpu.F[10] == 0xFF && cpu.F[11] == 0xFF)
+}
+
+// --check-decode
+pub fn run(machine: &mut Machine) -> i32 {
+	let fresh = machine.cpu.lock().unwrap().save_state();
+	let mut problems = Vec::new();
+	for opcode in 0..=255u8 {
+		let decoded = decodes(machine, &fresh, opcode);
+		match isa::by_opcode(opcode) {
+			Some(op) if expected(op) && !decoded =>
+				problems.push(format!("{} (0x{:02X}) IS IN THE TABLE BUT THE CPU FAULTS ILLEGAL INSTRUCTION", op.mnemonic, opcode)),
+			Some(op) if !expected(op) && decoded =>
+				problems.push(format!("{} (0x{:02X}) RUNS WITHOUT THE CRYPTO FEATURE", op.mnemonic, opcode)),
+			None if decoded =>
+				problems.push(format!("0x{:02X} RUNS BUT IS NOT IN THE TABLE", opcode)),
+			_ => { },
+		}
+	}
+	machine.cpu.lock().unwrap().load_state(&fresh);
+	
+	for p in &problems {
+		println!("OPCHECK: {}", p);
+	}
+	println!("OPCHECK: {} OPCODES IN THE TABLE, {} PROBLEMS", OPS.len(), problems.len());
+	if problems.is_empty() { 0 } else { EXIT_MISMATCH }
+}
@@ -0,0 +1,231 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{thread, time};
+use crate::sync::Mutex;
+use crate::bus::{Memory32, BusError};
+use crate::charset::CodePage;
+use crate::frontend;
+use serde::{Serialize, Deserialize};
+
+// OperatorConsole: messages from the guest to the operator, and the
+// operator's replies back, a record at a time
+//
+// Registers (words): command at 0, replies waiting at 4, record length at 8,
+// message number at 12, record at 16 (RECORD_SIZE bytes).
+//
+//   1 SEND     show the record as a message to the operator; it gets the next
+//              message number, left in the number register
+//   2 RECEIVE  take the oldest reply into the record, its length into the
+//              length register and the number of the message it answers (0
+//              for none) into the number register; a length of all ones means
+//              there was no reply waiting
+//
+// Replies are queued from the monitor's reply command, or with --reply before
+// the machine starts. OPCON_IPL is pending for as long as a reply is waiting.
+//
+// A keymap (--keymap) translates the operator's characters before they are
+// put in the guest's code page, so a host key the code page lacks can stand
+// for one it has. Pasting (--paste, or the monitor's paste command) types a
+// text file in as replies a line at a time, each once the guest has taken the
+// last and --paste-delay has passed, for loading small programs through the
+// console.
+
+pub const OPCON_REGION_SIZE: u32 = 16 + RECORD_SIZE;
+pub const OPCON_IPL: usize = 3;
+
+pub const OP_SEND: u32 = 1;
+pub const OP_RECEIVE: u32 = 2;
+
+const RECORD_SIZE: u32 = 128;
+
+const NO_REPLY: u32 = 0xFFFFFFFF;
+
+pub const PASTE_DELAY: time::Duration = time::Duration::from_millis(100);
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Reply {
+	pub id: u32,
+	pub text: String
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OperatorConsole {
+	pub codepage: CodePage,
+	pub outstanding: Vec<Reply>,	// messages the operator has not answered
+	pub replies: VecDeque<Reply>,
+	next_id: u32,
+	regs: Vec<u8>,
+	
+	#[serde(skip)]
+	pub keymap: BTreeMap<char, char>,
+	#[serde(skip)]
+	pub paste_delay: time::Duration,
+	#[serde(skip)]
+	ipl: Arc<AtomicBool>
+}
+
+impl OperatorConsole {
+	pub fn new(ipl_line: Arc<AtomicBool>) -> OperatorConsole {
+		OperatorConsole {
+			codepage: CodePage::Latin1,
+			outstanding: Vec::new(),
+			replies: VecDeque::new(),
+			next_id: 1,
+			regs: vec![0 as u8; OPCON_REGION_SIZE as usize],
+			keymap: BTreeMap::new(),
+			paste_delay: PASTE_DELAY,
+			ipl: ipl_line
+		}
+	}
+	
+	fn reg(&self, offset: u32) -> u32 {
+		self.regs.read_w(offset).unwrap()
+	}
+	
+	fn update(&mut self) {
+		self.regs.write_w(4, self.replies.len() as u32).unwrap();
+		self.ipl.store(!self.replies.is_empty(), Ordering::Relaxed);
+	}
+	
+	// queue the operator's answer to message id, or 0 for none in particular
+	pub fn reply(&mut self, id: u32, text: &str) -> Result<(), String> {
+		if id != 0 && !self.outstanding.iter().any(|x| x.id == id) {
+			return Err(format!("no message {:X} waiting for a reply", id));
+		}
+		let text: String = text.chars().map(|c| *self.keymap.get(&c).unwrap_or(&c)).collect();
+		let text = text.as_str();
+		if self.codepage.encode_strict(text).map_or(true, |x| x.len() > RECORD_SIZE as usize) {
+			return Err(format!("a reply must be at most {} characters of {:?}", RECORD_SIZE, self.codepage));
+		}
+		self.outstanding.retain(|x| x.id != id);
+		self.replies.push_back(Reply { id: id, text: text.to_string() });
+		self.update();
+		Ok(())
+	}
+	
+	fn send(&mut self) {
+		let len = self.reg(8).min(RECORD_SIZE) as usize;
+		let text = self.codepage.decode(&self.regs[16..16 + len]);
+		let id = self.next_id;
+		self.next_id += 1;
+		frontend::message(id, text.trim_end());
+		self.outstanding.push(Reply { id: id, text: text });
+		self.regs.write_w(12, id).unwrap();
+	}
+	
+	fn receive(&mut self) {
+		match self.replies.pop_front() {
+			Some(r) => {
+				let record = self.codepage.encode(&r.text);
+				self.regs[16..16 + record.len()].copy_from_slice(&record);
+				self.regs.write_w(8, record.len() as u32).unwrap();
+				self.regs.write_w(12, r.id).unwrap();
+			},
+			None => self.regs.write_w(8, NO_REPLY).unwrap(),
+		}
+		self.update();
+	}
+}
+
+// a keymap file: a line for each key, the host character and then the guest
+// character it types, each as itself or U+XXXX; # starts a comment, so # the
+// key is U+0023
+pub fn load_keymap(path: &str) -> Result<BTreeMap<char, char>, String> {
+	let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+	let key = |x: &str| -> Option<char> {
+		match x.strip_prefix("U+") {
+			Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+			None if x.chars().count() == 1 => x.chars().next(),
+			None => None,
+		}
+	};
+	let mut map = BTreeMap::new();
+	for (n, line) in text.lines().enumerate() {
+		let fields: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+		match fields.as_slice() {
+			[] => { },
+			[from, to] => match (key(from), key(to)) {
+				(Some(from), Some(to)) => { map.insert(from, to); },
+				_ => return Err(format!("{}:{}: bad key", path, n + 1)),
+			},
+			_ => return Err(format!("{}:{}: a line needs the host and guest characters", path, n + 1)),
+		}
+	}
+	Ok(map)
+}
+
+// type text in as replies, a line at a time, each once the guest has taken
+// the one before; a line the console can't take is reported and skipped
+pub fn paste(console: Arc<Mutex<OperatorConsole>>, text: String) {
+	let delay = console.lock().unwrap().paste_delay;
+	thread::spawn(move || {
+		for line in text.lines() {
+			loop {
+				thread::sleep(delay);
+				if console.lock().unwrap().replies.is_empty() {
+					break;
+				}
+			}
+			if let Err(e) = console.lock().unwrap().reply(0, line) {
+				println!("PASTE: {}", e);
+			}
+		}
+	});
+}
+
+impl Memory32<u32, BusError> for OperatorConsole {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.regs.read_b(addr)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h(addr)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h_big(addr)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		self.regs.read_w(addr)
+	}
+	
+	// the waiting count and message number are the device's to set
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		if addr < 16 {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_b(addr, data)
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		if addr < 16 {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_h(addr, data)
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		match addr {
+			0 => match data {
+				OP_SEND => self.send(),
+				OP_RECEIVE => self.receive(),
+				_ => { },
+			},
+			4 | 12 => return Err(BusError::InvalidAddress),
+			_ => self.regs.write_w(addr, data)?,
+		}
+		Ok(())
+	}
+	
+	fn save_state(&self) -> Option<Vec<u8>> {
+		bincode::serialize(self).ok()
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		let saved: OperatorConsole = bincode::deserialize(state).map_err(|_| BusError::InvalidState)?;
+		self.codepage = saved.codepage;
+		self.outstanding = saved.outstanding;
+		self.replies = saved.replies;
+		self.next_id = saved.next_id;
+		self.regs = saved.regs;
+		self.update();
+		Ok(())
+	}
+}
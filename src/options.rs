@@ -0,0 +1,784 @@
+use std::time;
+use crate::charset::CodePage;
+use crate::diskimage::SyncPolicy;
+use crate::lp1204;
+use crate::port;
+
+// Options: command line settings for a run of the emulator
+
+pub struct Options {
+	pub batch: bool,
+	pub bench: bool,
+	pub irq_storm: Option<u64>,
+	pub check: bool,
+	pub check_devices: bool,
+	pub contracts: bool,
+	pub check_decode: bool,
+	pub selftest: bool,
+	pub selftest_case: Option<String>,
+	pub memory_map: bool,
+	pub monitor: bool,
+	pub window: bool,
+	pub load: Vec<(String, u32)>,
+	pub elf: Option<String>,
+	pub example: Option<String>,
+	pub deck: Option<String>,
+	pub peer: Option<String>,
+	pub port_depth: Option<usize>,
+	pub port_socket: Option<String>,
+	pub remote: Vec<(String, u32)>,
+	pub serve_memory: Option<String>,
+	pub shared: Vec<(String, u32)>,
+	pub map: Option<String>,
+	pub coverage: Option<String>,
+	pub boot: bool,
+	pub job_queue: Option<String>,
+	pub dasd: Option<String>,
+	pub dasd_overlay: Option<String>,
+	pub dasd_cache: Option<usize>,
+	pub dasd_sync: Option<SyncPolicy>,
+	pub tapes: Vec<String>,
+	pub tape_length: Option<u64>,
+	pub switches: Option<u32>,
+	pub print_out: Option<String>,
+	pub vfu: Option<String>,
+	pub display_font: Option<String>,
+	pub http: Option<String>,
+	pub print_width: Option<u32>,
+	pub print_buffers: Option<u32>,
+	pub paper: Option<u64>,
+	pub punch_out: Option<String>,
+	pub codepages: Vec<(Option<String>, CodePage)>,
+	pub exit_reg: Option<usize>,
+	pub exit_word: Option<u32>,
+	pub max_cycles: Option<u64>,
+	pub max_seconds: Option<f64>,
+	pub watchdog: Option<f64>,
+	pub semihost: bool,
+	pub clipboard: bool,
+	pub attention_ipl: Option<usize>,
+	pub attention_port: Option<String>,
+	pub host_commands: Vec<String>,
+	pub replies: Vec<String>,
+	pub keymap: Option<String>,
+	pub paste: Option<String>,
+	pub paste_delay: Option<time::Duration>,
+	pub spool: Option<String>,
+	pub random_layout: Option<u64>,
+	pub assert_access: bool,
+	pub align_fixup: bool,
+	pub yield_stop: bool,
+	pub protect_low: Option<u32>,
+	pub no_host_time: bool,
+	pub taint: bool,
+	pub taint_fault: bool,
+	pub smc: bool,
+	pub smc_fault: bool,
+	pub watch_selectors: bool,
+	pub log_ops: Vec<String>,
+	pub trace: Option<String>,
+	pub trace_on: bool,
+	pub queued_printer: bool,
+	pub dma_storm: bool,
+	pub dma_spacing: Option<u64>,
+	pub deterministic: bool,
+	pub cpu_hz: Option<u64>,
+	pub transcript: Option<String>,
+	pub restore: Option<String>,
+	pub checkpoint: Option<String>,
+	pub checkpoint_secs: Option<f64>,
+	pub checkpoint_mcycles: Option<u64>,
+	pub checkpoint_keep: Option<u32>,
+	pub guest_args: Vec<String>,
+	
+	pub migrate_to: Option<String>,
+	pub migrate_listen: Option<String>,
+	pub migrate_after: u64,
+}
+
+pub const USAGE: &str = "\
+Usage: rustframe [options] [-- guest arguments]
+  --batch                run headless until HLT or the cycle limit, then exit
+  --bench                run headless like --batch and report the instruction
+                         rate; runs a built-in memory copy loop when no
+                         program is given
+  --irq-storm US         run headless while interrupts arrive on random levels
+                         every US microseconds on average, and report the
+                         worst delivery latency and any lost; runs a built-in
+                         workload for 2 seconds when no program is given
+  --check                load and validate everything named, print the memory
+                         map and exit without running the CPU
+  --check-devices        put every device through the device contract's
+                         checks, report on each and exit 1 if any fails
+  --contracts            check every device access the guest makes against
+                         the device contract while it runs, and report each
+                         rule a device breaks
+  --check-decode         execute every opcode once, check the CPU decodes
+                         exactly the opcodes in the ISA table and exit 1 if
+                         not
+  --selftest             run each built-in guest conformance test in batch
+                         under the other options given, report which pass
+                         and exit 1 if any failed
+  --selftest-case NAME   load the built-in conformance test NAME and start at
+                         its origin
+  --memory-map           print the memory map and loaded images, then exit
+  --monitor              run the operator monitor on stdin
+  --window               show the printer, the operator console, the text
+                         display and the registers in a window while the
+                         machine runs, and take replies, attention and the
+                         switches there; needs the window feature
+  --display-font FILE    load the text display's font ROM from FILE, 256
+                         glyphs of eight bytes, top row first and bit 7 the
+                         leftmost pixel (default the window's ASCII glyphs)
+  --http ADDR            serve pictures of the text display at ADDR:
+                         /screen.png, and /screen.apng?seconds=N for an
+                         animated PNG of the next N seconds (default 10)
+  --load FILE[@ADDR]     load a raw, .hex, .srec or ELF image; ADDR places
+                         raw images (default 0)
+  --elf FILE             load an ELF executable and start at its entry point
+  --example NAME         load the built-in example NAME and start at its origin
+                         (supervisor: a preemptive round-robin supervisor;
+                         irqstorm: a handler on every level, for --irq-storm)
+  --deck FILE            place a text file in the card reader hopper
+  --boot                 start in the firmware loader, reading the deck, or
+                         replies on the operator console with the low switch
+                         up (--switches 1)
+  --job-queue DIR        run the job files (a $JOB card, then a deck) submitted
+                         to DIR one at a time, with their printer and punch
+                         output in DIR/output
+  --dasd FILE            mount the disk image FILE on the disk drive (made with
+                         sqdisk; compressed images are read-only)
+  --dasd-overlay FILE    leave the --dasd image as it is and keep the guest's
+                         writes in the overlay FILE, created if missing
+  --dasd-cache N         hold up to N written blocks in a write-back cache,
+                         written to the image on a guest flush, when full and
+                         at exit
+  --dasd-sync POLICY     sync the image to the host disk never, on each flush
+                         (the default) or always, after every block written
+  --tape FILE            put the reel FILE (SIMH format, created if missing) in
+                         the tape autoloader's magazine; may be repeated, and
+                         the first reel starts in the drive
+  --tape-length BYTES    signal end of tape once a reel holds BYTES
+  --switches VALUE       set the front panel switch register to VALUE
+  --port-depth N         give the data port FIFOs of N words each way (1-255,
+                         default 1)
+  --port-socket ADDR     cable the data port to a peripheral process serving
+                         ADDR, a Unix socket path or HOST:PORT
+  --peer FILE            run FILE on a second machine whose data port is
+                         cabled to this one's
+  --remote HOST:PORT@ADDR
+                         attach the region served at HOST:PORT at ADDR
+  --serve-memory ADDR    serve main memory at ADDR for other emulators to
+                         attach with --remote
+  --shared FILE@ADDR     attach FILE at ADDR for the guest and host tools to
+                         share (created with 64K of zeros if missing)
+  --map FILE             read guest symbols from an sqasm map file
+  --coverage FILE        write a coverage report for the --map program to FILE
+  --print-out FILE       send printer output to FILE
+  --vfu FILE             load the printer's carriage tape from FILE, a line for
+                         each line of the form listing its channels (default
+                         66 lines, channel 1 at the top and 12 on line 61)
+  --print-width N        give the printer N print positions: 80, 132, 144 or
+                         160 (default 144)
+  --print-buffers N      give the printer N line buffers, 1 or 2 (default 1)
+  --paper N              load the printer with N pages of forms, after which it
+                         reports out of paper (default endless)
+  --punch-out FILE       send punched cards to FILE
+  --codepage [DEV=]NAME  translate the text device DEV (printer, reader, punch,
+                         console, clipboard or debug), or all of them, with the
+                         code page NAME: ascii, latin1 (the default) or
+                         ebcdic037; may be repeated, the last for a device
+                         counting
+  --queued-printer       run the printer behind a message queue rather than
+                         on the bus lock
+  --dma-spacing N        let the CPU run N instructions between DMA grant
+                         rounds (default 1)
+  --dma-storm            keep DMA channel 0 busy, to check forward progress
+  --deterministic        run the device clocks from the CPU's cycle count
+                         rather than host time, so device timing is the same
+                         from run to run
+  --cpu-hz N             the CPU clock the device clocks are reckoned from
+                         with --deterministic, in instructions a second
+                         (default 10000000)
+  --exit-reg N           exit status is the low byte of register N
+  --exit-word ADDR       exit status is the low byte of the word at ADDR
+  --max-cycles N         stop after N instructions (batch default 100000000)
+  --max-seconds S        stop after S seconds of wall-clock time
+  --watchdog S           report the emulator's threads, locks and trace ring
+                         when the CPU or a device thread is stuck for S seconds
+  --semihost             give the guest host services at 0x41000
+  --clipboard            let the guest put text on the host clipboard and read
+                         it, through the device at 0x48000
+  --no-host-time         make the profiler's host clock read 0, so the guest
+                         can't see real time through it
+  --attention-ipl N      priority level of the attention key's interrupt
+                         (1-7, default 7)
+  --attention-port ADDR  press the attention key for each telnet Break or
+                         Interrupt Process sent to ADDR
+  --host-command NAME    let the guest run the host program NAME through the
+                         host command interface at 0x43000; may be repeated
+  --spool DIR            directory for host command files (default spool)
+  --reply TEXT           queue TEXT on the operator console for the guest to
+                         read as a reply; may be repeated
+  --keymap FILE          translate operator console input through the keymap
+                         FILE, a line for each host and guest character
+  --paste FILE           type FILE in on the operator console as replies, a
+                         line each time the guest takes the last
+  --paste-delay MS       wait at least MS milliseconds between pasted lines
+                         (default 100)
+  --random-layout SEED   attach the devices at addresses chosen from SEED;
+                         guests must find them in the discovery table
+  --assert-access        check each guest bus access against the segment
+                         checks (panics in debug builds)
+  --align-fixup          make unaligned loads and stores a byte at a time
+                         instead of faulting, counting them in F9
+  --yield-stop           stop the CPU at each YIELD, as at a breakpoint, so
+                         the monitor or a host running the guest a slice at a
+                         time gets control back there
+  --protect-low BYTES    make application-state stores to the first BYTES of
+                         physical memory take a segmentation fault, whatever
+                         the segment allows
+  --taint                warn when the guest reads main memory nothing has
+                         written since power-on
+  --taint-fault          as --taint, but such reads take a read fault
+  --smc                  warn when the guest stores into main memory it has
+                         fetched instructions from
+  --smc-fault            as --smc, but such stores take a write fault
+  --watch-selectors      note the descriptor table entries SSEL and SSELHC
+                         load, and warn about a selector past the end of the
+                         table or an entry that looks like garbage
+  --log-op MNEMONIC      print every execution of an instruction with its
+                         operands and result; may be repeated
+  --trace FILE           write every instruction retired to FILE while the
+                         guest has tracing on (trace control register)
+  --trace-on             start with tracing on instead of waiting for the guest
+  --transcript FILE      log console, printer, punch and monitor input to FILE
+                         with timestamps
+  --restore FILE         resume from a snapshot or checkpoint file
+  --checkpoint PATH      write rotating checkpoints PATH.0 (newest), PATH.1, ...
+                         while the guest runs (every 60 seconds by default)
+  --checkpoint-secs S    checkpoint every S seconds of wall-clock time
+  --checkpoint-mcycles M checkpoint every M million instructions
+  --checkpoint-keep N    checkpoint files to keep (default 3)
+  --migrate-to HOST:PORT send the running machine to another emulator
+  --migrate-listen ADDR  wait for a machine to be migrated in
+  --migrate-after MS     delay before migrating out (default 1000)";
+
+// the devices --codepage can name
+pub const CODEPAGE_DEVICES: &[&str] = &["printer", "reader", "punch", "console", "clipboard", "debug"];
+
+// decimal, or hexadecimal with a 0x prefix
+pub fn parse_number(s: &str) -> Option<u64> {
+	if s.starts_with("0x") || s.starts_with("0X") {
+		u64::from_str_radix(&s[2..], 16).ok()
+	} else {
+		s.parse().ok()
+	}
+}
+
+fn parse_addr(s: &str) -> Result<u32, String> {
+	match parse_number(s) {
+		Some(x) if x <= 0xFFFFFFFF => Ok(x as u32),
+		_ => Err(format!("Bad address {}", s)),
+	}
+}
+
+impl Options {
+	pub fn parse(args: &[String]) -> Result<Options, String> {
+		let mut opt = Options {
+			batch: false,
+			bench: false,
+			irq_storm: None,
+			check: false,
+			check_devices: false,
+			contracts: false,
+			check_decode: false,
+			selftest: false,
+			selftest_case: None,
+			memory_map: false,
+			monitor: false,
+			window: false,
+			load: Vec::new(),
+			elf: None,
+			example: None,
+			deck: None,
+			peer: None,
+			port_depth: None,
+			port_socket: None,
+			remote: Vec::new(),
+			serve_memory: None,
+			shared: Vec::new(),
+			map: None,
+			coverage: None,
+			boot: false,
+			job_queue: None,
+			dasd: None,
+			dasd_overlay: None,
+			dasd_cache: None,
+			dasd_sync: None,
+			tapes: Vec::new(),
+			tape_length: None,
+			switches: None,
+			print_out: None,
+			vfu: None,
+			display_font: None,
+			http: None,
+			print_width: None,
+			print_buffers: None,
+			paper: None,
+			punch_out: None,
+			codepages: Vec::new(),
+			exit_reg: None,
+			exit_word: None,
+			max_cycles: None,
+			max_seconds: None,
+			watchdog: None,
+			semihost: false,
+			clipboard: false,
+			attention_ipl: None,
+			attention_port: None,
+			host_commands: Vec::new(),
+			replies: Vec::new(),
+			keymap: None,
+			paste: None,
+			paste_delay: None,
+			spool: None,
+			random_layout: None,
+			assert_access: false,
+			align_fixup: false,
+			yield_stop: false,
+			protect_low: None,
+			no_host_time: false,
+			taint: false,
+			taint_fault: false,
+			smc: false,
+			smc_fault: false,
+			watch_selectors: false,
+			log_ops: Vec::new(),
+			trace: None,
+			trace_on: false,
+			queued_printer: false,
+			dma_storm: false,
+			dma_spacing: None,
+			deterministic: false,
+			cpu_hz: None,
+			transcript: None,
+			restore: None,
+			checkpoint: None,
+			checkpoint_secs: None,
+			checkpoint_mcycles: None,
+			checkpoint_keep: None,
+			guest_args: Vec::new(),
+			
+			migrate_to: None,
+			migrate_listen: None,
+			migrate_after: 1000,
+		};
+		
+		let mut n = 1;
+		while n < args.len() {
+			let flag = args[n].as_str();
+			match flag {
+				"--batch" => {
+					opt.batch = true;
+					n += 1;
+					continue;
+				},
+				"--bench" => {
+					opt.bench = true;
+					n += 1;
+					continue;
+				},
+				"--check" => {
+					opt.check = true;
+					n += 1;
+					continue;
+				},
+				"--check-devices" => {
+					opt.check_devices = true;
+					n += 1;
+					continue;
+				},
+				"--contracts" => {
+					opt.contracts = true;
+					n += 1;
+					continue;
+				},
+				"--check-decode" => {
+					opt.check_decode = true;
+					n += 1;
+					continue;
+				},
+				"--selftest" => {
+					opt.selftest = true;
+					n += 1;
+					continue;
+				},
+				"--memory-map" => {
+					opt.memory_map = true;
+					n += 1;
+					continue;
+				},
+				"--boot" => {
+					opt.boot = true;
+					n += 1;
+					continue;
+				},
+				"--monitor" => {
+					opt.monitor = true;
+					n += 1;
+					continue;
+				},
+				"--window" => {
+					opt.window = true;
+					n += 1;
+					continue;
+				},
+				"--semihost" => {
+					opt.semihost = true;
+					n += 1;
+					continue;
+				},
+				"--clipboard" => {
+					opt.clipboard = true;
+					n += 1;
+					continue;
+				},
+				"--trace-on" => {
+					opt.trace_on = true;
+					n += 1;
+					continue;
+				},
+				"--assert-access" => {
+					opt.assert_access = true;
+					n += 1;
+					continue;
+				},
+				"--no-host-time" => {
+					opt.no_host_time = true;
+					n += 1;
+					continue;
+				},
+				"--align-fixup" => {
+					opt.align_fixup = true;
+					n += 1;
+					continue;
+				},
+				"--yield-stop" => {
+					opt.yield_stop = true;
+					n += 1;
+					continue;
+				},
+				"--taint" => {
+					opt.taint = true;
+					n += 1;
+					continue;
+				},
+				"--taint-fault" => {
+					opt.taint = true;
+					opt.taint_fault = true;
+					n += 1;
+					continue;
+				},
+				"--smc" => {
+					opt.smc = true;
+					n += 1;
+					continue;
+				},
+				"--watch-selectors" => {
+					opt.watch_selectors = true;
+					n += 1;
+					continue;
+				},
+				"--smc-fault" => {
+					opt.smc = true;
+					opt.smc_fault = true;
+					n += 1;
+					continue;
+				},
+				"--queued-printer" => {
+					opt.queued_printer = true;
+					n += 1;
+					continue;
+				},
+				"--dma-storm" => {
+					opt.dma_storm = true;
+					n += 1;
+					continue;
+				},
+				"--deterministic" => {
+					opt.deterministic = true;
+					n += 1;
+					continue;
+				},
+				"--" => {
+					opt.guest_args = args[n + 1..].to_vec();
+					break;
+				},
+				_ => { },
+			}
+			
+			// everything else takes a value
+			if n + 1 >= args.len() {
+				return Err(format!("Unknown option {}", flag));
+			}
+			let value = args[n + 1].clone();
+			
+			match flag {
+				"--load" => {
+					match value.rfind('@') {
+						Some(at) => {
+							let addr = parse_addr(&value[at + 1..])?;
+							opt.load.push((value[..at].to_string(), addr));
+						},
+						None => opt.load.push((value, 0)),
+					}
+				},
+				"--elf" => opt.elf = Some(value),
+				"--example" => opt.example = Some(value),
+				"--selftest-case" => opt.selftest_case = Some(value),
+				"--deck" => opt.deck = Some(value),
+				"--dasd" => opt.dasd = Some(value),
+				"--dasd-overlay" => opt.dasd_overlay = Some(value),
+				"--tape" => opt.tapes.push(value),
+				"--tape-length" => {
+					match parse_number(&value) {
+						Some(x) if x > 0 => opt.tape_length = Some(x),
+						_ => return Err(format!("Bad tape length {}", value)),
+					}
+				},
+				"--switches" => opt.switches = Some(parse_addr(&value)?),
+				"--dasd-cache" => {
+					match parse_number(&value) {
+						Some(x) if x > 0 => opt.dasd_cache = Some(x as usize),
+						_ => return Err(format!("Bad cache size {}", value)),
+					}
+				},
+				"--dasd-sync" => {
+					match SyncPolicy::parse(&value) {
+						Some(x) => opt.dasd_sync = Some(x),
+						None => return Err(format!("Bad sync policy {}; use never, flush or always", value)),
+					}
+				},
+				"--peer" => opt.peer = Some(value),
+				"--port-socket" => opt.port_socket = Some(value),
+				"--port-depth" => {
+					match value.parse::<usize>() {
+						Ok(n) if n >= 1 && n <= port::MAX_DEPTH => opt.port_depth = Some(n),
+						_ => return Err(format!("Bad port depth {}", value)),
+					}
+				},
+				"--job-queue" => opt.job_queue = Some(value),
+				"--remote" => {
+					match value.rfind('@') {
+						Some(at) => {
+							let addr = parse_addr(&value[at + 1..])?;
+							opt.remote.push((value[..at].to_string(), addr));
+						},
+						None => return Err(format!("Bad remote region {}", value)),
+					}
+				},
+				"--serve-memory" => opt.serve_memory = Some(value),
+				"--shared" => {
+					match value.rfind('@') {
+						Some(at) => {
+							let addr = parse_addr(&value[at + 1..])?;
+							opt.shared.push((value[..at].to_string(), addr));
+						},
+						None => return Err(format!("Bad shared region {}", value)),
+					}
+				},
+				"--map" => opt.map = Some(value),
+				"--coverage" => opt.coverage = Some(value),
+				"--log-op" => opt.log_ops.push(value),
+				"--trace" => opt.trace = Some(value),
+				"--attention-ipl" => {
+					match value.parse::<usize>() {
+						Ok(n) if n >= 1 && n <= 7 => opt.attention_ipl = Some(n),
+						_ => return Err(format!("Bad priority level {}", value)),
+					}
+				},
+				"--attention-port" => opt.attention_port = Some(value),
+				"--host-command" => opt.host_commands.push(value),
+				"--reply" => opt.replies.push(value),
+				"--keymap" => opt.keymap = Some(value),
+				"--paste" => opt.paste = Some(value),
+				"--paste-delay" => {
+					match value.parse::<u64>() {
+						Ok(n) => opt.paste_delay = Some(time::Duration::from_millis(n)),
+						_ => return Err(format!("Bad paste delay {}", value)),
+					}
+				},
+				"--spool" => opt.spool = Some(value),
+				"--print-out" => opt.print_out = Some(value),
+				"--vfu" => opt.vfu = Some(value),
+				"--display-font" => opt.display_font = Some(value),
+				"--http" => opt.http = Some(value),
+				"--codepage" => {
+					let (device, name) = match value.split_once('=') {
+						Some((d, n)) if CODEPAGE_DEVICES.contains(&d) => (Some(d.to_string()), n),
+						Some((d, _)) => return Err(format!("No text device {}", d)),
+						None => (None, value.as_str()),
+					};
+					match CodePage::from_name(name) {
+						Some(c) => opt.codepages.push((device, c)),
+						None => return Err(format!("Unknown code page {}", name)),
+					}
+				},
+				"--print-width" => {
+					match value.parse::<u32>() {
+						Ok(n) if lp1204::WIDTHS.contains(&n) => opt.print_width = Some(n),
+						_ => return Err(format!("Bad print width {}", value)),
+					}
+				},
+				"--print-buffers" => {
+					match value.parse::<u32>() {
+						Ok(n) if n >= 1 && n <= lp1204::MAX_BUFFERS => opt.print_buffers = Some(n),
+						_ => return Err(format!("Bad number of print buffers {}", value)),
+					}
+				},
+				"--paper" => {
+					match value.parse::<u64>() {
+						Ok(n) if n != lp1204::ENDLESS => opt.paper = Some(n),
+						_ => return Err(format!("Bad number of pages {}", value)),
+					}
+				},
+				"--punch-out" => opt.punch_out = Some(value),
+				"--exit-reg" => {
+					match value.parse::<usize>() {
+						Ok(r) if r < 16 => opt.exit_reg = Some(r),
+						_ => return Err(format!("Bad register {}", value)),
+					}
+				},
+				"--exit-word" => opt.exit_word = Some(parse_addr(&value)?),
+				"--protect-low" => opt.protect_low = Some(parse_addr(&value)?),
+				"--max-cycles" => {
+					match parse_number(&value) {
+						Some(x) if x > 0 => opt.max_cycles = Some(x),
+						_ => return Err(format!("Bad cycle count {}", value)),
+					}
+				},
+				"--irq-storm" => {
+					match parse_number(&value) {
+						Some(x) if x > 0 && x <= 1_000_000 => opt.irq_storm = Some(x),
+						_ => return Err(format!("Bad interrupt interval {}", value)),
+					}
+				},
+				"--random-layout" => {
+					match parse_number(&value) {
+						Some(x) => opt.random_layout = Some(x),
+						_ => return Err(format!("Bad seed {}", value)),
+					}
+				},
+				"--dma-spacing" => {
+					match parse_number(&value) {
+						Some(x) if x > 0 => opt.dma_spacing = Some(x),
+						_ => return Err(format!("Bad DMA spacing {}", value)),
+					}
+				},
+				"--cpu-hz" => {
+					match parse_number(&value) {
+						Some(x) if x > 0 => opt.cpu_hz = Some(x),
+						_ => return Err(format!("Bad CPU clock {}", value)),
+					}
+				},
+				"--max-seconds" => {
+					match value.parse::<f64>() {
+						Ok(x) if x > 0.0 && x.is_finite() => opt.max_seconds = Some(x),
+						_ => return Err(format!("Bad time limit {}", value)),
+					}
+				},
+				"--watchdog" => {
+					match value.parse::<f64>() {
+						Ok(x) if x > 0.0 && x.is_finite() => opt.watchdog = Some(x),
+						_ => return Err(format!("Bad watchdog interval {}", value)),
+					}
+				},
+				"--transcript" => opt.transcript = Some(value),
+				"--restore" => opt.restore = Some(value),
+				"--checkpoint" => opt.checkpoint = Some(value),
+				"--checkpoint-secs" => {
+					match value.parse::<f64>() {
+						Ok(x) if x > 0.0 && x.is_finite() => opt.checkpoint_secs = Some(x),
+						_ => return Err(format!("Bad checkpoint interval {}", value)),
+					}
+				},
+				"--checkpoint-mcycles" => {
+					match parse_number(&value) {
+						Some(x) if x > 0 && x <= u64::MAX / 1_000_000 => opt.checkpoint_mcycles = Some(x),
+						_ => return Err(format!("Bad checkpoint interval {}", value)),
+					}
+				},
+				"--checkpoint-keep" => {
+					match value.parse::<u32>() {
+						Ok(x) if x > 0 => opt.checkpoint_keep = Some(x),
+						_ => return Err(format!("Bad checkpoint count {}", value)),
+					}
+				},
+				"--migrate-to" => opt.migrate_to = Some(value),
+				"--migrate-listen" => opt.migrate_listen = Some(value),
+				"--migrate-after" => {
+					opt.migrate_after = value.parse().unwrap_or(opt.migrate_after);
+				},
+				x => return Err(format!("Unknown option {}", x)),
+			}
+			n += 2;
+		}
+		
+		if opt.irq_storm.is_some() && (opt.bench || opt.batch) {
+			return Err("--irq-storm runs headless on its own, not with --bench or --batch".to_string());
+		}
+		if opt.window && !cfg!(feature = "window") {
+			return Err("--window needs rustframe built with the window feature".to_string());
+		}
+		if opt.window && (opt.batch || opt.bench || opt.irq_storm.is_some() || opt.monitor || opt.job_queue.is_some()) {
+			return Err("--window runs the machine interactively, not with --batch, --bench, --irq-storm, --monitor or --job-queue".to_string());
+		}
+		if opt.exit_reg.is_some() && opt.exit_word.is_some() {
+			return Err("--exit-reg and --exit-word are exclusive".to_string());
+		}
+		if opt.dasd.is_none() && (opt.dasd_overlay.is_some() || opt.dasd_cache.is_some() || opt.dasd_sync.is_some()) {
+			return Err("--dasd-overlay, --dasd-cache and --dasd-sync need a volume mounted with --dasd".to_string());
+		}
+		if opt.peer.is_some() && opt.port_socket.is_some() {
+			return Err("--peer and --port-socket both need the data port".to_string());
+		}
+		if opt.job_queue.is_some() && (opt.print_out.is_some() || opt.punch_out.is_some()) {
+			return Err("--job-queue puts output in the queue directory, not --print-out or --punch-out".to_string());
+		}
+		let program = !opt.load.is_empty() || opt.elf.is_some() || opt.example.is_some() || opt.selftest_case.is_some()
+			|| opt.boot || opt.job_queue.is_some() || opt.restore.is_some();
+		if opt.selftest && (program || opt.batch || opt.bench || opt.irq_storm.is_some() || opt.check || opt.monitor || opt.window || opt.exit_reg.is_some() || opt.exit_word.is_some()) {
+			return Err("--selftest runs its own programs in batch, so takes no program, mode or exit status".to_string());
+		}
+		if opt.coverage.is_some() && opt.map.is_none() {
+			return Err("--coverage needs --map".to_string());
+		}
+		if opt.trace_on && opt.trace.is_none() {
+			return Err("--trace-on needs --trace".to_string());
+		}
+		let timed = opt.checkpoint_secs.is_some() || opt.checkpoint_mcycles.is_some() || opt.checkpoint_keep.is_some();
+		if timed && opt.checkpoint.is_none() {
+			return Err("--checkpoint-secs, --checkpoint-mcycles and --checkpoint-keep need --checkpoint".to_string());
+		}
+		Ok(opt)
+	}
+	
+	pub fn time_limit(&self) -> Option<time::Duration> {
+		self.max_seconds.map(time::Duration::from_secs_f64)
+	}
+	
+	pub fn watchdog_interval(&self) -> Option<time::Duration> {
+		self.watchdog.map(time::Duration::from_secs_f64)
+	}
+	
+	// the checkpoint interval, by default a minute when no interval is given
+	pub fn checkpoint_interval(&self) -> (Option<u64>, Option<time::Duration>) {
+		let cycles = self.checkpoint_mcycles.map(|m| m * 1_000_000);
+		let secs = match (self.checkpoint_secs, cycles) {
+			(None, None) => Some(60.0),
+			(s, _) => s,
+		};
+		(cycles, secs.map(time::Duration::from_secs_f64))
+	}
+}
@@ -0,0 +1,44 @@
+use crate::batch::BATCH_CYCLE_LIMIT;
+use crate::machine::{Machine, Stop};
+use crate::options::Options;
+
+// Pair: a second machine in the same process, its data port cabled to the
+// first one's through a null modem
+//
+// The peer has its own memory and standard devices and runs the image named by
+// --peer from power-on, in the same layout and port depth as the first
+// machine. It starts first, and in batch mode the job is only over when it has
+// halted too. It is not part of snapshots, checkpoints or migration.
+
+pub fn start(machine: &mut Machine, opt: &Options) -> Result<Option<Machine>, String> {
+	let path = match &opt.peer {
+		Some(x) => x,
+		None => return Ok(None),
+	};
+	let mut peer = Machine::new();
+	if let Some(seed) = opt.random_layout {
+		peer.randomize_layout(seed);
+	}
+	if let Some(n) = opt.port_depth {
+		peer.dataport.lock().unwrap().set_depth(n);
+	}
+	peer.load_file(path, 0).map_err(|e| format!("{}: {}", path, e))?;
+	machine.link(&mut peer);
+	if opt.batch {
+		peer.cpu.lock().unwrap().cycle_limit = opt.max_cycles.unwrap_or(BATCH_CYCLE_LIMIT);
+	}
+	peer.start();
+	Ok(Some(peer))
+}
+
+// after the first machine's batch job: false if the peer ran into a limit
+pub fn wait(peer: &mut Machine, opt: &Options) -> bool {
+	let limit = match peer.wait_limit(opt.time_limit()) {
+		Stop::Halted => return true,
+		Stop::CycleLimit => "CYCLE",
+		Stop::TimeLimit => "TIME",
+	};
+	println!("BATCH: PEER {} LIMIT EXCEEDED", limit);
+	peer.dump();
+	false
+}
@@ -0,0 +1,106 @@
+use crate::bus::{Memory32, BusError};
+use crate::frontend;
+
+// Panel: the front panel's switch register and lamp register
+//
+// Registers (words): switches at 0, lamps at 4. The guest and the operator
+// can both read and set either one: the operator sets the switches with
+// --switches or the monitor's panel command, a guest reads them for options at
+// startup and shows what it likes in the lamps. The monitor's panel deposit
+// and examine go through the same registers, so a bootstrap can be toggled in
+// a word at a time, as on the real thing. The frontend is told whenever
+// either one changes.
+
+pub const PANEL_REGION_SIZE: u32 = 8;
+
+pub struct Panel {
+	pub regs: Vec<u8>
+}
+
+impl Panel {
+	pub fn new() -> Panel {
+		Panel { regs: vec![0 as u8; PANEL_REGION_SIZE as usize] }
+	}
+	
+	pub fn switches(&self) -> u32 {
+		self.regs.read_w(0).unwrap()
+	}
+	pub fn set_switches(&mut self, x: u32) {
+		let before = self.both();
+		self.regs.write_w(0, x).unwrap();
+		self.changed(before);
+	}
+	pub fn lamps(&self) -> u32 {
+		self.regs.read_w(4).unwrap()
+	}
+	pub fn set_lamps(&mut self, x: u32) {
+		let before = self.both();
+		self.regs.write_w(4, x).unwrap();
+		self.changed(before);
+	}
+	
+	fn both(&self) -> (u32, u32) {
+		(self.switches(), self.lamps())
+	}
+	
+	fn changed(&self, before: (u32, u32)) {
+		if self.both() != before {
+			frontend::panel(self.switches(), self.lamps());
+		}
+	}
+}
+
+// a register as a row of switches or lamps, a byte to a group
+pub fn row(x: u32) -> String {
+	let bits = format!("{:032b}", x);
+	let groups: Vec<&str> = (0..4).map(|n| &bits[8 * n..8 * n + 8]).collect();
+	format!("{} (0x{:08X})", groups.join(" "), x)
+}
+
+impl Memory32<u32, BusError> for Panel {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.regs.read_b(addr)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h(addr)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h_big(addr)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		self.regs.read_w(addr)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		let before = self.both();
+		self.regs.write_b(addr, data)?;
+		self.changed(before);
+		Ok(())
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		let before = self.both();
+		self.regs.write_h(addr, data)?;
+		self.changed(before);
+		Ok(())
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		let before = self.both();
+		self.regs.write_w(addr, data)?;
+		self.changed(before);
+		Ok(())
+	}
+	
+	// the switches are where the operator left them, so only the lamps are saved
+	fn save_state(&self) -> Option<Vec<u8>> {
+		bincode::serialize(&self.lamps()).ok()
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		let lamps: u32 = bincode::deserialize(state).map_err(|_| BusError::InvalidState)?;
+		self.set_lamps(lamps);
+		Ok(())
+	}
+	
+	fn idempotent(&self, _addr: u32) -> bool {
+		true
+	}
+}
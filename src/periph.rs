@@ -0,0 +1,117 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread;
+use crate::sync::Mutex;
+use crate::port::{self, Format, Port, ACKNOWLEDGE, ERROR, INBOUND};
+use crate::transcript;
+
+// Peripheral: the data port's far side played by another process, over a
+// Unix socket (a path) or TCP (HOST:PORT) it serves, named with --port-socket
+//
+// Messages in both directions are four bytes: a kind, an argument byte and a
+// halfword, little-endian.
+//   'D' 0 word		a word: from the emulator, one the guest wrote; from the
+//					peripheral, one for the guest
+//   'L' lines 0	set the port's lines (from the peripheral)
+//   'R' lines 0	raise lines, leaving those up until the guest reads them
+//   'I' 0 0		interrupt the guest, whatever its line mask says
+//   'F' control divisor
+//					how the peripheral frames what it sends, with the port's
+//					line control and baud divisor layout; until it says, as
+//					the port does
+// A word the peripheral sends waits for room in the receive FIFO and then
+// raises Inbound; one the guest writes raises Acknowledge once it is sent. The
+// emulator sends nothing else, so a peripheral that only listens needs only
+// read 'D' messages. With the port's UART on, each word takes a character's
+// time on the line each way (port.rs). Should the connection fail, the port
+// raises Error and is left unconnected.
+
+const WORD: u8 = b'D';
+const LINES: u8 = b'L';
+const RAISE: u8 = b'R';
+const INTERRUPT: u8 = b'I';
+const FRAMING: u8 = b'F';
+
+#[cfg(unix)]
+fn connect_unix(path: &str) -> io::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+	let stream = std::os::unix::net::UnixStream::connect(path)?;
+	Ok((Box::new(stream.try_clone()?), Box::new(stream)))
+}
+
+#[cfg(windows)]
+fn connect_unix(_path: &str) -> io::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+	Err(io::Error::new(io::ErrorKind::Unsupported, "no Unix sockets on this host"))
+}
+
+fn hang_up(port: &Mutex<Port>, e: io::Error) {
+	transcript::record("PORT", &format!("PERIPHERAL DISCONNECTED: {}", e));
+	port.lock().unwrap().raise(ERROR);
+}
+
+// connect and start passing words; the port must have nothing else cabled to it
+pub fn connect(addr: &str, port: Arc<Mutex<Port>>) -> io::Result<()> {
+	let (mut input, mut output) = if addr.contains(':') {
+		let stream = TcpStream::connect(addr)?;
+		stream.set_nodelay(true)?;
+		(Box::new(stream.try_clone()?) as Box<dyn Read + Send>, Box::new(stream) as Box<dyn Write + Send>)
+	} else {
+		connect_unix(addr)?
+	};
+	
+	let to = Arc::clone(&port);
+	thread::spawn(move || {
+		loop {
+			let word = to.lock().unwrap().recv();
+			match word {
+				Some(x) => {
+					port::pace(&to);
+					let [lo, hi] = x.to_le_bytes();
+					if let Err(e) = output.write_all(&[WORD, 0, lo, hi]).and_then(|_| output.flush()) {
+						hang_up(&to, e);
+						return;
+					}
+					to.lock().unwrap().raise(ACKNOWLEDGE);
+				},
+				None => thread::yield_now(),
+			}
+		}
+	});
+	
+	thread::spawn(move || {
+		let mut message = [0 as u8; 4];
+		let mut framing: Option<Format> = None;
+		loop {
+			if let Err(e) = input.read_exact(&mut message) {
+				hang_up(&port, e);
+				return;
+			}
+			let [kind, arg, lo, hi] = message;
+			match kind {
+				WORD => {
+					port::pace(&port);
+					loop {
+						let p = port.lock().unwrap();
+						if p.room() {
+							p.send_framed(u16::from_le_bytes([lo, hi]), framing.unwrap_or(p.format()));
+							p.raise(INBOUND);
+							break;
+						}
+						drop(p);
+						thread::yield_now();
+					}
+				},
+				LINES => port.lock().unwrap().flag(arg),
+				RAISE => port.lock().unwrap().raise(arg),
+				INTERRUPT => port.lock().unwrap().ipl.store(true, Ordering::SeqCst),
+				FRAMING => framing = Some(Format { divisor: u16::from_le_bytes([lo, hi]), control: arg }),
+				_ => {
+					hang_up(&port, io::Error::new(io::ErrorKind::InvalidData, format!("bad message {:02X}", kind)));
+					return;
+				},
+			}
+		}
+	});
+	Ok(())
+}
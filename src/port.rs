@@ -0,0 +1,505 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use crate::sync::Mutex;
+use std::sync::atomic::{AtomicU8, AtomicU16, AtomicBool, Ordering};
+use std::thread;
+use crate::bus::{Memory32, BusError};
+use crate::clock::Domain;
+use crate::watchdog;
+use serde::{Serialize, Deserialize};
+
+// Port: 2200 data port interface, a FIFO of words in each direction
+//
+// Registers: data at 0 (halfword: a read takes the oldest word from the
+// receive FIFO, a write puts one on the transmit FIFO; reading the byte at 0
+// takes a word, and the byte at 1 is the high byte of the word last taken),
+// lines at 2 (cleared by reading them), line interrupt mask at 3, FIFO status
+// at 4, FIFO interrupt mask at 5, receive threshold at 6, transmit threshold
+// at 7, receive fill at 8, transmit fill at 9, depth at 10 (read-only) and
+// control at 11.
+//
+// FIFO status: [EUOORTTN] from bit 0, Receive Not empty, Receive at its
+// Threshold (fill at least the receive threshold, when that is not 0),
+// Transmit Full, Transmit at its Threshold (fill at most the transmit
+// threshold), Receive Overflow (the peripheral's word was dropped), Transmit
+// Overflow (the guest's word was dropped), receive Underflow (a read found
+// nothing, and gave 0) and transmit Error (a word was lost on the way, see
+// below). The last four stay until the status is read, and raise the Error
+// line as they are set. The port interrupts at
+// IPL 6 while a line in the line mask or a status bit in the FIFO mask is up.
+//
+// Inbound is raised again as a word is read while more are waiting. Both FIFOs
+// hold --port-depth words (1 by default, as the port always had).
+//
+// Control: bit 0 is loopback, in which each word written goes straight to the
+// receive FIFO, raising Inbound and Acknowledge as a peripheral would, and
+// nothing is sent; bit 1 injects an error, losing the next word written with
+// transmit Error set, and clears itself. With them a driver can test itself,
+// and its error handling, with nothing cabled to the port.
+//
+// UART: with a baud divisor (halfword at 12) other than 0 the port is a serial
+// line running at UART_CLOCK / divisor baud, each word a character. Line
+// control at 14 is laid out as a 16550's: [...EPSLL] from bit 0, the data
+// bits less 5, two Stop bits, Parity on and Even parity (odd if clear). Only
+// the low data bits of a word written go out. A character takes a start bit,
+// the data, parity and stop bits at the baud rate, timed on the port's clock
+// domain (clock.rs), between leaving the transmit FIFO and reaching the far
+// side, whichever way it goes. What comes in is framed as the far side sent
+// it: a peripheral process declares its settings with its 'F' message
+// (periph.rs), a port over the null modem has its own. A baud rate or number
+// of data bits other than this port's, or a parity bit where this port wants
+// a stop bit and finds 0, makes a framing error; a parity bit other than the
+// one this port's parity expects, or none where it expects one, a parity
+// error. The character is received regardless. Line status at 15: bit 0
+// framing error, bit 1 parity error, which stay until it is read and raise the
+// Error line as they are set. Loopback takes no time and frames nothing
+// wrongly.
+
+pub const RX_NOT_EMPTY: u8 = 0b00000001;
+pub const RX_THRESHOLD: u8 = 0b00000010;
+pub const TX_FULL: u8 = 0b00000100;
+pub const TX_THRESHOLD: u8 = 0b00001000;
+pub const RX_OVERFLOW: u8 = 0b00010000;
+pub const TX_OVERFLOW: u8 = 0b00100000;
+pub const RX_UNDERFLOW: u8 = 0b01000000;
+pub const TX_ERROR: u8 = 0b10000000;
+
+pub const LOOPBACK: u8 = 0b00000001;
+pub const INJECT_ERROR: u8 = 0b00000010;
+
+pub const MAX_DEPTH: usize = 255;
+
+// the UART's reference clock, and its clock domain's rate
+pub const UART_CLOCK: u64 = 115200;
+
+pub const FRAMING_ERROR: u8 = 0b00000001;
+pub const PARITY_ERROR: u8 = 0b00000010;
+
+pub const TWO_STOP_BITS: u8 = 0b00000100;
+pub const PARITY_ON: u8 = 0b00001000;
+pub const EVEN_PARITY: u8 = 0b00010000;
+const LINE_CONTROL_BITS: u8 = 0b00011111;
+
+// how one side of a serial line frames its characters
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Format {
+	pub divisor: u16,			// 0 for no UART, words passing as they are
+	pub control: u8
+}
+
+impl Format {
+	fn data_bits(&self) -> u32 {
+		5 + (self.control & 0b11) as u32
+	}
+	
+	fn mask(&self) -> u16 {
+		if self.divisor == 0 { 0xFFFF } else { (1 << self.data_bits()) - 1 }
+	}
+	
+	// the bit sent after the data: parity, or else the first stop bit
+	fn after_data(&self, data: u16) -> bool {
+		if self.control & PARITY_ON == 0 {
+			return true;
+		}
+		let odd = (data & self.mask()).count_ones() % 2 == 1;
+		// even parity makes the ones even, odd parity odd
+		if self.control & EVEN_PARITY != 0 { odd } else { !odd }
+	}
+	
+	// clock ticks a character takes on the line
+	pub fn ticks(&self) -> u64 {
+		if self.divisor == 0 {
+			return 0;
+		}
+		let parity = (self.control & PARITY_ON != 0) as u32;
+		let stop = if self.control & TWO_STOP_BITS != 0 { 2 } else { 1 };
+		(1 + self.data_bits() + parity + stop) as u64 * self.divisor as u64
+	}
+	
+	// the line status errors in receiving data sent framed as from
+	pub fn errors(&self, from: Format, data: u16) -> u8 {
+		if self.divisor == 0 || from.divisor == 0 {
+			return 0;
+		}
+		if from.divisor != self.divisor || from.data_bits() != self.data_bits() {
+			return FRAMING_ERROR;
+		}
+		let sent = from.after_data(data);
+		match (self.control & PARITY_ON != 0, from.control & PARITY_ON != 0) {
+			(true, true) if sent != self.after_data(data) => PARITY_ERROR,
+			(true, false) => PARITY_ERROR,
+			(false, true) if !sent => FRAMING_ERROR,
+			_ => 0,
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Port {
+	pub tx: RefCell<VecDeque<u16>>,
+	pub rx: RefCell<VecDeque<u16>>,
+	pub last: AtomicU16,		// word last taken, for the byte at 1
+	pub lines: AtomicU8, // [3210IEAR] - Device Specific Lines, Inbound, Error, Acknowledge, Ready
+	pub imask: AtomicU8,
+	pub errors: AtomicU8,		// overflow and underflow status bits not yet read
+	pub fmask: AtomicU8,
+	pub rx_threshold: AtomicU8,
+	pub tx_threshold: AtomicU8,
+	pub control: AtomicU8,
+	pub divisor: AtomicU16,
+	pub line_control: AtomicU8,
+	pub line_status: AtomicU8,	// framing and parity errors not yet read
+	pub depth: usize,
+	
+	#[serde(skip)]
+	pub ipl: Arc<AtomicBool>,
+	#[serde(skip, default = "host_clock")]
+	pub clock: Arc<Domain>
+}
+
+// a clock on host time alone, until the machine gives the port one of its
+// domains
+pub fn host_clock() -> Arc<Domain> {
+	Domain::host("data port", UART_CLOCK)
+}
+
+impl Port {
+	pub fn new(ipl_line: Arc<AtomicBool>) -> Port {
+		Port {
+			tx: RefCell::new(VecDeque::new()),
+			rx: RefCell::new(VecDeque::new()),
+			last: AtomicU16::new(0),
+			lines: AtomicU8::new(0),
+			imask: AtomicU8::new(0),
+			errors: AtomicU8::new(0),
+			fmask: AtomicU8::new(0),
+			rx_threshold: AtomicU8::new(1),
+			tx_threshold: AtomicU8::new(0),
+			control: AtomicU8::new(0),
+			divisor: AtomicU16::new(0),
+			line_control: AtomicU8::new(0),
+			line_status: AtomicU8::new(0),
+			depth: 1,
+			
+			ipl: ipl_line,
+			clock: host_clock()
+		}
+	}
+	
+	// change the FIFO depth, dropping whatever no longer fits
+	pub fn set_depth(&mut self, depth: usize) {
+		self.depth = depth;
+		self.tx.borrow_mut().truncate(depth);
+		self.rx.borrow_mut().truncate(depth);
+	}
+	
+	pub fn status(&self) -> u8 {
+		let (rx, tx) = (self.rx.borrow().len(), self.tx.borrow().len());
+		let rx_threshold = self.rx_threshold.load(Ordering::SeqCst) as usize;
+		let mut s = self.errors.load(Ordering::SeqCst);
+		if rx > 0 {
+			s |= RX_NOT_EMPTY;
+		}
+		if rx_threshold > 0 && rx >= rx_threshold {
+			s |= RX_THRESHOLD;
+		}
+		if tx >= self.depth {
+			s |= TX_FULL;
+		}
+		if tx <= self.tx_threshold.load(Ordering::SeqCst) as usize {
+			s |= TX_THRESHOLD;
+		}
+		s
+	}
+	
+	// raise the interrupt if a FIFO condition the guest asked for holds
+	fn check(&self) {
+		if self.status() & self.fmask.load(Ordering::SeqCst) != 0 {
+			self.ipl.store(true, Ordering::SeqCst);
+		}
+	}
+	
+	fn error(&self, bit: u8) {
+		self.errors.fetch_or(bit, Ordering::SeqCst);
+		self.raise(ERROR);
+	}
+	
+	pub fn format(&self) -> Format {
+		Format {
+			divisor: self.divisor.load(Ordering::SeqCst),
+			control: self.line_control.load(Ordering::SeqCst)
+		}
+	}
+	
+	// peripheral side
+	
+	// false, and the word dropped, if the receive FIFO is full
+	pub fn send(&self, data: u16) -> bool {
+		let full = self.rx.borrow().len() >= self.depth;
+		if full {
+			self.error(RX_OVERFLOW);
+		} else {
+			self.rx.borrow_mut().push_back(data);
+		}
+		self.check();
+		!full
+	}
+	
+	pub fn recv(&self) -> Option<u16> {
+		let x = self.tx.borrow_mut().pop_front();
+		self.check();
+		x
+	}
+	
+	// as send, for a character the far side framed as from
+	pub fn send_framed(&self, data: u16, from: Format) -> bool {
+		let format = self.format();
+		let errors = format.errors(from, data);
+		if errors != 0 {
+			self.line_status.fetch_or(errors, Ordering::SeqCst);
+			self.raise(ERROR);
+		}
+		self.send(data & format.mask())
+	}
+	
+	// whether the receive FIFO has room for another word
+	pub fn room(&self) -> bool {
+		self.rx.borrow().len() < self.depth
+	}
+	
+	pub fn flag(&self, data: u8) {
+		self.lines.store(data, Ordering::SeqCst);
+		if data & self.imask.load(Ordering::SeqCst) != 0 {
+			self.ipl.store(true, Ordering::SeqCst);
+		}
+	}
+	
+	// as flag, but leaving lines already up until the guest reads them
+	pub fn raise(&self, data: u8) {
+		self.lines.fetch_or(data, Ordering::SeqCst);
+		if data & self.imask.load(Ordering::SeqCst) != 0 {
+			self.ipl.store(true, Ordering::SeqCst);
+		}
+	}
+	
+	// bus side
+	pub fn write(&self, data: u16) {
+		let data = data & self.format().mask();
+		let control = self.control.fetch_and(!INJECT_ERROR, Ordering::SeqCst);
+		if control & INJECT_ERROR != 0 {
+			self.error(TX_ERROR);
+		} else if control & LOOPBACK != 0 {
+			if self.send(data) {
+				self.raise(INBOUND | ACKNOWLEDGE);
+			}
+		} else if self.tx.borrow().len() >= self.depth {
+			self.error(TX_OVERFLOW);
+		} else {
+			self.tx.borrow_mut().push_back(data);
+		}
+		self.check();
+	}
+	
+	pub fn read(&self) -> u16 {
+		self.ipl.store(false, Ordering::SeqCst);
+		let x = match self.rx.borrow_mut().pop_front() {
+			Some(x) => x,
+			None => {
+				self.error(RX_UNDERFLOW);
+				0
+			},
+		};
+		self.last.store(x, Ordering::SeqCst);
+		// a guest waiting on Inbound for each word sees the ones still queued
+		if !self.rx.borrow().is_empty() {
+			self.raise(INBOUND);
+		}
+		self.check();
+		x
+	}
+	
+	fn byte(&self, addr: u32) -> Option<u8> {
+		let x = match addr {
+			2 => self.lines.swap(0, Ordering::SeqCst),
+			3 => self.imask.load(Ordering::SeqCst),
+			4 => {
+				let s = self.status();
+				self.errors.store(0, Ordering::SeqCst);
+				s
+			},
+			5 => self.fmask.load(Ordering::SeqCst),
+			6 => self.rx_threshold.load(Ordering::SeqCst),
+			7 => self.tx_threshold.load(Ordering::SeqCst),
+			8 => self.rx.borrow().len() as u8,
+			9 => self.tx.borrow().len() as u8,
+			10 => self.depth as u8,
+			11 => self.control.load(Ordering::SeqCst),
+			12 => self.divisor.load(Ordering::SeqCst) as u8,
+			13 => (self.divisor.load(Ordering::SeqCst) >> 8) as u8,
+			14 => self.line_control.load(Ordering::SeqCst),
+			15 => self.line_status.swap(0, Ordering::SeqCst),
+			_ => return None,
+		};
+		Some(x)
+	}
+}
+
+impl Memory32<u32, BusError> for Port {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		match addr {
+			0 => Ok((self.read() & 0xFF) as u8),
+			1 => Ok(((self.last.load(Ordering::SeqCst) & 0xFF00) >> 8) as u8),
+			_ => self.byte(addr).ok_or(BusError::InvalidAddress)
+		}
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		match addr {
+			0 => Ok(self.read()),
+			12 => Ok(self.divisor.load(Ordering::SeqCst)),
+			_ => Err(BusError::InvalidAddress)
+		}
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		Err(BusError::InvalidAddress)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		Err(BusError::InvalidAddress)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		match addr {
+			// 2 => Ok(self.lines.store(data, Ordering::SeqCst)),
+			3 => self.imask.store(data, Ordering::SeqCst),
+			5 => self.fmask.store(data, Ordering::SeqCst),
+			6 => self.rx_threshold.store(data, Ordering::SeqCst),
+			7 => self.tx_threshold.store(data, Ordering::SeqCst),
+			11 => self.control.store(data & (LOOPBACK | INJECT_ERROR), Ordering::SeqCst),
+			14 => self.line_control.store(data & LINE_CONTROL_BITS, Ordering::SeqCst),
+			_ => return Err(BusError::InvalidAddress)
+		}
+		self.check();
+		Ok(())
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		match addr {
+			0 => Ok(self.write(data)),
+			12 => Ok(self.divisor.store(data, Ordering::SeqCst)),
+			_ => Err(BusError::InvalidAddress)
+		}
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		Err(BusError::InvalidAddress)
+	}
+	
+	fn save_state(&self) -> Option<Vec<u8>> {
+		bincode::serialize(self).ok()
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		let saved: Port = match bincode::deserialize(state) {
+			Err(_) => return Err(BusError::InvalidState),
+			Ok(x) => x,
+		};
+		
+		self.tx = saved.tx;
+		self.rx = saved.rx;
+		self.last.store(saved.last.into_inner(), Ordering::SeqCst);
+		self.lines.store(saved.lines.into_inner(), Ordering::SeqCst);
+		self.imask.store(saved.imask.into_inner(), Ordering::SeqCst);
+		self.errors.store(saved.errors.into_inner(), Ordering::SeqCst);
+		self.fmask.store(saved.fmask.into_inner(), Ordering::SeqCst);
+		self.rx_threshold.store(saved.rx_threshold.into_inner(), Ordering::SeqCst);
+		self.tx_threshold.store(saved.tx_threshold.into_inner(), Ordering::SeqCst);
+		self.control.store(saved.control.into_inner(), Ordering::SeqCst);
+		self.divisor.store(saved.divisor.into_inner(), Ordering::SeqCst);
+		self.line_control.store(saved.line_control.into_inner(), Ordering::SeqCst);
+		self.line_status.store(saved.line_status.into_inner(), Ordering::SeqCst);
+		// the depth is the machine's, not the snapshot's
+		let depth = self.depth;
+		self.set_depth(depth);
+		Ok(())
+	}
+}
+
+// a character's time on the line, for a peripheral or the null modem to take
+// after taking a word the guest sent, or before giving it one; without the
+// port's lock, which the CPU may want meanwhile
+pub fn pace(port: &Mutex<Port>) {
+	let (clock, ticks) = {
+		let p = port.lock().unwrap();
+		(Arc::clone(&p.clock), p.format().ticks())
+	};
+	if ticks != 0 {
+		clock.wait(ticks);
+	}
+}
+
+// echo peripheral: acknowledge and print every word the guest sends
+
+pub fn echo(port: Arc<Mutex<Port>>) {
+	let heart = watchdog::register("data port echo");
+	thread::spawn(move || {
+		heart.idle(false);
+		port.lock().unwrap().flag(0b00000001);
+		
+		loop {
+			heart.beat();
+			// wait for port data
+			let word = port.lock().unwrap().recv();
+			if let Some(x) = word {
+				pace(&port);
+				println!("Got data {:04X}", x);
+				port.lock().unwrap().flag(0b00000011);
+			}
+		}
+	});
+}
+
+// null modem: cross-connect the data ports of two machines. A word one guest
+// sends is passed on once the other has room for it, raising Inbound there
+// and Acknowledge at the sender; until then it waits in the transmit FIFO.
+// Each way has a thread of its own, as a line each way would.
+
+pub const READY: u8 = 0b00000001;
+pub const ACKNOWLEDGE: u8 = 0b00000010;
+pub const ERROR: u8 = 0b00000100;
+pub const INBOUND: u8 = 0b00001000;
+
+pub fn null_modem(a: Arc<Mutex<Port>>, b: Arc<Mutex<Port>>) {
+	a.lock().unwrap().flag(READY);
+	b.lock().unwrap().flag(READY);
+	cable(Arc::clone(&a), Arc::clone(&b));
+	cable(b, a);
+}
+
+// one way of the null modem; the ports are never locked together, so the two
+// ways can't deadlock
+fn cable(from: Arc<Mutex<Port>>, to: Arc<Mutex<Port>>) {
+	let heart = watchdog::register("null modem");
+	thread::spawn(move || {
+		heart.idle(false);
+		loop {
+			heart.beat();
+			let room = to.lock().unwrap().room();
+			let word = if room {
+				let from = from.lock().unwrap();
+				from.recv().map(|x| (x, from.format()))
+			} else {
+				None
+			};
+			match word {
+				Some((x, format)) => {
+					heart.idle(true);
+					pace(&from);
+					heart.idle(false);
+					let to = to.lock().unwrap();
+					to.send_framed(x, format);
+					to.raise(INBOUND);
+					drop(to);
+					from.lock().unwrap().raise(ACKNOWLEDGE);
+				},
+				None => thread::yield_now(),
+			}
+		}
+	});
+}
@@ -0,0 +1,58 @@
+use crate::bus::{Bus, BusError, Memory32};
+use crate::ram::FETCH_BLOCK;
+
+// Prefetch: instruction fetch from a buffered block of main memory
+//
+// A refill copies one aligned FETCH_BLOCK out of main memory, and sequential
+// fetches are served from it until they run off the end. A fetch that isn't
+// the one after the last (a branch, a fault, a new segment) drops the buffer,
+// as does any write to the block; Ram watches for those. Fetches outside main
+// memory go to the bus every time. Each fetch from main memory is also marked
+// there for the self-modifying code watch.
+
+const BLOCK_WORDS: usize = (FETCH_BLOCK / 4) as usize;
+
+#[derive(Default)]
+pub struct Prefetch {
+	block: Option<u32>,		// physical address of the buffered block
+	data: [u32; BLOCK_WORDS],
+	next: u32,				// physical address a sequential fetch would use
+	pub fetches: u64,
+	pub refills: u64
+}
+
+impl Prefetch {
+	// the big-endian halfword at physical address addr
+	pub fn fetch(&mut self, bus: &Bus, addr: u32) -> Result<u16, BusError> {
+		if addr != self.next {
+			self.block = None;
+		}
+		self.next = addr.wrapping_add(2);
+		self.fetches += 1;
+		
+		let (ram, offset) = match bus.ram_at(addr) {
+			Some(x) if x.1 % 2 == 0 => x,
+			_ => {
+				self.block = None;
+				return bus.read_h_big(addr);
+			},
+		};
+		ram.executed(offset);
+		let base = addr - offset % FETCH_BLOCK;
+		if self.block != Some(base) || ram.fetch_stale() {
+			match ram.fetch_block(offset) {
+				Some(words) => {
+					self.data = words;
+					self.block = Some(base);
+					self.refills += 1;
+				},
+				None => {
+					self.block = None;
+					return bus.read_h_big(addr);
+				},
+			}
+		}
+		let word = self.data[((offset % FETCH_BLOCK) / 4) as usize];
+		Ok(((word >> ((offset % 4) * 8)) as u16).swap_bytes())
+	}
+}
@@ -0,0 +1,161 @@
+use std::cell::Cell;
+use std::time::Instant;
+use serde::{Serialize, Deserialize};
+use crate::bus::{Bus, Memory32, BusError};
+
+// Profiler: samples the running program's PC into a ring in main memory
+//
+// Registers, all words:
+//   0 control	.......E enable; E....... set by the device when a sample
+//				could not be stored, which also turns sampling off
+//   4 period	clock ticks between samples (0 counts as 1)
+//   8 ring		physical address of the ring, word aligned, in main memory
+//  12 length	ring size in entries
+//  16 head		entry the next sample goes in; wraps to 0 at length
+//  20 count	samples taken, wrapping
+//  24 clock	host nanoseconds since the emulator started, low half; reading
+//				it latches the high half at 28 (both read-only)
+//
+// A tick is every TOD_INTERVAL cycles, when the CPU looks at the clock
+// comparator. Each entry is two words: the PS segment base and the PC of the
+// instruction about to run, which is the one an interrupt taken at that tick
+// would return to. The ring is overwritten oldest first; a profiler keeps its
+// own tail and can tell from count whether it fell behind.
+//
+// The clock is for calibrating guest benchmarks against real time. It isn't
+// part of the machine's state, and reads 0 with --no-host-time, so that a run
+// can be kept from depending on the host.
+
+pub const PROFILE_CONTROL: u32 = 0;
+pub const PROFILE_PERIOD: u32 = 4;
+pub const PROFILE_RING: u32 = 8;
+pub const PROFILE_LENGTH: u32 = 12;
+pub const PROFILE_HEAD: u32 = 16;
+pub const PROFILE_COUNT: u32 = 20;
+pub const PROFILE_CLOCK: u32 = 24;
+pub const PROFILE_CLOCK_HIGH: u32 = 28;
+pub const PROFILE_REGION_SIZE: u32 = 32;
+
+pub const PROFILE_ENABLE: u32 = 0b00000001;
+pub const PROFILE_ERROR: u32 = 0b10000000;
+
+const ENTRY: u32 = 8;
+
+#[derive(Serialize, Deserialize)]
+pub struct Profiler {
+	pub regs: Vec<u8>,
+	ticks: u32,		// since the last sample
+	#[serde(skip)]
+	pub clock: Option<Instant>,	// when the host clock started, or None if it's off
+	#[serde(skip)]
+	latched: Cell<u32>
+}
+
+impl Profiler {
+	pub fn new() -> Profiler {
+		Profiler {
+			regs: vec![0; PROFILE_CLOCK as usize],
+			ticks: 0,
+			clock: Some(Instant::now()),
+			latched: Cell::new(0)
+		}
+	}
+	
+	fn reg(&self, offset: u32) -> u32 {
+		self.regs.read_w(offset).unwrap()
+	}
+	
+	fn set_reg(&mut self, offset: u32, value: u32) {
+		self.regs.write_w(offset, value).unwrap();
+	}
+	
+	// called by the CPU on every clock tick; the ring is written straight to
+	// main memory, since the CPU thread already holds the bus
+	pub fn tick(&mut self, base: u32, pc: u32, bus: &Bus) {
+		let control = self.reg(PROFILE_CONTROL);
+		if control & PROFILE_ENABLE == 0 {
+			return;
+		}
+		self.ticks += 1;
+		if self.ticks < self.reg(PROFILE_PERIOD).max(1) {
+			return;
+		}
+		self.ticks = 0;
+		
+		let (length, mut head) = (self.reg(PROFILE_LENGTH), self.reg(PROFILE_HEAD));
+		if head >= length {
+			head = 0;
+		}
+		let addr = self.reg(PROFILE_RING).wrapping_add(head.wrapping_mul(ENTRY));
+		let stored = length != 0 && match bus.ram_at(addr) {
+			Some((ram, offset)) => ram.write_w(offset, base).and_then(|_| ram.write_w(offset + 4, pc)).is_ok(),
+			None => false,
+		};
+		if stored {
+			self.set_reg(PROFILE_HEAD, if head + 1 >= length { 0 } else { head + 1 });
+			let count = self.reg(PROFILE_COUNT);
+			self.set_reg(PROFILE_COUNT, count.wrapping_add(1));
+		} else {
+			self.set_reg(PROFILE_CONTROL, (control & !PROFILE_ENABLE) | PROFILE_ERROR);
+		}
+	}
+}
+
+impl Memory32<u32, BusError> for Profiler {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.regs.read_b(addr)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h(addr)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h_big(addr)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		match addr {
+			PROFILE_CLOCK => {
+				let nanos = self.clock.map_or(0, |x| x.elapsed().as_nanos() as u64);
+				self.latched.set((nanos >> 32) as u32);
+				Ok(nanos as u32)
+			},
+			PROFILE_CLOCK_HIGH => Ok(self.latched.get()),
+			_ => self.regs.read_w(addr),
+		}
+	}
+	
+	// turning sampling on or off starts the period over
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		self.regs.write_b(addr, data)?;
+		if addr < PROFILE_CONTROL + 4 {
+			self.ticks = 0;
+		}
+		Ok(())
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		self.regs.write_h(addr, data)?;
+		if addr < PROFILE_CONTROL + 4 {
+			self.ticks = 0;
+		}
+		Ok(())
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		self.regs.write_w(addr, data)?;
+		if addr == PROFILE_CONTROL {
+			self.ticks = 0;
+		}
+		Ok(())
+	}
+	
+	fn save_state(&self) -> Option<Vec<u8>> {
+		bincode::serialize(self).ok()
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		match bincode::deserialize(state) {
+			Err(_) => Err(BusError::InvalidState),
+			Ok(x) => {
+				*self = Profiler { clock: self.clock, ..x };
+				Ok(())
+			},
+		}
+	}
+}
@@ -0,0 +1,291 @@
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use crate::bus::{Memory32, BusError};
+
+// Ram: main memory the bus can reach without taking a region lock
+//
+// Contents are little-endian words in relaxed atomics, so any number of
+// holders can share one Ram through an Arc. Whoever owns the bus (the CPU, or
+// a DMA channel it has granted) is the only writer, and handing the bus over
+// already orders their accesses; byte and halfword writes are a plain
+// load/merge/store of the word for the same reason.
+//
+// Ram also watches the block held in the CPU's prefetch buffer, so a write to
+// it from anywhere (a store, DMA, semihosting) marks the buffer stale.
+//
+// In taint mode a shadow bit per byte records whether it has ever been
+// written. Reading a byte that hasn't either fails the access (so the CPU
+// takes a read fault) or is noted for the CPU to warn about after the
+// instruction. Bulk copies for snapshots and host services aren't checked.
+//
+// In self-modifying code mode a second shadow bit per halfword records
+// whether it has been fetched as an instruction since it was last written.
+// A store to one either fails (a write fault) or is noted for the CPU to warn
+// about, like an uninitialized read; the bits are cleared by the store, so
+// code written again only counts once it has been run again.
+
+pub const FETCH_BLOCK: u32 = 16;
+
+const NO_BLOCK: u32 = u32::MAX;
+const NO_TAINT: u32 = u32::MAX;
+const NO_STORE: u32 = u32::MAX;
+
+pub struct Ram {
+	words: Vec<AtomicU32>,
+	size: u32,
+	fetched: AtomicU32,		// offset of the prefetched block, or NO_BLOCK
+	stale: AtomicBool,
+	shadow: OnceLock<Vec<AtomicU32>>,	// taint mode: bit set once the byte is written
+	taint_fault: AtomicBool,
+	tainted: AtomicU32,		// first uninitialized offset read since last taken, or NO_TAINT
+	code: OnceLock<Vec<AtomicU32>>,	// self-modifying code mode: bit set once the halfword is fetched
+	code_fault: AtomicBool,
+	modified: AtomicU32		// first fetched offset stored to since last taken, or NO_STORE
+}
+
+impl Ram {
+	// storage is rounded up to a whole word
+	pub fn new(size: u32) -> Ram {
+		Ram {
+			words: (0..(size as usize + 3) / 4).map(|_| AtomicU32::new(0)).collect(),
+			size: size,
+			fetched: AtomicU32::new(NO_BLOCK),
+			stale: AtomicBool::new(false),
+			shadow: OnceLock::new(),
+			taint_fault: AtomicBool::new(false),
+			tainted: AtomicU32::new(NO_TAINT),
+			code: OnceLock::new(),
+			code_fault: AtomicBool::new(false),
+			modified: AtomicU32::new(NO_STORE)
+		}
+	}
+	
+	// start taint mode with every byte uninitialized; only the first call counts
+	pub fn track_taint(&self, fault: bool) {
+		self.taint_fault.store(fault, Ordering::Relaxed);
+		let _ = self.shadow.set((0..(self.size as usize + 31) / 32).map(|_| AtomicU32::new(0)).collect());
+	}
+	
+	pub fn take_taint(&self) -> Option<u32> {
+		match self.tainted.swap(NO_TAINT, Ordering::Relaxed) {
+			NO_TAINT => None,
+			x => Some(x),
+		}
+	}
+	
+	fn taint_check(&self, addr: u32, width: u32) -> Result<(), BusError> {
+		let shadow = match self.shadow.get() {
+			Some(x) => x,
+			None => return Ok(()),
+		};
+		let bits = shadow[(addr / 32) as usize].load(Ordering::Relaxed) >> (addr % 32);
+		if bits & ((1 << width) - 1) == (1 << width) - 1 {
+			return Ok(());
+		}
+		if self.taint_fault.load(Ordering::Relaxed) {
+			Err(BusError::InvalidState)
+		} else {
+			let _ = self.tainted.compare_exchange(NO_TAINT, addr, Ordering::Relaxed, Ordering::Relaxed);
+			Ok(())
+		}
+	}
+	
+	// start watching for stores into fetched code; only the first call counts
+	pub fn track_code(&self, fault: bool) {
+		self.code_fault.store(fault, Ordering::Relaxed);
+		let _ = self.code.set((0..(self.size as usize + 63) / 64).map(|_| AtomicU32::new(0)).collect());
+	}
+	
+	pub fn take_modified(&self) -> Option<u32> {
+		match self.modified.swap(NO_STORE, Ordering::Relaxed) {
+			NO_STORE => None,
+			x => Some(x),
+		}
+	}
+	
+	// the instruction halfword at addr has been fetched
+	pub fn executed(&self, addr: u32) {
+		if let Some(code) = self.code.get() {
+			code[(addr / 64) as usize].fetch_or(1 << (addr / 2 % 32), Ordering::Relaxed);
+		}
+	}
+	
+	fn code_check(&self, addr: u32, width: u32) -> Result<(), BusError> {
+		let code = match self.code.get() {
+			Some(x) => x,
+			None => return Ok(()),
+		};
+		let mask = ((1 << ((width + 1) / 2)) - 1) << (addr / 2 % 32);
+		let cell = &code[(addr / 64) as usize];
+		if cell.load(Ordering::Relaxed) & mask == 0 {
+			return Ok(());
+		}
+		if self.code_fault.load(Ordering::Relaxed) {
+			return Err(BusError::InvalidState);
+		}
+		cell.fetch_and(!mask, Ordering::Relaxed);
+		let _ = self.modified.compare_exchange(NO_STORE, addr, Ordering::Relaxed, Ordering::Relaxed);
+		Ok(())
+	}
+	
+	pub fn size(&self) -> u32 {
+		self.size
+	}
+	
+	fn check(&self, addr: u32, width: u32) -> Result<(), BusError> {
+		if addr.checked_add(width).map_or(true, |end| end > self.size) {
+			Err(BusError::InvalidAddress)
+		} else if addr % width != 0 {
+			Err(BusError::AlignmentCheck)
+		} else {
+			Ok(())
+		}
+	}
+	
+	fn word(&self, addr: u32) -> u32 {
+		self.words[(addr / 4) as usize].load(Ordering::Relaxed)
+	}
+	
+	// accesses are aligned, so the bits for one never straddle shadow words
+	fn written(&self, addr: u32, width: u32) {
+		if addr & !(FETCH_BLOCK - 1) == self.fetched.load(Ordering::Relaxed) {
+			self.stale.store(true, Ordering::Relaxed);
+		}
+		if let Some(shadow) = self.shadow.get() {
+			shadow[(addr / 32) as usize].fetch_or(((1 << width) - 1) << (addr % 32), Ordering::Relaxed);
+		}
+	}
+	
+	fn merge(&self, addr: u32, mask: u32, data: u32) {
+		self.written(addr, if mask == 0xFF { 1 } else { 2 });
+		let shift = (addr % 4) * 8;
+		let cell = &self.words[(addr / 4) as usize];
+		let old = cell.load(Ordering::Relaxed);
+		cell.store((old & !(mask << shift)) | ((data & mask) << shift), Ordering::Relaxed);
+	}
+	
+	pub fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.check(addr, 1)?;
+		self.taint_check(addr, 1)?;
+		Ok((self.word(addr) >> ((addr % 4) * 8)) as u8)
+	}
+	pub fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.check(addr, 2)?;
+		self.taint_check(addr, 2)?;
+		Ok((self.word(addr) >> ((addr % 4) * 8)) as u16)
+	}
+	pub fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		self.read_h(addr).map(|x| x.swap_bytes())
+	}
+	pub fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		self.check(addr, 4)?;
+		self.taint_check(addr, 4)?;
+		Ok(self.word(addr))
+	}
+	
+	pub fn write_b(&self, addr: u32, data: u8) -> Result<(), BusError> {
+		self.check(addr, 1)?;
+		self.code_check(addr, 1)?;
+		self.merge(addr, 0xFF, data as u32);
+		Ok(())
+	}
+	pub fn write_h(&self, addr: u32, data: u16) -> Result<(), BusError> {
+		self.check(addr, 2)?;
+		self.code_check(addr, 2)?;
+		self.merge(addr, 0xFFFF, data as u32);
+		Ok(())
+	}
+	pub fn write_w(&self, addr: u32, data: u32) -> Result<(), BusError> {
+		self.check(addr, 4)?;
+		self.code_check(addr, 4)?;
+		self.written(addr, 4);
+		self.words[(addr / 4) as usize].store(data, Ordering::Relaxed);
+		Ok(())
+	}
+	
+	// len bytes from addr, or None if any of them is out of range
+	pub fn read_bytes(&self, addr: u32, len: u32) -> Option<Vec<u8>> {
+		let end = addr.checked_add(len)?;
+		if end > self.size {
+			return None;
+		}
+		Some((addr..end).map(|a| (self.word(a) >> ((a % 4) * 8)) as u8).collect())
+	}
+	
+	pub fn write_bytes(&self, addr: u32, data: &[u8]) -> bool {
+		match addr.checked_add(data.len() as u32) {
+			Some(end) if end <= self.size => {
+				for (a, b) in (addr..end).zip(data) {
+					self.merge(a, 0xFF, *b as u32);
+				}
+				true
+			},
+			_ => false,
+		}
+	}
+	
+	// copy out the aligned block holding addr and watch it for writes; None if
+	// the block runs past the end
+	pub fn fetch_block(&self, addr: u32) -> Option<[u32; (FETCH_BLOCK / 4) as usize]> {
+		let block = addr & !(FETCH_BLOCK - 1);
+		if block.checked_add(FETCH_BLOCK).map_or(true, |end| end > self.size) {
+			return None;
+		}
+		self.fetched.store(block, Ordering::Relaxed);
+		self.stale.store(false, Ordering::Relaxed);
+		let first = (block / 4) as usize;
+		let mut words = [0; (FETCH_BLOCK / 4) as usize];
+		for (n, w) in words.iter_mut().enumerate() {
+			*w = self.words[first + n].load(Ordering::Relaxed);
+		}
+		Some(words)
+	}
+	
+	// true once the watched block has been written
+	pub fn fetch_stale(&self) -> bool {
+		self.stale.load(Ordering::Relaxed)
+	}
+	
+	pub fn to_vec(&self) -> Vec<u8> {
+		self.read_bytes(0, self.size).unwrap()
+	}
+}
+
+// attached to the bus as a region too, so snapshots and the region list see it
+
+impl Memory32<u32, BusError> for Arc<Ram> {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		Ram::read_b(self, addr)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		Ram::read_h(self, addr)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		Ram::read_h_big(self, addr)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		Ram::read_w(self, addr)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		Ram::write_b(self, addr, data)
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		Ram::write_h(self, addr, data)
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		Ram::write_w(self, addr, data)
+	}
+	
+	fn save_state(&self) -> Option<Vec<u8>> {
+		Some(self.to_vec())
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		if state.len() != self.size as usize {
+			Err(BusError::InvalidState)
+		} else {
+			self.write_bytes(0, state);
+			Ok(())
+		}
+	}
+}
@@ -0,0 +1,224 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use crate::sync::Mutex;
+use crate::bus::{BusError, Memory32};
+
+// Remote: a bus region whose device lives in another process, over TCP
+//
+// Every message in either direction is a u32 length and then that many bytes;
+// numbers are little-endian. The client opens with "SQRB" and the server
+// answers with the size of its region. Then, one request at a time:
+//   'R' width addr			read 1, 2 or 4 bytes; answered status, value (u32)
+//   'W' width addr value	write; answered status
+//   'S'					save state; answered status, then the state if any
+//   'L' state				load state; answered status
+// Status is 0 for done, or 1, 2, 3 for the bus errors InvalidAddress,
+// AlignmentCheck and InvalidState. Every access waits for its answer, so a
+// slow server holds up the CPU; once the connection fails every access is
+// InvalidState. A message longer than the region plus FRAME_SLACK is taken as
+// a broken or hostile peer, and ends the connection.
+
+const REMOTE_MAGIC: &[u8; 4] = b"SQRB";
+
+// room past the region's size for a request's header, and for whatever state
+// a device saves beyond its registers
+const FRAME_SLACK: u64 = 0x10000;
+
+const OK: u8 = 0;
+const INVALID_ADDRESS: u8 = 1;
+const ALIGNMENT_CHECK: u8 = 2;
+const INVALID_STATE: u8 = 3;
+
+fn send(mut stream: &TcpStream, data: &[u8]) -> io::Result<()> {
+	stream.write_all(&(data.len() as u32).to_le_bytes())?;
+	stream.write_all(data)?;
+	stream.flush()
+}
+
+fn recv(mut stream: &TcpStream, limit: u64) -> io::Result<Vec<u8>> {
+	let mut len = [0 as u8; 4];
+	stream.read_exact(&mut len)?;
+	let len = u32::from_le_bytes(len) as u64;
+	if len > limit {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, format!("message of {} bytes is too large", len)));
+	}
+	// grown as the bytes arrive, not allocated up front on the peer's word
+	let mut data = Vec::new();
+	stream.take(len).read_to_end(&mut data)?;
+	if data.len() as u64 != len {
+		return Err(io::ErrorKind::UnexpectedEof.into());
+	}
+	Ok(data)
+}
+
+fn frame_limit(size: u32) -> u64 {
+	size as u64 + FRAME_SLACK
+}
+
+fn word(data: &[u8], at: usize) -> Option<u32> {
+	data.get(at..at + 4).map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+}
+
+fn status<T>(result: &Result<T, BusError>) -> u8 {
+	match result {
+		Ok(_) => OK,
+		Err(BusError::InvalidAddress) => INVALID_ADDRESS,
+		Err(BusError::AlignmentCheck) => ALIGNMENT_CHECK,
+		Err(BusError::InvalidState) => INVALID_STATE,
+	}
+}
+
+fn error(status: u8) -> BusError {
+	match status {
+		INVALID_ADDRESS => BusError::InvalidAddress,
+		ALIGNMENT_CHECK => BusError::AlignmentCheck,
+		_ => BusError::InvalidState,
+	}
+}
+
+// RemoteRegion: the client, attached to the bus like any other region
+
+pub struct RemoteRegion {
+	stream: TcpStream,
+	pub size: u32,
+	lost: AtomicBool
+}
+
+impl RemoteRegion {
+	pub fn connect(addr: &str) -> io::Result<RemoteRegion> {
+		let stream = TcpStream::connect(addr)?;
+		stream.set_nodelay(true)?;
+		send(&stream, REMOTE_MAGIC)?;
+		let size = word(&recv(&stream, 4)?, 0)
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a remote region server"))?;
+		Ok(RemoteRegion { stream: stream, size: size, lost: AtomicBool::new(false) })
+	}
+	
+	// one request and its answer, status byte first
+	fn request(&self, data: &[u8]) -> Result<Vec<u8>, BusError> {
+		if self.lost.load(Ordering::Relaxed) {
+			return Err(BusError::InvalidState);
+		}
+		match send(&self.stream, data).and_then(|_| recv(&self.stream, frame_limit(self.size))) {
+			Ok(answer) => match answer.first() {
+				Some(&OK) => Ok(answer[1..].to_vec()),
+				Some(&x) => Err(error(x)),
+				None => Err(BusError::InvalidState),
+			},
+			Err(_) => {
+				self.lost.store(true, Ordering::Relaxed);
+				Err(BusError::InvalidState)
+			},
+		}
+	}
+	
+	fn read(&self, addr: u32, width: u8) -> Result<u32, BusError> {
+		let mut data = vec![b'R', width];
+		data.extend_from_slice(&addr.to_le_bytes());
+		word(&self.request(&data)?, 0).ok_or(BusError::InvalidState)
+	}
+	
+	fn write(&self, addr: u32, width: u8, value: u32) -> Result<(), BusError> {
+		let mut data = vec![b'W', width];
+		data.extend_from_slice(&addr.to_le_bytes());
+		data.extend_from_slice(&value.to_le_bytes());
+		self.request(&data).map(|_| ())
+	}
+}
+
+impl Memory32<u32, BusError> for RemoteRegion {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.read(addr, 1).map(|x| x as u8)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.read(addr, 2).map(|x| x as u16)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		self.read(addr, 2).map(|x| (x as u16).swap_bytes())
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		self.read(addr, 4)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		self.write(addr, 1, data as u32)
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		self.write(addr, 2, data as u32)
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		self.write(addr, 4, data)
+	}
+	
+	fn save_state(&self) -> Option<Vec<u8>> {
+		self.request(b"S").ok().filter(|x| !x.is_empty())
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		let mut data = vec![b'L'];
+		data.extend_from_slice(state);
+		self.request(&data).map(|_| ())
+	}
+}
+
+// the server side: answer clients, each on its own thread, from a region
+// that stays in this process
+
+pub fn serve(addr: &str, size: u32, region: Arc<Mutex<dyn Memory32<u32, BusError> + Send>>) -> io::Result<()> {
+	let listener = TcpListener::bind(addr)?;
+	thread::spawn(move || {
+		for stream in listener.incoming().flatten() {
+			let region = Arc::clone(&region);
+			thread::spawn(move || {
+				stream.set_nodelay(true).ok();
+				match recv(&stream, REMOTE_MAGIC.len() as u64) {
+					Ok(magic) if magic == REMOTE_MAGIC => { },
+					_ => return,
+				}
+				if send(&stream, &size.to_le_bytes()).is_err() {
+					return;
+				}
+				while let Ok(request) = recv(&stream, frame_limit(size)) {
+					if send(&stream, &answer(&region, &request)).is_err() {
+						return;
+					}
+				}
+			});
+		}
+	});
+	Ok(())
+}
+
+fn answer(region: &Arc<Mutex<dyn Memory32<u32, BusError> + Send>>, request: &[u8]) -> Vec<u8> {
+	let mut mem = region.lock().unwrap();
+	let (width, addr) = (request.get(1).cloned().unwrap_or(0), word(request, 2).unwrap_or(0));
+	let (code, mut data) = match request.first() {
+		Some(b'R') => {
+			let value = match width {
+				1 => mem.read_b(addr).map(|x| x as u32),
+				2 => mem.read_h(addr).map(|x| x as u32),
+				4 => mem.read_w(addr),
+				_ => Err(BusError::InvalidState),
+			};
+			(status(&value), value.unwrap_or(0).to_le_bytes().to_vec())
+		},
+		Some(b'W') => {
+			let value = word(request, 6).unwrap_or(0);
+			let result = match width {
+				1 => mem.write_b(addr, value as u8),
+				2 => mem.write_h(addr, value as u16),
+				4 => mem.write_w(addr, value),
+				_ => Err(BusError::InvalidState),
+			};
+			(status(&result), Vec::new())
+		},
+		Some(b'S') => (OK, mem.save_state().unwrap_or_default()),
+		Some(b'L') => (status(&mem.load_state(&request[1..])), Vec::new()),
+		_ => (INVALID_STATE, Vec::new()),
+	};
+	let mut out = vec![code];
+	out.append(&mut data);
+	out
+}
@@ -0,0 +1,45 @@
+use crate::bus::{Memory32, BusError};
+
+// Rom: read-only memory holding built-in firmware
+
+pub struct Rom {
+	data: Vec<u8>
+}
+
+impl Rom {
+	// contents are zero padded out to size
+	pub fn new(contents: &[u8], size: u32) -> Rom {
+		let mut data = contents.to_vec();
+		data.resize(size as usize, 0);
+		Rom { data: data }
+	}
+}
+
+impl Memory32<u32, BusError> for Rom {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.data.read_b(addr)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.data.read_h(addr)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		self.data.read_h_big(addr)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		self.data.read_w(addr)
+	}
+	
+	fn write_b(&mut self, _addr: u32, _data: u8) -> Result<(), BusError> {
+		Err(BusError::InvalidAddress)
+	}
+	fn write_h(&mut self, _addr: u32, _data: u16) -> Result<(), BusError> {
+		Err(BusError::InvalidAddress)
+	}
+	fn write_w(&mut self, _addr: u32, _data: u32) -> Result<(), BusError> {
+		Err(BusError::InvalidAddress)
+	}
+	
+	fn idempotent(&self, _addr: u32) -> bool {
+		true
+	}
+}
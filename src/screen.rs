@@ -0,0 +1,197 @@
+use std::fs;
+use std::io;
+use crate::bus::{Memory32, BusError};
+use crate::font;
+
+// Screen: the text display, and the font ROM its character generator reads
+//
+// Registers: control at 0 (byte: bit 0 turns the display on, bit 1 shows the
+// cursor), the cursor's cell at 2 (halfword, row * COLUMNS + column), and the
+// columns and rows at 4 and 5 (bytes, read-only). The cells follow from
+// CELLS, a halfword for each, row by row: the character in the low byte and
+// its attributes in the high byte, INVERSE and BLINK. The character picks
+// one of 256 glyphs in the font ROM, eight bytes each, top row first, bit 7
+// of a row its leftmost pixel. The font ROM is a region of its own the guest
+// can read; it holds the window's ASCII glyphs unless --display-font loads
+// another. Blinking characters and the cursor flash at BLINK_MS.
+//
+// The window draws the screen when it is open (window.rs), and the monitor
+// and --http can take pictures of it (capture.rs).
+
+pub const COLUMNS: usize = 80;
+pub const ROWS: usize = 24;
+
+pub const CELLS: u32 = 0x100;
+pub const SCREEN_REGION_SIZE: u32 = CELLS + (COLUMNS * ROWS * 2) as u32;
+
+pub const GLYPH_ROWS: usize = 8;
+pub const FONT_SIZE: u32 = 256 * GLYPH_ROWS as u32;
+
+pub const WIDTH: usize = COLUMNS * 8;
+pub const HEIGHT: usize = ROWS * GLYPH_ROWS;
+
+pub const DISPLAY_ON: u8 = 0b01;
+pub const CURSOR_ON: u8 = 0b10;
+
+pub const INVERSE: u8 = 0b01;
+pub const BLINK: u8 = 0b10;
+
+pub const BLINK_MS: u128 = 500;
+
+// green on black
+pub const LIT: u32 = 0x60F090;
+pub const DARK: u32 = 0x000000;
+
+const REGS: usize = 6;
+
+// the font ROM as built: the printable ASCII characters, the rest blank
+pub fn builtin_font() -> Vec<u8> {
+	let mut out = vec![0 as u8; FONT_SIZE as usize];
+	for c in ' '..='~' {
+		for (n, row) in font::glyph(c).iter().enumerate() {
+			out[c as usize * GLYPH_ROWS + n] = row.reverse_bits();
+		}
+	}
+	out
+}
+
+// a font ROM image from a file, exactly FONT_SIZE bytes
+pub fn load_font(path: &str) -> io::Result<Vec<u8>> {
+	let data = fs::read(path)?;
+	if data.len() != FONT_SIZE as usize {
+		return Err(io::Error::new(io::ErrorKind::InvalidData, format!("a font is {} bytes, not {}", FONT_SIZE, data.len())));
+	}
+	Ok(data)
+}
+
+pub struct Screen {
+	regs: Vec<u8>,
+	cells: Vec<u8>,
+	pub font: Vec<u8>		// what the font ROM holds
+}
+
+impl Screen {
+	pub fn new() -> Screen {
+		let mut regs = vec![0 as u8; REGS];
+		regs[4] = COLUMNS as u8;
+		regs[5] = ROWS as u8;
+		Screen {
+			regs: regs,
+			cells: vec![0 as u8; COLUMNS * ROWS * 2],
+			font: builtin_font()
+		}
+	}
+	
+	pub fn on(&self) -> bool {
+		self.regs[0] & DISPLAY_ON != 0
+	}
+	
+	// the screen as WIDTH by HEIGHT pixels, LIT or DARK; blinking characters
+	// and the cursor show only while blink is on
+	pub fn render(&self, blink: bool) -> Vec<u32> {
+		let mut out = vec![DARK; WIDTH * HEIGHT];
+		if !self.on() {
+			return out;
+		}
+		let cursor = if self.regs[0] & CURSOR_ON != 0 && blink {
+			Some(self.regs.read_h(2).unwrap() as usize)
+		} else {
+			None
+		};
+		for n in 0..COLUMNS * ROWS {
+			let (c, attr) = (self.cells[2 * n] as usize, self.cells[2 * n + 1]);
+			let (x0, y0) = (n % COLUMNS * 8, n / COLUMNS * GLYPH_ROWS);
+			for y in 0..GLYPH_ROWS {
+				let mut bits = if attr & BLINK != 0 && !blink { 0 } else { self.font[c * GLYPH_ROWS + y] };
+				if attr & INVERSE != 0 {
+					bits = !bits;
+				}
+				// an underline
+				if cursor == Some(n) && y == GLYPH_ROWS - 1 {
+					bits = !bits;
+				}
+				for x in 0..8 {
+					if bits & (0x80 >> x) != 0 {
+						out[(y0 + y) * WIDTH + x0 + x] = LIT;
+					}
+				}
+			}
+		}
+		out
+	}
+	
+	// the columns and rows registers are read-only
+	fn check_write(addr: u32, len: u32) -> Result<(), BusError> {
+		if addr < 6 && addr + len > 4 {
+			return Err(BusError::InvalidAddress);
+		}
+		Ok(())
+	}
+}
+
+impl Memory32<u32, BusError> for Screen {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		if addr >= CELLS {
+			return self.cells.read_b(addr - CELLS);
+		}
+		self.regs.read_b(addr)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		if addr >= CELLS {
+			return self.cells.read_h(addr - CELLS);
+		}
+		self.regs.read_h(addr)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		if addr >= CELLS {
+			return self.cells.read_h_big(addr - CELLS);
+		}
+		self.regs.read_h_big(addr)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		if addr >= CELLS {
+			return self.cells.read_w(addr - CELLS);
+		}
+		self.regs.read_w(addr)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		if addr >= CELLS {
+			return self.cells.write_b(addr - CELLS, data);
+		}
+		Screen::check_write(addr, 1)?;
+		self.regs.write_b(addr, data)
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		if addr >= CELLS {
+			return self.cells.write_h(addr - CELLS, data);
+		}
+		Screen::check_write(addr, 2)?;
+		self.regs.write_h(addr, data)
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		if addr >= CELLS {
+			return self.cells.write_w(addr - CELLS, data);
+		}
+		Screen::check_write(addr, 4)?;
+		self.regs.write_w(addr, data)
+	}
+	
+	// the font is the ROM's, not state
+	fn save_state(&self) -> Option<Vec<u8>> {
+		bincode::serialize(&(&self.regs, &self.cells)).ok()
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		let (regs, cells): (Vec<u8>, Vec<u8>) = bincode::deserialize(state).map_err(|_| BusError::InvalidState)?;
+		if regs.len() != self.regs.len() || cells.len() != self.cells.len() {
+			return Err(BusError::InvalidState);
+		}
+		self.regs = regs;
+		self.cells = cells;
+		Ok(())
+	}
+	
+	fn idempotent(&self, _addr: u32) -> bool {
+		true
+	}
+}
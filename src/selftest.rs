@@ -0,0 +1,84 @@
+use std::env;
+use std::process::Command;
+use crate::batch::{EXIT_LIMIT, EXIT_HOST_ERROR};
+use crate::machine::{EXAMPLES, SELFTESTS};
+
+// Selftest: run the built-in guest conformance tests and report on each
+//
+// The tests are the programs under selftest/ and the examples, assembled by
+// the build; each halts with R1 = 0 if it passed, else the number of the check
+// that failed, and runs in batch. After them the tod test runs again under
+// --dma-storm, which must not keep it from halting, the firmware loader boots
+// a deck typed in on the operator console, and --check-decode holds the CPU's
+// decoder to the ISA table. Every test runs in a process of its own, so one
+// that hangs or crashes the emulator is reported and the rest still run, with
+// whatever other options the command line gave: --random-layout, --port-depth
+// and the like check the guest-visible behaviour they change.
+
+pub const EXIT_FAILED: i32 = 1;
+
+// the wall-clock limit on the DMA storm run, so a CPU starved of the bus is
+// reported rather than hanging the suite
+const STORM_SECONDS: &str = "30";
+
+// the deck the console boot loads: MV 9, 0 and HLT at 0x1000, then its entry.
+// The loader halts with a column's character or a device type in R9 wherever
+// it gives up, so only the loaded program passes.
+const BOOT_DECK: [&str; 2] = ["D00001000060090FFFF0000", "E00001000"];
+
+// what a test's exit status says about it
+fn verdict(code: Option<i32>) -> Option<String> {
+	match code {
+		Some(0) => None,
+		Some(EXIT_LIMIT) => Some("CYCLE OR TIME LIMIT EXCEEDED".to_string()),
+		Some(EXIT_HOST_ERROR) => Some("COULD NOT BE SET UP".to_string()),
+		Some(n) => Some(format!("CHECK {} FAILED", n)),
+		None => Some("EMULATOR CRASHED".to_string()),
+	}
+}
+
+pub fn run(args: &[String]) -> i32 {
+	let exe = match env::current_exe() {
+		Ok(x) => x,
+		Err(e) => {
+			println!("SELFTEST: {}", e);
+			return EXIT_HOST_ERROR;
+		},
+	};
+	let options: Vec<&String> = args[1..].iter().filter(|x| *x != "--selftest").collect();
+	let guest = |flag, name| (name, vec!["--batch", "--exit-reg", "1", flag, name]);
+	let tests = SELFTESTS.iter().map(|x| guest("--selftest-case", x.0))
+		.chain(EXAMPLES.iter().map(|x| guest("--example", x.0)))
+		.chain([
+			("tod --dma-storm", vec!["--batch", "--exit-reg", "1", "--dma-storm", "--max-seconds", STORM_SECONDS, "--selftest-case", "tod"]),
+			("boot --switches 1", vec!["--batch", "--exit-reg", "9", "--boot", "--switches", "1", "--reply", BOOT_DECK[0], "--reply", BOOT_DECK[1]]),
+			("decode", vec!["--check-decode"]),
+		]);
+	
+	let mut failed = 0;
+	let mut total = 0;
+	for (name, test) in tests {
+		total += 1;
+		let out = Command::new(&exe).args(&options).args(&test).output();
+		let why = match &out {
+			Ok(x) => verdict(x.status.code()),
+			Err(e) => Some(e.to_string()),
+		};
+		match why {
+			None => println!("SELFTEST: PASS {}", name),
+			Some(why) => {
+				failed += 1;
+				println!("SELFTEST: FAIL {}: {}", name, why);
+				// what the test printed, to show what went wrong
+				if let Ok(x) = &out {
+					for line in String::from_utf8_lossy(&x.stdout).lines() {
+						println!("  {}", line);
+					}
+				}
+			},
+		}
+	}
+	
+	println!("SELFTEST: {} PASSED, {} FAILED", total - failed, failed);
+	if failed == 0 { 0 } else { EXIT_FAILED }
+}
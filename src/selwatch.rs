@@ -0,0 +1,39 @@
+use std::collections::{BTreeSet, HashSet};
+use crate::bus::{Bus, Memory32};
+
+// SelectorWatch: a debugging check on every descriptor SSEL and SSELHC load
+//
+// Records which descriptor table entries are ever loaded, and warns on the
+// host about a selector past the end of the table (which faults) or an entry
+// that looks like garbage: an empty segment, or a base with nothing attached
+// there. A table length set too long otherwise just reads whatever follows
+// the table as descriptors. Each entry of each table is warned about once.
+
+#[derive(Default)]
+pub struct SelectorWatch {
+	pub loaded: BTreeSet<u8>,
+	warned: HashSet<(u32, u8)>		// (table base, selector)
+}
+
+impl SelectorWatch {
+	// why the descriptor selector names looks wrong, the first time it does
+	pub fn check(&mut self, bus: &Bus, table: u32, len: u8, selector: u8) -> Option<String> {
+		let why = if selector > len {
+			Some(format!("OUTSIDE THE TABLE OF {} ENTRIES AT 0x{:08X}", len as u32 + 1, table))
+		} else {
+			self.loaded.insert(selector);
+			let entry = table + 12 * selector as u32;
+			match (bus.read_w(entry), bus.read_w(entry + 4)) {
+				(Ok(base), Ok(limit)) if limit <= base =>
+					Some(format!("IS EMPTY (0x{:08X}->0x{:08X})", base, limit)),
+				(Ok(base), Ok(_)) if !bus.regions().iter().any(|&(b, size)| base >= b && base - b < size) =>
+					Some(format!("HAS NOTHING ATTACHED AT ITS BASE 0x{:08X}", base)),
+				_ => None,
+			}
+		};
+		match why {
+			Some(x) if self.warned.insert((table, selector)) => Some(x),
+			_ => None,
+		}
+	}
+}
@@ -0,0 +1,177 @@
+use std::fs;
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::bus::{Memory32, BusError};
+use crate::ram::Ram;
+use crate::transcript;
+
+// Semihost: host services for guest test programs, disabled unless asked for
+//
+// Registers (words): function at 0, arguments at 4, 8 and 12, result at 16.
+// Storing a function number performs the call at once; the result is a byte
+// count or -1 on failure. Guest buffers are physical addresses in main memory.
+//
+//   1 WRITE     write arg1 bytes at arg0 to the host's stdout
+//   2 READFILE  read the file named by the NUL-terminated string at arg0 into
+//               arg1, at most arg2 bytes
+//   3 GETARGS   copy the guest arguments, space separated and NUL-terminated,
+//               to arg0, at most arg1 bytes
+//   4 EXIT      stop the machine with exit status arg0
+
+pub const SEMIHOST_REGION_SIZE: u32 = 20;
+
+pub const SH_WRITE: u32 = 1;
+pub const SH_READFILE: u32 = 2;
+pub const SH_GETARGS: u32 = 3;
+pub const SH_EXIT: u32 = 4;
+
+const SH_FAIL: u32 = 0xFFFFFFFF;
+
+pub struct Semihost {
+	pub enabled: bool,
+	pub args: Vec<String>,
+	pub exit_code: Option<i32>,
+	
+	regs: Vec<u8>,
+	ram: Arc<Ram>,
+	running: Arc<AtomicBool>
+}
+
+impl Semihost {
+	pub fn new(ram: Arc<Ram>, running: Arc<AtomicBool>) -> Semihost {
+		Semihost {
+			enabled: false,
+			args: Vec::new(),
+			exit_code: None,
+			
+			regs: vec![0 as u8; SEMIHOST_REGION_SIZE as usize],
+			ram: ram,
+			running: running
+		}
+	}
+	
+	fn arg(&self, n: u32) -> u32 {
+		self.regs.read_w(4 + 4 * n).unwrap()
+	}
+	
+	fn guest_bytes(&self, addr: u32, len: u32) -> Option<Vec<u8>> {
+		self.ram.read_bytes(addr, len)
+	}
+	
+	fn guest_string(&self, addr: u32) -> Option<String> {
+		let len = (addr..self.ram.size()).position(|a| matches!(self.ram.read_b(a), Ok(0)))?;
+		String::from_utf8(self.ram.read_bytes(addr, len as u32)?).ok()
+	}
+	
+	// copy to guest memory, truncated to max bytes; returns bytes copied
+	fn put_guest(&self, addr: u32, data: &[u8], max: u32) -> u32 {
+		let len = data.len().min(max as usize);
+		if self.ram.write_bytes(addr, &data[..len]) {
+			len as u32
+		} else {
+			SH_FAIL
+		}
+	}
+	
+	fn call(&mut self, function: u32) -> u32 {
+		match function {
+			SH_WRITE => {
+				match self.guest_bytes(self.arg(0), self.arg(1)) {
+					Some(data) => {
+						let mut out = io::stdout().lock();
+						if out.write_all(&data).and_then(|_| out.flush()).is_err() {
+							return SH_FAIL;
+						}
+						transcript::record("GUEST", &String::from_utf8_lossy(&data));
+						data.len() as u32
+					},
+					None => SH_FAIL,
+				}
+			},
+			SH_READFILE => {
+				let data = match self.guest_string(self.arg(0)) {
+					Some(path) => fs::read(path),
+					None => return SH_FAIL,
+				};
+				match data {
+					Ok(x) => self.put_guest(self.arg(1), &x, self.arg(2)),
+					Err(_) => SH_FAIL,
+				}
+			},
+			SH_GETARGS => {
+				let max = self.arg(1);
+				if max == 0 {
+					return SH_FAIL;
+				}
+				let mut data = self.args.join(" ").into_bytes();
+				data.truncate(max as usize - 1);
+				data.push(0);
+				self.put_guest(self.arg(0), &data, max)
+			},
+			SH_EXIT => {
+				self.exit_code = Some(self.arg(0) as i32);
+				self.running.store(false, Ordering::Relaxed);
+				0
+			},
+			_ => SH_FAIL,
+		}
+	}
+	
+	fn store(&mut self, addr: u32) {
+		if addr == 0 {
+			let function = self.regs.read_w(0).unwrap();
+			let result = self.call(function);
+			self.regs.write_w(16, result).unwrap();
+		}
+	}
+}
+
+impl Memory32<u32, BusError> for Semihost {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		if !self.enabled {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.read_b(addr)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		if !self.enabled {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.read_h(addr)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		if !self.enabled {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.read_h_big(addr)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		if !self.enabled {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.read_w(addr)
+	}
+	
+	// calls are made with a word store to the function register
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		if !self.enabled || addr < 4 {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_b(addr, data)
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		if !self.enabled || addr < 4 {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_h(addr, data)
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		if !self.enabled {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_w(addr, data)?;
+		self.store(addr);
+		Ok(())
+	}
+}
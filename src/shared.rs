@@ -0,0 +1,117 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use crate::bus::{BusError, Memory32};
+
+// Shared: a region backed by a host file, for exchanging bulk data with host
+// tools while the guest runs
+//
+// Every access goes straight to the file, so a tool that maps or reads the
+// file sees guest stores at once and the guest sees the tool's writes on its
+// next load. On Linux a file in /dev/shm is a POSIX shared memory object, which
+// the tool can open with shm_open. Data is little-endian like main memory. An
+// existing file is used at its length; a new one is created with
+// SHARED_DEFAULT_SIZE zero bytes. The contents belong to the file, not the
+// machine, so snapshots leave them alone.
+
+pub const SHARED_DEFAULT_SIZE: u32 = 0x10000;
+
+pub struct SharedRegion {
+	file: File,
+	pub size: u32
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+	use std::os::unix::fs::FileExt;
+	file.read_exact_at(buf, offset)
+}
+
+#[cfg(unix)]
+fn write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+	use std::os::unix::fs::FileExt;
+	file.write_all_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+	use std::os::windows::fs::FileExt;
+	let mut done = 0;
+	while done < buf.len() {
+		match file.seek_read(&mut buf[done..], offset + done as u64)? {
+			0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+			n => done += n,
+		}
+	}
+	Ok(())
+}
+
+#[cfg(windows)]
+fn write_at(file: &File, buf: &[u8], offset: u64) -> io::Result<()> {
+	use std::os::windows::fs::FileExt;
+	let mut done = 0;
+	while done < buf.len() {
+		done += file.seek_write(&buf[done..], offset + done as u64)?;
+	}
+	Ok(())
+}
+
+impl SharedRegion {
+	pub fn open(path: &str) -> io::Result<SharedRegion> {
+		let file = OpenOptions::new().read(true).write(true).create(true).open(path)?;
+		let len = file.metadata()?.len();
+		if len == 0 {
+			file.set_len(SHARED_DEFAULT_SIZE as u64)?;
+		} else if len > u32::MAX as u64 {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "file is too large for the bus"));
+		}
+		let size = if len == 0 { SHARED_DEFAULT_SIZE } else { len as u32 };
+		Ok(SharedRegion { file: file, size: size })
+	}
+	
+	fn check(&self, addr: u32, width: u32) -> Result<(), BusError> {
+		if addr.checked_add(width).map_or(true, |end| end > self.size) {
+			Err(BusError::InvalidAddress)
+		} else if addr % width != 0 {
+			Err(BusError::AlignmentCheck)
+		} else {
+			Ok(())
+		}
+	}
+	
+	fn read<const N: usize>(&self, addr: u32) -> Result<[u8; N], BusError> {
+		self.check(addr, N as u32)?;
+		let mut buf = [0 as u8; N];
+		read_at(&self.file, &mut buf, addr as u64).map_err(|_| BusError::InvalidState)?;
+		Ok(buf)
+	}
+	
+	fn write(&self, addr: u32, data: &[u8]) -> Result<(), BusError> {
+		self.check(addr, data.len() as u32)?;
+		write_at(&self.file, data, addr as u64).map_err(|_| BusError::InvalidState)
+	}
+}
+
+impl Memory32<u32, BusError> for SharedRegion {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.read::<1>(addr).map(|x| x[0])
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.read(addr).map(u16::from_le_bytes)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		self.read(addr).map(u16::from_be_bytes)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		self.read(addr).map(u32::from_le_bytes)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		self.write(addr, &[data])
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		self.write(addr, &data.to_le_bytes())
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		self.write(addr, &data.to_le_bytes())
+	}
+}
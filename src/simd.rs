@@ -0,0 +1,39 @@
+// Packed arithmetic for the vector extension (opcodes 0x80-0x8D)
+//
+// Each instruction works on a quadruple of registers, d..d+3 against r..r+3,
+// treating every register as four byte lanes (0x80-0x85) or two halfword
+// lanes (0x88-0x8D). Lanes never carry into each other and no flags change.
+// Comparisons leave all ones in lanes where they hold and zero elsewhere;
+// ordering is unsigned throughout.
+//
+//   +0 VADD  wrapping add			+3 VCGT  d lane > r lane
+//   +1 VSUB  wrapping subtract		+4 VMIN  smaller lane
+//   +2 VCEQ  d lane == r lane		+5 VMAX  larger lane
+
+const VECTOR_HALF: u8 = 0x08;
+
+pub fn is_vector(opcode: u8) -> bool {
+	matches!(opcode, 0x80..=0x85 | 0x88..=0x8D)
+}
+
+fn lane(op: u8, a: u32, b: u32, mask: u32) -> u32 {
+	match op {
+		0 => a.wrapping_add(b) & mask,
+		1 => a.wrapping_sub(b) & mask,
+		2 => if a == b { mask } else { 0 },
+		3 => if a > b { mask } else { 0 },
+		4 => a.min(b),
+		_ => a.max(b),
+	}
+}
+
+// one register's worth of lanes
+pub fn packed(opcode: u8, a: u32, b: u32) -> u32 {
+	let (width, mask) = if opcode & VECTOR_HALF != 0 { (16, 0xFFFF) } else { (8, 0xFF) };
+	let op = opcode & 0x07;
+	let mut result = 0;
+	for shift in (0..32).step_by(width) {
+		result |= lane(op, (a >> shift) & mask, (b >> shift) & mask, mask) << shift;
+	}
+	result
+}
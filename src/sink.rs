@@ -0,0 +1,45 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::Arc;
+use crate::frontend;
+use crate::sync::Mutex;
+
+// Sink: destination for a device's host-side text output (the frontend or a
+// file)
+
+pub enum Sink {
+	Frontend(&'static str),		// the device's transcript tag
+	File(File),
+	Shared(Arc<Mutex<Sink>>)	// one its owner can redirect while the device runs
+}
+
+// untagged; devices start with their own
+impl Default for Sink {
+	fn default() -> Sink {
+		Sink::Frontend("")
+	}
+}
+
+impl Sink {
+	pub fn file(path: &str) -> io::Result<Sink> {
+		Ok(Sink::File(File::create(path)?))
+	}
+	
+	pub fn write_line(&mut self, line: &str) {
+		self.write(&format!("{}\n", line));
+	}
+	
+	// text as it is, for devices that end their own lines
+	pub fn write(&mut self, text: &str) {
+		match self {
+			Sink::Frontend(device) => frontend::output(device, text),
+			Sink::File(f) => {
+				// flush every time so output survives the emulator being killed
+				if f.write_all(text.as_bytes()).and_then(|_| f.flush()).is_err() {
+					frontend::output("", text);
+				}
+			},
+			Sink::Shared(s) => s.lock().unwrap().write(text),
+		}
+	}
+}
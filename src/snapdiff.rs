@@ -0,0 +1,128 @@
+use crate::cpu::CpuState;
+use crate::machine::Region;
+use crate::snapshot::Snapshot;
+use crate::symbols::SymbolTable;
+
+// SnapDiff: what changed between two snapshots of the same machine
+//
+// Registers are listed with their before and after values, then memory as
+// runs of changed bytes. A region's saved state is taken as its memory when it
+// is exactly the region's size (main memory and plain buffers); any other
+// device state is only reported as changed. Runs closer than GAP bytes are
+// merged, and each shows at most SHOW_BYTES bytes of before and after.
+
+const GAP: usize = 4;
+const SHOW_BYTES: usize = 16;
+
+// longest list of runs before it gives up
+const RUN_LIMIT: usize = 64;
+
+fn reg_name(n: usize) -> String {
+	match n {
+		14 => "LR".to_string(),
+		15 => "PC".to_string(),
+		_ => format!("R{}", n),
+	}
+}
+
+fn registers(a: &CpuState, b: &CpuState, out: &mut Vec<String>) {
+	for n in 0..16 {
+		if a.R[n] != b.R[n] {
+			out.push(format!("{:<8} {:08X} -> {:08X}", reg_name(n), a.R[n], b.R[n]));
+		}
+	}
+	for n in 0..16 {
+		if a.F[n] != b.F[n] {
+			out.push(format!("{:<8} {:02X} -> {:02X}", format!("F{}", n), a.F[n], b.F[n]));
+		}
+	}
+	for n in 0..16 {
+		if a.FP[n] != b.FP[n] {
+			out.push(format!("{:<8} {:08X} -> {:08X}", format!("FP{}", n), a.FP[n], b.FP[n]));
+		}
+	}
+	for n in 0..16 {
+		let (x, y) = ((a.S_selector[n], a.S_base[n], a.S_limit[n], a.S_key[n], a.S_flags[n]),
+			(b.S_selector[n], b.S_base[n], b.S_limit[n], b.S_key[n], b.S_flags[n]));
+		if x != y {
+			out.push(format!("{:<8} {:02X} ({:08X}->{:08X}; {:02X}, {:02X}) -> {:02X} ({:08X}->{:08X}; {:02X}, {:02X})",
+				format!("SSR{}", n), x.0, x.1, x.2, x.3, x.4, y.0, y.1, y.2, y.3, y.4));
+		}
+	}
+	for n in 0..16 {
+		if a.MPK[n] != b.MPK[n] {
+			out.push(format!("{:<8} {:02X} -> {:02X}", format!("MPK{}", n), a.MPK[n], b.MPK[n]));
+		}
+	}
+	if (a.SDTR_base, a.SDTR_len) != (b.SDTR_base, b.SDTR_len) {
+		out.push(format!("{:<8} {:08X}/{:02X} -> {:08X}/{:02X}", "SDTR", a.SDTR_base, a.SDTR_len, b.SDTR_base, b.SDTR_len));
+	}
+	if a.PEBA_base != b.PEBA_base {
+		out.push(format!("{:<8} {:08X} -> {:08X}", "PEBA", a.PEBA_base, b.PEBA_base));
+	}
+	if a.PLBA_base != b.PLBA_base {
+		out.push(format!("{:<8} {:08X} -> {:08X}", "PLBA", a.PLBA_base, b.PLBA_base));
+	}
+	if a.cycles != b.cycles {
+		out.push(format!("{:<8} {} -> {} ({:+})", "CYCLES", a.cycles, b.cycles, b.cycles as i128 - a.cycles as i128));
+	}
+}
+
+fn hex(bytes: &[u8]) -> String {
+	let shown: Vec<String> = bytes.iter().take(SHOW_BYTES).map(|x| format!("{:02X}", x)).collect();
+	let more = if bytes.len() > SHOW_BYTES { " ..." } else { "" };
+	format!("{}{}", shown.join(" "), more)
+}
+
+// the runs of changed bytes between two images of one region
+fn runs(a: &[u8], b: &[u8]) -> Vec<(usize, usize)> {
+	let mut out: Vec<(usize, usize)> = Vec::new();
+	for n in (0..a.len()).filter(|&n| a[n] != b[n]) {
+		match out.last_mut() {
+			Some((_, end)) if n - *end <= GAP => *end = n + 1,
+			_ => out.push((n, n + 1)),
+		}
+	}
+	out
+}
+
+pub fn diff(a: &Snapshot, b: &Snapshot, regions: &[Region], symbols: &SymbolTable) -> Result<Vec<String>, String> {
+	if a.bus.base != b.bus.base || a.bus.size != b.bus.size {
+		return Err("the snapshots are of machines with different memory maps".to_string());
+	}
+	let mut out = Vec::new();
+	registers(&a.cpu, &b.cpu, &mut out);
+	
+	let mut listed = 0;
+	for n in 0..a.bus.base.len() {
+		let (base, size) = (a.bus.base[n], a.bus.size[n]);
+		let name = regions.iter().find(|r| r.base == base).map_or("region", |r| r.name);
+		let (x, y) = match (&a.bus.region[n], &b.bus.region[n]) {
+			(Some(x), Some(y)) => (x, y),
+			_ => continue,
+		};
+		if x.len() != size as usize || y.len() != size as usize {
+			if x != y {
+				out.push(format!("{:08X}          {} STATE CHANGED", base, name.to_uppercase()));
+			}
+			continue;
+		}
+		for (start, end) in runs(x, y) {
+			if listed == RUN_LIMIT {
+				out.push("MORE CHANGES NOT LISTED".to_string());
+				return Ok(out);
+			}
+			listed += 1;
+			let addr = base + start as u32;
+			let label = symbols.describe(addr);
+			out.push(format!("{:08X}-{:08X} {} BYTE{}{}{}", addr, base + end as u32 - 1, end - start,
+				if end - start == 1 { "" } else { "S" }, if label.is_empty() { "" } else { " AT " }, label));
+			out.push(format!("  BEFORE {}", hex(&x[start..end])));
+			out.push(format!("  AFTER  {}", hex(&y[start..end])));
+		}
+	}
+	if out.is_empty() {
+		out.push("NO CHANGES".to_string());
+	}
+	Ok(out)
+}
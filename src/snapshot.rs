@@ -0,0 +1,240 @@
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use serde::{Serialize, Deserialize};
+use crate::bus::BusState;
+use crate::cpu::CpuState;
+
+// Snapshot: complete machine state, as written to disk or sent to another host
+//
+// The file is the magic, the version (word), flags (halfword), the length of
+// the header (word), the header and then the state. The header is a Config,
+// the machine the state was taken from, so it can be read and checked without
+// the state. With SNAPSHOT_COMPRESSED, which snapshots are always written
+// with, the state is run-length encoded: a series of runs, each 0, a count
+// and a byte for that many copies of the byte, or 1, a count and that many
+// bytes as they are. Counts are LEB128. Mostly-zero memory shrinks to almost
+// nothing.
+
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"SQSS";
+pub const SNAPSHOT_VERSION: u32 = 10;
+
+pub const SNAPSHOT_COMPRESSED: u16 = 0x0001;
+
+pub const DEVICE_MAGIC: &[u8; 4] = b"SQDV";
+pub const DEVICE_VERSION: u32 = 1;
+
+// shortest stretch of one byte worth a run of its own
+const MIN_RUN: usize = 4;
+
+const RUN: u8 = 0;
+const LITERAL: u8 = 1;
+
+// what the state may hold beyond the regions the header lists: the CPU's own
+// and what devices keep past their registers, as the card reader's deck
+const STATE_SLACK: usize = 64 << 20;
+
+// Config: the machine a snapshot was taken from, the only kind it restores into
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Config {
+	pub emulator: String,			// the version that wrote the snapshot
+	pub regions: Vec<(u32, u32)>,	// base and size of each region, in attach order
+	pub features: Vec<String>		// build features the state may rely on
+}
+
+impl Config {
+	// this build's, for regions attached in the order given
+	pub fn new(regions: Vec<(u32, u32)>) -> Config {
+		let mut features = Vec::new();
+		if cfg!(feature = "crypto") {
+			features.push("crypto".to_string());
+		}
+		Config {
+			emulator: env!("CARGO_PKG_VERSION").to_string(),
+			regions: regions,
+			features: features
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+	#[serde(skip)]
+	pub config: Config,		// kept in the header
+	pub cpu: CpuState,
+	pub bus: BusState,
+}
+
+fn invalid(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn put_count(out: &mut Vec<u8>, mut n: usize) {
+	while n >= 0x80 {
+		out.push((n & 0x7F) as u8 | 0x80);
+		n >>= 7;
+	}
+	out.push(n as u8);
+}
+
+fn get_count(data: &[u8], pos: &mut usize) -> Option<usize> {
+	let mut n = 0;
+	for shift in (0..64).step_by(7) {
+		let x = *data.get(*pos)?;
+		*pos += 1;
+		n |= ((x & 0x7F) as usize) << shift;
+		if x & 0x80 == 0 {
+			return Some(n);
+		}
+	}
+	None
+}
+
+fn encode(data: &[u8]) -> Vec<u8> {
+	let mut out = Vec::new();
+	let (mut n, mut literal) = (0, 0);
+	while n < data.len() {
+		let run = data[n..].iter().take_while(|&&x| x == data[n]).count();
+		if run < MIN_RUN {
+			n += run;
+			continue;
+		}
+		if literal < n {
+			out.push(LITERAL);
+			put_count(&mut out, n - literal);
+			out.extend_from_slice(&data[literal..n]);
+		}
+		out.push(RUN);
+		put_count(&mut out, run);
+		out.push(data[n]);
+		n += run;
+		literal = n;
+	}
+	if literal < data.len() {
+		out.push(LITERAL);
+		put_count(&mut out, data.len() - literal);
+		out.extend_from_slice(&data[literal..]);
+	}
+	out
+}
+
+// the state, or None if it's corrupt or would come to more than limit bytes
+fn decode(data: &[u8], limit: usize) -> Option<Vec<u8>> {
+	let mut out = Vec::new();
+	let mut pos = 0;
+	while pos < data.len() {
+		let kind = data[pos];
+		pos += 1;
+		let count = get_count(data, &mut pos)?;
+		if count > limit - out.len() {
+			return None;
+		}
+		match kind {
+			RUN => {
+				let x = *data.get(pos)?;
+				pos += 1;
+				out.resize(out.len() + count, x);
+			},
+			LITERAL => {
+				out.extend_from_slice(data.get(pos..pos.checked_add(count)?)?);
+				pos += count;
+			},
+			_ => return None,
+		}
+	}
+	Some(out)
+}
+
+impl Snapshot {
+	pub fn new(cpu: CpuState, bus: BusState) -> Snapshot {
+		Snapshot {
+			config: Config::new(bus.base.iter().cloned().zip(bus.size.iter().cloned()).collect()),
+			cpu: cpu,
+			bus: bus
+		}
+	}
+	
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let header = bincode::serialize(&self.config).unwrap();
+		let mut data = Vec::new();
+		data.extend_from_slice(SNAPSHOT_MAGIC);
+		data.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+		data.extend_from_slice(&SNAPSHOT_COMPRESSED.to_le_bytes());
+		data.extend_from_slice(&(header.len() as u32).to_le_bytes());
+		data.extend(header);
+		data.extend(encode(&bincode::serialize(self).unwrap()));
+		data
+	}
+	
+	// the magic, version and header; the header's length and the flags
+	fn header(data: &[u8]) -> io::Result<(Config, usize, u16)> {
+		if data.len() < 14 || &data[0..4] != SNAPSHOT_MAGIC {
+			return Err(invalid("not a SeriesQ snapshot"));
+		}
+		let version = u32::from_le_bytes(data[4..8].try_into().unwrap());
+		if version != SNAPSHOT_VERSION {
+			return Err(invalid(&format!("snapshot version {} is not supported (this emulator reads version {})",
+				version, SNAPSHOT_VERSION)));
+		}
+		let flags = u16::from_le_bytes(data[8..10].try_into().unwrap());
+		let len = u32::from_le_bytes(data[10..14].try_into().unwrap()) as usize;
+		let config = data.get(14..14 + len).and_then(|x| bincode::deserialize(x).ok())
+			.ok_or_else(|| invalid("corrupt snapshot header"))?;
+		Ok((config, 14 + len, flags))
+	}
+	
+	pub fn from_bytes(data: &[u8]) -> io::Result<Snapshot> {
+		let (config, start, flags) = Snapshot::header(data)?;
+		let state = if flags & SNAPSHOT_COMPRESSED != 0 {
+			let limit = config.regions.iter().fold(STATE_SLACK, |n, x| n.saturating_add(x.1 as usize));
+			decode(&data[start..], limit).ok_or_else(|| invalid("corrupt snapshot"))?
+		} else {
+			data[start..].to_vec()
+		};
+		let mut snap: Snapshot = bincode::deserialize(&state).map_err(|_| invalid("corrupt snapshot"))?;
+		snap.config = config;
+		Ok(snap)
+	}
+	
+	pub fn save(&self, path: &str) -> io::Result<()> {
+		fs::write(path, self.to_bytes())
+	}
+	
+	pub fn load(path: &str) -> io::Result<Snapshot> {
+		Snapshot::from_bytes(&fs::read(path)?)
+	}
+}
+
+// DeviceSnapshot: the saved state of one region, restored on its own into a
+// machine with a region of the same kind and size
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeviceSnapshot {
+	pub name: String,
+	pub kind: u32,		// discovery table type
+	pub base: u32,
+	pub size: u32,
+	pub state: Vec<u8>
+}
+
+impl DeviceSnapshot {
+	pub fn save(&self, path: &str) -> io::Result<()> {
+		let mut data = Vec::new();
+		data.extend_from_slice(DEVICE_MAGIC);
+		data.extend_from_slice(&DEVICE_VERSION.to_le_bytes());
+		data.extend(bincode::serialize(self).unwrap());
+		fs::write(path, data)
+	}
+	
+	pub fn load(path: &str) -> io::Result<DeviceSnapshot> {
+		let data = fs::read(path)?;
+		if data.len() < 8 || &data[0..4] != DEVICE_MAGIC {
+			return Err(invalid("not a SeriesQ device snapshot"));
+		}
+		if data[4..8] != DEVICE_VERSION.to_le_bytes() {
+			return Err(invalid("unsupported device snapshot version"));
+		}
+		bincode::deserialize(&data[8..]).map_err(|_| invalid("corrupt device snapshot"))
+	}
+}
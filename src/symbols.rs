@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+use std::{fs, io};
+
+// SymbolTable: guest code labels and instructions read from an sqasm map file (-m)
+//
+// Symbol lines are `VALUE KIND NAME` with VALUE in hex; only KIND T (labels) is
+// kept, since values set with = or .equ are usually not addresses. Instruction
+// lines are `ADDR S LEN FILE:LINE SOURCE`.
+
+pub struct SourceLine {
+	pub addr: u32,
+	pub len: u32,
+	pub place: String,
+	pub text: String
+}
+
+#[derive(Default)]
+pub struct SymbolTable {
+	by_addr: BTreeMap<u32, String>,
+	pub lines: Vec<SourceLine>
+}
+
+impl SymbolTable {
+	pub fn load(path: &str) -> io::Result<SymbolTable> {
+		let text = fs::read_to_string(path)?;
+		let mut table = SymbolTable::default();
+		for (n, line) in text.lines().enumerate() {
+			let fields: Vec<&str> = line.splitn(5, ' ').collect();
+			let bad = || io::Error::new(io::ErrorKind::InvalidData, format!("line {}: bad map entry", n + 1));
+			if line.trim().is_empty() {
+				continue;
+			}
+			let value = u32::from_str_radix(fields[0], 16).map_err(|_| bad())?;
+			match fields.as_slice() {
+				[_, "T", name] => {
+					// first name wins where labels share an address
+					table.by_addr.entry(value).or_insert(name.to_string());
+				},
+				[_, "A", _] => { },
+				[_, "S", len, place, text] => table.lines.push(SourceLine {
+					addr: value,
+					len: len.parse().map_err(|_| bad())?,
+					place: place.to_string(),
+					text: text.to_string()
+				}),
+				_ => return Err(bad()),
+			}
+		}
+		Ok(table)
+	}
+	
+	pub fn len(&self) -> usize {
+		self.by_addr.len()
+	}
+	
+	// nearest label at or below addr, and the distance from it
+	pub fn lookup(&self, addr: u32) -> Option<(&str, u32)> {
+		self.by_addr.range(..=addr).next_back().map(|(a, name)| (name.as_str(), addr - a))
+	}
+	
+	// `name+0x10`, or nothing when no label precedes addr
+	pub fn describe(&self, addr: u32) -> String {
+		match self.lookup(addr) {
+			Some((name, 0)) => name.to_string(),
+			Some((name, off)) => format!("{}+0x{:X}", name, off),
+			None => String::new(),
+		}
+	}
+}
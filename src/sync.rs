@@ -0,0 +1,72 @@
+// Sync: the Mutex and Condvar used for the bus, channels and devices
+//
+// std::sync by default. With the parking_lot feature the same names wrap
+// parking_lot's smaller, faster locks, which never poison; lock(), try_lock()
+// and wait() keep the std signatures so callers don't care which is in use.
+
+#[cfg(not(feature = "parking_lot"))]
+pub use std::sync::{Condvar, Mutex};
+
+#[cfg(feature = "parking_lot")]
+pub use self::fast::{Condvar, Mutex};
+
+#[cfg(feature = "parking_lot")]
+mod fast {
+	use std::sync::{LockResult, TryLockError, TryLockResult};
+	use serde::{Serialize, Serializer, Deserialize, Deserializer};
+	
+	pub type MutexGuard<'a, T> = parking_lot::MutexGuard<'a, T>;
+	
+	#[derive(Default)]
+	pub struct Mutex<T: ?Sized>(parking_lot::Mutex<T>);
+	
+	impl<T> Mutex<T> {
+		pub fn new(value: T) -> Mutex<T> {
+			Mutex(parking_lot::Mutex::new(value))
+		}
+	}
+	
+	impl<T: ?Sized> Mutex<T> {
+		pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+			Ok(self.0.lock())
+		}
+		
+		pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+			self.0.try_lock().ok_or(TryLockError::WouldBlock)
+		}
+	}
+	
+	impl<T: ?Sized + Serialize> Serialize for Mutex<T> {
+		fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+			self.0.lock().serialize(serializer)
+		}
+	}
+	
+	impl<'de, T: Deserialize<'de>> Deserialize<'de> for Mutex<T> {
+		fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Mutex<T>, D::Error> {
+			T::deserialize(deserializer).map(Mutex::new)
+		}
+	}
+	
+	#[derive(Default)]
+	pub struct Condvar(parking_lot::Condvar);
+	
+	impl Condvar {
+		pub fn new() -> Condvar {
+			Condvar(parking_lot::Condvar::new())
+		}
+		
+		pub fn wait<'a, T>(&self, mut guard: MutexGuard<'a, T>) -> LockResult<MutexGuard<'a, T>> {
+			self.0.wait(&mut guard);
+			Ok(guard)
+		}
+		
+		pub fn notify_one(&self) {
+			self.0.notify_one();
+		}
+		
+		pub fn notify_all(&self) {
+			self.0.notify_all();
+		}
+	}
+}
@@ -0,0 +1,331 @@
+use std::io;
+use crate::bus::{Memory32, BusError};
+use crate::transcript;
+use crate::transport::{self, BlockTransport, Mode};
+
+// Tape: a magnetic tape drive with an autoloader holding a magazine of reels
+//
+// Registers: command at 0 (word), status at 4, record length at 8 (word),
+// volume at 12 (word: the mounted reel's place in the magazine from 1, or 0),
+// record buffer at TAPE_BUFFER (TAPE_BUFFER_SIZE bytes, past the ID block).
+//
+//   1 READ       read the next record into the buffer and its length into the
+//                length register; a longer record is cut to the buffer
+//   2 WRITE      write the length register's bytes of the buffer as a record
+//   3 MARK       write a tape mark
+//   4 REWIND     go back to the load point
+//   5 BACKSPACE  go back over one record or tape mark
+//   6 UNLOAD     rewind and take the reel out of the drive
+//   7 LOAD       put the magazine's next reel in the drive
+//
+// As on a real drive, writing erases whatever followed on the reel. Commands
+// complete at once. Status: ELEMTLPR (Error, Length check, End of tape,
+// Magazine empty, Tape mark, Load point, file Protected, Ready). Tape mark is
+// set by a READ or BACKSPACE that met one. End of tape is set by a READ past the
+// last record, or by a WRITE or MARK that takes the reel past its length
+// (--tape-length), which is when a guest should go on to the next volume. A
+// write to a protected reel or of length 0, a LOAD with a reel in or the
+// magazine empty, or any other command with no reel in, sets Error alone.
+//
+// Reels are host files in the SIMH tape format: each record is its length
+// (word), the data padded to an even length and the length again; a tape mark
+// is a zero word. A reel that is not there is made empty, for writing.
+
+pub const TAPE_BUFFER: u32 = 0x100;
+pub const TAPE_BUFFER_SIZE: u32 = 2048;
+pub const TAPE_REGION_SIZE: u32 = TAPE_BUFFER + TAPE_BUFFER_SIZE;
+
+pub const TAPE_STATUS: u32 = 4;
+
+pub const TAPE_READ: u32 = 1;
+pub const TAPE_WRITE: u32 = 2;
+pub const TAPE_MARK_CMD: u32 = 3;
+pub const TAPE_REWIND: u32 = 4;
+pub const TAPE_BACKSPACE: u32 = 5;
+pub const TAPE_UNLOAD: u32 = 6;
+pub const TAPE_LOAD: u32 = 7;
+
+pub const TAPE_READY: u8 = 0b00000001;
+pub const TAPE_PROTECTED: u8 = 0b00000010;
+pub const TAPE_LOAD_POINT: u8 = 0b00000100;
+pub const TAPE_MARK: u8 = 0b00001000;
+pub const TAPE_MAGAZINE_EMPTY: u8 = 0b00010000;
+pub const TAPE_END: u8 = 0b00100000;
+pub const TAPE_LENGTH_CHECK: u8 = 0b01000000;
+pub const TAPE_ERROR: u8 = 0b10000000;
+
+const END_OF_MEDIUM: u32 = 0xFFFFFFFF;
+
+pub struct Reel {
+	pub path: String,
+	pub read_only: bool,
+	pub pos: u64,
+	file: Box<dyn BlockTransport>
+}
+
+// what a read or backspace met
+enum Block {
+	Record(usize),
+	Mark,
+	End
+}
+
+impl Reel {
+	pub fn open(path: &str) -> io::Result<Reel> {
+		let (file, read_only) = match transport::open(path, Mode::Create) {
+			Ok(f) => (f, false),
+			Err(e) if e.kind() == io::ErrorKind::PermissionDenied => (transport::open(path, Mode::Read)?, true),
+			Err(e) => return Err(e),
+		};
+		Ok(Reel { path: path.to_string(), read_only: read_only, pos: 0, file: file })
+	}
+	
+	fn word(&mut self, at: u64) -> io::Result<u32> {
+		let mut w = [0 as u8; 4];
+		self.file.read_at(at, &mut w)?;
+		Ok(u32::from_le_bytes(w))
+	}
+	
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<Block> {
+		if self.pos + 4 > self.file.size()? {
+			return Ok(Block::End);
+		}
+		let len = match self.word(self.pos)? {
+			0 => {
+				self.pos += 4;
+				return Ok(Block::Mark);
+			},
+			END_OF_MEDIUM => return Ok(Block::End),
+			x => x as u64,
+		};
+		let n = buf.len().min(len as usize);
+		self.file.read_at(self.pos + 4, &mut buf[..n])?;
+		self.pos += 8 + (len + 1) / 2 * 2;
+		Ok(Block::Record(len as usize))
+	}
+	
+	fn backspace(&mut self) -> io::Result<Block> {
+		if self.pos < 4 {
+			return Ok(Block::End);
+		}
+		match self.word(self.pos - 4)? {
+			0 => {
+				self.pos -= 4;
+				Ok(Block::Mark)
+			},
+			x => {
+				self.pos -= 8 + (x as u64 + 1) / 2 * 2;
+				Ok(Block::Record(x as usize))
+			},
+		}
+	}
+	
+	// write at the current position, erasing the rest of the reel
+	fn write(&mut self, data: &[u8]) -> io::Result<()> {
+		let mut out = Vec::with_capacity(data.len() + 9);
+		if data.is_empty() {
+			out.extend_from_slice(&0u32.to_le_bytes());
+		} else {
+			out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+			out.extend_from_slice(data);
+			if data.len() % 2 != 0 {
+				out.push(0);
+			}
+			out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+		}
+		self.file.write_at(self.pos, &out)?;
+		self.pos += out.len() as u64;
+		self.file.set_size(self.pos)
+	}
+}
+
+pub struct TapeDrive {
+	pub regs: Vec<u8>,
+	pub magazine: Vec<String>,
+	pub next: usize,			// magazine slot the next LOAD takes
+	pub reel: Option<Reel>,
+	pub length: Option<u64>		// bytes a reel holds before end of tape
+}
+
+impl TapeDrive {
+	pub fn new() -> TapeDrive {
+		let mut regs = vec![0 as u8; TAPE_REGION_SIZE as usize];
+		regs[TAPE_STATUS as usize] = TAPE_MAGAZINE_EMPTY;
+		TapeDrive {
+			regs: regs,
+			magazine: Vec::new(),
+			next: 0,
+			reel: None,
+			length: None
+		}
+	}
+	
+	// fill the magazine and put its first reel in the drive
+	pub fn load_magazine(&mut self, reels: &[String]) -> io::Result<()> {
+		self.magazine = reels.to_vec();
+		self.next = 0;
+		self.reel = None;
+		self.load()?;
+		self.status(0);
+		Ok(())
+	}
+	
+	fn load(&mut self) -> io::Result<()> {
+		let path = match self.magazine.get(self.next) {
+			Some(x) => x.clone(),
+			None => return Ok(()),
+		};
+		let reel = Reel::open(&path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+		self.next += 1;
+		self.reel = Some(reel);
+		self.regs.write_w(12, self.next as u32).unwrap();
+		transcript::record("TAPE", &format!("MOUNTED {}", path));
+		Ok(())
+	}
+	
+	fn unload(&mut self) {
+		if let Some(reel) = self.reel.take() {
+			transcript::record("TAPE", &format!("UNLOADED {}", reel.path));
+		}
+		self.regs.write_w(12, 0).unwrap();
+	}
+	
+	// the status for what is in the drive, with the bits a command left
+	fn status(&mut self, bits: u8) {
+		let mut s = bits;
+		match &self.reel {
+			Some(r) => {
+				s |= TAPE_READY;
+				if r.read_only {
+					s |= TAPE_PROTECTED;
+				}
+				if r.pos == 0 {
+					s |= TAPE_LOAD_POINT;
+				}
+				if self.length.map_or(false, |x| r.pos >= x) {
+					s |= TAPE_END;
+				}
+			},
+			None => { },
+		}
+		if self.next >= self.magazine.len() {
+			s |= TAPE_MAGAZINE_EMPTY;
+		}
+		self.regs[TAPE_STATUS as usize] = s;
+	}
+	
+	fn command(&mut self, command: u32) {
+		if command == TAPE_LOAD {
+			let bits = if self.reel.is_some() || self.next >= self.magazine.len() {
+				TAPE_ERROR
+			} else if let Err(e) = self.load() {
+				transcript::record("TAPE", &e.to_string());
+				TAPE_ERROR
+			} else {
+				0
+			};
+			self.status(bits);
+			return;
+		}
+		let reel = match &mut self.reel {
+			Some(x) => x,
+			None => {
+				self.status(TAPE_ERROR);
+				return;
+			},
+		};
+		let len = self.regs.read_w(8).unwrap().min(TAPE_BUFFER_SIZE) as usize;
+		let buffer = &mut self.regs[TAPE_BUFFER as usize..];
+		let mut record = None;
+		let result = match command {
+			TAPE_READ => reel.read(buffer).map(|b| match b {
+				Block::Record(n) => {
+					record = Some(n.min(TAPE_BUFFER_SIZE as usize));
+					if n > TAPE_BUFFER_SIZE as usize { TAPE_LENGTH_CHECK } else { 0 }
+				},
+				Block::Mark => TAPE_MARK,
+				Block::End => TAPE_END,
+			}),
+			TAPE_BACKSPACE => reel.backspace().map(|b| match b {
+				Block::Mark => TAPE_MARK,
+				_ => 0,
+			}),
+			TAPE_WRITE | TAPE_MARK_CMD if reel.read_only => Ok(TAPE_ERROR),
+			TAPE_WRITE if len == 0 => Ok(TAPE_ERROR),
+			TAPE_WRITE => reel.write(&buffer[..len]).map(|_| 0),
+			TAPE_MARK_CMD => reel.write(&[]).map(|_| 0),
+			TAPE_REWIND | TAPE_UNLOAD => {
+				reel.pos = 0;
+				Ok(0)
+			},
+			_ => Ok(TAPE_ERROR),
+		};
+		let bits = match result {
+			Ok(x) => x,
+			Err(e) => {
+				transcript::record("TAPE", &format!("{}: {}", reel.path, e));
+				TAPE_ERROR
+			},
+		};
+		if let Some(n) = record {
+			self.regs.write_w(8, n as u32).unwrap();
+		}
+		if command == TAPE_UNLOAD {
+			self.unload();
+		}
+		self.status(bits);
+	}
+	
+	// the record buffer and length are the guest's
+	fn writable(addr: u32, width: u32) -> bool {
+		(addr >= 8 && addr as u64 + width as u64 <= 12) || addr >= TAPE_BUFFER
+	}
+}
+
+impl Memory32<u32, BusError> for TapeDrive {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.regs.read_b(addr)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h(addr)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h_big(addr)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		self.regs.read_w(addr)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		if !TapeDrive::writable(addr, 1) {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_b(addr, data)
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		if !TapeDrive::writable(addr, 2) {
+			return Err(BusError::InvalidAddress);
+		}
+		self.regs.write_h(addr, data)
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		match addr {
+			0 => self.command(data),
+			_ if TapeDrive::writable(addr, 4) => self.regs.write_w(addr, data)?,
+			_ => return Err(BusError::InvalidAddress),
+		}
+		Ok(())
+	}
+	
+	// the reels belong to their files, not the machine
+	fn save_state(&self) -> Option<Vec<u8>> {
+		bincode::serialize(&self.regs).ok()
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		let regs: Vec<u8> = bincode::deserialize(state).map_err(|_| BusError::InvalidState)?;
+		self.regs[8..].copy_from_slice(&regs[8..]);
+		self.regs.write_w(12, if self.reel.is_some() { self.next as u32 } else { 0 }).unwrap();
+		self.status(0);
+		Ok(())
+	}
+}
@@ -0,0 +1,62 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+
+// TOD: the guest time-of-day clock and clock comparator
+//
+// TOD counts microseconds since 1970-01-01 00:00 UTC in 64 bits. It runs with
+// the host clock; setting it only changes the offset between the two, so the
+// setting survives snapshots and migration. The comparator interrupt is
+// pending on TOD_IPL for as long as TOD is past the comparator, and is
+// cleared by setting a later comparator; all ones (the reset value) never
+// fires. Neither is related to the cycle count.
+//
+//   LTOD d, r		R[d], R[r] = low, high halves of TOD, latched together
+//   STOD d, r		set TOD to R[r]:R[d] (supervisor)
+//   LTODC d, r		R[d], R[r] = low, high halves of the comparator (supervisor)
+//   STODC d, r		set the comparator to R[r]:R[d] (supervisor)
+
+pub const TOD_IPL: usize = 5;
+
+// how often the running CPU looks at the comparator, in cycles
+pub const TOD_INTERVAL: u64 = 1024;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Tod {
+	pub offset: i64,		// TOD minus host time
+	pub comparator: u64
+}
+
+impl Default for Tod {
+	fn default() -> Tod {
+		Tod {
+			offset: 0,
+			comparator: u64::MAX
+		}
+	}
+}
+
+fn host_micros() -> u64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_micros() as u64)
+}
+
+impl Tod {
+	pub fn now(&self) -> u64 {
+		host_micros().wrapping_add(self.offset as u64)
+	}
+	
+	pub fn set(&mut self, value: u64) {
+		self.offset = value.wrapping_sub(host_micros()) as i64;
+	}
+	
+	pub fn passed(&self) -> bool {
+		self.comparator != u64::MAX && self.now() > self.comparator
+	}
+}
+
+pub fn split(value: u64) -> (u32, u32) {
+	(value as u32, (value >> 32) as u32)
+}
+
+pub fn join(low: u32, high: u32) -> u64 {
+	((high as u64) << 32) | low as u64
+}
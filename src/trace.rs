@@ -0,0 +1,130 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::sync::Mutex;
+use crate::isa;
+
+// TraceRing: the last few retired instructions, kept even with tracing off
+//
+// Recording is two stores into a fixed array, cheap enough to leave on all the
+// time; the ring is dumped when the CPU takes a system fault or panics, and by
+// the watchdog when the CPU thread is stuck holding its own lock.
+
+pub const TRACE_RING_SIZE: usize = 64;
+
+#[derive(Clone, Copy, Default)]
+pub struct TraceEntry {
+	pub base: u32,		// PS base when fetched
+	pub pc: u32,
+	pub iword0: u16,
+	pub iword1: u16,
+	pub result: u32		// destination register d after execution
+}
+
+impl fmt::Display for TraceEntry {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let op = (self.iword0 >> 8) as u8;
+		let mnemonic = isa::by_opcode(op).map_or("???", |x| x.mnemonic);
+		let iword1 = if isa::length(self.iword0) == 4 { format!("{:04X}", self.iword1) } else { "    ".to_string() };
+		write!(f, "@{:08X}::{:08X}  {:04X} {}  {:<6} R{}={:08X}", self.base, self.pc, self.iword0, iword1,
+			mnemonic, (self.iword0 >> 4) & 0xF, self.result)
+	}
+}
+
+pub struct TraceRing {
+	// an entry packed into two words, so the ring can be read without the CPU's
+	// lock while the CPU thread goes on writing it
+	entries: Vec<(AtomicU64, AtomicU64)>,
+	count: AtomicU64
+}
+
+impl Default for TraceRing {
+	fn default() -> TraceRing {
+		TraceRing {
+			entries: (0..TRACE_RING_SIZE).map(|_| (AtomicU64::new(0), AtomicU64::new(0))).collect(),
+			count: AtomicU64::new(0)
+		}
+	}
+}
+
+impl TraceRing {
+	// only the CPU thread retires instructions
+	pub fn retire(&self, entry: TraceEntry) {
+		let n = self.count.load(Ordering::Relaxed);
+		let slot = &self.entries[n as usize % TRACE_RING_SIZE];
+		slot.0.store((entry.base as u64) << 32 | entry.pc as u64, Ordering::Relaxed);
+		slot.1.store((entry.iword0 as u64) << 48 | (entry.iword1 as u64) << 32 | entry.result as u64, Ordering::Relaxed);
+		self.count.store(n + 1, Ordering::Release);
+	}
+	
+	// oldest first; read while the CPU runs, the oldest may already be overwritten
+	pub fn entries(&self) -> Vec<TraceEntry> {
+		let count = self.count.load(Ordering::Acquire);
+		let held = count.min(TRACE_RING_SIZE as u64);
+		(count - held..count).map(|n| {
+			let slot = &self.entries[n as usize % TRACE_RING_SIZE];
+			let (a, b) = (slot.0.load(Ordering::Relaxed), slot.1.load(Ordering::Relaxed));
+			TraceEntry {
+				base: (a >> 32) as u32,
+				pc: a as u32,
+				iword0: (b >> 48) as u16,
+				iword1: (b >> 32) as u16,
+				result: b as u32
+			}
+		}).collect()
+	}
+	
+	pub fn dump(&self) {
+		let entries = self.entries();
+		println!("TRACE: LAST {} OF {} INSTRUCTIONS", entries.len(), self.count.load(Ordering::Acquire));
+		for e in entries {
+			println!("  {}", e);
+		}
+	}
+}
+
+// TraceLog: every instruction retired while tracing is on, written to a host
+// file given with --trace
+//
+// The guest turns tracing on and off and puts markers in the log through the
+// trace control register (tracectl.rs), so a trace can cover one routine and
+// not everything since power-on. The log is flushed whenever tracing goes off
+// and when the CPU stops.
+
+pub struct TraceLog {
+	pub on: AtomicBool,
+	out: Mutex<BufWriter<File>>
+}
+
+impl TraceLog {
+	pub fn create(path: &str) -> io::Result<TraceLog> {
+		Ok(TraceLog {
+			on: AtomicBool::new(false),
+			out: Mutex::new(BufWriter::new(File::create(path)?))
+		})
+	}
+	
+	pub fn retire(&self, entry: &TraceEntry) {
+		if self.on.load(Ordering::Relaxed) {
+			let _ = writeln!(self.out.lock().unwrap(), "{}", entry);
+		}
+	}
+	
+	pub fn note(&self, line: &str) {
+		let _ = writeln!(self.out.lock().unwrap(), "{}", line);
+	}
+	
+	pub fn set(&self, on: bool) {
+		if self.on.swap(on, Ordering::Relaxed) != on {
+			self.note(if on { "TRACE ON" } else { "TRACE OFF" });
+			if !on {
+				self.flush();
+			}
+		}
+	}
+	
+	pub fn flush(&self) {
+		let _ = self.out.lock().unwrap().flush();
+	}
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+use crate::bus::{Memory32, BusError};
+use crate::trace::TraceLog;
+
+// TraceControl: lets supervisor code turn host-side instruction tracing on and
+// off and mark places in the trace
+//
+// Registers (words): control at 0, marker at 4.
+//
+//   control  bit 0 on: every instruction retired goes to the --trace file
+//   marker   a word written here goes in the trace as MARK and its value;
+//            reads return the last one
+//
+// The CPU refuses application-state accesses to the region, so a program
+// can't turn tracing on behind its supervisor's back. Without --trace the
+// registers still hold what is written and nothing is logged.
+
+pub const TRACE_REGION_SIZE: u32 = 8;
+
+pub const TRACE_ON: u32 = 1;
+
+pub struct TraceControl {
+	pub log: Option<Arc<TraceLog>>,
+	regs: Vec<u8>
+}
+
+impl TraceControl {
+	pub fn new() -> TraceControl {
+		TraceControl {
+			log: None,
+			regs: vec![0 as u8; TRACE_REGION_SIZE as usize]
+		}
+	}
+	
+	pub fn set_control(&mut self, x: u32) {
+		self.regs.write_w(0, x & TRACE_ON).unwrap();
+		if let Some(log) = &self.log {
+			log.set(x & TRACE_ON != 0);
+		}
+	}
+	
+	fn mark(&mut self, x: u32) {
+		self.regs.write_w(4, x).unwrap();
+		if let Some(log) = &self.log {
+			log.note(&format!("MARK {:08X}", x));
+		}
+	}
+	
+	// a store of any width, made to a copy of the registers so the word it
+	// leaves is what the control or marker sees
+	fn store<F: FnOnce(&mut Vec<u8>) -> Result<(), BusError>>(&mut self, addr: u32, f: F) -> Result<(), BusError> {
+		let mut regs = self.regs.clone();
+		f(&mut regs)?;
+		let word = regs.read_w(addr & !3)?;
+		if addr < 4 {
+			self.set_control(word);
+		} else {
+			self.mark(word);
+		}
+		Ok(())
+	}
+}
+
+impl Memory32<u32, BusError> for TraceControl {
+	fn read_b(&self, addr: u32) -> Result<u8, BusError> {
+		self.regs.read_b(addr)
+	}
+	fn read_h(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h(addr)
+	}
+	fn read_h_big(&self, addr: u32) -> Result<u16, BusError> {
+		self.regs.read_h_big(addr)
+	}
+	fn read_w(&self, addr: u32) -> Result<u32, BusError> {
+		self.regs.read_w(addr)
+	}
+	
+	fn write_b(&mut self, addr: u32, data: u8) -> Result<(), BusError> {
+		self.store(addr, |r| r.write_b(addr, data))
+	}
+	fn write_h(&mut self, addr: u32, data: u16) -> Result<(), BusError> {
+		self.store(addr, |r| r.write_h(addr, data))
+	}
+	fn write_w(&mut self, addr: u32, data: u32) -> Result<(), BusError> {
+		self.store(addr, |r| r.write_w(addr, data))
+	}
+	
+	fn save_state(&self) -> Option<Vec<u8>> {
+		bincode::serialize(&self.regs).ok()
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), BusError> {
+		let regs: Vec<u8> = bincode::deserialize(state).map_err(|_| BusError::InvalidState)?;
+		if regs.len() != self.regs.len() {
+			return Err(BusError::InvalidState);
+		}
+		self.regs = regs;
+		let control = self.regs.read_w(0).unwrap();
+		self.set_control(control);
+		Ok(())
+	}
+	
+	fn idempotent(&self, _addr: u32) -> bool {
+		true
+	}
+}
@@ -0,0 +1,108 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Transcript: one timestamped log of the whole session
+//
+// Console output arrives through println!/print! (see main.rs) and devices add
+// their own lines tagged PRINTER, PUNCH, DEBUG or GUEST, whether or not they
+// also spool to a file; the monitor adds the operator's commands as INPUT.
+// Each line reads `HH:MM:SS.mmm TAG text` in UTC.
+
+struct Transcript {
+	path: String,
+	file: File,
+	partial: String		// console text not yet ended by a newline
+}
+
+static TRANSCRIPT: Mutex<Option<Transcript>> = Mutex::new(None);
+
+// console output can come from a panicking thread, so never give up on the lock
+fn transcript() -> std::sync::MutexGuard<'static, Option<Transcript>> {
+	TRANSCRIPT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn now() -> (u64, u32) {
+	let t = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+	(t.as_secs(), t.subsec_millis())
+}
+
+fn clock(secs: u64, millis: u32) -> String {
+	let day = secs % 86400;
+	format!("{:02}:{:02}:{:02}.{:03}", day / 3600, day / 60 % 60, day % 60, millis)
+}
+
+// year, month, day from days since 1970-01-01
+fn civil(days: i64) -> (i64, u32, u32) {
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z.rem_euclid(146097);
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+	let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+	(yoe + era * 400 + if m <= 2 { 1 } else { 0 }, m, d)
+}
+
+impl Transcript {
+	fn line(&mut self, tag: &str, text: &str) {
+		let (secs, millis) = now();
+		// a transcript that can't be written must not take the console down with it
+		let _ = writeln!(self.file, "{} {:<7} {}", clock(secs, millis), tag, text).and_then(|_| self.file.flush());
+	}
+}
+
+pub fn open(path: &str) -> io::Result<()> {
+	let mut t = Transcript {
+		path: path.to_string(),
+		file: File::create(path)?,
+		partial: String::new()
+	};
+	let (secs, _) = now();
+	let (y, m, d) = civil((secs / 86400) as i64);
+	t.line("OPEN", &format!("transcript started {:04}-{:02}-{:02} UTC", y, m, d));
+	close();
+	*transcript() = Some(t);
+	Ok(())
+}
+
+// stop recording; the path of the transcript that was open, if any
+pub fn close() -> Option<String> {
+	let mut guard = transcript();
+	let mut t = guard.take()?;
+	if !t.partial.is_empty() {
+		let partial = std::mem::take(&mut t.partial);
+		t.line("CONSOLE", &partial);
+	}
+	t.line("CLOSE", "transcript ended");
+	Some(t.path)
+}
+
+pub fn path() -> Option<String> {
+	transcript().as_ref().map(|t| t.path.clone())
+}
+
+// whole lines from a device or the operator
+pub fn record(tag: &str, text: &str) {
+	if let Some(t) = transcript().as_mut() {
+		if text.is_empty() {
+			t.line(tag, "");
+		}
+		for line in text.lines() {
+			t.line(tag, line);
+		}
+	}
+}
+
+// console output as printed, which may end part way through a line
+pub fn console(text: &str) {
+	if let Some(t) = transcript().as_mut() {
+		t.partial.push_str(text);
+		while let Some(n) = t.partial.find('\n') {
+			let line: String = t.partial.drain(..=n).collect();
+			t.line("CONSOLE", line.trim_end_matches(&['\r', '\n'][..]));
+		}
+	}
+}
@@ -0,0 +1,305 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Transport: where a block device's medium is kept
+//
+// A disk volume, a tape reel and a card deck are each a run of bytes that the
+// device lays out in its own format. A BlockTransport is the run of bytes:
+// reads and writes at an offset, its size (the only geometry it has; cylinders
+// and records are the format's business) and a flush that makes the writes so
+// far stable. Devices get theirs from open, so none of them does its own file
+// I/O:
+//
+//   FileTransport    a host file
+//   MemoryTransport  bytes in memory, as a compressed volume is expanded into
+//   RemoteTransport  a medium another process serves, for a path of
+//                    tcp:HOST:PORT
+//
+// serve answers remote transports from any other transport, as sqdisk serve
+// does for an image. Messages are framed as remote.rs frames them, a u32
+// length and then the bytes, numbers little-endian. The client opens with
+// "SQBT" and the server answers with 1 if its medium is read-only, else 0.
+// Then, one request at a time:
+//   'R' offset (u64) length (u32)	answered status, then the bytes
+//   'W' offset (u64) data			answered status
+//   'Z'							answered status, then the size (u64)
+//   'T' size (u64)					set the size; answered status
+//   'F'							flush; answered status
+// Status is 0 for done, 1 for a read past the end, or 2 followed by the host's
+// message for any other error.
+
+const TRANSPORT_MAGIC: &[u8; 4] = b"SQBT";
+const REMOTE_PREFIX: &str = "tcp:";
+
+const OK: u8 = 0;
+const END: u8 = 1;
+const FAILED: u8 = 2;
+
+pub trait BlockTransport: Send {
+	// fill buf from offset; running into the end is UnexpectedEof
+	fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+	// write data at offset, growing the medium if it ends before then
+	fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()>;
+	fn size(&mut self) -> io::Result<u64>;
+	fn set_size(&mut self, size: u64) -> io::Result<()>;
+	fn flush(&mut self) -> io::Result<()>;
+	fn read_only(&self) -> bool;
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+	Read,
+	ReadWrite,
+	Create			// read and write, made empty if it isn't there
+}
+
+// the medium at path, a host file or a tcp:HOST:PORT server
+pub fn open(path: &str, mode: Mode) -> io::Result<Box<dyn BlockTransport>> {
+	match path.strip_prefix(REMOTE_PREFIX) {
+		Some(addr) => Ok(Box::new(RemoteTransport::connect(addr, mode)?)),
+		None => Ok(Box::new(FileTransport::open(path, mode)?)),
+	}
+}
+
+pub struct FileTransport {
+	file: File,
+	read_only: bool
+}
+
+impl FileTransport {
+	pub fn open(path: &str, mode: Mode) -> io::Result<FileTransport> {
+		let file = match mode {
+			Mode::Read => File::open(path)?,
+			Mode::ReadWrite => OpenOptions::new().read(true).write(true).open(path)?,
+			Mode::Create => OpenOptions::new().read(true).write(true).create(true).open(path)?,
+		};
+		Ok(FileTransport { file: file, read_only: mode == Mode::Read })
+	}
+	
+	// a new file, failing if one is there already
+	pub fn create(path: &str) -> io::Result<FileTransport> {
+		let file = OpenOptions::new().read(true).write(true).create_new(true).open(path)?;
+		Ok(FileTransport { file: file, read_only: false })
+	}
+}
+
+impl BlockTransport for FileTransport {
+	fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+		self.file.seek(SeekFrom::Start(offset))?;
+		self.file.read_exact(buf)
+	}
+	fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+		self.file.seek(SeekFrom::Start(offset))?;
+		self.file.write_all(data)
+	}
+	fn size(&mut self) -> io::Result<u64> {
+		Ok(self.file.metadata()?.len())
+	}
+	fn set_size(&mut self, size: u64) -> io::Result<()> {
+		self.file.set_len(size)
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		self.file.sync_data()
+	}
+	fn read_only(&self) -> bool {
+		self.read_only
+	}
+}
+
+pub struct MemoryTransport {
+	data: Vec<u8>
+}
+
+impl MemoryTransport {
+	pub fn new(data: Vec<u8>) -> MemoryTransport {
+		MemoryTransport { data: data }
+	}
+}
+
+impl BlockTransport for MemoryTransport {
+	fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+		match offset.checked_add(buf.len() as u64) {
+			Some(end) if end <= self.data.len() as u64 => {
+				buf.copy_from_slice(&self.data[offset as usize..end as usize]);
+				Ok(())
+			},
+			_ => Err(io::ErrorKind::UnexpectedEof.into()),
+		}
+	}
+	fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+		let end = offset as usize + data.len();
+		if end > self.data.len() {
+			self.data.resize(end, 0);
+		}
+		self.data[offset as usize..end].copy_from_slice(data);
+		Ok(())
+	}
+	fn size(&mut self) -> io::Result<u64> {
+		Ok(self.data.len() as u64)
+	}
+	fn set_size(&mut self, size: u64) -> io::Result<()> {
+		self.data.resize(size as usize, 0);
+		Ok(())
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+	fn read_only(&self) -> bool {
+		false
+	}
+}
+
+fn send(mut stream: &TcpStream, data: &[u8]) -> io::Result<()> {
+	stream.write_all(&(data.len() as u32).to_le_bytes())?;
+	stream.write_all(data)?;
+	stream.flush()
+}
+
+fn recv(mut stream: &TcpStream) -> io::Result<Vec<u8>> {
+	let mut len = [0 as u8; 4];
+	stream.read_exact(&mut len)?;
+	let mut data = vec![0 as u8; u32::from_le_bytes(len) as usize];
+	stream.read_exact(&mut data)?;
+	Ok(data)
+}
+
+fn long(data: &[u8], at: usize) -> Option<u64> {
+	data.get(at..at + 8).map(|x| u64::from_le_bytes([x[0], x[1], x[2], x[3], x[4], x[5], x[6], x[7]]))
+}
+
+fn invalid(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+pub struct RemoteTransport {
+	stream: TcpStream,
+	read_only: bool
+}
+
+impl RemoteTransport {
+	// a writable mode fails with PermissionDenied if the server's medium is
+	// read-only, as opening a read-only file would
+	pub fn connect(addr: &str, mode: Mode) -> io::Result<RemoteTransport> {
+		let stream = TcpStream::connect(addr)?;
+		stream.set_nodelay(true)?;
+		send(&stream, TRANSPORT_MAGIC)?;
+		let read_only = match recv(&stream)?.first() {
+			Some(&x) => x != 0,
+			None => return Err(invalid("not a block transport server")),
+		};
+		if read_only && mode != Mode::Read {
+			return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} serves a read-only medium", addr)));
+		}
+		Ok(RemoteTransport { stream: stream, read_only: mode == Mode::Read })
+	}
+	
+	// one request and what followed the status in its answer
+	fn request(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+		send(&self.stream, data)?;
+		let answer = recv(&self.stream)?;
+		match answer.first() {
+			Some(&OK) => Ok(answer[1..].to_vec()),
+			Some(&END) => Err(io::ErrorKind::UnexpectedEof.into()),
+			Some(_) => Err(io::Error::new(io::ErrorKind::Other, String::from_utf8_lossy(&answer[1..]).to_string())),
+			None => Err(invalid("empty answer from block transport server")),
+		}
+	}
+}
+
+impl BlockTransport for RemoteTransport {
+	fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+		let mut data = vec![b'R'];
+		data.extend_from_slice(&offset.to_le_bytes());
+		data.extend_from_slice(&(buf.len() as u32).to_le_bytes());
+		let bytes = self.request(&data)?;
+		if bytes.len() != buf.len() {
+			return Err(invalid("short answer from block transport server"));
+		}
+		buf.copy_from_slice(&bytes);
+		Ok(())
+	}
+	fn write_at(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+		let mut out = vec![b'W'];
+		out.extend_from_slice(&offset.to_le_bytes());
+		out.extend_from_slice(data);
+		self.request(&out).map(|_| ())
+	}
+	fn size(&mut self) -> io::Result<u64> {
+		long(&self.request(b"Z")?, 0).ok_or_else(|| invalid("short answer from block transport server"))
+	}
+	fn set_size(&mut self, size: u64) -> io::Result<()> {
+		let mut data = vec![b'T'];
+		data.extend_from_slice(&size.to_le_bytes());
+		self.request(&data).map(|_| ())
+	}
+	fn flush(&mut self) -> io::Result<()> {
+		self.request(b"F").map(|_| ())
+	}
+	fn read_only(&self) -> bool {
+		self.read_only
+	}
+}
+
+// answer clients, each on its own thread, from a medium in this process; only
+// returns if the address can't be listened on. sqdisk serves, the emulator
+// only connects
+#[allow(dead_code)]
+pub fn serve(addr: &str, transport: Box<dyn BlockTransport>) -> io::Result<()> {
+	let listener = TcpListener::bind(addr)?;
+	let read_only = transport.read_only();
+	let medium = Arc::new(Mutex::new(transport));
+	for stream in listener.incoming().flatten() {
+		let medium = Arc::clone(&medium);
+		thread::spawn(move || {
+			stream.set_nodelay(true).ok();
+			match recv(&stream) {
+				Ok(magic) if magic == TRANSPORT_MAGIC => { },
+				_ => return,
+			}
+			if send(&stream, &[read_only as u8]).is_err() {
+				return;
+			}
+			while let Ok(request) = recv(&stream) {
+				let answer = answer(&mut **medium.lock().unwrap(), &request, read_only);
+				if send(&stream, &answer).is_err() {
+					return;
+				}
+			}
+		});
+	}
+	Ok(())
+}
+
+fn answer(medium: &mut dyn BlockTransport, request: &[u8], read_only: bool) -> Vec<u8> {
+	let denied = || Err(io::Error::new(io::ErrorKind::PermissionDenied, "medium is read-only"));
+	let offset = long(request, 1);
+	let result = match (request.first(), offset) {
+		(Some(b'R'), Some(offset)) => {
+			let len = request.get(9..13).map_or(0, |x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]));
+			let mut buf = vec![0 as u8; len as usize];
+			medium.read_at(offset, &mut buf).map(|_| buf)
+		},
+		(Some(b'W'), Some(_)) | (Some(b'T'), Some(_)) | (Some(b'F'), _) if read_only => denied(),
+		(Some(b'W'), Some(offset)) => medium.write_at(offset, &request[9..]).map(|_| Vec::new()),
+		(Some(b'Z'), _) => medium.size().map(|x| x.to_le_bytes().to_vec()),
+		(Some(b'T'), Some(size)) => medium.set_size(size).map(|_| Vec::new()),
+		(Some(b'F'), _) => medium.flush().map(|_| Vec::new()),
+		_ => Err(invalid("bad request")),
+	};
+	match result {
+		Ok(mut data) => {
+			let mut out = vec![OK];
+			out.append(&mut data);
+			out
+		},
+		Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => vec![END],
+		Err(e) => {
+			let mut out = vec![FAILED];
+			out.extend_from_slice(e.to_string().as_bytes());
+			out
+		},
+	}
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+use crate::breakpoint::{self, Expr};
+use crate::bus::Bus;
+use crate::cpu::{SeriesQ, PC, PS};
+
+// Watch: expressions reported whenever their value changes
+//
+// Anything a breakpoint condition can use may be watched, and `w:ADDR`,
+// `h:ADDR` and `b:ADDR` are short for [ADDR], H[ADDR] and B[ADDR]. While any
+// watch is set the CPU evaluates them all before each instruction, so a change
+// is reported along with the instruction that made it.
+
+pub struct Watch {
+	pub id: u32,
+	expr: Expr,
+	source: String,
+	value: Result<u32, String>
+}
+
+#[derive(Default)]
+pub struct Watches {
+	pub list: Vec<Watch>,
+	next_id: u32,
+	last: Option<(u32, u32)>	// segment base and PC of the instruction just run
+}
+
+fn show(value: &Result<u32, String>) -> String {
+	match value {
+		Ok(x) => format!("{:08X}", x),
+		Err(e) => format!("({})", e),
+	}
+}
+
+fn parse(spec: &str) -> Result<Expr, String> {
+	let lower = spec.to_ascii_lowercase();
+	let width = match lower.get(..2) {
+		Some("w:") => 4,
+		Some("h:") => 2,
+		Some("b:") => 1,
+		_ => return breakpoint::parse_expr(spec),
+	};
+	Ok(Expr::Mem(width, Box::new(breakpoint::parse_expr(&spec[2..])?)))
+}
+
+impl fmt::Display for Watch {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{:>3}  {:<24} {}", self.id, self.source, show(&self.value))
+	}
+}
+
+impl Watches {
+	pub fn is_empty(&self) -> bool {
+		self.list.is_empty()
+	}
+	
+	pub fn add(&mut self, spec: &str, cpu: &SeriesQ, bus: &Bus) -> Result<&Watch, String> {
+		let spec = spec.trim();
+		let expr = parse(spec)?;
+		self.next_id += 1;
+		self.list.push(Watch {
+			id: self.next_id,
+			value: breakpoint::eval(&expr, cpu, bus),
+			expr: expr,
+			source: spec.to_string()
+		});
+		Ok(self.list.last().unwrap())
+	}
+	
+	pub fn remove(&mut self, id: u32) -> bool {
+		let before = self.list.len();
+		self.list.retain(|w| w.id != id);
+		self.list.len() != before
+	}
+	
+	pub fn clear(&mut self) {
+		self.list.clear();
+	}
+	
+	// the CPU is starting; whatever changed while it was stopped was the operator
+	pub fn start(cpu: &mut SeriesQ, bus: &Bus) {
+		let mut watches = std::mem::take(&mut cpu.watches);
+		watches.last = None;
+		for w in watches.list.iter_mut() {
+			w.value = breakpoint::eval(&w.expr, cpu, bus);
+		}
+		cpu.watches = watches;
+	}
+	
+	// called before each instruction
+	pub fn check(cpu: &mut SeriesQ, bus: &Bus) {
+		let mut watches = std::mem::take(&mut cpu.watches);
+		for w in watches.list.iter_mut() {
+			let value = breakpoint::eval(&w.expr, cpu, bus);
+			if value != w.value {
+				match watches.last {
+					Some((base, pc)) => println!("WATCH {} {} = {} (was {}) after @{:08X}::{:08X}",
+						w.id, w.source, show(&value), show(&w.value), base, pc),
+					None => println!("WATCH {} {} = {} (was {})", w.id, w.source, show(&value), show(&w.value)),
+				}
+				w.value = value;
+			}
+		}
+		watches.last = Some((cpu.S_base[PS], cpu.R[PC]));
+		cpu.watches = watches;
+	}
+}
@@ -0,0 +1,147 @@
+use std::sync::{Arc, TryLockError};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::{thread, time};
+use crate::sync::Mutex;
+use crate::machine::Machine;
+use crate::trace::TraceRing;
+
+// Watchdog: notices an emulator thread that has stopped getting anywhere
+//
+// The CPU and device threads each beat a Heartbeat as they go round their
+// loops, and mark themselves idle while they block waiting for work (or have
+// stopped). With --watchdog SECS a supervisor thread looks at them every
+// quarter of the interval; a thread that is busy but hasn't beaten for SECS is
+// taken to be stuck, and the watchdog reports every thread's state, which of
+// the machine's locks are held and the trace ring. The locks don't record who
+// holds them, so each is tried and listed with the thread that holds it in the
+// normal course of things. A stuck thread is reported once, and again only
+// after it has got going and stuck anew.
+
+pub struct Heartbeat {
+	pub name: &'static str,
+	beats: AtomicU64,
+	idle: AtomicBool
+}
+
+impl Heartbeat {
+	// only the thread a heart belongs to beats it
+	pub fn beat(&self) {
+		self.beats.store(self.beats.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+	}
+	
+	pub fn idle(&self, idle: bool) {
+		self.idle.store(idle, Ordering::Relaxed);
+		self.beat();
+	}
+}
+
+static HEARTS: std::sync::Mutex<Vec<Arc<Heartbeat>>> = std::sync::Mutex::new(Vec::new());
+
+// a heartbeat for a thread, idle until the thread says otherwise
+pub fn register(name: &'static str) -> Arc<Heartbeat> {
+	let heart = Arc::new(Heartbeat {
+		name: name,
+		beats: AtomicU64::new(0),
+		idle: AtomicBool::new(true)
+	});
+	HEARTS.lock().unwrap().push(Arc::clone(&heart));
+	heart
+}
+
+// a lock taken and let go of all the time is only reported held if it is at
+// every one of this many tries, a millisecond apart
+const TRIES: usize = 20;
+
+// a lock to try, and who holds it when all is well
+struct Probe {
+	name: &'static str,
+	holder: &'static str,
+	try_lock: Box<dyn Fn() -> &'static str + Send>
+}
+
+fn probe<T: ?Sized + Send + 'static>(name: &'static str, holder: &'static str, lock: &Arc<Mutex<T>>) -> Probe {
+	let lock = Arc::clone(lock);
+	Probe {
+		name: name,
+		holder: holder,
+		try_lock: Box::new(move || {
+			for _ in 0..TRIES {
+				match lock.try_lock() {
+					Ok(_) => return "FREE",
+					Err(TryLockError::WouldBlock) => thread::sleep(time::Duration::from_millis(1)),
+					Err(TryLockError::Poisoned(_)) => return "POISONED",
+				}
+			}
+			"HELD"
+		})
+	}
+}
+
+fn report(stuck: &[(&'static str, time::Duration)], probes: &[Probe], trace: &TraceRing) {
+	for (name, quiet) in stuck {
+		println!("WATCHDOG: {} THREAD STUCK FOR {:.1}S", name.to_uppercase(), quiet.as_secs_f64());
+	}
+	for heart in HEARTS.lock().unwrap().iter() {
+		let state = if heart.idle.load(Ordering::Relaxed) { "IDLE" } else { "BUSY" };
+		println!("  THREAD {:<14} {} AFTER {} BEATS", heart.name, state, heart.beats.load(Ordering::Relaxed));
+	}
+	for p in probes {
+		let state = (p.try_lock)();
+		if state == "HELD" && !p.holder.is_empty() {
+			println!("  LOCK   {:<14} HELD (normally by {})", p.name, p.holder);
+		} else {
+			println!("  LOCK   {:<14} {}", p.name, state);
+		}
+	}
+	trace.dump();
+}
+
+pub fn start(machine: &Machine, interval: time::Duration) {
+	let trace = Arc::clone(&machine.cpu.lock().unwrap().trace);
+	let probes = vec![
+		probe("cpu", "the CPU thread while it runs", &machine.cpu),
+		probe("bus", "the CPU thread between DMA grants", &machine.bus),
+		probe("printer", "the printer thread", &machine.printer),
+		probe("print buffer", "", &machine.printer_buffer),
+		probe("data port", "", &machine.dataport),
+		probe("card reader", "", &machine.reader),
+		probe("card punch", "", &machine.punch),
+		probe("debug port", "", &machine.debugport),
+		probe("semihost", "", &machine.semihost),
+		probe("host command", "", &machine.hostcmd),
+		probe("console", "", &machine.opconsole),
+		probe("disk", "", &machine.dasd),
+		probe("tape", "", &machine.tape),
+		probe("panel", "", &machine.panel),
+		probe("clipboard", "", &machine.clipboard),
+		probe("trace control", "", &machine.tracectl),
+		probe("i/o translation", "", &machine.iommu),
+	];
+	
+	thread::spawn(move || {
+		// each heart's beats when last seen to change, when that was, and
+		// whether it has been reported since
+		let mut seen: Vec<(u64, time::Instant, bool)> = Vec::new();
+		loop {
+			thread::sleep(interval / 4);
+			let now = time::Instant::now();
+			let mut stuck = Vec::new();
+			for (n, heart) in HEARTS.lock().unwrap().iter().enumerate() {
+				let beats = heart.beats.load(Ordering::Relaxed);
+				if n == seen.len() {
+					seen.push((beats, now, false));
+				}
+				let last = &mut seen[n];
+				if beats != last.0 || heart.idle.load(Ordering::Relaxed) {
+					*last = (beats, now, false);
+				} else if !last.2 && now - last.1 >= interval {
+					last.2 = true;
+					stuck.push((heart.name, now - last.1));
+				}
+			}
+			if !stuck.is_empty() {
+				report(&stuck, &probes, &trace);
+			}
+		}
+	});
+}
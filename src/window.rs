@@ -0,0 +1,348 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::{thread, time};
+use minifb::{InputCallback, Key, KeyRepeat, MouseButton, MouseMode, Scale, WindowOptions};
+use crate::font;
+use crate::isa::FLAG_NAMES;
+use crate::frontend::{self, Event, Frontend, Registers, Shown};
+use crate::machine::Machine;
+use crate::screen::{self, Screen, BLINK_MS};
+use crate::sync;
+
+// Window: the frontend --window installs, a window onto the printer, the
+// operator console, the text display and the registers
+//
+// The printer pane shows the last lines the printer and punch put out, cut at
+// PANE_COLS; the console pane under it the operator messages, attention
+// presses and the operator's replies, with the line being typed below them.
+// Enter sends that line as a reply to no message in particular, and F12
+// presses attention. The sidebar shows the registers as the CPU last copied
+// them out, and the panel's lamps and switches; clicking a switch flips it.
+// The display pane at the bottom shows the text display as the guest has it
+// (screen.rs).
+// Closing the window stops the machine.
+//
+// minifb's window belongs to the thread that opened it, so it lives in a
+// thread local of the main thread, which draws it and reads its input from
+// poll. The frontend itself only holds what the panes show, which the devices
+// add to from their own threads.
+
+const CELL_WIDTH: usize = 8;
+const CELL_HEIGHT: usize = 10;
+
+const PANE_COLS: usize = 80;
+const SIDEBAR: usize = PANE_COLS + 2;
+const COLS: usize = SIDEBAR + 20;
+
+const PRINTER_ROW: usize = 1;
+const PRINTER_ROWS: usize = 24;
+const CONSOLE_ROW: usize = PRINTER_ROW + PRINTER_ROWS + 1;
+const CONSOLE_ROWS: usize = 8;
+const INPUT_ROW: usize = CONSOLE_ROW + CONSOLE_ROWS;
+const SCREEN_ROW: usize = INPUT_ROW + 3;
+const ROWS: usize = SCREEN_ROW + (screen::HEIGHT + CELL_HEIGHT - 1) / CELL_HEIGHT;
+
+const LAMPS_ROW: usize = 25;
+const SWITCHES_ROW: usize = 29;
+
+const WIDTH: usize = COLS * CELL_WIDTH;
+const HEIGHT: usize = ROWS * CELL_HEIGHT;
+
+const BACKGROUND: u32 = 0x101418;
+const TEXT: u32 = 0xD0D0D0;
+const TITLE: u32 = 0x304050;
+const LAMP_ON: u32 = 0xFF6030;
+const LAMP_OFF: u32 = 0x402018;
+const SWITCH_UP: u32 = 0xF0F0F0;
+const SWITCH_DOWN: u32 = 0x505050;
+
+// redrawn at most this often; poll runs every millisecond or so
+const FRAME: time::Duration = time::Duration::from_millis(30);
+
+// lines of text, the last one still being written
+struct Pane {
+	lines: VecDeque<String>,
+	rows: usize
+}
+
+impl Pane {
+	fn new(rows: usize) -> Pane {
+		Pane { lines: VecDeque::from(vec![String::new()]), rows: rows }
+	}
+	
+	fn write(&mut self, text: &str) {
+		for c in text.chars() {
+			match c {
+				'\n' => self.lines.push_back(String::new()),
+				'\r' => self.lines.back_mut().unwrap().clear(),
+				// a new page
+				'\x0C' => self.lines.back_mut().unwrap().push_str(&"-".repeat(PANE_COLS)),
+				c if c.is_control() => { },
+				c => self.lines.back_mut().unwrap().push(c),
+			}
+		}
+		// one more than shown, for the line being written
+		while self.lines.len() > self.rows + 1 {
+			self.lines.pop_front();
+		}
+	}
+	
+	fn line(&mut self, text: &str) {
+		self.write(text);
+		self.write("\n");
+	}
+	
+	// the lines to show, oldest first; the one being written only if it has
+	// something in it yet
+	fn shown(&self) -> impl Iterator<Item = &String> {
+		let last = self.lines.back().map_or(false, |x| !x.is_empty());
+		let n = self.lines.len() - if last { 0 } else { 1 };
+		self.lines.iter().take(n).skip(n.saturating_sub(self.rows))
+	}
+}
+
+pub struct Window {
+	printer: Pane,
+	console: Pane,
+	input: String,
+	shown: Shown,
+	switches: u32,
+	lamps: u32,
+	screen: Arc<sync::Mutex<Screen>>,
+	opened: time::Instant		// for blinking
+}
+
+// characters typed since the last poll
+struct Typed(Rc<RefCell<String>>);
+
+impl InputCallback for Typed {
+	fn add_char(&mut self, c: u32) {
+		if let Some(c) = char::from_u32(c).filter(|x| (' '..='~').contains(x)) {
+			self.0.borrow_mut().push(c);
+		}
+	}
+}
+
+struct Display {
+	window: minifb::Window,
+	buffer: Vec<u32>,
+	typed: Rc<RefCell<String>>,
+	drawn: time::Instant,
+	pressed: bool // the left button was down at the last poll
+}
+
+thread_local! {
+	static DISPLAY: RefCell<Option<Display>> = RefCell::new(None);
+}
+
+// open the window on this thread and make it the frontend
+pub fn open(machine: &Machine) -> Result<(), String> {
+	let options = WindowOptions { scale: Scale::FitScreen, ..WindowOptions::default() };
+	let mut window = minifb::Window::new("rustframe", WIDTH, HEIGHT, options)
+		.map_err(|e| format!("--window: {}", e))?;
+	let typed = Rc::new(RefCell::new(String::new()));
+	window.set_input_callback(Box::new(Typed(Rc::clone(&typed))));
+	DISPLAY.with(|d| *d.borrow_mut() = Some(Display {
+		window: window,
+		buffer: vec![BACKGROUND; WIDTH * HEIGHT],
+		typed: typed,
+		drawn: time::Instant::now() - FRAME,
+		pressed: false
+	}));
+	
+	let shown = Arc::new(Mutex::new(Registers::default()));
+	machine.cpu.lock().unwrap().shown = Some(Arc::clone(&shown));
+	let panel = machine.panel.lock().unwrap();
+	frontend::install(Box::new(Window {
+		printer: Pane::new(PRINTER_ROWS),
+		console: Pane::new(CONSOLE_ROWS),
+		input: String::new(),
+		shown: shown,
+		switches: panel.switches(),
+		lamps: panel.lamps(),
+		screen: Arc::clone(&machine.screen),
+		opened: time::Instant::now()
+	}));
+	Ok(())
+}
+
+pub fn is_open() -> bool {
+	DISPLAY.with(|d| d.borrow().is_some())
+}
+
+// keep the window up after the machine stops, until the operator closes it;
+// what they do there meanwhile goes nowhere
+pub fn linger() {
+	while is_open() {
+		frontend::poll();
+		thread::sleep(time::Duration::from_millis(1));
+	}
+}
+
+fn text(buffer: &mut [u32], col: usize, row: usize, text: &str, fg: u32, bg: u32) {
+	// cut at the window's edge
+	for (n, c) in text.chars().take(COLS - col).enumerate() {
+		let glyph = font::glyph(c);
+		let (x0, y0) = ((col + n) * CELL_WIDTH, row * CELL_HEIGHT);
+		for y in 0..CELL_HEIGHT {
+			// a pixel of space above and below each glyph
+			let bits = if y >= 1 && y <= 8 { glyph[y - 1] } else { 0 };
+			for x in 0..CELL_WIDTH {
+				buffer[(y0 + y) * WIDTH + x0 + x] = if bits & (1 << x) != 0 { fg } else { bg };
+			}
+		}
+	}
+}
+
+// a bar across cols with title in it
+fn title(buffer: &mut [u32], col: usize, row: usize, cols: usize, title: &str) {
+	text(buffer, col, row, &format!(" {:<1$}", title, cols - 1), TEXT, TITLE);
+}
+
+// a square in a cell, for a lamp or switch
+fn square(buffer: &mut [u32], col: usize, row: usize, colour: u32) {
+	let (x0, y0) = (col * CELL_WIDTH, row * CELL_HEIGHT);
+	for y in 2..CELL_HEIGHT - 2 {
+		for x in 1..CELL_WIDTH - 1 {
+			buffer[(y0 + y) * WIDTH + x0 + x] = colour;
+		}
+	}
+}
+
+// bits 31 to 16 on row, 15 to 0 on the next, a gap between bytes
+fn bit_cell(bit: usize, row: usize) -> (usize, usize) {
+	let n = 31 - bit;
+	(SIDEBAR + n % 16 + n % 16 / 8, row + n / 16)
+}
+
+fn bits(buffer: &mut [u32], row: usize, x: u32, on: u32, off: u32) {
+	for bit in 0..32 {
+		let (col, row) = bit_cell(bit, row);
+		square(buffer, col, row, if x & (1 << bit) != 0 { on } else { off });
+	}
+}
+
+// the switch drawn at pixel x, y, if any
+fn switch_at(x: usize, y: usize) -> Option<usize> {
+	(0..32).find(|&bit| bit_cell(bit, SWITCHES_ROW) == (x / CELL_WIDTH, y / CELL_HEIGHT))
+}
+
+impl Window {
+	fn draw(&self, buffer: &mut [u32]) {
+		buffer.fill(BACKGROUND);
+		
+		title(buffer, 0, 0, PANE_COLS, "PRINTER");
+		for (n, line) in self.printer.shown().enumerate() {
+			let line: String = line.chars().take(PANE_COLS).collect();
+			text(buffer, 0, PRINTER_ROW + n, &line, TEXT, BACKGROUND);
+		}
+		title(buffer, 0, CONSOLE_ROW - 1, PANE_COLS, "CONSOLE");
+		for (n, line) in self.console.shown().enumerate() {
+			let line: String = line.chars().take(PANE_COLS).collect();
+			text(buffer, 0, CONSOLE_ROW + n, &line, TEXT, BACKGROUND);
+		}
+		text(buffer, 0, INPUT_ROW, &format!("> {}_", self.input), TEXT, BACKGROUND);
+		text(buffer, 0, INPUT_ROW + 1, "ENTER REPLIES, F12 PRESSES ATTENTION, CLICK A SWITCH TO FLIP IT", SWITCH_DOWN, BACKGROUND);
+		
+		let screen = self.screen.lock().unwrap();
+		title(buffer, 0, SCREEN_ROW - 1, PANE_COLS, if screen.on() { "DISPLAY" } else { "DISPLAY (OFF)" });
+		let blink = self.opened.elapsed().as_millis() / BLINK_MS % 2 == 0;
+		let pixels = screen.render(blink);
+		drop(screen);
+		for (y, row) in pixels.chunks(screen::WIDTH).enumerate() {
+			let at = (SCREEN_ROW * CELL_HEIGHT + y) * WIDTH;
+			buffer[at..at + screen::WIDTH].copy_from_slice(row);
+		}
+		
+		let r = *self.shown.lock().unwrap();
+		let sidebar = COLS - SIDEBAR;
+		title(buffer, SIDEBAR, 0, sidebar, if r.running { "RUNNING" } else { "STOPPED" });
+		for n in 0..16 {
+			let name = match n {
+				14 => "LR".to_string(),
+				15 => "PC".to_string(),
+				n => format!("R{}", n),
+			};
+			text(buffer, SIDEBAR, 1 + n, &format!("{:<3} {:08X}", name, r.R[n]), TEXT, BACKGROUND);
+		}
+		let flags: String = FLAG_NAMES.chars().enumerate()
+			.map(|(n, c)| if r.F0 & (0x80 >> n) != 0 { c } else { '.' }).collect();
+		text(buffer, SIDEBAR, 18, &format!("F0  {}", flags), TEXT, BACKGROUND);
+		text(buffer, SIDEBAR, 19, &format!("F8  {:02X}", r.F8), TEXT, BACKGROUND);
+		text(buffer, SIDEBAR, 20, &format!("PS  {:08X}", r.ps_base), TEXT, BACKGROUND);
+		text(buffer, SIDEBAR, 21, &format!("{} CYCLES", r.cycles), TEXT, BACKGROUND);
+		
+		title(buffer, SIDEBAR, LAMPS_ROW - 1, sidebar, "LAMPS");
+		bits(buffer, LAMPS_ROW, self.lamps, LAMP_ON, LAMP_OFF);
+		title(buffer, SIDEBAR, SWITCHES_ROW - 1, sidebar, "SWITCHES");
+		bits(buffer, SWITCHES_ROW, self.switches, SWITCH_UP, SWITCH_DOWN);
+	}
+	
+	fn input(&mut self, display: &mut Display) -> Vec<Event> {
+		let mut events = Vec::new();
+		for c in display.typed.borrow_mut().drain(..) {
+			if self.input.len() < PANE_COLS - 3 {
+				self.input.push(c);
+			}
+		}
+		for key in display.window.get_keys_pressed(KeyRepeat::Yes) {
+			match key {
+				Key::Enter | Key::NumPadEnter => {
+					let line = std::mem::take(&mut self.input);
+					self.console.line(&format!("> {}", line));
+					events.push(Event::Reply(0, line));
+				},
+				Key::Backspace => {
+					self.input.pop();
+				},
+				Key::F12 => events.push(Event::Attention),
+				_ => { },
+			}
+		}
+		
+		let pressed = display.window.get_mouse_down(MouseButton::Left);
+		if pressed && !display.pressed {
+			let at = display.window.get_mouse_pos(MouseMode::Discard);
+			if let Some(bit) = at.and_then(|(x, y)| switch_at(x as usize, y as usize)) {
+				events.push(Event::Switches(self.switches ^ (1 << bit)));
+			}
+		}
+		display.pressed = pressed;
+		events
+	}
+}
+
+impl Frontend for Window {
+	fn output(&mut self, _device: &str, text: &str) {
+		self.printer.write(text);
+	}
+	fn message(&mut self, id: u32, text: &str) {
+		self.console.line(&format!("{:X}: {}", id, text));
+	}
+	fn attention(&mut self, source: &str) {
+		self.console.line(&format!("ATTENTION FROM {}", source));
+	}
+	fn panel(&mut self, switches: u32, lamps: u32) {
+		self.switches = switches;
+		self.lamps = lamps;
+	}
+	// only from the thread that opened the window
+	fn poll(&mut self) -> Vec<Event> {
+		DISPLAY.with(|d| {
+			let mut d = d.borrow_mut();
+			let display = match d.as_mut() {
+				Some(x) if x.drawn.elapsed() >= FRAME => x,
+				_ => return Vec::new(),
+			};
+			self.draw(&mut display.buffer);
+			display.drawn = time::Instant::now();
+			if display.window.update_with_buffer(&display.buffer, WIDTH, HEIGHT).is_err() || !display.window.is_open() {
+				*d = None;
+				return vec![Event::Stop];
+			}
+			self.input(display)
+		})
+	}
+}